@@ -0,0 +1,335 @@
+use colorbox::matrix::AdaptationMethod;
+use image_fmt::Chromaticities;
+
+use crate::egui::{self, RichText, Ui};
+
+/// The color space the RGB of the source images is in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InputSpace {
+    /// From the images' embedded color profiles, or the camera's color
+    /// matrix for camera raw files.  Images without either are assumed
+    /// to be sRGB.
+    FromFiles,
+    Srgb,
+    DisplayP3,
+    AdobeRgb,
+    Custom,
+    /// The camera's color matrix, from camera raw files.
+    CameraMatrix,
+}
+
+impl InputSpace {
+    pub const ALL: [InputSpace; 6] = [
+        InputSpace::FromFiles,
+        InputSpace::Srgb,
+        InputSpace::DisplayP3,
+        InputSpace::AdobeRgb,
+        InputSpace::Custom,
+        InputSpace::CameraMatrix,
+    ];
+
+    /// The chromaticities of the fixed color spaces.
+    pub fn chromaticities(&self, custom: Chromaticities) -> Option<Chromaticities> {
+        match *self {
+            InputSpace::FromFiles | InputSpace::CameraMatrix => None,
+            InputSpace::Srgb => Some(image_fmt::REC709),
+            InputSpace::DisplayP3 => Some(from_colorbox(colorbox::chroma::DISPLAY_P3)),
+            InputSpace::AdobeRgb => Some(from_colorbox(colorbox::chroma::ADOBE_RGB)),
+            InputSpace::Custom => Some(custom),
+        }
+    }
+
+    pub fn ui_text(&self) -> &'static str {
+        match *self {
+            InputSpace::FromFiles => "From Files",
+            InputSpace::Srgb => "sRGB",
+            InputSpace::DisplayP3 => "Display P3",
+            InputSpace::AdobeRgb => "Adobe RGB",
+            InputSpace::Custom => "Custom",
+            InputSpace::CameraMatrix => "Camera Matrix",
+        }
+    }
+
+    pub fn hover_text(&self) -> &'static str {
+        match *self {
+            InputSpace::FromFiles => "Use the images' embedded color profiles, or the camera's color matrix for camera raw files.  Images with neither are assumed to be sRGB.",
+            InputSpace::Srgb => "sRGB / Rec.709 primaries, regardless of what the files say.",
+            InputSpace::DisplayP3 => "Display P3 primaries, regardless of what the files say.",
+            InputSpace::AdobeRgb => "Adobe RGB (1998) primaries, regardless of what the files say.",
+            InputSpace::Custom => "Enter the xy chromaticities of the primaries and white point.",
+            InputSpace::CameraMatrix => "The camera's color matrix.  Only available for camera raw files from supported cameras.",
+        }
+    }
+}
+
+/// The color space the HDRI is output in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WorkingSpace {
+    Rec709,
+    Rec2020,
+    AcesCg,
+    Aces2065,
+}
+
+impl WorkingSpace {
+    pub const ALL: [WorkingSpace; 4] = [
+        WorkingSpace::Rec709,
+        WorkingSpace::Rec2020,
+        WorkingSpace::AcesCg,
+        WorkingSpace::Aces2065,
+    ];
+
+    pub fn chromaticities(&self) -> Chromaticities {
+        match *self {
+            WorkingSpace::Rec709 => image_fmt::REC709,
+            WorkingSpace::Rec2020 => from_colorbox(colorbox::chroma::REC2020),
+            WorkingSpace::AcesCg => from_colorbox(colorbox::chroma::ACES_AP1),
+            WorkingSpace::Aces2065 => from_colorbox(colorbox::chroma::ACES_AP0),
+        }
+    }
+
+    pub fn ui_text(&self) -> &'static str {
+        match *self {
+            WorkingSpace::Rec709 => "Linear Rec.709",
+            WorkingSpace::Rec2020 => "Linear Rec.2020",
+            WorkingSpace::AcesCg => "ACEScg",
+            WorkingSpace::Aces2065 => "ACES2065-1",
+        }
+    }
+}
+
+/// The chromatic adaptation used when the input and output white
+/// points differ.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Adaptation {
+    Bradford,
+    VonKries,
+    XyzScale,
+}
+
+impl Adaptation {
+    pub const ALL: [Adaptation; 3] = [
+        Adaptation::Bradford,
+        Adaptation::VonKries,
+        Adaptation::XyzScale,
+    ];
+
+    pub fn method(&self) -> AdaptationMethod {
+        match *self {
+            Adaptation::Bradford => AdaptationMethod::Bradford,
+            Adaptation::VonKries => AdaptationMethod::Hunt,
+            Adaptation::XyzScale => AdaptationMethod::XYZScale,
+        }
+    }
+
+    pub fn ui_text(&self) -> &'static str {
+        match *self {
+            Adaptation::Bradford => "Bradford",
+            Adaptation::VonKries => "Von Kries",
+            Adaptation::XyzScale => "XYZ Scaling",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ColorSettings {
+    pub input: InputSpace,
+    pub custom_input: Chromaticities,
+    pub output: WorkingSpace,
+    pub adaptation: Adaptation,
+}
+
+impl ColorSettings {
+    pub fn new() -> ColorSettings {
+        ColorSettings {
+            input: InputSpace::FromFiles,
+            custom_input: image_fmt::REC709,
+            output: WorkingSpace::Rec709,
+            adaptation: Adaptation::Bradford,
+        }
+    }
+}
+
+pub fn color_space_ui(ui: &mut Ui, app: &mut crate::AppMain, job_count: usize) {
+    ui.add(egui::widgets::Label::new(RichText::new("Color:").strong()));
+
+    ui.add_enabled_ui(job_count == 0, |ui| {
+        let ui_data = &mut *app.ui_data.lock_mut();
+        let color = &mut ui_data.color;
+
+        egui::Grid::new("color_spaces").show(ui, |ui| {
+            ui.label("In:");
+            egui::ComboBox::from_id_source("Input Color Space")
+                .selected_text(color.input.ui_text())
+                .show_ui(ui, |ui| {
+                    for space in InputSpace::ALL {
+                        ui.selectable_value(&mut color.input, space, space.ui_text())
+                            .on_hover_text(space.hover_text());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Out:");
+            egui::ComboBox::from_id_source("Output Color Space")
+                .selected_text(color.output.ui_text())
+                .show_ui(ui, |ui| {
+                    for space in WorkingSpace::ALL {
+                        ui.selectable_value(&mut color.output, space, space.ui_text());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Adapt:").on_hover_text(
+                "How white points are adapted when the input and output white points differ.",
+            );
+            egui::ComboBox::from_id_source("Chromatic Adaptation")
+                .selected_text(color.adaptation.ui_text())
+                .show_ui(ui, |ui| {
+                    for adaptation in Adaptation::ALL {
+                        ui.selectable_value(
+                            &mut color.adaptation,
+                            adaptation,
+                            adaptation.ui_text(),
+                        );
+                    }
+                });
+            ui.end_row();
+        });
+
+        // Custom chromaticity coordinates.
+        if color.input == InputSpace::Custom {
+            ui.indent("custom_input_chroma_container", |ui| {
+                egui::Grid::new("custom_input_chroma")
+                    .min_col_width(4.0)
+                    .show(ui, |ui| {
+                        let precision = 0.0001;
+
+                        ui.label("");
+                        ui.label("x");
+                        ui.label("y");
+                        ui.end_row();
+
+                        let custom = &mut color.custom_input;
+                        for (label, xy) in [
+                            ("R", &mut custom.r),
+                            ("G", &mut custom.g),
+                            ("B", &mut custom.b),
+                            ("W", &mut custom.w),
+                        ] {
+                            ui.label(label);
+                            ui.add(
+                                egui::widgets::DragValue::new(&mut xy.0)
+                                    .clamp_range(-1.0..=2.0)
+                                    .speed(precision),
+                            );
+                            ui.add(
+                                egui::widgets::DragValue::new(&mut xy.1)
+                                    .clamp_range(-1.0..=2.0)
+                                    .speed(precision),
+                            );
+                            ui.end_row();
+                        }
+                    });
+                if let Err(message) = check_chromaticities(&color.custom_input) {
+                    ui.label(RichText::new(message).color(ui.visuals().error_fg_color));
+                }
+            });
+        }
+    });
+}
+
+/// Checks that chromaticities describe a usable RGB color space: none
+/// of the points can be at y = 0 (and the white point has to be above
+/// it), and the primaries can't be on a line.
+pub fn check_chromaticities(chroma: &Chromaticities) -> Result<(), String> {
+    for (name, (x, y), is_white) in [
+        ("red primary", chroma.r, false),
+        ("green primary", chroma.g, false),
+        ("blue primary", chroma.b, false),
+        ("white point", chroma.w, true),
+    ] {
+        // Some wide gamut primaries (e.g. ACES AP0's blue) are below
+        // y = 0, but nothing is on it.
+        let valid_y = if is_white {
+            y > 1.0e-6
+        } else {
+            y.abs() > 1.0e-6
+        };
+        if !x.is_finite() || !y.is_finite() || !valid_y {
+            return Err(format!(
+                "The input color space's {} has an invalid chromaticity.  Its y coordinate can't be zero{}.",
+                name,
+                if is_white { " or less" } else { "" }
+            ));
+        }
+    }
+
+    // The determinant of the primaries' xyz, which is zero when
+    // they're on a line.
+    let xyz = |(x, y): (f64, f64)| [x, y, 1.0 - x - y];
+    let (r, g, b) = (xyz(chroma.r), xyz(chroma.g), xyz(chroma.b));
+    let det = r[0] * (g[1] * b[2] - g[2] * b[1]) - g[0] * (r[1] * b[2] - r[2] * b[1])
+        + b[0] * (r[1] * g[2] - r[2] * g[1]);
+    if det.abs() < 1.0e-6 {
+        return Err(
+            "The input color space's primaries are on a line, so they don't form a color space."
+                .into(),
+        );
+    }
+
+    Ok(())
+}
+
+fn from_colorbox(chroma: colorbox::chroma::Chromaticities) -> Chromaticities {
+    Chromaticities {
+        r: chroma.r,
+        g: chroma.g,
+        b: chroma.b,
+        w: chroma.w,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        for space in InputSpace::ALL {
+            if let Some(chroma) = space.chromaticities(image_fmt::REC709) {
+                assert_eq!(check_chromaticities(&chroma), Ok(()), "{:?}", space);
+            }
+        }
+        for space in WorkingSpace::ALL {
+            assert_eq!(
+                check_chromaticities(&space.chromaticities()),
+                Ok(()),
+                "{:?}",
+                space
+            );
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let zero_y = Chromaticities {
+            g: (0.3, 0.0),
+            ..image_fmt::REC709
+        };
+        assert!(check_chromaticities(&zero_y).is_err());
+
+        let zero_white = Chromaticities {
+            w: (0.0, 0.0),
+            ..image_fmt::REC709
+        };
+        assert!(check_chromaticities(&zero_white).is_err());
+
+        let on_a_line = Chromaticities {
+            r: (0.6, 0.3),
+            g: (0.4, 0.4),
+            b: (0.2, 0.5),
+            w: (0.3127, 0.329),
+        };
+        assert!(check_chromaticities(&on_a_line).is_err());
+    }
+}
@@ -0,0 +1,435 @@
+//! Detection of things that moved between exposures, such as people,
+//! foliage, and clouds, which would otherwise show up as translucent
+//! "ghosts" in the merged HDRI.
+//!
+//! Ghosts are found by comparing the linearized radiance of the images
+//! against each other, and each ghost region is then taken from a
+//! single image instead of being blended from all of them.
+
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+use image_align::GrayImage;
+use sensor_analysis::eval_transfer_function_lut;
+
+use crate::merger::{MIN_READ_NOISE_VAR, SHOT_NOISE_SCALE};
+
+/// Relative radiance differences that are expected even without
+/// movement, e.g. from errors in the estimated transfer function.
+const RELATIVE_TOLERANCE: f32 = 0.1;
+
+/// The range of normalized pixel values that are compared.  Darker
+/// pixels are too noisy, and brighter ones may be clipped.
+const WELL_EXPOSED: (f32, f32) = (0.05, 0.85);
+
+/// Radiance differences over this range, in standard deviations of the
+/// expected noise, fade in as ghosts.
+const GHOST_SIGMAS: (f32, f32) = (3.0, 6.0);
+
+/// How far the mask is grown and feathered, relative to its larger
+/// dimension.
+const FEATHER: f32 = 0.005;
+
+/// Marks pixels that aren't replaced.
+const NO_SOURCE: u16 = u16::MAX;
+
+/// Where moving objects were found, and which image replaces them.
+#[derive(Debug, Clone)]
+pub struct GhostMask {
+    /// How much of each pixel is replaced, in [0.0, 1.0].
+    mask: GrayImage,
+
+    /// The index of the image each pixel is replaced from.
+    sources: Vec<u16>,
+}
+
+impl GhostMask {
+    /// Finds ghosts in a set of images.
+    ///
+    /// `images` are small grayscale versions of the images (e.g. the
+    /// ones used for alignment, already aligned), in the order they're
+    /// merged.  `floor_ceil` and `linearizing_curves` are the same as
+    /// for `HDRIMerger::add_image()`.
+    pub fn detect(
+        images: &[GrayImage],
+        exposures: &[f32],
+        floor_ceil: &[(f32, f32)],
+        linearizing_curves: &[Vec<f32>],
+    ) -> GhostMask {
+        let width = images[0].width;
+        let height = images[0].height;
+
+        let read_noise = (floor_ceil[0].0 + floor_ceil[1].0 + floor_ceil[2].0) * (1.0 / 3.0);
+        let read_noise_var = (read_noise * read_noise).max(MIN_READ_NOISE_VAR);
+
+        // The grayscale images are averages of the channels, so these
+        // average the per-channel curves.
+        let is_well_exposed = |n: f32| {
+            let n = floor_ceil
+                .iter()
+                .map(|&(floor, ceil)| (n - floor) / (ceil - floor))
+                .sum::<f32>()
+                * (1.0 / 3.0);
+            n >= WELL_EXPOSED.0 && n <= WELL_EXPOSED.1
+        };
+        let linearize = |n: f32| {
+            linearizing_curves
+                .iter()
+                .map(|curve| eval_transfer_function_lut(&curve[..], n))
+                .sum::<f32>()
+                * (1.0 / 3.0)
+        };
+
+        // Score each pixel by how much the radiances of consecutive
+        // well-exposed images disagree, relative to the expected noise.
+        let scores: Vec<f32> = (0..(width * height))
+            .into_par_iter()
+            .map(|i| {
+                let mut score = 0.0f32;
+                let mut prev: Option<(f32, f32)> = None; // (radiance, variance)
+                for (img, &exposure) in images.iter().zip(exposures.iter()) {
+                    let n = img.pixels[i];
+                    if !is_well_exposed(n) {
+                        continue;
+                    }
+                    let linear = linearize(n);
+                    let radiance = linear / exposure;
+                    let relative = linear * RELATIVE_TOLERANCE;
+                    let variance =
+                        (linear * SHOT_NOISE_SCALE + read_noise_var + relative * relative)
+                            / (exposure * exposure);
+
+                    if let Some((prev_radiance, prev_variance)) = prev {
+                        let sigmas =
+                            (radiance - prev_radiance).abs() / (variance + prev_variance).sqrt();
+                        let s = (sigmas - GHOST_SIGMAS.0) / (GHOST_SIGMAS.1 - GHOST_SIGMAS.0);
+                        score = score.max(s.clamp(0.0, 1.0));
+                    }
+                    prev = Some((radiance, variance));
+                }
+                score
+            })
+            .collect();
+
+        // Drop isolated pixels, which are just noise, and then grow and
+        // feather what's left so the replacement blends in smoothly.
+        let ghosts: Vec<bool> = scores.iter().map(|&s| s > 0.5).collect();
+        let ghosts = dilate(&erode(&ghosts, width, height, 1), width, height, 1);
+        let radius = ((width.max(height) as f32 * FEATHER).ceil() as usize).max(2);
+        let grown: Vec<f32> = dilate(&ghosts, width, height, radius)
+            .iter()
+            .map(|&g| if g { 1.0 } else { 0.0 })
+            .collect();
+        let mask: Vec<f32> = box_blur(&grown, width, height, radius)
+            .iter()
+            .map(|&n| n * n * (3.0 - 2.0 * n))
+            .collect();
+
+        // Take each connected ghost region from the image that has the
+        // most of it well exposed, preferring the middle exposures.
+        let mut sources = vec![NO_SOURCE; width * height];
+        let mut visited = vec![false; width * height];
+        let mut region = Vec::new();
+        let mut stack = Vec::new();
+        let middle = images.len() / 2;
+        for start in 0..(width * height) {
+            if visited[start] || mask[start] <= 0.0 {
+                continue;
+            }
+
+            // Flood fill.
+            region.clear();
+            stack.push(start);
+            visited[start] = true;
+            while let Some(i) = stack.pop() {
+                region.push(i);
+                let (x, y) = (i % width, i / width);
+                let neighbors = [
+                    (x > 0, i.wrapping_sub(1)),
+                    (x + 1 < width, i + 1),
+                    (y > 0, i.wrapping_sub(width)),
+                    (y + 1 < height, i + width),
+                ];
+                for &(in_bounds, n) in neighbors.iter() {
+                    if in_bounds && !visited[n] && mask[n] > 0.0 {
+                        visited[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+
+            let source = (0..images.len())
+                .max_by_key(|&img_i| {
+                    let well_exposed = region
+                        .iter()
+                        .filter(|&&i| is_well_exposed(images[img_i].pixels[i]))
+                        .count();
+                    let distance = (img_i as isize - middle as isize).abs();
+                    (well_exposed, -distance)
+                })
+                .unwrap_or(middle);
+            for &i in region.iter() {
+                sources[i] = source as u16;
+            }
+        }
+
+        GhostMask {
+            mask: GrayImage::new(mask, width, height),
+            sources,
+        }
+    }
+
+    /// The fraction of the image that's (at least partially) replaced.
+    pub fn coverage(&self) -> f32 {
+        self.sources.iter().filter(|&&s| s != NO_SOURCE).count() as f32 / self.sources.len() as f32
+    }
+
+    /// Upsamples the mask to an image with the given resolution, for
+    /// the rows in `rows`.  The resolution doesn't need to match the
+    /// mask's.
+    ///
+    /// Returns, for each pixel in row-major order, how much it's
+    /// replaced and which image it's replaced from, or `None` if it
+    /// isn't replaced.
+    pub fn sample_rows(
+        &self,
+        rows: Range<usize>,
+        width: usize,
+        height: usize,
+    ) -> Vec<Option<(f32, usize)>> {
+        let mw = self.mask.width;
+        let mh = self.mask.height;
+        let to_mask = |n: usize, size: usize, mask_size: usize| {
+            ((n as f32 + 0.5) * mask_size as f32 / size as f32 - 0.5)
+                .clamp(0.0, (mask_size - 1) as f32)
+        };
+
+        // The mask positions of each column, which are the same for
+        // every row: (x0, x1, fx, nearest x).
+        let columns: Vec<(usize, usize, f32, usize)> = (0..width)
+            .map(|x| {
+                let mx = to_mask(x, width, mw);
+                let x0 = mx as usize;
+                (
+                    x0,
+                    (x0 + 1).min(mw - 1),
+                    mx - x0 as f32,
+                    mx.round() as usize,
+                )
+            })
+            .collect();
+
+        let mut samples = Vec::with_capacity(rows.len() * width);
+        for y in rows {
+            let my = to_mask(y, height, mh);
+            let y0 = my as usize;
+            let y1 = (y0 + 1).min(mh - 1);
+            let fy = my - y0 as f32;
+            let nearest_row = &self.sources[(my.round() as usize * mw)..][..mw];
+
+            samples.extend(columns.iter().map(|&(x0, x1, fx, nearest_x)| {
+                let source = nearest_row[nearest_x];
+                if source == NO_SOURCE {
+                    return None;
+                }
+
+                let top = self.mask.get(x0, y0) * (1.0 - fx) + self.mask.get(x1, y0) * fx;
+                let bottom = self.mask.get(x0, y1) * (1.0 - fx) + self.mask.get(x1, y1) * fx;
+                let amount = top * (1.0 - fy) + bottom * fy;
+                if amount > 0.0 {
+                    Some((amount, source as usize))
+                } else {
+                    None
+                }
+            }));
+        }
+
+        samples
+    }
+}
+
+/// Grows the true areas of a bitmap by `radius` pixels (in a square).
+fn dilate(bits: &[bool], width: usize, height: usize, radius: usize) -> Vec<bool> {
+    let mut horizontal = vec![false; width * height];
+    horizontal
+        .par_chunks_mut(width)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let src = &bits[(y * width)..((y + 1) * width)];
+            for (x, bit) in row.iter_mut().enumerate() {
+                let start = x.saturating_sub(radius);
+                let end = (x + radius + 1).min(width);
+                *bit = src[start..end].iter().any(|&b| b);
+            }
+        });
+
+    let mut vertical = vec![false; width * height];
+    vertical
+        .par_chunks_mut(width)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let start = y.saturating_sub(radius);
+            let end = (y + radius + 1).min(height);
+            for (x, bit) in row.iter_mut().enumerate() {
+                *bit = (start..end).any(|sy| horizontal[sy * width + x]);
+            }
+        });
+
+    vertical
+}
+
+/// Shrinks the true areas of a bitmap by `radius` pixels (in a square).
+fn erode(bits: &[bool], width: usize, height: usize, radius: usize) -> Vec<bool> {
+    let inverted: Vec<bool> = bits.iter().map(|&b| !b).collect();
+    dilate(&inverted, width, height, radius)
+        .iter()
+        .map(|&b| !b)
+        .collect()
+}
+
+/// Averages each value with the others within `radius` pixels (in a
+/// square).
+fn box_blur(values: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+    let mut horizontal = vec![0.0f32; width * height];
+    horizontal
+        .par_chunks_mut(width)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let src = &values[(y * width)..((y + 1) * width)];
+            for (x, n) in row.iter_mut().enumerate() {
+                let start = x.saturating_sub(radius);
+                let end = (x + radius + 1).min(width);
+                *n = src[start..end].iter().sum::<f32>() / (end - start) as f32;
+            }
+        });
+
+    let mut vertical = vec![0.0f32; width * height];
+    vertical
+        .par_chunks_mut(width)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let start = y.saturating_sub(radius);
+            let end = (y + radius + 1).min(height);
+            for (x, n) in row.iter_mut().enumerate() {
+                *n = (start..end)
+                    .map(|sy| horizontal[sy * width + x])
+                    .sum::<f32>()
+                    / (end - start) as f32;
+            }
+        });
+
+    vertical
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 64;
+
+    /// Bitmaps as strings, one row per line.
+    fn bitmap(rows: &[&str]) -> Vec<bool> {
+        rows.iter()
+            .flat_map(|row| row.chars().map(|c| c == '#'))
+            .collect()
+    }
+
+    #[test]
+    fn morphology() {
+        let bits = bitmap(&[
+            "......", //
+            ".#....", "......", "...##.", "...##.", "......",
+        ]);
+        assert_eq!(
+            dilate(&bits, 6, 6, 1),
+            bitmap(&[
+                "###...", //
+                "###...", "######", "..####", "..####", "..####",
+            ])
+        );
+        assert_eq!(
+            erode(&dilate(&bits, 6, 6, 1), 6, 6, 1),
+            bitmap(&[
+                "##....", //
+                "##....", "......", "...###", "...###", "...###",
+            ])
+        );
+        // Erosion removes the isolated pixel.
+        assert_eq!(dilate(&erode(&bits, 6, 6, 1), 6, 6, 1), vec![false; 36]);
+    }
+
+    #[test]
+    fn blur() {
+        let values: Vec<f32> = (0..12).map(|i| if i == 5 { 9.0 } else { 0.0 }).collect();
+        let blurred = box_blur(&values, 4, 3, 1);
+        for (i, &n) in blurred.iter().enumerate() {
+            let (x, y) = (i % 4, i / 4);
+            let expected = if x <= 2 {
+                // The window is clipped at the edges.
+                let columns = if x == 0 { 2.0 } else { 3.0 };
+                let rows = if y == 1 { 3.0 } else { 2.0 };
+                9.0 / (columns * rows)
+            } else {
+                0.0
+            };
+            assert!((n - expected).abs() < 1.0e-6, "{} {}: {}", x, y, n);
+        }
+    }
+
+    /// A gray scene captured with the given exposure, with a brighter
+    /// square in it at `square`, if any.
+    fn capture(exposure: f32, square: Option<(usize, usize)>) -> GrayImage {
+        let pixels = (0..(SIZE * SIZE))
+            .map(|i| {
+                let (x, y) = (i % SIZE, i / SIZE);
+                let in_square = square
+                    .map(|(sx, sy)| x >= sx && x < sx + 10 && y >= sy && y < sy + 10)
+                    .unwrap_or(false);
+                if in_square {
+                    0.9 * exposure
+                } else {
+                    0.3 * exposure
+                }
+            })
+            .collect();
+        GrayImage::new(pixels, SIZE, SIZE)
+    }
+
+    #[test]
+    fn moving_square() {
+        let images = [
+            capture(0.25, Some((8, 8))),
+            capture(0.5, Some((8, 8))),
+            capture(0.9, Some((40, 30))),
+        ];
+        let floor_ceil = vec![(0.0, 1.0); 3];
+        let curves = vec![vec![0.0, 1.0]; 3];
+        let mask = GhostMask::detect(&images, &[0.25, 0.5, 0.9], &floor_ceil, &curves);
+
+        // Both where the square was and where it went are ghosts, taken
+        // from the middle exposure, and nothing else is.
+        let samples = mask.sample_rows(0..SIZE, SIZE, SIZE);
+        assert_eq!(samples[12 * SIZE + 12].map(|(_, source)| source), Some(1));
+        assert_eq!(samples[34 * SIZE + 44].map(|(_, source)| source), Some(1));
+        assert!(samples[12 * SIZE + 12].unwrap().0 > 0.9);
+        assert!(samples[34 * SIZE + 44].unwrap().0 > 0.9);
+        assert_eq!(samples[60 * SIZE + 4], None);
+        assert_eq!(samples[4 * SIZE + 60], None);
+        assert!(mask.coverage() > 0.05 && mask.coverage() < 0.2);
+
+        // Upsampling to a higher resolution gives the same result at
+        // corresponding positions.
+        let upsampled = mask.sample_rows(24..26, SIZE * 2, SIZE * 2);
+        for x in 0..SIZE {
+            let low = samples[12 * SIZE + x].map(|(_, source)| source);
+            let high = upsampled[1 + x * 2].map(|(_, source)| source);
+            if low.is_some() != high.is_some() {
+                // Only at the feathered edges.
+                assert!(samples[12 * SIZE + x].map_or(0.0, |(a, _)| a) < 0.1);
+            } else {
+                assert_eq!(low, high);
+            }
+        }
+    }
+}
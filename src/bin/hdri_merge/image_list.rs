@@ -2,17 +2,28 @@ use crate::egui::{self, Context, RichText, Ui};
 
 pub fn image_list(ctx: &Context, ui: &mut Ui, app: &mut crate::AppMain, job_count: usize) {
     let mut remove_i = None; // Temp to store index of an image to remove.
+    let mut set_exposure = None; // Temp to store an entered exposure and the index of its image.
 
     // Selected image info.
     // (Extra scope to contain ui_data's mutex guard.)
     {
         use egui::widgets::Label;
-        let ui_data = app.ui_data.lock();
+        let ui_data = &mut *app.ui_data.lock_mut();
         let spacing = 4.0;
 
         ui.add_space(spacing + 4.0);
         if ui_data.selected_image_index < ui_data.thumbnails.len() {
-            let info = &ui_data.thumbnails[ui_data.selected_image_index].3;
+            let selected_image_index = ui_data.selected_image_index;
+            let info = &ui_data.thumbnails[selected_image_index].3;
+            let reference = lib::exposure_entry::reference_image(
+                ui_data
+                    .thumbnails
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != selected_image_index)
+                    .map(|(_, thumbnail)| &thumbnail.3),
+            );
+            let exposure_entry = &mut ui_data.exposure_entry;
             ui.add(Label::new(RichText::new("Filename:").strong()));
             ui.indent("", |ui| ui.label(format!("{}", info.filename)));
 
@@ -32,7 +43,30 @@ pub fn image_list(ctx: &Context, ui: &mut Ui, app: &mut crate::AppMain, job_coun
                 })
             });
 
+            if let Some(transform) = info.alignment {
+                ui.add_space(spacing);
+                ui.add(Label::new(RichText::new("Alignment:").strong()));
+                ui.indent("", |ui| {
+                    let (x, y) =
+                        transform.offset_at(info.width as f64 * 0.5, info.height as f64 * 0.5);
+                    ui.label(format!("Offset: {:.1}, {:.1} px", x, y));
+                    if !transform.is_translation() {
+                        let m = &transform.matrix;
+                        ui.label(format!(
+                            "Rotation: {:.2}°",
+                            m[1][0].atan2(m[0][0]).to_degrees()
+                        ));
+                    }
+                });
+            }
+
             ui.add_space(spacing * 1.5);
+            ui.collapsing("set exposure", |ui| {
+                if let Some(estimate) = exposure_entry.ui(ui, job_count == 0, reference) {
+                    set_exposure = Some((selected_image_index, estimate));
+                }
+            });
+
             ui.collapsing("more", |ui| {
                 ui.add(Label::new("Filepath:"));
                 ui.indent("", |ui| ui.label(format!("{}", info.full_filepath)));
@@ -75,6 +109,25 @@ pub fn image_list(ctx: &Context, ui: &mut Ui, app: &mut crate::AppMain, job_coun
                         }
                     ))
                 });
+
+                if let Some((route, confidence)) = info.exposure_source {
+                    ui.indent("", |ui| {
+                        ui.label(format!(
+                            "Exposure from: {} ({} confidence)",
+                            route.ui_text(),
+                            confidence.ui_text()
+                        ))
+                    });
+                }
+
+                let fields = info.metadata.display_fields();
+                if !fields.is_empty() {
+                    ui.add_space(spacing);
+                    ui.add(Label::new("Metadata:"));
+                    for (label, value) in fields {
+                        ui.indent("", |ui| ui.label(format!("{}: {}", label, value)));
+                    }
+                }
             });
         } else {
             ui.label("No images loaded.");
@@ -83,6 +136,16 @@ pub fn image_list(ctx: &Context, ui: &mut Ui, app: &mut crate::AppMain, job_coun
 
     ui.add(egui::widgets::Separator::default().spacing(16.0));
 
+    // How the images are linearized.
+    crate::transfer_function::transfer_function_ui(ui, app, job_count);
+
+    ui.add_space(8.0);
+
+    // Color spaces.
+    crate::color_space::color_space_ui(ui, app, job_count);
+
+    ui.add(egui::widgets::Separator::default().spacing(16.0));
+
     // Image thumbnails.
     egui::containers::ScrollArea::vertical()
         .auto_shrink([false, false])
@@ -116,4 +179,7 @@ pub fn image_list(ctx: &Context, ui: &mut Ui, app: &mut crate::AppMain, job_coun
     if let Some(img_i) = remove_i {
         app.remove_image(img_i, ctx);
     }
+    if let Some((img_i, estimate)) = set_exposure {
+        app.set_image_exposure(img_i, estimate);
+    }
 }
@@ -1,4 +1,6 @@
 use crate::egui::{self, Align, Context, Ui};
+use crate::stats;
+use crate::view_transform::{false_color_legend, view_transform_ui, ViewTransform};
 use crate::ShowImage;
 
 pub fn image_view(
@@ -9,6 +11,8 @@ pub fn image_view(
     have_hdri: bool,
     have_hdri_preview_tex: bool,
 ) {
+    let have_ghost_mask = app.ui_data.lock().ghost_mask_tex.is_some();
+
     ui.horizontal(|ui| {
         let spacing = 16.0;
 
@@ -35,11 +39,27 @@ pub fn image_view(
             {
                 *show_image = ShowImage::HDRI;
             }
+            if ui
+                .add_enabled(
+                    have_ghost_mask,
+                    egui::widgets::RadioButton::new(
+                        *show_image == ShowImage::GhostMask,
+                        "Show Ghost Mask",
+                    ),
+                )
+                .on_hover_text(
+                    "Highlights the parts of the HDRI that deghosting took from a single image.",
+                )
+                .clicked()
+            {
+                *show_image = ShowImage::GhostMask;
+            }
         });
 
         ui.add_space(spacing);
 
-        if app.ui_data.lock().show_image == ShowImage::HDRI {
+        let show_image = app.ui_data.lock().show_image;
+        if show_image == ShowImage::HDRI || show_image == ShowImage::GhostMask {
             ui.add_space(spacing);
             if ui
                 .add(
@@ -51,6 +71,17 @@ pub fn image_view(
             {
                 app.compute_hdri_preview(ctx);
             }
+
+            ui.add_space(spacing);
+            let mut view = app.ui_data.lock().view_transform;
+            if view_transform_ui(ui, &mut view) {
+                app.ui_data.lock_mut().view_transform = view;
+                app.compute_hdri_preview(ctx);
+            }
+            if view == ViewTransform::FalseColor {
+                ui.add_space(spacing);
+                false_color_legend(ui);
+            }
         }
 
         ui.with_layout(egui::Layout::right_to_left(Align::Max), |ui| {
@@ -76,19 +107,96 @@ pub fn image_view(
         .auto_shrink([false, false])
         .show(ui, |ui| {
             if show_image == ShowImage::HDRI && have_hdri_preview_tex {
-                if let Some((ref tex_handle, _, _)) = app.ui_data.lock().hdri_preview_tex {
-                    ui.add(
-                        egui::widgets::Image::from_texture(tex_handle)
-                            .fit_to_original_size(image_zoom),
-                    );
+                let shown = match app.ui_data.lock().hdri_preview_tex {
+                    Some((ref tex_handle, width, height)) => Some((
+                        ui.add(
+                            egui::widgets::Image::from_texture(tex_handle)
+                                .fit_to_original_size(image_zoom),
+                        ),
+                        width,
+                        height,
+                    )),
+                    None => None,
+                };
+                if let Some((response, width, height)) = shown {
+                    hdri_probe(app, response, width, height);
+                }
+            } else if show_image == ShowImage::GhostMask {
+                let shown = match app.ui_data.lock().ghost_mask_tex {
+                    Some((ref tex_handle, width, height)) => Some((
+                        ui.add(
+                            egui::widgets::Image::from_texture(tex_handle)
+                                .fit_to_original_size(image_zoom),
+                        ),
+                        width,
+                        height,
+                    )),
+                    None => None,
+                };
+                if let Some((response, width, height)) = shown {
+                    hdri_probe(app, response, width, height);
                 }
             } else if show_image == ShowImage::SelectedImage && image_count > 0 {
-                if let Some((ref tex_handle, _, _)) = app.ui_data.lock().image_preview_tex {
+                // The preview texture can be smaller than the image, so
+                // it's sized by the image's resolution instead.
+                if let Some((ref tex_handle, width, height)) = app.ui_data.lock().image_preview_tex
+                {
                     ui.add(
-                        egui::widgets::Image::from_texture(tex_handle)
-                            .fit_to_original_size(image_zoom),
+                        egui::widgets::Image::from_texture(tex_handle).fit_to_exact_size(
+                            egui::Vec2::new(width as f32, height as f32) * image_zoom,
+                        ),
                     );
                 }
             }
         });
 }
+
+/// Shows the HDRI's values under the cursor, when it's hovered over
+/// its preview.
+fn hdri_probe(app: &crate::AppMain, response: egui::Response, width: usize, height: usize) {
+    let pos = match response.hover_pos() {
+        Some(pos) => pos,
+        None => return,
+    };
+    let rect = response.rect;
+    let x = ((pos.x - rect.min.x) / rect.width() * width as f32) as usize;
+    let y = ((pos.y - rect.min.y) / rect.height() * height as f32) as usize;
+
+    // Don't wait for jobs that are using the HDRI.
+    let probe = match app.hdri_merger.try_lock() {
+        Some(hdri) => hdri.as_ref().and_then(|hdri| {
+            if x < hdri.width && y < hdri.height {
+                let rgb = hdri.pixels[y * hdri.width + x];
+                let luminance =
+                    stats::luminance(rgb, stats::luminance_weights(hdri.chromaticities));
+                Some((rgb, luminance))
+            } else {
+                None
+            }
+        }),
+        None => None,
+    };
+
+    if let Some(([r, g, b], luminance)) = probe {
+        response.on_hover_ui_at_pointer(|ui| {
+            egui::Grid::new("hdri_probe").show(ui, |ui| {
+                ui.label("Pixel");
+                ui.label(format!("{}, {}", x, y));
+                ui.end_row();
+                ui.label("RGB");
+                ui.label(format!("{:.4}, {:.4}, {:.4}", r, g, b));
+                ui.end_row();
+                ui.label("Luminance");
+                ui.label(format!("{:.4}", luminance));
+                ui.end_row();
+                ui.label("EV (vs. 18% gray)");
+                ui.label(if luminance > 0.0 {
+                    format!("{:+.2}", stats::ev(luminance))
+                } else {
+                    "-".to_string()
+                });
+                ui.end_row();
+            });
+        });
+    }
+}
@@ -1,18 +1,39 @@
 #![windows_subsystem = "windows"] // Don't go through console on Windows.
 
+mod color_space;
+mod deghost;
 mod image_list;
 mod image_view;
 mod menu;
+mod merger;
+mod stats;
+mod transfer_function;
+mod view_transform;
 
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use eframe::egui;
 use rayon::prelude::*;
 
-use sensor_analysis::eval_transfer_function_lut;
+use image_align::{GrayImage, Method as AlignMethod};
+use sensor_analysis::{eval_transfer_function_lut, Histogram};
 use shared_data::Shared;
 
-use lib::{ImageBuf, ImageInfo, SourceImage};
+use lib::{
+    demosaic::Demosaic,
+    exposure_entry::ExposureEntry,
+    job_helpers::LoadOptions,
+    metadata::{Confidence, ExposureEstimate, ExposureRoute},
+    ImageBuf, ImageInfo,
+};
+
+use color_space::{ColorSettings, InputSpace};
+use deghost::GhostMask;
+use merger::{HDRIMerger, Weighting};
+use stats::HdriStats;
+use transfer_function::TransferFunction;
+use view_transform::{DisplayLut, ViewTransform};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -37,7 +58,7 @@ pub fn main() {
 pub struct AppMain {
     job_queue: job_queue::JobQueue,
 
-    images: Shared<Vec<SourceImage>>,
+    images: Shared<Vec<LoadedImage>>,
     hdri_merger: Shared<Option<HDRIMerger>>,
 
     ui_data: Shared<UIData>,
@@ -66,38 +87,91 @@ impl AppMain {
 
             ui_data: Shared::new(UIData {
                 preview_exposure: 0.0,
+                view_transform: ViewTransform::Standard,
                 selected_image_index: 0,
                 image_zoom: 1.0,
                 show_image: ShowImage::SelectedImage,
                 save_with_preview_exposure: false,
+                hdr_rle: true,
+                exr_precision: hdr::ExrPrecision::Half,
+                exr_compression: hdr::ExrCompression::Piz,
+                demosaic: Demosaic::Ahd,
+                apply_orientation: true,
+                exposure_entry: ExposureEntry::default(),
+                weighting: Weighting::Smooth,
+                alignment: None,
+                deghost: false,
+                transfer_function: TransferFunction::new(),
+                color: ColorSettings::new(),
 
                 thumbnails: Vec::new(),
                 image_preview_tex: None,
                 hdri_preview_tex: None,
+                ghost_mask_tex: None,
+                hdri_stats: None,
+                display_luts: Vec::new(),
             }),
         }
     }
 }
 
+/// A loaded source image.  Its full-resolution pixels aren't kept, to
+/// keep memory use bounded with many large images, and are instead
+/// loaded again from disk when merging.
+pub struct LoadedImage {
+    path: PathBuf,
+    load_options: LoadOptions,
+    info: ImageInfo,
+
+    histograms: [Histogram; 3],
+    samples: Vec<[f32; 3]>,           // For estimating missing exposures.
+    align_image: GrayImage,           // For estimating alignment.
+    preview: (Vec<u8>, usize, usize), // (RGBA pixels, width, height)
+}
+
+/// The maximum width and height of the image previews.
+const PREVIEW_MAX_RES: usize = 2048;
+
+/// The maximum width and height of the images alignment is estimated
+/// from.  The estimated transforms are sub-pixel accurate, so this
+/// loses little precision even for large images.
+const ALIGN_MAX_RES: usize = 2048;
+
 /// The data that the UI needs realtime access to for responsiveness.
 pub struct UIData {
     // Widgets.
     preview_exposure: f32,
+    view_transform: ViewTransform,
     selected_image_index: usize,
     image_zoom: f32,
     show_image: ShowImage,
     save_with_preview_exposure: bool,
+    hdr_rle: bool,
+    exr_precision: hdr::ExrPrecision,
+    exr_compression: hdr::ExrCompression,
+    demosaic: Demosaic,
+    apply_orientation: bool,
+    exposure_entry: ExposureEntry,
+    weighting: Weighting,
+    alignment: Option<AlignMethod>,
+    deghost: bool,
+    transfer_function: TransferFunction,
+    color: ColorSettings,
 
     // Others.
     thumbnails: Vec<(egui::TextureHandle, usize, usize, ImageInfo)>, // (GPU texture, width, height, info)
-    image_preview_tex: Option<(egui::TextureHandle, usize, usize)>,
+    image_preview_tex: Option<(egui::TextureHandle, usize, usize)>, // (GPU texture, image width, image height)
     hdri_preview_tex: Option<(egui::TextureHandle, usize, usize)>,
+    ghost_mask_tex: Option<(egui::TextureHandle, usize, usize)>, // The HDRI preview with the ghost mask over it.
+    hdri_stats: Option<HdriStats>,
+    display_luts: Vec<(ViewTransform, Arc<DisplayLut>)>, // Built as they're needed.
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ShowImage {
     SelectedImage,
     HDRI,
+    GhostMask,
 }
 
 impl eframe::App for AppMain {
@@ -123,16 +197,29 @@ impl eframe::App for AppMain {
                 "All Images",
                 &[
                     "jpg", "JPG", "jpeg", "JPEG", "tiff", "TIFF", "tif", "TIF", "webp", "WEBP",
-                    "png", "PNG",
-                ],
+                    "png", "PNG", "exr", "EXR", "dpx", "DPX", "cin", "CIN",
+                ]
+                .iter()
+                .map(|e| e.to_string())
+                .chain(lib::raw::raw_file_extensions())
+                .collect::<Vec<_>>(),
             )
             .add_filter("jpeg", &["jpg", "JPG", "jpeg", "JPEG"])
             .add_filter("tiff", &["tiff", "TIFF", "tif", "TIF"])
             .add_filter("webp", &["webp", "WEBP"])
-            .add_filter("png", &["png", "PNG"]);
+            .add_filter("png", &["png", "PNG"])
+            .add_filter("exr", &["exr", "EXR"])
+            .add_filter("dpx / cineon", &["dpx", "DPX", "cin", "CIN"])
+            .add_filter("camera raw", &lib::raw::raw_file_extensions());
+        let open_hdri_dialog = rfd::FileDialog::new()
+            .set_title("Open HDRI")
+            .add_filter("All HDRIs", &["hdr", "HDR", "exr", "EXR"])
+            .add_filter(".hdr", &["hdr", "HDR"])
+            .add_filter(".exr", &["exr", "EXR"]);
         let save_hdri_dialog = rfd::FileDialog::new()
             .set_title("Save HDRI")
-            .add_filter(".hdr", &["hdr", "HDR"]);
+            .add_filter(".hdr", &["hdr", "HDR"])
+            .add_filter(".exr", &["exr", "EXR"]);
 
         //----------------
         // GUI.
@@ -142,6 +229,7 @@ impl eframe::App for AppMain {
             ctx,
             self,
             &add_images_dialog,
+            &open_hdri_dialog,
             &save_hdri_dialog,
             have_hdri,
             job_count,
@@ -158,6 +246,22 @@ impl eframe::App for AppMain {
                 image_list::image_list(ctx, ui, self, job_count);
             });
 
+        // HDRI statistics (right-side panel).
+        if have_hdri {
+            egui::containers::panel::SidePanel::right("hdri_stats")
+                .min_width(200.0)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.add_space(4.0);
+                    match self.ui_data.lock().hdri_stats {
+                        Some(ref stats) => stats::stats_ui(ui, stats),
+                        None => {
+                            ui.label("Computing statistics...");
+                        }
+                    }
+                });
+        }
+
         // Main area.
         egui::containers::panel::CentralPanel::default().show(ctx, |ui| {
             ui.horizontal_top(|ui| {
@@ -171,8 +275,50 @@ impl eframe::App for AppMain {
                     }
                 }
 
+                // Demosaicing method for camera raw files.
+                ui.add_enabled_ui(job_count == 0, |ui| {
+                    let ui_data = &mut *self.ui_data.lock_mut();
+                    egui::ComboBox::from_id_source("Raw Demosaic")
+                        .selected_text(format!("Raw: {}", ui_data.demosaic.ui_text()))
+                        .show_ui(ui, |ui| {
+                            for method in [Demosaic::Bilinear, Demosaic::Ahd] {
+                                ui.selectable_value(&mut ui_data.demosaic, method, method.ui_text());
+                            }
+                        });
+                    ui.checkbox(&mut ui_data.apply_orientation, "Auto-rotate")
+                        .on_hover_text("Rotate and flip images as specified by their Exif orientation.");
+                });
+
                 ui.label(" ➡ ");
 
+                // Merge weighting.
+                ui.add_enabled_ui(job_count == 0, |ui| {
+                    let ui_data = &mut *self.ui_data.lock_mut();
+                    egui::ComboBox::from_id_source("Merge Weighting")
+                        .selected_text(format!("Weighting: {}", ui_data.weighting.ui_text()))
+                        .show_ui(ui, |ui| {
+                            for weighting in Weighting::ALL {
+                                ui.selectable_value(&mut ui_data.weighting, weighting, weighting.ui_text())
+                                    .on_hover_text(weighting.hover_text());
+                            }
+                        });
+                    ui.checkbox(&mut ui_data.deghost, "Deghost")
+                        .on_hover_text("Take things that moved between the images (people, foliage, clouds...) from a single image, rather than blending them into translucent ghosts.");
+                });
+
+                // Alignment of handheld brackets.
+                ui.add_enabled_ui(job_count == 0, |ui| {
+                    let ui_data = &mut *self.ui_data.lock_mut();
+                    egui::ComboBox::from_id_source("Alignment")
+                        .selected_text(format!("Align: {}", alignment_ui_text(ui_data.alignment)))
+                        .show_ui(ui, |ui| {
+                            for alignment in [None, Some(AlignMethod::Translation), Some(AlignMethod::Homography)] {
+                                ui.selectable_value(&mut ui_data.alignment, alignment, alignment_ui_text(alignment))
+                                    .on_hover_text(alignment_hover_text(alignment));
+                            }
+                        });
+                });
+
                 // Build HDRI button.
                 if ui
                     .add_enabled(
@@ -209,6 +355,51 @@ impl eframe::App for AppMain {
                         "Save with preview exposure",
                     ),
                 );
+
+                ui.add_space(20.0);
+
+                // HDR output options.
+                ui.add_enabled(
+                    job_count == 0,
+                    egui::widgets::Checkbox::new(
+                        &mut self.ui_data.lock_mut().hdr_rle,
+                        "Compress .hdr",
+                    ),
+                );
+
+                ui.add_space(20.0);
+
+                // EXR output options.
+                ui.add_enabled_ui(job_count == 0, |ui| {
+                    let ui_data = &mut *self.ui_data.lock_mut();
+                    ui.label("EXR:");
+                    egui::ComboBox::from_id_source("EXR Precision")
+                        .selected_text(exr_precision_ui_text(ui_data.exr_precision))
+                        .show_ui(ui, |ui| {
+                            for precision in [hdr::ExrPrecision::Half, hdr::ExrPrecision::Float] {
+                                ui.selectable_value(
+                                    &mut ui_data.exr_precision,
+                                    precision,
+                                    exr_precision_ui_text(precision),
+                                );
+                            }
+                        });
+                    egui::ComboBox::from_id_source("EXR Compression")
+                        .selected_text(exr_compression_ui_text(ui_data.exr_compression))
+                        .show_ui(ui, |ui| {
+                            for compression in [
+                                hdr::ExrCompression::None,
+                                hdr::ExrCompression::Zip,
+                                hdr::ExrCompression::Piz,
+                            ] {
+                                ui.selectable_value(
+                                    &mut ui_data.exr_compression,
+                                    compression,
+                                    exr_compression_ui_text(compression),
+                                );
+                            }
+                        });
+                });
             });
 
             ui.add(egui::widgets::Separator::default().spacing(12.0));
@@ -240,6 +431,10 @@ impl AppMain {
     fn add_image_files(&mut self, mut image_paths: Vec<PathBuf>, ctx: &egui::Context) {
         let images = self.images.clone_ref();
         let ui_data = self.ui_data.clone_ref();
+        let load_options = LoadOptions {
+            demosaic: self.ui_data.lock().demosaic,
+            apply_orientation: self.ui_data.lock().apply_orientation,
+        };
         let ctx1 = ctx.clone();
         let ctx2 = ctx.clone();
 
@@ -255,8 +450,30 @@ impl AppMain {
                     (img_i + 1) as f32 / len,
                 );
 
+                // Ensure it has the same resolution as the other images.
+                // When possible this is checked before decoding, so that
+                // mismatched images don't take a full load to reject.
+                let needed_dimensions = images
+                    .lock()
+                    .first()
+                    .map(|img| (img.info.width, img.info.height));
+                let log_resolution_mismatch = || {
+                    status.lock_mut().log_error(format!(
+                        "Image has a different resolution: \"{}\".  Not loading.  Note: all images must have the same resolution.",
+                        path.to_string_lossy()
+                    ));
+                };
+                if let (Some(needed_dimensions), Some(dimensions)) =
+                    (needed_dimensions, lib::job_helpers::probe_dimensions(&path, &load_options))
+                {
+                    if dimensions != needed_dimensions {
+                        log_resolution_mismatch();
+                        continue;
+                    }
+                }
+
                 // Load image.
-                let img = match lib::job_helpers::load_image(&path) {
+                let img = match lib::job_helpers::load_image(&path, &load_options) {
                     Ok(img) => img,
                     Err(image_fmt::ReadError::IO(e)) => {
                         status.lock_mut().log_error(format!(
@@ -280,17 +497,20 @@ impl AppMain {
                         ));
                         return;
                     }
+                    Err(e) => {
+                        status.lock_mut().log_error(format!(
+                            "Unable to load image file \"{}\": {}",
+                            path.to_string_lossy(),
+                            e,
+                        ));
+                        return;
+                    }
                 };
 
                 // Ensure it has the same resolution as the other images.
-                if !images.lock().is_empty() {
-                    let needed_width = images.lock()[0].image.width();
-                    let needed_height = images.lock()[0].image.height();
-                    if img.image.width() != needed_width || img.image.height() != needed_height {
-                        status.lock_mut().log_error(format!(
-                            "Image has a different resolution: \"{}\".  Not loading.  Note: all images must have the same resolution.",
-                            path.to_string_lossy()
-                        ));
+                if let Some(needed_dimensions) = needed_dimensions {
+                    if img.image.dimensions != needed_dimensions {
+                        log_resolution_mismatch();
                         continue;
                     }
                 }
@@ -298,7 +518,20 @@ impl AppMain {
                 // Check if we got exposure data from it.
                 if img.info.exposure.is_none() {
                     status.lock_mut().log_warning(format!(
-                        "Image file lacks Exif data needed to compute exposure value: \"{}\".  HDRI merging will not work correctly.",
+                        "Image file lacks Exif data needed to compute exposure value: \"{}\".  Its exposure will be estimated from its pixels when building the HDRI, unless you enter it manually.",
+                        path.to_string_lossy()
+                    ));
+                } else if matches!(img.info.exposure_source, Some((ExposureRoute::ExposureBias, _))) {
+                    status.lock_mut().log_warning(format!(
+                        "Image file lacks an exposure time, so its exposure was computed from its exposure bias: \"{}\".  This only works if all images are from the same auto-bracketed sequence.",
+                        path.to_string_lossy()
+                    ));
+                }
+
+                // Check if it's in a different gamut than the usual.
+                if !lib::job_helpers::image_chromaticities(&img.info).approx_eq(&image_fmt::REC709) {
+                    status.lock_mut().log_warning(format!(
+                        "Image file has an embedded color profile with non-sRGB primaries: \"{}\".  The merged HDRI will be converted to the output color space.",
                         path.to_string_lossy()
                     ));
                 }
@@ -317,19 +550,37 @@ impl AppMain {
                     )
                 };
 
+                // Keep what we need of the image.
+                let loaded_image = LoadedImage {
+                    path: path.clone(),
+                    load_options,
+                    info: img.info.clone(),
+
+                    histograms: lib::job_helpers::compute_image_histograms(&img),
+                    samples: lib::job_helpers::sample_linear_pixels(&img, 1 << 16),
+                    align_image: lib::job_helpers::make_alignment_image(&img, ALIGN_MAX_RES),
+                    preview: lib::job_helpers::make_image_preview(
+                        &img,
+                        Some(PREVIEW_MAX_RES),
+                        Some(PREVIEW_MAX_RES),
+                    ),
+                };
+                drop(img);
+
                 // Add image and thumbnail to our lists.
                 {
+                    let mut images = images.lock_mut();
                     let mut ui_data = ui_data.lock_mut();
                     ui_data.thumbnails
-                        .push((thumbnail_tex_handle, thumbnail_width, thumbnail_height, img.info.clone()));
-                    ui_data.thumbnails
-                        .sort_unstable_by(|a, b| a.3.exposure.partial_cmp(&b.3.exposure).unwrap());
-                }
-                {
-                    let mut images = images.lock_mut();
-                    images.push(img);
-                    images
-                        .sort_unstable_by(|a, b| a.info.exposure.partial_cmp(&b.info.exposure).unwrap());
+                        .push((thumbnail_tex_handle, thumbnail_width, thumbnail_height, loaded_image.info.clone()));
+                    images.push(loaded_image);
+
+                    // Any previous alignment was for a different set of
+                    // images, so it no longer applies.
+                    for img in images.iter_mut() {
+                        img.info.alignment = None;
+                    }
+                    sort_images(&mut images, &mut ui_data.thumbnails);
                 }
             }
         });
@@ -348,10 +599,20 @@ impl AppMain {
                 .set_progress(format!("Removing image..."), 0.0);
 
             {
-                images.lock_mut().remove(image_index);
+                let mut images = images.lock_mut();
+                images.remove(image_index);
+
+                // Any previous alignment was for a different set of
+                // images, so it no longer applies.
+                for img in images.iter_mut() {
+                    img.info.alignment = None;
+                }
 
                 let mut ui_data = ui_data.lock_mut();
                 let _ = ui_data.thumbnails.remove(image_index);
+                for thumbnail in ui_data.thumbnails.iter_mut() {
+                    thumbnail.3.alignment = None;
+                }
                 if ui_data.selected_image_index > image_index {
                     ui_data.selected_image_index -= 1;
                 }
@@ -362,47 +623,423 @@ impl AppMain {
         self.compute_image_preview(selected_image_index, ctx);
     }
 
+    /// Sets the exposure of an image, e.g. to one entered by the user.
+    fn set_image_exposure(&self, image_index: usize, estimate: ExposureEstimate) {
+        let mut images = self.images.lock_mut();
+        let mut ui_data = self.ui_data.lock_mut();
+        if image_index >= images.len() {
+            return;
+        }
+
+        images[image_index].info.set_exposure(estimate);
+        let order = sort_images(&mut images, &mut ui_data.thumbnails);
+
+        // Keep the same image selected.
+        if let Some(new_index) = order.iter().position(|&i| i == image_index) {
+            ui_data.selected_image_index = new_index;
+        }
+    }
+
+    /// Loads a LUT to linearize the images with, instead of estimating
+    /// their transfer function.
+    fn load_lut(&self, path: PathBuf) {
+        let ui_data = self.ui_data.clone_ref();
+
+        self.job_queue.add_job("Load LUT", move |status| {
+            status
+                .lock_mut()
+                .set_progress(format!("Loading LUT: {}", path.to_string_lossy()), 0.0);
+
+            let lut = match lib::job_helpers::load_1d_lut(&path) {
+                Ok(lut) => lut,
+                Err(colorbox::formats::ReadError::IoErr(_)) => {
+                    status.lock_mut().log_error(format!(
+                        "Unable to access file \"{}\".",
+                        path.to_string_lossy()
+                    ));
+                    return;
+                }
+                Err(colorbox::formats::ReadError::FormatErr) => {
+                    status.lock_mut().log_error(format!(
+                        "Not a 1D LUT file: \"{}\".",
+                        path.to_string_lossy()
+                    ));
+                    return;
+                }
+            };
+
+            ui_data.lock_mut().transfer_function.loaded_lut = Some((lut, path));
+        });
+    }
+
+    /// Estimates the sensor floor and ceiling for the loaded LUT from
+    /// the images' histograms.
+    fn estimate_sensor_floor_ceiling(&self) {
+        let images = self.images.clone_ref();
+        let ui_data = self.ui_data.clone_ref();
+
+        self.job_queue
+            .add_job("Estimate Floor/Ceiling", move |status| {
+                status
+                    .lock_mut()
+                    .set_progress("Estimating sensor floor and ceiling".into(), 0.0);
+
+                let mut histograms = [Vec::new(), Vec::new(), Vec::new()];
+                for img in images.lock().iter() {
+                    if let Some(exposure) = img.info.exposure {
+                        for (chan, chan_histograms) in histograms.iter_mut().enumerate() {
+                            chan_histograms.push((img.histograms[chan].clone(), exposure));
+                        }
+                    }
+                }
+
+                let floor_ceil = transfer_function::estimate_floor_ceiling(&histograms);
+                if floor_ceil.iter().any(|fc| fc.is_none()) {
+                    status.lock_mut().log_warning(
+                        "Unable to estimate the sensor floor and ceiling of some channels, which were left unchanged.  This needs images whose exposures differ by at least three stops.".into(),
+                    );
+                }
+
+                let tf = &mut ui_data.lock_mut().transfer_function;
+                for (chan, fc) in floor_ceil.iter().enumerate() {
+                    if let Some((floor, ceiling)) = *fc {
+                        tf.sensor_floor[chan] = floor;
+                        tf.sensor_ceiling[chan] = ceiling;
+                    }
+                }
+            });
+    }
+
+    /// Loads a previously saved HDRI, e.g. for re-exporting it.
+    fn open_hdri(&mut self, path: PathBuf, ctx: &egui::Context) {
+        let hdri = self.hdri_merger.clone_ref();
+        let ui_data = self.ui_data.clone_ref();
+
+        self.job_queue.add_job("Open HDRI", move |status| {
+            status
+                .lock_mut()
+                .set_progress(format!("Loading: {}", path.to_string_lossy()), 0.0);
+
+            let is_exr = path
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase() == "exr")
+                .unwrap_or(false);
+
+            let loaded = std::fs::File::open(&path)
+                .and_then(|file| {
+                    let mut reader = std::io::BufReader::new(file);
+                    if is_exr {
+                        hdr::read_exr(&mut reader)
+                    } else {
+                        hdr::read_hdr(&mut reader)
+                    }
+                })
+                .map(|img| {
+                    // Undo the file's exposure, to get the original
+                    // values back.
+                    let inv_exposure = 1.0 / img.exposure;
+                    let mut hdri_merger = HDRIMerger::from_pixels(
+                        img.pixels
+                            .iter()
+                            .map(|[r, g, b]| [r * inv_exposure, g * inv_exposure, b * inv_exposure])
+                            .collect(),
+                        img.width,
+                        img.height,
+                    );
+                    if let Some([r, g, b, w]) = img.primaries {
+                        let xy = |(x, y): (f32, f32)| (x as f64, y as f64);
+                        hdri_merger.chromaticities = image_fmt::Chromaticities {
+                            r: xy(r),
+                            g: xy(g),
+                            b: xy(b),
+                            w: xy(w),
+                        };
+                    }
+                    hdri_merger
+                });
+
+            match loaded {
+                Ok(hdri_merger) => {
+                    *hdri.lock_mut() = Some(hdri_merger);
+                    ui_data.lock_mut().show_image = ShowImage::HDRI;
+                }
+                Err(e) => {
+                    status.lock_mut().log_error(format!(
+                        "Unable to open HDRI \"{}\": {}.",
+                        path.to_string_lossy(),
+                        e
+                    ));
+                }
+            }
+        });
+
+        self.compute_hdri_preview(ctx);
+        self.compute_hdri_stats();
+    }
+
     fn build_hdri(&mut self, ctx: &egui::Context) {
         let images = self.images.clone_ref();
         let hdri = self.hdri_merger.clone_ref();
         let ui_data = self.ui_data.clone_ref();
+        let weighting = self.ui_data.lock().weighting;
+        let alignment = self.ui_data.lock().alignment;
+        let deghost = self.ui_data.lock().deghost;
+        let custom_tf = self.ui_data.lock().transfer_function.linearizing_curves();
+        let color = self.ui_data.lock().color;
 
         self.job_queue.add_job("Build HDRI", move |status| {
+            let custom_tf = match custom_tf {
+                Ok(custom_tf) => custom_tf,
+                Err(message) => {
+                    status.lock_mut().log_error(message);
+                    return;
+                }
+            };
+
             let img_len = images.lock().len();
-            let width = images.lock()[0].image.width();
-            let height = images.lock()[0].image.height();
+            let width = images.lock()[0].info.width;
+            let height = images.lock()[0].info.height;
 
-            status
-                .lock_mut()
-                .set_progress(format!("Estimating transfer function"), 0.0);
+            // Exposures computed from the exposure bias alone are relative
+            // to the camera's metering, and can't be compared to others.
+            {
+                let is_from_bias = |img: &LoadedImage| {
+                    matches!(img.info.exposure_source, Some((ExposureRoute::ExposureBias, _)))
+                };
+                let images = images.lock();
+                if images.iter().any(is_from_bias) && !images.iter().all(is_from_bias) {
+                    status.lock_mut().log_warning(
+                        "Some images' exposures were computed from their exposure bias and others' weren't, so they may not line up correctly.".into()
+                    );
+                }
+            }
 
-            // Calculate histograms.
-            let mut histograms = [Vec::new(), Vec::new(), Vec::new()];
-            for img_i in 0..img_len {
-                if status.lock().is_canceled() {
+            // Estimate the exposures of images that lack them, from how
+            // their pixels compare to the other images'.  Previous
+            // estimates are cleared and redone, since the other images
+            // may have changed since.
+            {
+                let mut images = images.lock_mut();
+                for img in images.iter_mut() {
+                    if matches!(img.info.exposure_source, Some((ExposureRoute::Estimated, _))) {
+                        img.info.exposure = None;
+                        img.info.exposure_source = None;
+                    }
+                }
+                if images.iter().all(|img| img.info.exposure.is_none()) {
+                    // There's nothing to tie the estimates to.
+                    status.lock_mut().log_error(
+                        "None of the images have a known exposure, so there's nothing to estimate the others' exposures relative to.  Please enter the exposure of at least one image manually.".into()
+                    );
                     return;
                 }
-                let src_img = &images.lock()[img_i];
-                if let Some(exposure) = src_img.info.exposure {
-                    let img_hists = lib::job_helpers::compute_image_histograms(src_img);
-                    for (chan, hist) in std::iter::IntoIterator::into_iter(img_hists).enumerate() {
-                        histograms[chan].push((hist, exposure));
+                if images.iter().any(|img| img.info.exposure.is_none()) {
+                    status
+                        .lock_mut()
+                        .set_progress("Estimating exposures".into(), 0.0);
+
+                    let samples: Vec<_> = images.iter().map(|img| img.samples.clone()).collect();
+                    let known: Vec<_> = images.iter().map(|img| img.info.exposure).collect();
+                    let exposures = lib::job_helpers::estimate_missing_exposures(&samples, &known);
+
+                    for (img, exposure) in images.iter_mut().zip(exposures) {
+                        if img.info.exposure.is_some() {
+                            continue;
+                        }
+                        if let Some(exposure) = exposure {
+                            let estimate = ExposureEstimate {
+                                exposure,
+                                exposure_time: img.info.exposure_time,
+                                fstop: img.info.fstop,
+                                iso: img.info.iso,
+                                route: ExposureRoute::Estimated,
+                                confidence: Confidence::Low,
+                            };
+                            img.info.set_exposure(estimate);
+                        }
                     }
+
+                    // The merge relies on the images being in exposure
+                    // order, and the UI should show the new exposures.
+                    sort_images(&mut images, &mut ui_data.lock_mut().thumbnails);
+                }
+
+                // Images without an exposure can't be merged correctly.
+                let unknown: Vec<_> = images
+                    .iter()
+                    .filter(|img| img.info.exposure.is_none())
+                    .map(|img| format!("\"{}\"", img.info.filename))
+                    .collect();
+                if !unknown.is_empty() {
+                    status.lock_mut().log_error(format!(
+                        "Unable to estimate the exposures of some images, since they don't have enough well-exposed pixels in common with images of known exposure: {}.  Please enter their exposures manually.",
+                        unknown.join(", ")
+                    ));
+                    return;
                 }
             }
 
-            // Estimate linearizating curve.
-            let (inv_mapping, floor_ceil_pairs, _) =
-                sensor_analysis::estimate_transfer_function(&[
-                    &histograms[0],
-                    &histograms[1],
-                    &histograms[2],
-                ]);
+            // Align the images to the middle exposure, which has the
+            // most in common with the others.  The transforms are
+            // estimated on the small alignment images, and kept at that
+            // scale for deghosting.
+            let align_transforms = {
+                let mut images = images.lock_mut();
+                let transforms = if let Some(method) = alignment {
+                    status
+                        .lock_mut()
+                        .set_progress("Aligning images".into(), 0.0);
+
+                    let align_images: Vec<_> = images.iter().map(|img| &img.align_image).collect();
+                    image_align::align_sequence(&align_images, img_len / 2, method)
+                        .into_iter()
+                        .map(Some)
+                        .collect()
+                } else {
+                    vec![None; img_len]
+                };
+
+                // Scale the transforms up to full resolution.
+                let scale_x = width as f64 / images[0].align_image.width as f64;
+                let scale_y = height as f64 / images[0].align_image.height as f64;
+                for (img, transform) in images.iter_mut().zip(transforms.iter()) {
+                    img.info.alignment = transform.map(|t| t.scaled(scale_x, scale_y));
+                }
+                let mut ui_data = ui_data.lock_mut();
+                for (thumbnail, img) in ui_data.thumbnails.iter_mut().zip(images.iter()) {
+                    thumbnail.3 = img.info.clone();
+                }
+
+                transforms
+            };
+
+            let (inv_mapping, floor_ceil_pairs) = if let Some(custom_tf) = custom_tf {
+                // Linearize with the user's LUT.
+                custom_tf
+            } else if images.lock().iter().all(|img| img.info.linear) {
+                // Already-linear images (e.g. camera raw) have nothing to
+                // estimate, and their black/white levels are already
+                // normalized away.
+                (
+                    (0..3).map(|_| vec![0.0, 1.0]).collect::<Vec<_>>(),
+                    (0..3).map(|_| (0.0, 1.0)).collect::<Vec<_>>(),
+                )
+            } else {
+                status
+                    .lock_mut()
+                    .set_progress("Estimating transfer function".into(), 0.0);
+
+                // Gather histograms.
+                let mut histograms = [Vec::new(), Vec::new(), Vec::new()];
+                for img in images.lock().iter() {
+                    if let Some(exposure) = img.info.exposure {
+                        for (chan, chan_histograms) in histograms.iter_mut().enumerate() {
+                            chan_histograms.push((img.histograms[chan].clone(), exposure));
+                        }
+                    }
+                }
+
+                // Estimate linearizating curve.
+                let (inv_mapping, floor_ceil_pairs, _) =
+                    sensor_analysis::estimate_transfer_function(&[
+                        &histograms[0],
+                        &histograms[1],
+                        &histograms[2],
+                    ]);
+                (inv_mapping, floor_ceil_pairs)
+            };
+
+            // Determine the conversion from the source images' RGB to
+            // the output color space, if any.  Merging images with
+            // different primaries doesn't make sense, so that's an error
+            // unless the input space is chosen manually.
+            let output_chroma = color.output.chromaticities();
+            let adaptation = color.adaptation.method();
+            let color_matrix = {
+                let images = images.lock();
+
+                // Camera matrices include the white balance, which can
+                // differ between the images, so the middle exposure's
+                // is used.
+                let camera_to_xyz = images[img_len / 2].info.camera_to_xyz;
+                let has_camera_matrix = images.iter().all(|img| img.info.camera_to_xyz.is_some());
+
+                match color.input {
+                    InputSpace::CameraMatrix if !has_camera_matrix => {
+                        status.lock_mut().log_error(
+                            "Not all of the images have a camera color matrix.  It's only available for camera raw files from supported cameras.".into(),
+                        );
+                        return;
+                    }
+                    InputSpace::CameraMatrix | InputSpace::FromFiles if has_camera_matrix => {
+                        Some(lib::job_helpers::camera_to_rgb_matrix(
+                            camera_to_xyz.unwrap(),
+                            output_chroma,
+                            adaptation,
+                        ))
+                    }
+                    _ => {
+                        let chroma = match color.input.chromaticities(color.custom_input) {
+                            Some(chroma) => chroma,
+                            None => {
+                                let chroma = lib::job_helpers::image_chromaticities(&images[0].info);
+                                if !images.iter().all(|img| {
+                                    lib::job_helpers::image_chromaticities(&img.info).approx_eq(&chroma)
+                                }) {
+                                    status.lock_mut().log_error(
+                                        "Source images have different color primaries, so they can't be merged.  Please choose their input color space manually.".into()
+                                    );
+                                    return;
+                                }
+                                chroma
+                            }
+                        };
+                        if let Err(message) = color_space::check_chromaticities(&chroma) {
+                            status.lock_mut().log_error(message);
+                            return;
+                        }
+                        if chroma.approx_eq(&output_chroma) {
+                            None
+                        } else {
+                            Some(lib::job_helpers::rgb_to_rgb_matrix(chroma, output_chroma, adaptation))
+                        }
+                    }
+                }
+            };
+
+            // Merge images, loading them one at a time.
+            let is_canceled = || status.lock().is_canceled();
+            let mut hdri_merger = HDRIMerger::new(width, height, weighting);
+            hdri_merger.set_transfer_function(&floor_ceil_pairs, &inv_mapping);
+
+            // Find moving objects, on the aligned alignment images.
+            if deghost {
+                status
+                    .lock_mut()
+                    .set_progress("Detecting moving objects".into(), 0.0);
+
+                let ghost_mask = {
+                    let images = images.lock();
+                    let small_images: Vec<GrayImage> = images
+                        .iter()
+                        .zip(align_transforms.iter())
+                        .map(|(img, transform)| match transform {
+                            Some(transform) => img.align_image.warped(transform),
+                            None => img.align_image.clone(),
+                        })
+                        .collect();
+                    let exposures: Vec<f32> = images.iter().map(|img| img.info.exposure.unwrap()).collect();
+                    GhostMask::detect(&small_images, &exposures, &floor_ceil_pairs, &inv_mapping)
+                };
+                status.lock_mut().log_note(format!(
+                    "Moving objects were found in {:.1}% of the image.",
+                    ghost_mask.coverage() * 100.0
+                ));
+                hdri_merger.set_ghost_mask(ghost_mask);
+            }
 
-            // Merge images.
-            let mut hdri_merger = HDRIMerger::new(width, height);
             for img_i in 0..img_len {
-                if status.lock().is_canceled() {
+                if is_canceled() {
                     return;
                 }
                 status.lock_mut().set_progress(
@@ -410,19 +1047,50 @@ impl AppMain {
                     (img_i + 1) as f32 / (img_len + 2) as f32,
                 );
 
-                let src_img = &images.lock()[img_i];
+                let (path, load_options, exposure, alignment) = {
+                    let img = &images.lock()[img_i];
+                    (img.path.clone(), img.load_options, img.info.exposure, img.info.alignment)
+                };
+                let mut src_img = match lib::job_helpers::load_image(&path, &load_options) {
+                    Ok(src_img) if src_img.image.dimensions == (width, height) => src_img,
+                    Ok(_) => {
+                        status.lock_mut().log_error(format!(
+                            "Image file has changed resolution since it was added: \"{}\".",
+                            path.to_string_lossy()
+                        ));
+                        return;
+                    }
+                    Err(e) => {
+                        status.lock_mut().log_error(format!(
+                            "Unable to load image file \"{}\": {}",
+                            path.to_string_lossy(),
+                            e
+                        ));
+                        return;
+                    }
+                };
+
+                // Parts of the frame that an image doesn't cover after
+                // warping don't contribute to the merge.
+                let coverage = match alignment {
+                    Some(transform) if transform != image_align::Transform::identity() => {
+                        Some(lib::job_helpers::warp_image(&mut src_img.image, &transform))
+                    }
+                    _ => None,
+                };
+
                 hdri_merger.add_image(
                     &src_img.image,
-                    src_img.info.exposure.unwrap_or(1.0),
-                    &floor_ceil_pairs,
-                    &inv_mapping,
+                    exposure.unwrap(),
+                    coverage.as_deref(),
                     img_i == 0,
                     img_i == img_len - 1,
+                    &is_canceled,
                 );
             }
 
             // Finalize.
-            if status.lock().is_canceled() {
+            if is_canceled() {
                 return;
             }
             status.lock_mut().set_progress(
@@ -430,12 +1098,19 @@ impl AppMain {
                 (img_len + 1) as f32 / (img_len + 2) as f32,
             );
             hdri_merger.finish();
+            // Either the colors were converted to the output space, or
+            // they were already in it.
+            if let Some(matrix) = color_matrix {
+                hdri_merger.transform_colors(matrix);
+            }
+            hdri_merger.chromaticities = output_chroma;
 
             *hdri.lock_mut() = Some(hdri_merger);
             ui_data.lock_mut().show_image = ShowImage::HDRI;
         });
 
         self.compute_hdri_preview(ctx);
+        self.compute_hdri_stats();
     }
 
     fn save_hdri(&mut self, path: PathBuf) {
@@ -445,20 +1120,124 @@ impl AppMain {
         } else {
             1.0
         };
+        let mut hdr_options = hdr::HdrOptions {
+            rle: self.ui_data.lock().hdr_rle,
+            primaries: None,
+        };
+        let mut exr_options = {
+            let ui_data = self.ui_data.lock();
+            hdr::ExrOptions {
+                precision: ui_data.exr_precision,
+                compression: ui_data.exr_compression,
+                chromaticities: None,
+            }
+        };
+        let save_as_exr = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase() == "exr")
+            .unwrap_or(false);
 
         self.job_queue.add_job("Save HDRI", move |status| {
             status
                 .lock_mut()
                 .set_progress(format!("Saving: {}", path.to_string_lossy()), 0.0);
             if let Some(ref hdri) = *hdri.lock() {
-                hdr::write_hdr(
-                    &mut std::io::BufWriter::new(std::fs::File::create(path).unwrap()),
-                    &hdri.pixels,
-                    hdri.width,
-                    hdri.height,
-                    exposure,
-                )
-                .unwrap();
+                // Record the HDRI's color space in the file.
+                let chroma = hdri.chromaticities;
+                let primaries = [
+                    (chroma.r.0 as f32, chroma.r.1 as f32),
+                    (chroma.g.0 as f32, chroma.g.1 as f32),
+                    (chroma.b.0 as f32, chroma.b.1 as f32),
+                    (chroma.w.0 as f32, chroma.w.1 as f32),
+                ];
+                hdr_options.primaries = Some(primaries);
+                exr_options.chromaticities = Some(primaries);
+
+                let write_result = (|| -> std::io::Result<()> {
+                    let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+                    if save_as_exr {
+                        hdr::write_exr(
+                            &mut file,
+                            &hdri.pixels,
+                            hdri.width,
+                            hdri.height,
+                            exposure,
+                            &exr_options,
+                        )
+                    } else {
+                        hdr::write_hdr(
+                            &mut file,
+                            &hdri.pixels,
+                            hdri.width,
+                            hdri.height,
+                            exposure,
+                            &hdr_options,
+                        )
+                    }
+                })();
+
+                if let Err(e) = write_result {
+                    status.lock_mut().log_error(format!(
+                        "couldn't write to {}: {}.",
+                        path.to_string_lossy(),
+                        e
+                    ));
+                }
+            }
+        });
+    }
+
+    /// Saves the HDRI as it's displayed in the preview, as an 8-bit
+    /// PNG or TIFF file.
+    fn save_ldr_preview(&self, path: PathBuf) {
+        let hdri = self.hdri_merger.clone_ref();
+        let ui_data = self.ui_data.clone_ref();
+        let exposure = 2.0f32.powf(self.ui_data.lock().preview_exposure);
+        let view = self.ui_data.lock().view_transform;
+        let format = path
+            .extension()
+            .and_then(|ext| image_fmt::Format::from_extension(&ext.to_string_lossy()))
+            .unwrap_or(image_fmt::Format::Png);
+
+        self.job_queue.add_job("Save LDR Preview", move |status| {
+            status
+                .lock_mut()
+                .set_progress(format!("Saving: {}", path.to_string_lossy()), 0.0);
+            let display_lut = display_lut(&ui_data, view);
+            if let Some(ref hdri) = *hdri.lock() {
+                let image = image_fmt::Image {
+                    dimensions: (hdri.width, hdri.height),
+                    data: ImageBuf::Rgba8(hdri_to_preview_pixels(
+                        &hdri.pixels,
+                        hdri.chromaticities,
+                        exposure,
+                        view,
+                        display_lut.as_deref(),
+                    ))
+                    .to_rgb(),
+                    color: Some(image_fmt::ColorDescription::srgb()),
+                };
+
+                let write_result = std::fs::File::create(&path)
+                    .map_err(image_fmt::WriteError::from)
+                    .and_then(|file| {
+                        image_fmt::save(
+                            std::io::BufWriter::new(file),
+                            &image,
+                            format,
+                            &image_fmt::SaveOptions {
+                                bit_depth: Some(image_fmt::BitDepth::U8),
+                            },
+                        )
+                    });
+
+                if let Err(e) = write_result {
+                    status.lock_mut().log_error(format!(
+                        "couldn't write to {}: {}",
+                        path.to_string_lossy(),
+                        e
+                    ));
+                }
             }
         });
     }
@@ -477,31 +1256,34 @@ impl AppMain {
                     .set_progress("Updating HDRI preview".to_string(), 0.0);
 
                 let exposure = 2.0f32.powf(ui_data.lock().preview_exposure);
-                let srgb_table: Vec<f32> = (0..256)
-                    .map(|n| colorbox::transfer_functions::srgb::from_linear(n as f32 / 255.0))
-                    .collect();
-                let map_val = |n: f32| {
-                    (eval_transfer_function_lut(&srgb_table, (n * exposure).max(0.0).min(1.0))
-                        * 255.0)
-                        .round() as u8
-                };
-
+                let view = ui_data.lock().view_transform;
+                let display_lut = display_lut(&ui_data, view);
                 let preview: Option<(Vec<u8>, usize, usize)> = hdri.lock().as_ref().map(|hdri| {
                     (
-                        hdri.pixels
-                            .par_iter()
-                            .map(|[r, g, b]| {
-                                let r = map_val(*r);
-                                let g = map_val(*g);
-                                let b = map_val(*b);
-                                [r, g, b, 255]
-                            })
-                            .flatten_iter()
-                            .collect(),
+                        hdri_to_preview_pixels(
+                            &hdri.pixels,
+                            hdri.chromaticities,
+                            exposure,
+                            view,
+                            display_lut.as_deref(),
+                        ),
                         hdri.width,
                         hdri.height,
                     )
                 });
+                let ghost_preview: Option<(Vec<u8>, usize, usize)> =
+                    match (hdri.lock().as_ref(), preview.as_ref()) {
+                        (Some(hdri), Some((pixels, width, height))) => {
+                            hdri.ghost_mask().map(|mask| {
+                                (
+                                    ghost_mask_preview_pixels(pixels, *width, *height, mask),
+                                    *width,
+                                    *height,
+                                )
+                            })
+                        }
+                        _ => None,
+                    };
 
                 if status.lock().is_canceled() {
                     return;
@@ -509,7 +1291,7 @@ impl AppMain {
 
                 if preview.is_some() {
                     // Update the HDRI preview texture.
-                    let tex_info = preview.as_ref().map(|(pixels, width, height)| {
+                    let to_texture = |(pixels, width, height): &(Vec<u8>, usize, usize)| {
                         (
                             ctx.load_texture(
                                 "",
@@ -523,16 +1305,39 @@ impl AppMain {
                             *width,
                             *height,
                         )
-                    });
+                    };
+                    let tex_info = preview.as_ref().map(to_texture);
+                    let ghost_tex_info = ghost_preview.as_ref().map(to_texture);
 
                     if let Some((tex_handle, width, height)) = tex_info {
                         let mut ui_data = ui_data.lock_mut();
                         ui_data.hdri_preview_tex = Some((tex_handle, width, height));
+                        ui_data.ghost_mask_tex = ghost_tex_info;
+                        if ui_data.ghost_mask_tex.is_none()
+                            && ui_data.show_image == ShowImage::GhostMask
+                        {
+                            ui_data.show_image = ShowImage::HDRI;
+                        }
                     }
                 }
             });
     }
 
+    fn compute_hdri_stats(&self) {
+        let hdri = self.hdri_merger.clone_ref();
+        let ui_data = self.ui_data.clone_ref();
+
+        self.job_queue
+            .add_job("Compute HDRI statistics", move |status| {
+                status
+                    .lock_mut()
+                    .set_progress("Computing HDRI statistics".to_string(), 0.0);
+
+                let stats = hdri.lock().as_ref().map(HdriStats::compute);
+                ui_data.lock_mut().hdri_stats = stats;
+            });
+    }
+
     fn compute_image_preview(&self, image_index: usize, ctx: &egui::Context) {
         let images = self.images.clone_ref();
         let ui_data = self.ui_data.clone_ref();
@@ -545,20 +1350,30 @@ impl AppMain {
                     .lock_mut()
                     .set_progress("Updating image preview".to_string(), 0.0);
 
-                let preview = images
-                    .lock()
-                    .get(image_index)
-                    .map(|image| lib::job_helpers::make_image_preview(image, None, None));
+                // The preview may be smaller than the image, but is
+                // displayed at the image's resolution.
+                let preview = images.lock().get(image_index).map(|img| {
+                    (
+                        img.preview.0.clone(),
+                        img.preview.1,
+                        img.preview.2,
+                        img.info.width,
+                        img.info.height,
+                    )
+                });
 
                 if status.lock().is_canceled() {
                     return;
                 }
 
-                if let Some((pixels, width, height)) = preview {
+                if let Some((pixels, preview_width, preview_height, width, height)) = preview {
                     // Update the image preview texture.
                     let tex_handle = ctx.load_texture(
                         "",
-                        egui::ColorImage::from_rgba_unmultiplied([width, height], &pixels),
+                        egui::ColorImage::from_rgba_unmultiplied(
+                            [preview_width, preview_height],
+                            &pixels,
+                        ),
                         egui::TextureOptions {
                             magnification: egui::TextureFilter::Linear,
                             minification: egui::TextureFilter::Linear,
@@ -573,132 +1388,168 @@ impl AppMain {
     }
 }
 
-#[derive(Debug)]
-struct HDRIMerger {
-    pixels: Vec<[f32; 3]>, // Vec<[r, g, b]>
-    pixel_weights: Vec<f32>,
-    width: usize,
-    height: usize,
-}
-
-impl HDRIMerger {
-    fn new(width: usize, height: usize) -> HDRIMerger {
-        HDRIMerger {
-            pixels: vec![[0.0; 3]; width * height],
-            pixel_weights: vec![0.0; width * height],
-            width: width,
-            height: height,
-        }
+/// Sorts the images by exposure, with their thumbnails in the same order
+/// and with up-to-date image info.
+///
+/// Returns the new order, as the previous index of each image.
+fn sort_images(
+    images: &mut Vec<LoadedImage>,
+    thumbnails: &mut Vec<(egui::TextureHandle, usize, usize, ImageInfo)>,
+) -> Vec<usize> {
+    debug_assert_eq!(images.len(), thumbnails.len());
+
+    let mut order: Vec<usize> = (0..images.len()).collect();
+    order.sort_by(
+        |&a, &b| match (images[a].info.exposure, images[b].info.exposure) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        },
+    );
+
+    let mut old_images: Vec<_> = images.drain(..).map(Some).collect();
+    let mut old_thumbnails: Vec<_> = thumbnails.drain(..).map(Some).collect();
+    for &i in order.iter() {
+        let img = old_images[i].take().unwrap();
+        let mut thumbnail = old_thumbnails[i].take().unwrap();
+        thumbnail.3 = img.info.clone();
+        images.push(img);
+        thumbnails.push(thumbnail);
     }
 
-    fn add_image(
-        &mut self,
-        img: &image_fmt::Image,
-        exposure: f32,
-        floor_ceil: &[(f32, f32)],
-        linearizing_curves: &[Vec<f32>],
-        is_lowest_exposed: bool,
-        is_highest_exposed: bool,
-    ) {
-        debug_assert_eq!(self.width, img.width());
-        debug_assert_eq!(self.height, img.height());
-
-        let r_floor = floor_ceil[0].0;
-        let r_norm = 1.0 / (floor_ceil[0].1 - floor_ceil[0].0);
-        let g_floor = floor_ceil[1].0;
-        let g_norm = 1.0 / (floor_ceil[1].1 - floor_ceil[1].0);
-        let b_floor = floor_ceil[2].0;
-        let b_norm = 1.0 / (floor_ceil[2].1 - floor_ceil[2].0);
-
-        let calc_weight = |encoded_rgb: (f32, f32, f32), linear_rgb: (f32, f32, f32)| -> f32 {
-            let r = (encoded_rgb.0 - r_floor) * r_norm;
-            let g = (encoded_rgb.1 - g_floor) * g_norm;
-            let b = (encoded_rgb.2 - b_floor) * b_norm;
-            let (lr, lg, lb) = linear_rgb;
-
-            if r.min(g).min(b).min(lr).min(lg).min(lb) < 0.0 {
-                return 0.0;
-            }
-
-            let n = if r.max(g).max(b) >= 1.0 {
-                // Make sure clipped colors are treated as such.
-                1.0
-            } else {
-                // Otherwise use the average because it seems to
-                // work the best in practice.
-                ((r + g + b) * (1.0 / 3.0)).min(1.0)
-            };
+    order
+}
 
-            // Triangle weight.
-            let tri = if (is_lowest_exposed && n > 0.5) || (is_highest_exposed && n < 0.5) {
-                // For highest/lowest exposed image, make the appropriate
-                // half a constant 1.0 instead of sloping down to zero.
-                1.0
-            } else {
-                ((0.5 - (n - 0.5).abs()) * 4.0).min(1.0)
-            };
+fn alignment_ui_text(alignment: Option<AlignMethod>) -> &'static str {
+    match alignment {
+        None => "Off",
+        Some(AlignMethod::Translation) => "Shift",
+        Some(AlignMethod::Homography) => "Perspective",
+    }
+}
 
-            // Triangle -> smooth step weight.
-            let smooth = tri * tri * (3.0 - 2.0 * tri);
+fn alignment_hover_text(alignment: Option<AlignMethod>) -> &'static str {
+    match alignment {
+        None => "Use the images as-is, e.g. when shot on a tripod.",
+        Some(AlignMethod::Translation) => "Shift the images to line up.  Good for most handheld brackets.",
+        Some(AlignMethod::Homography) => "Shift, rotate, and warp the images to line up.  For handheld brackets where the camera also rotated between shots.",
+    }
+}
 
-            smooth * smooth * smooth
-        };
+fn exr_precision_ui_text(precision: hdr::ExrPrecision) -> &'static str {
+    match precision {
+        hdr::ExrPrecision::Half => "Half float",
+        hdr::ExrPrecision::Float => "Full float",
+    }
+}
 
-        let inv_exposure = 1.0 / exposure;
-        match img.data {
-            ImageBuf::Rgb8(ref inner) => {
-                let quant_norm = 1.0 / ((1usize << 8) - 1) as f32;
-                for (i, pixel) in inner.chunks(3).enumerate() {
-                    let r = pixel[0] as f32 * quant_norm;
-                    let g = pixel[1] as f32 * quant_norm;
-                    let b = pixel[2] as f32 * quant_norm;
+fn exr_compression_ui_text(compression: hdr::ExrCompression) -> &'static str {
+    match compression {
+        hdr::ExrCompression::None => "No compression",
+        hdr::ExrCompression::Zip => "ZIP",
+        hdr::ExrCompression::Piz => "PIZ",
+    }
+}
 
-                    let r_linear = eval_transfer_function_lut(&linearizing_curves[0][..], r);
-                    let g_linear = eval_transfer_function_lut(&linearizing_curves[1][..], g);
-                    let b_linear = eval_transfer_function_lut(&linearizing_curves[2][..], b);
+/// The display LUT of a view transform, which is only built the first
+/// time it's needed.
+fn display_lut(ui_data: &Shared<UIData>, view: ViewTransform) -> Option<Arc<DisplayLut>> {
+    let cached = ui_data
+        .lock()
+        .display_luts
+        .iter()
+        .find(|(v, _)| *v == view)
+        .map(|(_, lut)| lut.clone());
+    if cached.is_some() {
+        return cached;
+    }
 
-                    let weight = calc_weight((r, g, b), (r_linear, g_linear, b_linear));
+    // Built without holding the lock, since it takes a moment.
+    let lut = Arc::new(view.display_lut()?);
+    ui_data.lock_mut().display_luts.push((view, lut.clone()));
+    Some(lut)
+}
 
-                    self.pixels[i][0] += r_linear * inv_exposure * weight;
-                    self.pixels[i][1] += g_linear * inv_exposure * weight;
-                    self.pixels[i][2] += b_linear * inv_exposure * weight;
-                    self.pixel_weights[i] += weight;
+/// Maps HDRI pixels in the given chromaticities to 8-bit sRGB
+/// `RGBARGBA...` data for display, with an exposure and view
+/// transform.  `display_lut` is the view transform's display LUT, if
+/// it has one.
+fn hdri_to_preview_pixels(
+    pixels: &[[f32; 3]],
+    chroma: image_fmt::Chromaticities,
+    exposure: f32,
+    view: ViewTransform,
+    display_lut: Option<&DisplayLut>,
+) -> Vec<u8> {
+    let to_rec709 = if chroma.approx_eq(&image_fmt::REC709) {
+        None
+    } else {
+        Some(lib::job_helpers::rgb_to_rgb_matrix(
+            chroma,
+            image_fmt::REC709,
+            colorbox::matrix::AdaptationMethod::Bradford,
+        ))
+    };
+    let srgb_table: Vec<f32> = (0..256)
+        .map(|n| colorbox::transfer_functions::srgb::from_linear(n as f32 / 255.0))
+        .collect();
+    let map_val =
+        |n: f32| (eval_transfer_function_lut(&srgb_table, n.clamp(0.0, 1.0)) * 255.0).round() as u8;
+
+    pixels
+        .par_iter()
+        .map(|&[r, g, b]| {
+            let [r, g, b] = match to_rec709 {
+                Some(matrix) => {
+                    let rgb =
+                        colorbox::matrix::transform_color([r as f64, g as f64, b as f64], matrix);
+                    [rgb[0] as f32, rgb[1] as f32, rgb[2] as f32]
+                }
+                None => [r, g, b],
+            };
+            let rgb = [r * exposure, g * exposure, b * exposure];
+            match (view, display_lut) {
+                (ViewTransform::FalseColor, _) => {
+                    let [r, g, b] = view_transform::false_color(rgb);
+                    [r, g, b, 255]
                 }
+                (_, Some(lut)) => {
+                    let [r, g, b] = lut.eval(rgb);
+                    [map_val(r), map_val(g), map_val(b), 255]
+                }
+                _ => [map_val(rgb[0]), map_val(rgb[1]), map_val(rgb[2]), 255],
             }
+        })
+        .flatten_iter()
+        .collect()
+}
 
-            ImageBuf::Rgb16(ref inner) => {
-                let quant_norm = 1.0 / ((1usize << 16) - 1) as f32;
-                for (i, pixel) in inner.chunks(3).enumerate() {
-                    let r = pixel[0] as f32 * quant_norm;
-                    let g = pixel[1] as f32 * quant_norm;
-                    let b = pixel[2] as f32 * quant_norm;
-
-                    let r_linear = eval_transfer_function_lut(&linearizing_curves[0][..], r);
-                    let g_linear = eval_transfer_function_lut(&linearizing_curves[1][..], g);
-                    let b_linear = eval_transfer_function_lut(&linearizing_curves[2][..], b);
-
-                    let weight = calc_weight((r, g, b), (r_linear, g_linear, b_linear));
-
-                    self.pixels[i][0] += r_linear * inv_exposure * weight;
-                    self.pixels[i][1] += g_linear * inv_exposure * weight;
-                    self.pixels[i][2] += b_linear * inv_exposure * weight;
-                    self.pixel_weights[i] += weight;
+/// Tints the preview pixels of an HDRI where deghosting replaced them.
+fn ghost_mask_preview_pixels(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    mask: &GhostMask,
+) -> Vec<u8> {
+    const TINT: [f32; 3] = [255.0, 40.0, 40.0];
+
+    let mut tinted = pixels.to_vec();
+    tinted
+        .par_chunks_mut(width * 4)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let ghosts = mask.sample_rows(y..(y + 1), width, height);
+            for (pixel, ghost) in row.chunks_mut(4).zip(ghosts) {
+                if let Some((amount, _)) = ghost {
+                    let amount = amount * 0.6;
+                    for chan in 0..3 {
+                        pixel[chan] =
+                            (pixel[chan] as f32 * (1.0 - amount) + TINT[chan] * amount) as u8;
+                    }
                 }
             }
+        });
 
-            _ => unreachable!(),
-        }
-    }
-
-    fn finish(&mut self) {
-        for (pixel, weight) in self.pixels.iter_mut().zip(self.pixel_weights.iter()) {
-            if *weight > 0.0 {
-                pixel[0] /= weight;
-                pixel[1] /= weight;
-                pixel[2] /= weight;
-            }
-        }
-    }
+    tinted
 }
 
 fn make_texture(img: (&[u8], usize, usize), ctx: &egui::Context) -> egui::TextureHandle {
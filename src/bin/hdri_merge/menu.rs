@@ -4,6 +4,7 @@ pub fn menu_bar(
     ctx: &Context,
     app: &mut crate::AppMain,
     add_images_dialog: &rfd::FileDialog,
+    open_hdri_dialog: &rfd::FileDialog,
     save_hdri_dialog: &rfd::FileDialog,
     have_hdri: bool,
     job_count: usize,
@@ -20,6 +21,15 @@ pub fn menu_bar(
                     }
                 }
 
+                if ui
+                    .add_enabled(job_count == 0, egui::widgets::Button::new("Open HDRI..."))
+                    .clicked()
+                {
+                    if let Some(path) = open_hdri_dialog.clone().pick_file() {
+                        app.open_hdri(path, ctx);
+                    }
+                }
+
                 if ui
                     .add_enabled(
                         have_hdri && job_count == 0,
@@ -32,6 +42,22 @@ pub fn menu_bar(
                     }
                 }
 
+                if ui
+                    .add_enabled(
+                        have_hdri && job_count == 0,
+                        egui::widgets::Button::new("Export LDR Preview..."),
+                    )
+                    .clicked()
+                {
+                    let save_ldr_dialog = rfd::FileDialog::new()
+                        .set_title("Export LDR Preview")
+                        .add_filter(".png", &["png", "PNG"])
+                        .add_filter(".tif", &["tif", "TIF", "tiff", "TIFF"]);
+                    if let Some(path) = save_ldr_dialog.save_file() {
+                        app.save_ldr_preview(path);
+                    }
+                }
+
                 ui.separator();
                 if ui.add(egui::widgets::Button::new("Quit")).clicked() {
                     ctx.send_viewport_cmd(egui::viewport::ViewportCommand::Close);
@@ -0,0 +1,615 @@
+use rayon::prelude::*;
+
+use sensor_analysis::eval_transfer_function_lut;
+
+use lib::ImageBuf;
+
+use crate::deghost::GhostMask;
+
+/// The number of scanlines merged together as one unit of work.
+const BAND_HEIGHT: usize = 16;
+
+/// The variance of photon shot noise per unit of linear pixel value,
+/// for noise-optimal weighting.  This roughly corresponds to a sensor
+/// with a full well capacity of a few thousand electrons.
+pub const SHOT_NOISE_SCALE: f32 = 1.0 / 4096.0;
+
+/// The minimum read noise variance for noise-optimal weighting, which
+/// also accounts for quantization.
+pub const MIN_READ_NOISE_VAR: f32 = 1.0e-7;
+
+/// How much each image contributes to each pixel of the merged HDRI.
+///
+/// The weights are based on how well exposed the pixel is in each
+/// image.  Broad weightings average more images together, reducing
+/// noise, while narrow ones are less prone to ghosting from movement.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Weighting {
+    /// A triangle with smoothstep-cubed falloff.  Strongly favors the
+    /// mid tones.
+    Smooth,
+
+    /// A hat: flat in the mid tones, with linear falloff in the darkest
+    /// and brightest quarters.
+    Hat,
+
+    /// A Gaussian centered on the mid tones (Robertson et al., 2003).
+    Gaussian,
+
+    /// A triangle peaking at the mid tones (Debevec and Malik, 1997).
+    DebevecMalik,
+
+    /// Inverse noise variance, from shot noise and the sensor floor.
+    /// Favors longer exposures, and gives the least noise for static
+    /// scenes.
+    NoiseOptimal,
+
+    /// Each pixel comes only from the brightest image where it isn't
+    /// clipped.  Noisier, but avoids ghosting from blending images.
+    BrightestUnclipped,
+}
+
+impl Weighting {
+    pub const ALL: [Weighting; 6] = [
+        Weighting::Smooth,
+        Weighting::Hat,
+        Weighting::Gaussian,
+        Weighting::DebevecMalik,
+        Weighting::NoiseOptimal,
+        Weighting::BrightestUnclipped,
+    ];
+
+    pub fn ui_text(&self) -> &'static str {
+        match *self {
+            Weighting::Smooth => "Smooth",
+            Weighting::Hat => "Hat",
+            Weighting::Gaussian => "Gaussian",
+            Weighting::DebevecMalik => "Debevec-Malik",
+            Weighting::NoiseOptimal => "Noise-optimal",
+            Weighting::BrightestUnclipped => "Brightest unclipped",
+        }
+    }
+
+    pub fn hover_text(&self) -> &'static str {
+        match *self {
+            Weighting::Smooth => "Strongly favors mid tones.  A good default.",
+            Weighting::Hat => "Uses everything but the darkest and brightest tones equally.  Less noise, more prone to ghosting.",
+            Weighting::Gaussian => "Smoothly favors mid tones.",
+            Weighting::DebevecMalik => "Linearly favors mid tones.",
+            Weighting::NoiseOptimal => "Favors longer exposures to minimize noise.  Best for static scenes, e.g. interiors.",
+            Weighting::BrightestUnclipped => "Takes each pixel from a single image, so moving things don't ghost.  Good for scenes with the sun or moving clouds.",
+        }
+    }
+}
+
+/// Counts of pixels that the images didn't cover well, for judging
+/// whether a set needs more exposures.
+#[derive(Debug, Copy, Clone)]
+pub struct MergeStats {
+    /// Pixels that are clipped even in the lowest exposed image.
+    pub clipped_pixels: usize,
+
+    /// Pixels that got no weight from any image, e.g. because they're
+    /// below the sensor floor in all of them.
+    pub zero_weight_pixels: usize,
+}
+
+#[derive(Debug)]
+pub struct HDRIMerger {
+    pub pixels: Vec<[f32; 3]>, // Vec<[r, g, b]>
+    pixel_weights: Vec<f32>,   // Emptied by `finish()`, since it's no longer needed.
+    pub width: usize,
+    pub height: usize,
+    pub chromaticities: image_fmt::Chromaticities, // Of the RGB in `pixels`.
+    weighting: Weighting,
+    images_added: usize,
+    clipped_pixels: usize,
+    stats: Option<MergeStats>, // Computed by `finish()`.
+
+    // The source images' transfer function.
+    floor_ceil: Vec<(f32, f32)>,       // Per channel.
+    linearizing_curves: Vec<Vec<f32>>, // Per channel.
+
+    // Deghosting.
+    ghost_mask: Option<GhostMask>,
+    ghost_pixels: Vec<[f32; 3]>, // Replacements for the masked pixels.  Emptied by `finish()`.
+}
+
+impl HDRIMerger {
+    pub fn new(width: usize, height: usize, weighting: Weighting) -> HDRIMerger {
+        HDRIMerger {
+            pixels: vec![[0.0; 3]; width * height],
+            pixel_weights: vec![0.0; width * height],
+            width,
+            height,
+            chromaticities: image_fmt::REC709,
+            weighting,
+            images_added: 0,
+            clipped_pixels: 0,
+            stats: None,
+            floor_ceil: vec![(0.0, 1.0); 3],
+            linearizing_curves: vec![vec![0.0, 1.0]; 3],
+            ghost_mask: None,
+            ghost_pixels: Vec::new(),
+        }
+    }
+
+    /// Creates an already-finished merger from existing HDR pixels.
+    pub fn from_pixels(pixels: Vec<[f32; 3]>, width: usize, height: usize) -> HDRIMerger {
+        assert_eq!(pixels.len(), width * height);
+        HDRIMerger {
+            pixels,
+            pixel_weights: Vec::new(),
+            width,
+            height,
+            chromaticities: image_fmt::REC709,
+            weighting: Weighting::Smooth,
+            images_added: 0,
+            clipped_pixels: 0,
+            stats: None,
+            floor_ceil: vec![(0.0, 1.0); 3],
+            linearizing_curves: vec![vec![0.0, 1.0]; 3],
+            ghost_mask: None,
+            ghost_pixels: Vec::new(),
+        }
+    }
+
+    /// Sets the sensor floor and ceiling and the linearizing curves of
+    /// the (non-float) source images, per channel.  Without them the
+    /// images are assumed to be linear already.
+    ///
+    /// Must be called before adding any images.
+    pub fn set_transfer_function(
+        &mut self,
+        floor_ceil: &[(f32, f32)],
+        linearizing_curves: &[Vec<f32>],
+    ) {
+        debug_assert_eq!(self.images_added, 0);
+        self.floor_ceil = floor_ceil.to_vec();
+        self.linearizing_curves = linearizing_curves.to_vec();
+    }
+
+    /// Enables deghosting: the masked pixels are taken from the image
+    /// the mask specifies, rather than from the weighted average of all
+    /// the images.
+    ///
+    /// Must be called before adding any images.  The mask's image
+    /// indices are in the order the images are added.
+    pub fn set_ghost_mask(&mut self, ghost_mask: GhostMask) {
+        debug_assert_eq!(self.images_added, 0);
+        self.ghost_mask = Some(ghost_mask);
+        self.ghost_pixels = vec![[0.0; 3]; self.width * self.height];
+    }
+
+    pub fn ghost_mask(&self) -> Option<&GhostMask> {
+        self.ghost_mask.as_ref()
+    }
+
+    /// The merge's clipped and zero-weight pixel counts.
+    ///
+    /// Returns `None` before `finish()` is called, and for HDRIs that
+    /// weren't merged (e.g. ones loaded from files).
+    pub fn stats(&self) -> Option<MergeStats> {
+        self.stats
+    }
+
+    /// Adds an image to the merge.  The image is processed in bands of
+    /// scanlines in parallel, and bands that haven't been started yet
+    /// are skipped once `is_canceled` returns true.
+    ///
+    /// Images must be added in order of increasing exposure.  Pixels
+    /// that are `false` in `coverage` (e.g. ones that an aligned image
+    /// doesn't cover) get no weight from the image.
+    pub fn add_image(
+        &mut self,
+        img: &image_fmt::Image,
+        exposure: f32,
+        coverage: Option<&[bool]>,
+        is_lowest_exposed: bool,
+        is_highest_exposed: bool,
+        is_canceled: &(dyn Fn() -> bool + Sync),
+    ) {
+        debug_assert_eq!(self.width, img.width());
+        debug_assert_eq!(self.height, img.height());
+        debug_assert!(coverage.is_none_or(|c| c.len() == self.width * self.height));
+
+        let floor_ceil = &self.floor_ceil;
+        let linearizing_curves = &self.linearizing_curves;
+        let r_floor = floor_ceil[0].0;
+        let r_norm = 1.0 / (floor_ceil[0].1 - floor_ceil[0].0);
+        let g_floor = floor_ceil[1].0;
+        let g_norm = 1.0 / (floor_ceil[1].1 - floor_ceil[1].0);
+        let b_floor = floor_ceil[2].0;
+        let b_norm = 1.0 / (floor_ceil[2].1 - floor_ceil[2].0);
+
+        let weighting = self.weighting;
+        let read_noise = (floor_ceil[0].0 + floor_ceil[1].0 + floor_ceil[2].0) * (1.0 / 3.0);
+        let read_noise_var = (read_noise * read_noise).max(MIN_READ_NOISE_VAR);
+
+        let calc_weight = |encoded_rgb: (f32, f32, f32), linear_rgb: (f32, f32, f32)| -> f32 {
+            let r = (encoded_rgb.0 - r_floor) * r_norm;
+            let g = (encoded_rgb.1 - g_floor) * g_norm;
+            let b = (encoded_rgb.2 - b_floor) * b_norm;
+            let (lr, lg, lb) = linear_rgb;
+            let is_clipped = r.max(g).max(b) >= 1.0;
+
+            if weighting == Weighting::BrightestUnclipped {
+                // Since images are added in order of increasing
+                // exposure, this is replaced by each brighter image
+                // where the pixel isn't clipped.
+                return if !is_clipped || is_lowest_exposed {
+                    1.0
+                } else {
+                    0.0
+                };
+            }
+
+            if r.min(g).min(b).min(lr).min(lg).min(lb) < 0.0 {
+                return 0.0;
+            }
+
+            if weighting == Weighting::NoiseOptimal {
+                // Fade out towards clipping, except in the lowest
+                // exposed image, which is all we have there.
+                let unclipped = if is_lowest_exposed {
+                    1.0
+                } else {
+                    ((1.0 - r.max(g).max(b)) * 20.0).clamp(0.0, 1.0)
+                };
+                let linear = (lr + lg + lb) * (1.0 / 3.0);
+                return unclipped * exposure * exposure
+                    / (linear * SHOT_NOISE_SCALE + read_noise_var);
+            }
+
+            let n = if is_clipped {
+                // Make sure clipped colors are treated as such.
+                1.0
+            } else {
+                // Otherwise use the average because it seems to
+                // work the best in practice.
+                ((r + g + b) * (1.0 / 3.0)).min(1.0)
+            };
+
+            // For highest/lowest exposed image, make the appropriate
+            // half a constant peak weight instead of sloping down to
+            // zero.
+            let n = if (is_lowest_exposed && n > 0.5) || (is_highest_exposed && n < 0.5) {
+                0.5
+            } else {
+                n
+            };
+
+            match weighting {
+                Weighting::Smooth => {
+                    // Triangle -> smooth step weight.
+                    let tri = ((0.5 - (n - 0.5).abs()) * 4.0).min(1.0);
+                    let smooth = tri * tri * (3.0 - 2.0 * tri);
+                    smooth * smooth * smooth
+                }
+                Weighting::Hat => ((0.5 - (n - 0.5).abs()) * 4.0).min(1.0),
+                Weighting::Gaussian => {
+                    // Offset and scaled to reach zero at the ends.
+                    let edge = (-4.0f32).exp();
+                    (((-16.0 * (n - 0.5) * (n - 0.5)).exp() - edge) / (1.0 - edge)).max(0.0)
+                }
+                Weighting::DebevecMalik => 1.0 - (2.0 * n - 1.0).abs(),
+                Weighting::NoiseOptimal | Weighting::BrightestUnclipped => unreachable!(),
+            }
+        };
+
+        let inv_exposure = 1.0 / exposure;
+        let image_index = self.images_added;
+        self.images_added += 1;
+        let (width, height) = (self.width, self.height);
+        let ghost_mask = self.ghost_mask.as_ref();
+
+        // Float images are already linear, so they skip the linearizing
+        // curves and keep their values above 1.0.  Only their weights
+        // are computed from the clipped values.
+        let clip = |n: f32| n.clamp(0.0, 1.0);
+
+        let band_len = self.width * BAND_HEIGHT;
+        let ghost_bands: Vec<Option<&mut [[f32; 3]]>> = if ghost_mask.is_some() {
+            self.ghost_pixels.chunks_mut(band_len).map(Some).collect()
+        } else {
+            (0..self.height.div_ceil(BAND_HEIGHT))
+                .map(|_| None)
+                .collect()
+        };
+        self.clipped_pixels += self
+            .pixels
+            .par_chunks_mut(band_len)
+            .zip(self.pixel_weights.par_chunks_mut(band_len))
+            .zip(ghost_bands.into_par_iter())
+            .enumerate()
+            .map(|(band_i, ((pixels, pixel_weights), mut ghost_pixels))| {
+                if is_canceled() {
+                    return 0;
+                }
+
+                // The band's range of channel values in the image.
+                let start = band_i * band_len;
+                let range = (start * 3)..((start + pixels.len()) * 3);
+                let mut clipped_pixels = 0;
+
+                // The ghost mask upsampled to the band's pixels.
+                let band_ghosts = ghost_mask.map(|ghost_mask| {
+                    let y = band_i * BAND_HEIGHT;
+                    ghost_mask.sample_rows(y..(y + pixels.len() / width), width, height)
+                });
+
+                let mut add_pixel = |i: usize, encoded: [f32; 3], linear: Option<[f32; 3]>| {
+                    let [r, g, b] = encoded;
+                    if is_lowest_exposed
+                        && ((r - r_floor) * r_norm)
+                            .max((g - g_floor) * g_norm)
+                            .max((b - b_floor) * b_norm)
+                            >= 1.0
+                    {
+                        clipped_pixels += 1;
+                    }
+
+                    let [r_linear, g_linear, b_linear] = linear.unwrap_or_else(|| {
+                        [
+                            eval_transfer_function_lut(&linearizing_curves[0][..], r),
+                            eval_transfer_function_lut(&linearizing_curves[1][..], g),
+                            eval_transfer_function_lut(&linearizing_curves[2][..], b),
+                        ]
+                    });
+
+                    // Deghosted pixels come from a single image.
+                    if let (Some(band_ghosts), Some(ghost_pixels)) =
+                        (band_ghosts.as_ref(), ghost_pixels.as_mut())
+                    {
+                        if let Some((_, source)) = band_ghosts[i] {
+                            if source == image_index {
+                                ghost_pixels[i] = [
+                                    r_linear * inv_exposure,
+                                    g_linear * inv_exposure,
+                                    b_linear * inv_exposure,
+                                ];
+                            }
+                        }
+                    }
+
+                    let weight = if coverage.is_none_or(|coverage| coverage[start + i]) {
+                        calc_weight((r, g, b), (r_linear, g_linear, b_linear))
+                    } else {
+                        0.0
+                    };
+
+                    if weighting == Weighting::BrightestUnclipped {
+                        if weight > 0.0 {
+                            pixels[i] = [
+                                r_linear * inv_exposure,
+                                g_linear * inv_exposure,
+                                b_linear * inv_exposure,
+                            ];
+                            pixel_weights[i] = 1.0;
+                        }
+                        return;
+                    }
+
+                    pixels[i][0] += r_linear * inv_exposure * weight;
+                    pixels[i][1] += g_linear * inv_exposure * weight;
+                    pixels[i][2] += b_linear * inv_exposure * weight;
+                    pixel_weights[i] += weight;
+                };
+
+                match img.data {
+                    ImageBuf::Rgb8(ref inner) => {
+                        let quant_norm = 1.0 / ((1usize << 8) - 1) as f32;
+                        for (i, pixel) in inner[range].chunks(3).enumerate() {
+                            add_pixel(
+                                i,
+                                [
+                                    pixel[0] as f32 * quant_norm,
+                                    pixel[1] as f32 * quant_norm,
+                                    pixel[2] as f32 * quant_norm,
+                                ],
+                                None,
+                            );
+                        }
+                    }
+
+                    ImageBuf::Rgb16(ref inner) => {
+                        let quant_norm = 1.0 / ((1usize << 16) - 1) as f32;
+                        for (i, pixel) in inner[range].chunks(3).enumerate() {
+                            add_pixel(
+                                i,
+                                [
+                                    pixel[0] as f32 * quant_norm,
+                                    pixel[1] as f32 * quant_norm,
+                                    pixel[2] as f32 * quant_norm,
+                                ],
+                                None,
+                            );
+                        }
+                    }
+
+                    ImageBuf::RgbF16(ref inner) => {
+                        for (i, pixel) in inner[range].chunks(3).enumerate() {
+                            let rgb = [pixel[0].to_f32(), pixel[1].to_f32(), pixel[2].to_f32()];
+                            add_pixel(
+                                i,
+                                [clip(rgb[0]), clip(rgb[1]), clip(rgb[2])],
+                                Some([rgb[0].max(0.0), rgb[1].max(0.0), rgb[2].max(0.0)]),
+                            );
+                        }
+                    }
+
+                    ImageBuf::RgbF32(ref inner) => {
+                        for (i, pixel) in inner[range].chunks(3).enumerate() {
+                            add_pixel(
+                                i,
+                                [clip(pixel[0]), clip(pixel[1]), clip(pixel[2])],
+                                Some([pixel[0].max(0.0), pixel[1].max(0.0), pixel[2].max(0.0)]),
+                            );
+                        }
+                    }
+
+                    _ => unreachable!(),
+                }
+
+                clipped_pixels
+            })
+            .sum::<usize>();
+    }
+
+    pub fn finish(&mut self) {
+        self.stats = Some(MergeStats {
+            clipped_pixels: self.clipped_pixels,
+            zero_weight_pixels: self.pixel_weights.par_iter().filter(|&&w| w <= 0.0).count(),
+        });
+
+        self.pixels
+            .par_iter_mut()
+            .zip(self.pixel_weights.par_iter())
+            .for_each(|(pixel, weight)| {
+                if *weight > 0.0 {
+                    pixel[0] /= weight;
+                    pixel[1] /= weight;
+                    pixel[2] /= weight;
+                }
+            });
+        self.pixel_weights = Vec::new();
+
+        if let Some(ref ghost_mask) = self.ghost_mask {
+            let (width, height) = (self.width, self.height);
+            let band_len = width * BAND_HEIGHT;
+            self.pixels
+                .par_chunks_mut(band_len)
+                .zip(self.ghost_pixels.par_chunks(band_len))
+                .enumerate()
+                .for_each(|(band_i, (pixels, ghost_pixels))| {
+                    let y = band_i * BAND_HEIGHT;
+                    let band_ghosts =
+                        ghost_mask.sample_rows(y..(y + pixels.len() / width), width, height);
+                    for ((pixel, ghost_pixel), ghost) in
+                        pixels.iter_mut().zip(ghost_pixels.iter()).zip(band_ghosts)
+                    {
+                        if let Some((amount, _)) = ghost {
+                            for (n, ghost_n) in pixel.iter_mut().zip(ghost_pixel.iter()) {
+                                *n += (ghost_n - *n) * amount;
+                            }
+                        }
+                    }
+                });
+            self.ghost_pixels = Vec::new();
+        }
+    }
+
+    /// Applies a color matrix to all pixels, e.g. for gamut conversion.
+    /// Doesn't change `chromaticities`, which is up to the caller.
+    pub fn transform_colors(&mut self, matrix: colorbox::matrix::Matrix) {
+        self.pixels.par_iter_mut().for_each(|pixel| {
+            let rgb = colorbox::matrix::transform_color(
+                [pixel[0] as f64, pixel[1] as f64, pixel[2] as f64],
+                matrix,
+            );
+            *pixel = [rgb[0] as f32, rgb[1] as f32, rgb[2] as f32];
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Several bands, with a partial one at the end.
+    const WIDTH: usize = 5;
+    const HEIGHT: usize = BAND_HEIGHT * 2 + 3;
+
+    /// The scene's linear value at each pixel, as a multiple of 1/255.
+    /// The last row is clipped in every exposure.
+    fn scene(i: usize) -> usize {
+        if i >= WIDTH * (HEIGHT - 1) {
+            255
+        } else if i.is_multiple_of(3) {
+            200
+        } else {
+            i % 60 + 1
+        }
+    }
+
+    /// An 8-bit linear capture of the scene.
+    fn capture(exposure: usize) -> image_fmt::Image {
+        let data = (0..(WIDTH * HEIGHT))
+            .flat_map(|i| {
+                let n = (scene(i) * exposure).min(255) as u8;
+                [n, n, n]
+            })
+            .collect();
+        image_fmt::Image {
+            dimensions: (WIDTH, HEIGHT),
+            data: ImageBuf::Rgb8(data),
+            color: None,
+        }
+    }
+
+    #[test]
+    fn two_exposures() {
+        let mut merger = HDRIMerger::new(WIDTH, HEIGHT, Weighting::Hat);
+        merger.add_image(&capture(1), 1.0, None, true, false, &|| false);
+        merger.add_image(&capture(4), 4.0, None, false, true, &|| false);
+        merger.finish();
+
+        for (i, pixel) in merger.pixels.iter().enumerate() {
+            let expected = scene(i) as f32 / 255.0;
+            for &n in pixel.iter() {
+                assert!(
+                    (n - expected).abs() < 1.0e-5,
+                    "pixel {}: {} != {}",
+                    i,
+                    n,
+                    expected
+                );
+            }
+        }
+        let stats = merger.stats().unwrap();
+        assert_eq!(stats.clipped_pixels, WIDTH);
+        assert_eq!(stats.zero_weight_pixels, 0);
+    }
+
+    #[test]
+    fn uncovered_pixels() {
+        // The brighter image doesn't cover the first column, and has
+        // junk there.
+        let coverage: Vec<bool> = (0..(WIDTH * HEIGHT)).map(|i| i % WIDTH != 0).collect();
+        let mut bright = capture(4);
+        if let ImageBuf::Rgb8(ref mut data) = bright.data {
+            for (pixel, &covered) in data.chunks_mut(3).zip(coverage.iter()) {
+                if !covered {
+                    pixel.copy_from_slice(&[17, 17, 17]);
+                }
+            }
+        }
+
+        let mut merger = HDRIMerger::new(WIDTH, HEIGHT, Weighting::Hat);
+        merger.add_image(&capture(1), 1.0, None, true, false, &|| false);
+        merger.add_image(&bright, 4.0, Some(&coverage), false, true, &|| false);
+        merger.finish();
+
+        for (i, pixel) in merger.pixels.iter().enumerate() {
+            let expected = scene(i) as f32 / 255.0;
+            assert!(
+                (pixel[0] - expected).abs() < 1.0e-5,
+                "pixel {}: {:?}",
+                i,
+                pixel
+            );
+        }
+    }
+
+    #[test]
+    fn canceled() {
+        let mut merger = HDRIMerger::new(WIDTH, HEIGHT, Weighting::Hat);
+        merger.add_image(&capture(1), 1.0, None, true, true, &|| true);
+        merger.finish();
+
+        assert!(merger.pixels.iter().all(|pixel| *pixel == [0.0; 3]));
+        let stats = merger.stats().unwrap();
+        assert_eq!(stats.clipped_pixels, 0);
+        assert_eq!(stats.zero_weight_pixels, WIDTH * HEIGHT);
+    }
+}
@@ -0,0 +1,230 @@
+use egui_plot::{Bar, BarChart, Plot};
+use rayon::prelude::*;
+
+use crate::egui::{self, RichText, Ui};
+use crate::merger::{HDRIMerger, MergeStats};
+
+/// Middle gray, which stops are relative to.
+const MID_GRAY: f32 = 0.18;
+
+/// The range of luminances that are tallied, in stops relative to
+/// middle gray.  Luminances outside of it are tallied at its ends.
+const TALLY_STOPS: (f32, f32) = (-32.0, 32.0);
+
+/// Tally buckets per stop.  Percentiles are accurate to a bucket.
+const TALLY_RES: usize = 16;
+
+/// Buckets per stop of the displayed histogram.
+const HISTOGRAM_RES: usize = 4;
+
+const PERCENTILES: [f32; 5] = [1.0, 5.0, 50.0, 95.0, 99.0];
+
+/// Luminance statistics of an HDRI.
+#[derive(Debug, Clone)]
+pub struct HdriStats {
+    pub pixel_count: usize,
+    pub min: f32,
+    pub max: f32,
+    pub percentiles: [(f32, f32); 5], // (percentile, luminance)
+
+    /// Pixels with zero or negative luminance, which aren't in the
+    /// histogram.
+    pub black_pixels: usize,
+
+    /// Pixel counts in `HISTOGRAM_RES` buckets per stop, starting at
+    /// `histogram_start` stops.
+    pub histogram: Vec<usize>,
+    pub histogram_start: f32,
+
+    pub merge: Option<MergeStats>,
+}
+
+impl HdriStats {
+    pub fn compute(hdri: &HDRIMerger) -> HdriStats {
+        let weights = luminance_weights(hdri.chromaticities);
+        let bucket_count = ((TALLY_STOPS.1 - TALLY_STOPS.0) as usize) * TALLY_RES;
+        let to_bucket = |luminance: f32| {
+            let stops = (luminance / MID_GRAY).log2();
+            let bucket = ((stops - TALLY_STOPS.0) * TALLY_RES as f32) as isize;
+            bucket.clamp(0, bucket_count as isize - 1) as usize
+        };
+
+        // (min, max, black pixels, tallies)
+        let (min, max, black_pixels, tallies) = hdri
+            .pixels
+            .par_chunks(hdri.width.max(1))
+            .map(|row| {
+                let mut min = f32::INFINITY;
+                let mut max = -f32::INFINITY;
+                let mut black_pixels = 0;
+                let mut tallies = vec![0usize; bucket_count];
+                for rgb in row.iter() {
+                    let y = luminance(*rgb, weights);
+                    min = min.min(y);
+                    max = max.max(y);
+                    if y > 0.0 {
+                        tallies[to_bucket(y)] += 1;
+                    } else {
+                        black_pixels += 1;
+                    }
+                }
+                (min, max, black_pixels, tallies)
+            })
+            .reduce(
+                || (f32::INFINITY, -f32::INFINITY, 0, vec![0usize; bucket_count]),
+                |a, b| {
+                    (
+                        a.0.min(b.0),
+                        a.1.max(b.1),
+                        a.2 + b.2,
+                        a.3.iter().zip(b.3.iter()).map(|(a, b)| a + b).collect(),
+                    )
+                },
+            );
+
+        // Percentiles, counting black pixels as the darkest.
+        let pixel_count = hdri.pixels.len();
+        let bucket_luminance =
+            |i: usize| MID_GRAY * (TALLY_STOPS.0 + (i as f32 + 0.5) / TALLY_RES as f32).exp2();
+        let mut percentiles = [(0.0, 0.0); 5];
+        for (percentile, p) in percentiles.iter_mut().zip(PERCENTILES.iter()) {
+            let target = (pixel_count as f32 * p / 100.0).ceil() as usize;
+            let mut count = black_pixels;
+            let mut luminance = min.min(0.0);
+            if count < target {
+                for (i, tally) in tallies.iter().enumerate() {
+                    count += tally;
+                    if count >= target {
+                        luminance = bucket_luminance(i).max(min).min(max);
+                        break;
+                    }
+                }
+            }
+            *percentile = (*p, luminance);
+        }
+
+        // Trim the histogram to the range that has pixels in it.
+        let first = tallies.iter().position(|&n| n > 0).unwrap_or(0);
+        let last = tallies.iter().rposition(|&n| n > 0).unwrap_or(0);
+        let step = TALLY_RES / HISTOGRAM_RES;
+        let first = first / step * step;
+        let histogram = tallies[first..=last]
+            .chunks(step)
+            .map(|chunk| chunk.iter().sum())
+            .collect();
+
+        HdriStats {
+            pixel_count,
+            min,
+            max,
+            percentiles,
+            black_pixels,
+            histogram,
+            histogram_start: TALLY_STOPS.0 + (first / step) as f32 / HISTOGRAM_RES as f32,
+            merge: hdri.stats(),
+        }
+    }
+}
+
+/// The weights that give the luminance of linear RGB in the given
+/// chromaticities.
+pub fn luminance_weights(chroma: image_fmt::Chromaticities) -> [f32; 3] {
+    let y =
+        colorbox::matrix::rgb_to_xyz_matrix(lib::job_helpers::colorbox_chromaticities(chroma))[1];
+    [y[0] as f32, y[1] as f32, y[2] as f32]
+}
+
+pub fn luminance(rgb: [f32; 3], weights: [f32; 3]) -> f32 {
+    rgb[0] * weights[0] + rgb[1] * weights[1] + rgb[2] * weights[2]
+}
+
+/// Luminance in stops relative to middle gray.
+pub fn ev(luminance: f32) -> f32 {
+    (luminance / MID_GRAY).log2()
+}
+
+pub fn stats_ui(ui: &mut Ui, stats: &HdriStats) {
+    let percent = |n: usize| n as f32 * 100.0 / stats.pixel_count.max(1) as f32;
+    let luminance_text = |luminance: f32| {
+        if luminance > 0.0 {
+            format!("{:.4}  ({:+.1} EV)", luminance, ev(luminance))
+        } else {
+            format!("{:.4}", luminance)
+        }
+    };
+
+    ui.add(egui::widgets::Label::new(
+        RichText::new("Luminance:").strong(),
+    ));
+    egui::Grid::new("hdri_luminance_stats").show(ui, |ui| {
+        ui.label("Min");
+        ui.label(luminance_text(stats.min));
+        ui.end_row();
+        for &(p, luminance) in stats.percentiles.iter() {
+            ui.label(format!("{}%", p));
+            ui.label(luminance_text(luminance));
+            ui.end_row();
+        }
+        ui.label("Max");
+        ui.label(luminance_text(stats.max));
+        ui.end_row();
+    });
+
+    ui.add_space(4.0);
+    Plot::new("hdri_luminance_histogram")
+        .height(120.0)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show_y(false)
+        .x_axis_label("EV")
+        .show(ui, |plot| {
+            let width = 1.0 / HISTOGRAM_RES as f64;
+            plot.bar_chart(
+                BarChart::new(
+                    stats
+                        .histogram
+                        .iter()
+                        .enumerate()
+                        .map(|(i, &n)| {
+                            Bar::new(
+                                stats.histogram_start as f64 + (i as f64 + 0.5) * width,
+                                n as f64,
+                            )
+                            .width(width)
+                        })
+                        .collect(),
+                )
+                .color(egui::Color32::GRAY),
+            );
+        });
+    if stats.black_pixels > 0 {
+        ui.label(format!(
+            "Black: {} ({:.2}%)",
+            stats.black_pixels,
+            percent(stats.black_pixels)
+        ))
+        .on_hover_text("Pixels with zero or negative luminance, which aren't in the histogram.");
+    }
+
+    ui.add_space(8.0);
+    ui.add(egui::widgets::Label::new(
+        RichText::new("Coverage:").strong(),
+    ));
+    if let Some(merge) = stats.merge {
+        ui.label(format!(
+            "Clipped: {} ({:.2}%)",
+            merge.clipped_pixels,
+            percent(merge.clipped_pixels)
+        ))
+        .on_hover_text("Pixels that are clipped even in the darkest image.  If there are many, the set needs a darker exposure.");
+        ui.label(format!(
+            "No data: {} ({:.2}%)",
+            merge.zero_weight_pixels,
+            percent(merge.zero_weight_pixels)
+        ))
+        .on_hover_text("Pixels that got no weight from any image, e.g. because they're below the sensor floor in all of them.  If there are many, the set needs a brighter exposure.");
+    } else {
+        ui.label("Only available for merged HDRIs.");
+    }
+}
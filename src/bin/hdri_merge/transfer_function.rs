@@ -0,0 +1,266 @@
+use std::path::PathBuf;
+
+use sensor_analysis::{estimate_sensor_floor_ceiling, utils::lerp_slice};
+
+use crate::egui::{self, RichText, Ui};
+
+/// The resolution of linearizing curves built from a loaded LUT.
+const CURVE_RESOLUTION: usize = 4096;
+
+/// Per-channel linearizing curves and sensor floor/ceiling pairs, as
+/// returned by `estimate_transfer_function()`.
+pub type LinearizingCurves = (Vec<Vec<f32>>, Vec<(f32, f32)>);
+
+/// How the source images are linearized.
+pub struct TransferFunction {
+    pub use_lut: bool, // When false, the transfer function is estimated from the images.
+    pub loaded_lut: Option<(colorbox::lut::Lut1D, PathBuf)>, // (to linear, path)
+    pub sensor_floor: [f32; 3],
+    pub sensor_ceiling: [f32; 3],
+}
+
+impl TransferFunction {
+    pub fn new() -> TransferFunction {
+        TransferFunction {
+            use_lut: false,
+            loaded_lut: None,
+            sensor_floor: [0.0; 3],
+            sensor_ceiling: [1.0; 3],
+        }
+    }
+
+    /// Returns the linearizing curves and floor/ceiling pairs to merge
+    /// with, in the same form as `estimate_transfer_function()`, or
+    /// `None` if they should be estimated instead.
+    ///
+    /// Returns an error if the loaded LUT doesn't increase between the
+    /// sensor floor and ceiling.
+    pub fn linearizing_curves(&self) -> Result<Option<LinearizingCurves>, String> {
+        let lut = match self.loaded_lut {
+            Some((ref lut, _)) if self.use_lut => lut,
+            _ => return Ok(None),
+        };
+
+        let mut curves = Vec::new();
+        let mut floor_ceil_pairs = Vec::new();
+        for chan in 0..3 {
+            let table = &lut.tables[chan.min(lut.tables.len() - 1)];
+            let range = lut.ranges[chan.min(lut.ranges.len() - 1)];
+            let eval = |n: f32| {
+                let t = ((n - range.0) / (range.1 - range.0)).clamp(0.0, 1.0);
+                lerp_slice(table, t)
+            };
+
+            // Normalize the output so that the sensor floor and ceiling
+            // map to 0.0 and 1.0, like the estimated curves.
+            let floor = self.sensor_floor[chan];
+            let ceiling = self.sensor_ceiling[chan];
+            let out_floor = eval(floor);
+            let out_ceil = eval(ceiling);
+            // Written to also catch NaNs.
+            if out_ceil.partial_cmp(&out_floor) != Some(std::cmp::Ordering::Greater) {
+                return Err(format!(
+                    "The transfer function LUT doesn't increase between the sensor floor and ceiling of the {} channel.  Please make sure it's a LUT to linear, and that the floor is below the ceiling.",
+                    ["red", "green", "blue"][chan]
+                ));
+            }
+            let out_norm = 1.0 / (out_ceil - out_floor);
+
+            curves.push(
+                (0..CURVE_RESOLUTION)
+                    .map(|i| {
+                        (eval(i as f32 / (CURVE_RESOLUTION - 1) as f32) - out_floor) * out_norm
+                    })
+                    .collect(),
+            );
+            floor_ceil_pairs.push((floor, ceiling));
+        }
+
+        Ok(Some((curves, floor_ceil_pairs)))
+    }
+}
+
+pub fn transfer_function_ui(ui: &mut Ui, app: &mut crate::AppMain, job_count: usize) {
+    let load_1d_lut_dialog = rfd::FileDialog::new()
+        .set_title("Load 1D LUT")
+        .add_filter("All Supported LUTs", &["spi1d", "cube"])
+        .add_filter("cube", &["cube"])
+        .add_filter("spi1d", &["spi1d"]);
+
+    ui.add(egui::widgets::Label::new(
+        RichText::new("Transfer Function:").strong(),
+    ));
+
+    ui.add_enabled_ui(job_count == 0, |ui| {
+        let ui_data = &mut *app.ui_data.lock_mut();
+        let tf = &mut ui_data.transfer_function;
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut tf.use_lut, false, "Estimate")
+                .on_hover_text("Estimate the transfer function from the images.");
+            ui.radio_value(&mut tf.use_lut, true, "From LUT")
+                .on_hover_text("Linearize the images with a 1D LUT, e.g. one made with LUT Maker.");
+        });
+    });
+
+    if !app.ui_data.lock().transfer_function.use_lut {
+        return;
+    }
+
+    ui.indent("", |ui| {
+        let lut_name = app
+            .ui_data
+            .lock()
+            .transfer_function
+            .loaded_lut
+            .as_ref()
+            .map(|(_, path)| {
+                path.file_name()
+                    .map(|name| name.to_string_lossy().into())
+                    .unwrap_or_else(|| "Unnamed LUT".to_string())
+            });
+        if let Some(name) = lut_name {
+            ui.horizontal(|ui| {
+                ui.strong(name);
+                if ui
+                    .add_enabled(job_count == 0, egui::widgets::Button::new("🗙"))
+                    .clicked()
+                {
+                    app.ui_data.lock_mut().transfer_function.loaded_lut = None;
+                }
+            });
+        } else if ui
+            .add_enabled(job_count == 0, egui::widgets::Button::new("Load 1D LUT..."))
+            .clicked()
+        {
+            if let Some(path) = load_1d_lut_dialog.pick_file() {
+                app.load_lut(path);
+            }
+        }
+
+        ui.add_space(4.0);
+        ui.horizontal(|ui| {
+            ui.label("Floor / Ceiling");
+            if ui
+                .add_enabled(
+                    job_count == 0 && app.ui_data.lock().thumbnails.len() >= 2,
+                    egui::widgets::Button::new("Estimate"),
+                )
+                .on_hover_text("Estimate the sensor floor and ceiling from the images.")
+                .clicked()
+            {
+                app.estimate_sensor_floor_ceiling();
+            }
+        });
+        ui.add_enabled_ui(job_count == 0, |ui| {
+            let ui_data = &mut *app.ui_data.lock_mut();
+            let tf = &mut ui_data.transfer_function;
+            for (label, (floor, ceiling)) in ["R: ", "G: ", "B: "]
+                .iter()
+                .zip(tf.sensor_floor.iter_mut().zip(tf.sensor_ceiling.iter_mut()))
+            {
+                ui.horizontal(|ui| {
+                    ui.label(*label);
+                    ui.add(
+                        egui::widgets::DragValue::new(floor)
+                            .clamp_range(0.0..=1.0)
+                            .speed(0.001)
+                            .max_decimals(5),
+                    );
+                    ui.add(
+                        egui::widgets::DragValue::new(ceiling)
+                            .clamp_range(0.0..=1.0)
+                            .speed(0.001)
+                            .max_decimals(5),
+                    );
+                });
+            }
+        });
+    });
+}
+
+/// Estimates the sensor floor and ceiling of each channel from the
+/// histograms of a set of images, normalized to [0.0, 1.0].
+///
+/// Channels that can't be estimated are left as `None`.
+pub fn estimate_floor_ceiling(
+    histograms: &[Vec<(sensor_analysis::Histogram, f32)>; 3],
+) -> [Option<(f32, f32)>; 3] {
+    let mut floor_ceil = [None; 3];
+    for chan in 0..3 {
+        if histograms[chan].len() < 2 {
+            continue;
+        }
+        let norm = 1.0 / (histograms[chan][0].0.buckets.len() - 1) as f32;
+        floor_ceil[chan] =
+            estimate_sensor_floor_ceiling(&histograms[chan]).map(|(f, c)| (f * norm, c * norm));
+    }
+    floor_ceil
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sensor_analysis::eval_transfer_function_lut;
+
+    const GAMMA: f32 = 2.2;
+
+    fn gamma_tf(floor: [f32; 3], ceiling: [f32; 3]) -> TransferFunction {
+        let table = (0..1024).map(|i| (i as f32 / 1023.0).powf(GAMMA)).collect();
+        TransferFunction {
+            use_lut: true,
+            loaded_lut: Some((
+                colorbox::lut::Lut1D {
+                    ranges: vec![(0.0, 1.0)],
+                    tables: vec![table],
+                },
+                PathBuf::from("gamma.cube"),
+            )),
+            sensor_floor: floor,
+            sensor_ceiling: ceiling,
+        }
+    }
+
+    #[test]
+    fn normalized_to_floor_and_ceiling() {
+        let floor = [0.1, 0.05, 0.0];
+        let ceiling = [0.9, 0.95, 1.0];
+        let (curves, floor_ceil) = gamma_tf(floor, ceiling)
+            .linearizing_curves()
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(curves.len(), 3);
+        assert_eq!(floor_ceil, vec![(0.1, 0.9), (0.05, 0.95), (0.0, 1.0)]);
+        for chan in 0..3 {
+            let curve = &curves[chan][..];
+            assert_eq!(curve.len(), CURVE_RESOLUTION);
+            let out_floor = floor[chan].powf(GAMMA);
+            let out_ceil = ceiling[chan].powf(GAMMA);
+            for &n in &[floor[chan], 0.25, 0.5, 0.75, ceiling[chan]] {
+                let expected = (n.powf(GAMMA) - out_floor) / (out_ceil - out_floor);
+                let linear = eval_transfer_function_lut(curve, n);
+                assert!(
+                    (linear - expected).abs() < 1.0e-3,
+                    "channel {} at {}: {} != {}",
+                    chan,
+                    n,
+                    linear,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn not_increasing() {
+        // Floor above the ceiling.
+        let tf = gamma_tf([0.5; 3], [0.4; 3]);
+        assert!(tf.linearizing_curves().is_err());
+
+        // Not used.
+        let mut tf = gamma_tf([0.0; 3], [1.0; 3]);
+        tf.use_lut = false;
+        assert_eq!(tf.linearizing_curves(), Ok(None));
+    }
+}
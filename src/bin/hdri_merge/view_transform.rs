@@ -0,0 +1,242 @@
+//! Display transforms for previewing HDRIs.
+//!
+//! The tone mappers are too slow to evaluate per pixel on large HDRIs,
+//! so they're baked into a 3D LUT in log2 space first.
+
+use colorbox::chroma;
+use ocio_gen::{make_agx_rec709, ToneCurve, Tonemapper};
+use rayon::prelude::*;
+
+use crate::egui::{self, Color32, RichText, Ui};
+
+/// Middle gray, which the false color bands and the LUT range are
+/// relative to.
+const MID_GRAY: f32 = 0.18;
+
+/// The range of the display LUT, in stops relative to middle gray.
+/// Values outside of it are clamped.
+const LUT_STOPS: (f32, f32) = (-12.0, 12.0);
+
+/// The resolution of each dimension of the display LUT.
+const LUT_RES: usize = 49;
+
+/// Rec.709 luminance weights.
+const LUMINANCE_WEIGHTS: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// The colors of the false color view, for each stop from
+/// `FALSE_COLOR_MIN_STOP` upward.  Values outside the range use the
+/// first or last color.
+const FALSE_COLOR_MIN_STOP: i32 = -6;
+const FALSE_COLORS: [[u8; 3]; 13] = [
+    [32, 0, 48],
+    [80, 0, 144],
+    [0, 0, 208],
+    [0, 96, 224],
+    [0, 176, 208],
+    [0, 160, 96],
+    [128, 128, 128], // Middle gray.
+    [160, 208, 64],
+    [240, 240, 0],
+    [255, 176, 0],
+    [255, 96, 0],
+    [224, 0, 0],
+    [255, 255, 255],
+];
+
+/// How the HDRI is mapped to the display in the preview.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ViewTransform {
+    Standard,
+    ToneyNeutral,
+    ToneyFilmic,
+    AgX,
+    FalseColor,
+}
+
+impl ViewTransform {
+    pub const ALL: [ViewTransform; 5] = [
+        ViewTransform::Standard,
+        ViewTransform::ToneyNeutral,
+        ViewTransform::ToneyFilmic,
+        ViewTransform::AgX,
+        ViewTransform::FalseColor,
+    ];
+
+    pub fn ui_text(&self) -> &'static str {
+        match *self {
+            ViewTransform::Standard => "Standard",
+            ViewTransform::ToneyNeutral => "Toney Neutral",
+            ViewTransform::ToneyFilmic => "Toney Filmic",
+            ViewTransform::AgX => "AgX",
+            ViewTransform::FalseColor => "False Color",
+        }
+    }
+
+    pub fn hover_text(&self) -> &'static str {
+        match *self {
+            ViewTransform::Standard => "Plain sRGB.  Everything above 1.0 is clipped.",
+            ViewTransform::ToneyNeutral => "The neutral tone mapper from OCIO Maker's configs.",
+            ViewTransform::ToneyFilmic => {
+                "The filmic tone mapper from OCIO Maker's configs, with more contrast."
+            }
+            ViewTransform::AgX => "Blender's AgX view transform.",
+            ViewTransform::FalseColor => {
+                "Colors the image by its luminance, in stops relative to 18% gray."
+            }
+        }
+    }
+
+    /// Builds the display LUT for the tone mapping transforms, or
+    /// returns `None` for the ones that don't use one.
+    ///
+    /// Input colors are Rec.709 scene linear.
+    pub fn display_lut(&self) -> Option<DisplayLut> {
+        match *self {
+            ViewTransform::Standard | ViewTransform::FalseColor => None,
+            ViewTransform::ToneyNeutral => {
+                let tonemapper = Tonemapper::new(
+                    1.0,
+                    ToneCurve::new(1.0, 0.18, 1.0, 4.0, 1.3),
+                    Some(chroma::REC709),
+                    (0.15, 0.7),
+                    0.25,
+                );
+                Some(DisplayLut::from_fn(|rgb| tonemapper.eval(rgb)))
+            }
+            ViewTransform::ToneyFilmic => {
+                let tonemapper = Tonemapper::new(
+                    1.0,
+                    ToneCurve::new(1.0, 0.18, 0.5, 2.5, 1.1),
+                    Some(chroma::REC709),
+                    (0.15, 0.7),
+                    0.25,
+                );
+                Some(DisplayLut::from_fn(|rgb| tonemapper.eval(rgb)))
+            }
+            ViewTransform::AgX => {
+                let agx = make_agx_rec709();
+                Some(DisplayLut::from_fn(|rgb| agx.eval(rgb)))
+            }
+        }
+    }
+}
+
+/// A scene linear to display linear transform, sampled in log2 space.
+pub struct DisplayLut {
+    table: Vec<[f32; 3]>,
+}
+
+impl DisplayLut {
+    fn from_fn<F>(f: F) -> DisplayLut
+    where
+        F: Fn([f64; 3]) -> [f64; 3] + Sync,
+    {
+        let decode = |i: usize| {
+            let t = i as f32 / (LUT_RES - 1) as f32;
+            (MID_GRAY * (LUT_STOPS.0 + (LUT_STOPS.1 - LUT_STOPS.0) * t).exp2()) as f64
+        };
+
+        let table = (0..(LUT_RES * LUT_RES * LUT_RES))
+            .into_par_iter()
+            .map(|i| {
+                let rgb = f([
+                    decode(i % LUT_RES),
+                    decode((i / LUT_RES) % LUT_RES),
+                    decode(i / (LUT_RES * LUT_RES)),
+                ]);
+                [rgb[0] as f32, rgb[1] as f32, rgb[2] as f32]
+            })
+            .collect();
+
+        DisplayLut { table }
+    }
+
+    /// Maps a scene linear color to display linear, with trilinear
+    /// interpolation.
+    pub fn eval(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let encode = |n: f32| {
+            let stops = (n.max(1.0e-10) / MID_GRAY).log2();
+            let t = ((stops - LUT_STOPS.0) / (LUT_STOPS.1 - LUT_STOPS.0)).clamp(0.0, 1.0);
+            let x = t * (LUT_RES - 1) as f32;
+            let i = (x as usize).min(LUT_RES - 2);
+            (i, x - i as f32)
+        };
+        let (ri, rf) = encode(rgb[0]);
+        let (gi, gf) = encode(rgb[1]);
+        let (bi, bf) = encode(rgb[2]);
+
+        let mut out = [0.0f32; 3];
+        for corner in 0..8 {
+            let (dr, dg, db) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let weight = (if dr == 1 { rf } else { 1.0 - rf })
+                * (if dg == 1 { gf } else { 1.0 - gf })
+                * (if db == 1 { bf } else { 1.0 - bf });
+            let sample =
+                self.table[(ri + dr) + (gi + dg) * LUT_RES + (bi + db) * LUT_RES * LUT_RES];
+            for chan in 0..3 {
+                out[chan] += sample[chan] * weight;
+            }
+        }
+        out
+    }
+}
+
+/// The false color of a Rec.709 scene linear color, as sRGB.
+pub fn false_color(rgb: [f32; 3]) -> [u8; 3] {
+    let luminance = rgb[0] * LUMINANCE_WEIGHTS[0]
+        + rgb[1] * LUMINANCE_WEIGHTS[1]
+        + rgb[2] * LUMINANCE_WEIGHTS[2];
+    let stop = (luminance.max(1.0e-10) / MID_GRAY).log2().round() as i32;
+    let i = (stop - FALSE_COLOR_MIN_STOP)
+        .max(0)
+        .min(FALSE_COLORS.len() as i32 - 1);
+    FALSE_COLORS[i as usize]
+}
+
+/// Shows which stops each false color stands for.
+pub fn false_color_legend(ui: &mut Ui) {
+    ui.horizontal(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        for (i, &[r, g, b]) in FALSE_COLORS.iter().enumerate() {
+            let stop = FALSE_COLOR_MIN_STOP + i as i32;
+            let text = if i == 0 {
+                format!(" ≤{} ", stop)
+            } else if i == FALSE_COLORS.len() - 1 {
+                format!(" ≥+{} ", stop)
+            } else {
+                format!(" {:+} ", stop)
+            };
+            let luminance = r as f32 * LUMINANCE_WEIGHTS[0]
+                + g as f32 * LUMINANCE_WEIGHTS[1]
+                + b as f32 * LUMINANCE_WEIGHTS[2];
+            let text_color = if luminance > 128.0 {
+                Color32::BLACK
+            } else {
+                Color32::WHITE
+            };
+            ui.label(
+                RichText::new(text)
+                    .monospace()
+                    .color(text_color)
+                    .background_color(Color32::from_rgb(r, g, b)),
+            )
+            .on_hover_text(format!("{:+} stops from 18% gray", stop));
+        }
+    });
+}
+
+pub fn view_transform_ui(ui: &mut Ui, view: &mut ViewTransform) -> bool {
+    let mut changed = false;
+    ui.label("View:");
+    egui::ComboBox::from_id_source("View Transform")
+        .selected_text(view.ui_text())
+        .show_ui(ui, |ui| {
+            for transform in ViewTransform::ALL {
+                changed |= ui
+                    .selectable_value(view, transform, transform.ui_text())
+                    .on_hover_text(transform.hover_text())
+                    .changed();
+            }
+        });
+    changed
+}
@@ -186,6 +186,7 @@ pub enum TransferFunctionID {
     CanonLog1,
     CanonLog2,
     CanonLog3,
+    Cineon,
     DJIDlog,
     FujifilmFlog,
     HLG,
@@ -213,6 +214,7 @@ pub const TRANSFER_FUNCTION_IDS: &[TransferFunctionID] = &[
     TransferFunctionID::CanonLog1,
     TransferFunctionID::CanonLog2,
     TransferFunctionID::CanonLog3,
+    TransferFunctionID::Cineon,
     TransferFunctionID::DJIDlog,
     TransferFunctionID::FujifilmFlog,
     TransferFunctionID::NikonNlog,
@@ -285,6 +287,7 @@ impl TransferFunction {
             CanonLog1 => canon::log1::to_linear(n),
             CanonLog2 => canon::log2::to_linear(n),
             CanonLog3 => canon::log3::to_linear(n),
+            Cineon => cineon::to_linear(n),
             DJIDlog => dji::dlog::to_linear(n),
             FujifilmFlog => fujifilm::flog::to_linear(n),
             HLG => rec2100_hlg::to_linear(n),
@@ -313,6 +316,7 @@ impl TransferFunction {
             CanonLog1 => canon::log1::from_linear(n),
             CanonLog2 => canon::log2::from_linear(n),
             CanonLog3 => canon::log3::from_linear(n),
+            Cineon => cineon::from_linear(n),
             DJIDlog => dji::dlog::from_linear(n),
             FujifilmFlog => fujifilm::flog::from_linear(n),
             HLG => rec2100_hlg::from_linear(n),
@@ -381,6 +385,13 @@ impl TransferFunction {
                 use canon::log3::*;
                 (NONLINEAR_BLACK, 1.0, LINEAR_MIN, LINEAR_MAX, LINEAR_MAX)
             }
+            Cineon => (
+                cineon::NONLINEAR_BLACK,
+                1.0,
+                cineon::to_linear(0.0),
+                cineon::to_linear(1.0),
+                cineon::to_linear(1.0),
+            ),
             DJIDlog => {
                 use dji::dlog::*;
                 (NONLINEAR_BLACK, 1.0, LINEAR_MIN, LINEAR_MAX, LINEAR_MAX)
@@ -440,6 +451,20 @@ impl TransferFunction {
 }
 
 impl TransferFunctionID {
+    /// The transfer function matching the transfer characteristic in a
+    /// DPX or Cineon file header, if we have one.
+    pub fn from_film_header(header: &image_fmt::FilmHeader) -> Option<TransferFunctionID> {
+        use image_fmt::Characteristic::*;
+        match header.transfer {
+            Linear => Some(TransferFunctionID::Linear),
+            PrintingDensity | Logarithmic => Some(TransferFunctionID::Cineon),
+            Rec709 | Smpte274M | Rec601Bg | Rec601M | NtscComposite | PalComposite => {
+                Some(TransferFunctionID::Rec709)
+            }
+            _ => None,
+        }
+    }
+
     pub fn ui_text(&self) -> &'static str {
         use TransferFunctionID::*;
         match *self {
@@ -452,6 +477,7 @@ impl TransferFunctionID {
             CanonLog1 => "Canon Log",
             CanonLog2 => "Canon Log 2",
             CanonLog3 => "Canon Log 3",
+            Cineon => "Cineon / DPX Printing Density",
             DJIDlog => "DJI D-Log",
             FujifilmFlog => "Fujifilm F-Log",
             HLG => "Rec.2100 - HLG",
@@ -498,3 +524,32 @@ fn arri_logc3_ei_ui_text(ei: colorbox::transfer_functions::arri::logc3::EI) -> &
         Ei1600 => "Exposure Index 1600",
     }
 }
+
+/// The standard Kodak Cineon log encoding, with reference black and
+/// white at 10-bit code values 95 and 685.
+mod cineon {
+    const BLACK_CODE: f32 = 95.0;
+    const WHITE_CODE: f32 = 685.0;
+    const DENSITY_PER_CODE: f32 = 0.002;
+    const NEGATIVE_GAMMA: f32 = 0.6;
+
+    pub const NONLINEAR_BLACK: f32 = BLACK_CODE / 1023.0;
+
+    fn black_offset() -> f32 {
+        10.0f32.powf((BLACK_CODE - WHITE_CODE) * DENSITY_PER_CODE / NEGATIVE_GAMMA)
+    }
+
+    pub fn to_linear(n: f32) -> f32 {
+        let offset = black_offset();
+        let code = n * 1023.0;
+        let lin = 10.0f32.powf((code - WHITE_CODE) * DENSITY_PER_CODE / NEGATIVE_GAMMA);
+        (lin - offset) / (1.0 - offset)
+    }
+
+    pub fn from_linear(n: f32) -> f32 {
+        let offset = black_offset();
+        let lin = (n * (1.0 - offset) + offset).max(f32::MIN_POSITIVE);
+        let code = WHITE_CODE + lin.log10() * NEGATIVE_GAMMA / DENSITY_PER_CODE;
+        code / 1023.0
+    }
+}
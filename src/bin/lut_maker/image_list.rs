@@ -4,13 +4,18 @@ use job_queue::JobQueue;
 use sensor_analysis::Histogram;
 use shared_data::Shared;
 
-use lib::ImageInfo;
+use lib::{
+    exposure_entry::ExposureEntry,
+    metadata::{Confidence, ExposureEstimate, ExposureRoute},
+    ImageInfo,
+};
 
 use crate::egui::{self, Context, Ui};
 
 pub struct ImageList {
     pub histogram_sets: Shared<Vec<Vec<([Histogram; 3], ImageInfo)>>>,
     ui_data: Shared<UiData>,
+    film_header: Shared<Option<image_fmt::FilmHeader>>, // From the most recently loaded DPX/Cineon file.
     multiple_sets: AtomicBool,
     uses_exif_data: AtomicBool,
 }
@@ -18,6 +23,8 @@ pub struct ImageList {
 struct UiData {
     thumbnail_sets: Vec<Vec<(egui::TextureHandle, usize, usize, ImageInfo)>>, // (tex_handle, width, height, ImageInfo)
     selected_idx: (usize, usize), // (set index, image index)
+    exposure_entry: ExposureEntry,
+    apply_orientation: bool,
 }
 
 impl ImageList {
@@ -27,7 +34,10 @@ impl ImageList {
             ui_data: Shared::new(UiData {
                 thumbnail_sets: Vec::new(),
                 selected_idx: (0, 0),
+                exposure_entry: ExposureEntry::default(),
+                apply_orientation: true,
             }),
+            film_header: Shared::new(None),
             multiple_sets: AtomicBool::new(multiple_sets),
             uses_exif_data: AtomicBool::new(uses_exif_data),
         }
@@ -42,6 +52,12 @@ impl ImageList {
             .sum()
     }
 
+    /// Returns the film header of the most recently loaded DPX or Cineon
+    /// file, if there is one that hasn't been taken yet.
+    pub fn take_film_header(&self) -> Option<image_fmt::FilmHeader> {
+        self.film_header.lock_mut().take()
+    }
+
     // Returns whether any data was changed or not.
     pub fn draw(
         &mut self,
@@ -61,13 +77,20 @@ impl ImageList {
                     "All Images",
                     &[
                         "jpg", "JPG", "jpeg", "JPEG", "tiff", "TIFF", "tif", "TIF", "webp", "WEBP",
-                        "png", "PNG",
-                    ],
+                        "png", "PNG", "exr", "EXR", "dpx", "DPX", "cin", "CIN",
+                    ]
+                    .iter()
+                    .map(|e| e.to_string())
+                    .chain(lib::raw::raw_file_extensions())
+                    .collect::<Vec<_>>(),
                 )
                 .add_filter("jpeg", &["jpg", "JPG", "jpeg", "JPEG"])
                 .add_filter("tiff", &["tiff", "TIFF", "tif", "TIF"])
                 .add_filter("webp", &["webp", "WEBP"])
-                .add_filter("png", &["png", "PNG"]);
+                .add_filter("png", &["png", "PNG"])
+                .add_filter("exr", &["exr", "EXR"])
+                .add_filter("dpx / cineon", &["dpx", "DPX", "cin", "CIN"])
+                .add_filter("camera raw", &lib::raw::raw_file_extensions());
             if !working_dir.as_os_str().is_empty() && working_dir.is_dir() {
                 d = d.set_directory(&working_dir);
             }
@@ -94,6 +117,61 @@ impl ImageList {
                 was_changed = true;
             }
         }
+        ui.add_enabled_ui(enable_changes, |ui| {
+            ui.checkbox(
+                &mut self.ui_data.lock_mut().apply_orientation,
+                "Auto-rotate",
+            )
+            .on_hover_text("Rotate and flip images as specified by their Exif orientation.");
+        });
+
+        // Selected image info.
+        let mut set_exposure = None; // (set index, image index, exposure)
+        {
+            let ui_data = &mut *self.ui_data.lock_mut();
+            let (set_i, img_i) = ui_data.selected_idx;
+            let exposure_entry = &mut ui_data.exposure_entry;
+            if let Some((_, _, _, info)) = ui_data
+                .thumbnail_sets
+                .get(set_i)
+                .and_then(|set| set.get(img_i))
+            {
+                ui.add_space(8.0);
+                ui.add(egui::widgets::Label::new(
+                    egui::RichText::new(&info.filename).strong(),
+                ));
+                if self.uses_exif() {
+                    let reference = lib::exposure_entry::reference_image(
+                        ui_data.thumbnail_sets[set_i]
+                            .iter()
+                            .enumerate()
+                            .filter(|(i, _)| *i != img_i)
+                            .map(|(_, thumbnail)| &thumbnail.3),
+                    );
+                    ui.collapsing("set exposure", |ui| {
+                        if let Some(estimate) = exposure_entry.ui(ui, enable_changes, reference) {
+                            set_exposure = Some((set_i, img_i, estimate));
+                        }
+                    });
+                }
+                ui.collapsing("more", |ui| {
+                    ui.label(format!("{} x {}", info.width, info.height));
+                    if let (Some(exposure), Some((route, confidence))) =
+                        (info.exposure, info.exposure_source)
+                    {
+                        ui.label(format!(
+                            "Log exposure: {:.1} (from {}, {} confidence)",
+                            exposure.log2(),
+                            route.ui_text(),
+                            confidence.ui_text()
+                        ));
+                    }
+                    for (label, value) in info.metadata.display_fields() {
+                        ui.label(format!("{}: {}", label, value));
+                    }
+                });
+            }
+        }
 
         // Image thumbnails.
         let mut remove_i = (None, None); // (set index, image index)
@@ -144,6 +222,11 @@ impl ImageList {
                     }
                 }
             });
+        if let Some((set_i, img_i, estimate)) = set_exposure {
+            self.set_image_exposure(set_i, img_i, estimate);
+            was_changed = true;
+        }
+
         match remove_i {
             (Some(set_i), Some(img_i)) => {
                 self.remove_image(set_i, img_i);
@@ -181,10 +264,17 @@ impl ImageList {
 
         let histogram_sets = self.histogram_sets.clone_ref();
         let ui_data = self.ui_data.clone_ref();
+        let film_header = self.film_header.clone_ref();
         let ctx = ctx.clone();
+        let load_options = lib::job_helpers::LoadOptions {
+            apply_orientation: self.ui_data.lock().apply_orientation,
+            ..Default::default()
+        };
 
         job_queue.add_job("Add Image(s)", move |status| {
             let len = image_paths.len() as f32;
+            let mut exposures = Vec::new(); // (filepath, exposure)
+            let mut pixel_samples = Vec::new();
 
             // Create a new image and thumbnail set.
             if use_sets || histogram_sets.lock().is_empty() {
@@ -192,6 +282,11 @@ impl ImageList {
                 ui_data.lock_mut().thumbnail_sets.push(Vec::new());
             }
 
+            // Exposures are only estimated from the images added here, so
+            // when they join images that are already in the set they need
+            // a known exposure to line them up with those.
+            let joins_existing_images = !histogram_sets.lock().last().unwrap().is_empty();
+
             // Load and add images.
             for (img_i, path) in image_paths.drain(..).enumerate() {
                 if status.lock().is_canceled() {
@@ -203,8 +298,32 @@ impl ImageList {
                     (img_i + 1) as f32 / len,
                 );
 
+                // Ensure it has the same resolution as the other images.
+                // When possible this is checked before decoding, so that
+                // mismatched images don't take a full load to reject.
+                let needed_dimensions = histogram_sets
+                    .lock()
+                    .last()
+                    .unwrap()
+                    .first()
+                    .map(|(_, info)| (info.width, info.height));
+                let log_resolution_mismatch = || {
+                    status.lock_mut().log_error(format!(
+                        "Image has a different resolution that the others in the set: \"{}\".  Not loading.  Note: all images in a set must have the same resolution.",
+                        path.to_string_lossy()
+                    ));
+                };
+                if let (Some(needed_dimensions), Some(dimensions)) =
+                    (needed_dimensions, lib::job_helpers::probe_dimensions(&path, &load_options))
+                {
+                    if dimensions != needed_dimensions {
+                        log_resolution_mismatch();
+                        continue;
+                    }
+                }
+
                 // Load image.
-                let img = match lib::job_helpers::load_image(&path) {
+                let img = match lib::job_helpers::load_image(&path, &load_options) {
                     Ok(img) => img,
                     Err(image_fmt::ReadError::IO(e)) => {
                         status.lock_mut().log_error(format!(
@@ -228,17 +347,20 @@ impl ImageList {
                         ));
                         return;
                     }
+                    Err(e) => {
+                        status.lock_mut().log_error(format!(
+                            "Unable to load image file \"{}\": {}",
+                            path.to_string_lossy(),
+                            e,
+                        ));
+                        return;
+                    }
                 };
 
                 // Ensure it has the same resolution as the other images.
-                if !histogram_sets.lock().last().unwrap().is_empty() {
-                    let needed_width = histogram_sets.lock().last().unwrap()[0].1.width;
-                    let needed_height = histogram_sets.lock().last().unwrap()[0].1.height;
-                    if img.image.width() != needed_width || img.image.height() != needed_height {
-                        status.lock_mut().log_error(format!(
-                            "Image has a different resolution that the others in the set: \"{}\".  Not loading.  Note: all images in a set must have the same resolution.",
-                            path.to_string_lossy()
-                        ));
+                if let Some(needed_dimensions) = needed_dimensions {
+                    if img.image.dimensions != needed_dimensions {
+                        log_resolution_mismatch();
                         continue;
                     }
                 }
@@ -246,11 +368,31 @@ impl ImageList {
                 // Check if we got exposure data from it.
                 if use_exif && img.info.exposure.is_none() {
                     status.lock_mut().log_warning(format!(
-                        "Image file lacks Exif data needed to compute exposure value: \"{}\".  This image will be excluded from transfer function, floor, and ceiling estimation.",
+                        "Image file lacks Exif data needed to compute exposure value: \"{}\".  Its exposure will be estimated from its pixels, relative to the rest of the set.",
+                        path.to_string_lossy()
+                    ));
+                } else if use_exif
+                    && matches!(img.info.exposure_source, Some((ExposureRoute::ExposureBias, _)))
+                {
+                    status.lock_mut().log_warning(format!(
+                        "Image file lacks an exposure time, so its exposure was computed from its exposure bias: \"{}\".  This only works if all images in the set are from the same auto-bracketed sequence.",
+                        path.to_string_lossy()
+                    ));
+                }
+
+                // LUT Maker works directly on the encoded values, so
+                // profiles other than sRGB are only worth pointing out.
+                if img.info.color.as_ref().map(|color| !color.is_srgb()).unwrap_or(false) {
+                    status.lock_mut().log_warning(format!(
+                        "Image file has an embedded color profile that isn't sRGB: \"{}\".  The estimated transfer function will include the profile's encoding, and its primaries won't be accounted for.",
                         path.to_string_lossy()
                     ));
                 }
 
+                if img.info.film_header.is_some() {
+                    *film_header.lock_mut() = img.info.film_header;
+                }
+
                 // Make a thumbnail texture.
                 let (thumbnail_tex_handle, thumbnail_width, thumbnail_height) = {
                     let (pixels, width, height) = lib::job_helpers::make_image_preview(&img, Some(128), None);
@@ -271,19 +413,75 @@ impl ImageList {
                 // Compute histograms.
                 let histograms = lib::job_helpers::compute_image_histograms(&img);
 
+                // Keep some pixels around for estimating missing
+                // exposures.
+                if use_exif {
+                    exposures.push((img.info.full_filepath.clone(), img.info.exposure));
+                    pixel_samples.push(lib::job_helpers::sample_linear_pixels(&img, 1 << 16));
+                }
+
                 // Add image and thumbnail to our lists.
                 {
+                    let mut histogram_sets = histogram_sets.lock_mut();
                     let mut ui_data = ui_data.lock_mut();
-                    let set = ui_data.thumbnail_sets.last_mut().unwrap();
-                    set.push((thumbnail_tex_handle, thumbnail_width, thumbnail_height, img.info.clone()));
-                    set.sort_unstable_by(|a, b| a.3.exposure.partial_cmp(&b.3.exposure).unwrap());
+                    let images = histogram_sets.last_mut().unwrap();
+                    let thumbnails = ui_data.thumbnail_sets.last_mut().unwrap();
+                    images.push((histograms, img.info.clone()));
+                    thumbnails.push((thumbnail_tex_handle, thumbnail_width, thumbnail_height, img.info.clone()));
+                    sort_set(images, thumbnails);
                 }
-                {
-                    let mut histogram_sets = histogram_sets.lock_mut();
-                    let set = histogram_sets.last_mut().unwrap();
-                    set.push((histograms, img.info.clone()));
-                    set.sort_unstable_by(|a, b| a.1.exposure.partial_cmp(&b.1.exposure).unwrap());
+            }
+
+            // Estimate the exposures of images that lack them, from how
+            // their pixels compare to the rest of the set's.
+            if exposures.iter().any(|(_, exposure)| exposure.is_none()) {
+                status
+                    .lock_mut()
+                    .set_progress("Estimating exposures".into(), 1.0);
+
+                let known: Vec<_> = exposures.iter().map(|(_, exposure)| *exposure).collect();
+                let anchored = !joins_existing_images || known.iter().any(|exposure| exposure.is_some());
+                let estimated = if anchored {
+                    lib::job_helpers::estimate_missing_exposures(&pixel_samples, &known)
+                } else {
+                    vec![None; known.len()]
+                };
+
+                let mut histogram_sets = histogram_sets.lock_mut();
+                let mut ui_data = ui_data.lock_mut();
+                let images = histogram_sets.last_mut().unwrap();
+                let thumbnails = ui_data.thumbnail_sets.last_mut().unwrap();
+                for ((filepath, known), exposure) in exposures.iter().zip(estimated) {
+                    if known.is_some() {
+                        continue;
+                    }
+                    let info = match images.iter_mut().find(|(_, info)| info.full_filepath == *filepath) {
+                        Some((_, info)) => info,
+                        None => continue,
+                    };
+                    if let Some(exposure) = exposure {
+                        let estimate = ExposureEstimate {
+                            exposure,
+                            exposure_time: info.exposure_time,
+                            fstop: info.fstop,
+                            iso: info.iso,
+                            route: ExposureRoute::Estimated,
+                            confidence: Confidence::Low,
+                        };
+                        info.set_exposure(estimate);
+                    } else if !anchored {
+                        status.lock_mut().log_warning(format!(
+                            "Unable to estimate the exposure of \"{}\", since none of the images added along with it have a known exposure to relate them to the rest of the set.  It will be excluded from transfer function, floor, and ceiling estimation unless you enter its exposure manually.",
+                            info.filename
+                        ));
+                    } else {
+                        status.lock_mut().log_warning(format!(
+                            "Unable to estimate the exposure of \"{}\", since it doesn't have enough well-exposed pixels in common with the rest of the set.  It will be excluded from transfer function, floor, and ceiling estimation unless you enter its exposure manually.",
+                            info.filename
+                        ));
+                    }
                 }
+                sort_set(images, thumbnails);
             }
         });
 
@@ -291,6 +489,35 @@ impl ImageList {
         // self.compute_exposure_mappings();
     }
 
+    /// Sets the exposure of an image, e.g. to one entered by the user.
+    fn set_image_exposure(
+        &mut self,
+        set_index: usize,
+        image_index: usize,
+        estimate: ExposureEstimate,
+    ) {
+        let mut histogram_sets = self.histogram_sets.lock_mut();
+        let mut ui_data = self.ui_data.lock_mut();
+        let ui_data = &mut *ui_data;
+        let (images, thumbnails) = match (
+            histogram_sets.get_mut(set_index),
+            ui_data.thumbnail_sets.get_mut(set_index),
+        ) {
+            (Some(images), Some(thumbnails)) if image_index < images.len() => (images, thumbnails),
+            _ => return,
+        };
+
+        images[image_index].1.set_exposure(estimate);
+        let order = sort_set(images, thumbnails);
+
+        // Keep the same image selected.
+        if ui_data.selected_idx.0 == set_index {
+            if let Some(new_index) = order.iter().position(|&i| i == image_index) {
+                ui_data.selected_idx.1 = new_index;
+            }
+        }
+    }
+
     fn remove_image(&mut self, set_index: usize, image_index: usize) {
         if set_index >= self.histogram_sets.lock().len() {
             return;
@@ -361,3 +588,34 @@ impl ImageList {
         // self.compute_exposure_mappings();
     }
 }
+
+/// Sorts a set's images by exposure, with their thumbnails in the same
+/// order and with up-to-date image info.
+///
+/// Returns the new order, as the previous index of each image.
+fn sort_set(
+    images: &mut Vec<([Histogram; 3], ImageInfo)>,
+    thumbnails: &mut Vec<(egui::TextureHandle, usize, usize, ImageInfo)>,
+) -> Vec<usize> {
+    debug_assert_eq!(images.len(), thumbnails.len());
+
+    let mut order: Vec<usize> = (0..images.len()).collect();
+    order.sort_by(
+        |&a, &b| match (images[a].1.exposure, images[b].1.exposure) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        },
+    );
+
+    let mut old_images: Vec<_> = images.drain(..).map(Some).collect();
+    let mut old_thumbnails: Vec<_> = thumbnails.drain(..).map(Some).collect();
+    for &i in order.iter() {
+        let image = old_images[i].take().unwrap();
+        let mut thumbnail = old_thumbnails[i].take().unwrap();
+        thumbnail.3 = image.1.clone();
+        images.push(image);
+        thumbnails.push(thumbnail);
+    }
+
+    order
+}
@@ -133,6 +133,20 @@ impl eframe::App for AppMain {
         let total_bracket_images = self.bracket_image_sets.total_image_count();
         let total_dark_images = self.dark_images.total_image_count();
 
+        // Pre-select the transfer function of newly loaded DPX/Cineon
+        // files, when they specify one that we have.
+        for film_header in [
+            self.bracket_image_sets.take_film_header(),
+            self.dark_images.take_film_header(),
+        ]
+        .iter()
+        .flatten()
+        {
+            if let Some(id) = generated_tf::TransferFunctionID::from_film_header(film_header) {
+                self.ui_data.lock_mut().generated.transfer_function.id = id;
+            }
+        }
+
         let mut working_dir = self
             .last_opened_directory
             .clone()
@@ -0,0 +1,455 @@
+//! Demosaicing of Bayer-pattern sensor data.
+
+use rayon::prelude::*;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Demosaic {
+    /// Fast, but prone to zippering and color fringes along edges.
+    Bilinear,
+
+    /// Adaptive homogeneity-directed interpolation (Hirakawa and Parks,
+    /// 2005).  Slower, but produces much cleaner edges.
+    Ahd,
+}
+
+impl Demosaic {
+    pub fn ui_text(&self) -> &'static str {
+        match *self {
+            Demosaic::Bilinear => "Bilinear",
+            Demosaic::Ahd => "AHD",
+        }
+    }
+}
+
+/// Demosaics a single-channel Bayer mosaic into `RGBRGBRGB...` data.
+///
+/// `cfa` gives the channel (0 = red, 1 = green, 2 = blue) of each
+/// position in the 2x2 Bayer tile, indexed as `cfa[y % 2][x % 2]`.
+///
+/// The mosaic is expected to already be normalized and white balanced.
+/// No clamping is done, so the output may go slightly outside the range
+/// of the input.
+pub fn demosaic(
+    mosaic: &[f32],
+    width: usize,
+    height: usize,
+    cfa: [[usize; 2]; 2],
+    method: Demosaic,
+) -> Vec<f32> {
+    assert_eq!(mosaic.len(), width * height);
+    let mosaic = Mosaic {
+        data: mosaic,
+        width,
+        height,
+        cfa,
+    };
+
+    match method {
+        Demosaic::Bilinear => bilinear(&mosaic),
+        Demosaic::Ahd => ahd(&mosaic),
+    }
+}
+
+struct Mosaic<'a> {
+    data: &'a [f32],
+    width: usize,
+    height: usize,
+    cfa: [[usize; 2]; 2],
+}
+
+impl<'a> Mosaic<'a> {
+    /// Sample value at the given coordinates, mirrored at the edges.
+    #[inline(always)]
+    fn get(&self, x: isize, y: isize) -> f32 {
+        self.data[self.index(x, y)]
+    }
+
+    /// Channel at the given coordinates, mirrored at the edges.
+    #[inline(always)]
+    fn color(&self, x: isize, y: isize) -> usize {
+        let x = reflect(x, self.width);
+        let y = reflect(y, self.height);
+        self.cfa[y % 2][x % 2]
+    }
+
+    #[inline(always)]
+    fn index(&self, x: isize, y: isize) -> usize {
+        reflect(y, self.height) * self.width + reflect(x, self.width)
+    }
+}
+
+/// Mirrors out-of-bounds coordinates back into `0..len`.
+///
+/// Mirroring (as opposed to clamping) preserves the parity of the
+/// coordinate, so the Bayer pattern stays consistent across the edge.
+#[inline(always)]
+fn reflect(i: isize, len: usize) -> usize {
+    let len = len as isize;
+    let i = if i < 0 {
+        -i
+    } else if i >= len {
+        2 * (len - 1) - i
+    } else {
+        i
+    };
+    i.max(0).min(len - 1) as usize
+}
+
+//-------------------------------------------------------------
+
+fn bilinear(mosaic: &Mosaic) -> Vec<f32> {
+    let width = mosaic.width;
+    let mut rgb = vec![0.0f32; width * mosaic.height * 3];
+
+    rgb.par_chunks_mut(width * 3)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let y = y as isize;
+            for x in 0..width as isize {
+                // For a Bayer pattern, averaging all the samples of a
+                // channel in the 3x3 neighborhood is exactly bilinear
+                // interpolation.
+                let mut sum = [0.0f32; 3];
+                let mut count = [0usize; 3];
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let c = mosaic.color(x + dx, y + dy);
+                        sum[c] += mosaic.get(x + dx, y + dy);
+                        count[c] += 1;
+                    }
+                }
+
+                let own = mosaic.color(x, y);
+                for c in 0..3 {
+                    row[x as usize * 3 + c] = if c == own {
+                        mosaic.get(x, y)
+                    } else {
+                        sum[c] / count[c].max(1) as f32
+                    };
+                }
+            }
+        });
+
+    rgb
+}
+
+//-------------------------------------------------------------
+
+fn ahd(mosaic: &Mosaic) -> Vec<f32> {
+    let width = mosaic.width;
+    let height = mosaic.height;
+
+    // Interpolate green horizontally and vertically.  Uses the
+    // Hamilton-Adams estimate, clamped to the neighboring greens to
+    // avoid overshoot.
+    let green: Vec<Vec<f32>> = [(1isize, 0isize), (0, 1)]
+        .iter()
+        .map(|&(dx, dy)| {
+            let mut plane = vec![0.0f32; width * height];
+            plane
+                .par_chunks_mut(width)
+                .enumerate()
+                .for_each(|(y, row)| {
+                    let y = y as isize;
+                    for x in 0..width as isize {
+                        let c = mosaic.get(x, y);
+                        row[x as usize] = if mosaic.color(x, y) == 1 {
+                            c
+                        } else {
+                            let g1 = mosaic.get(x - dx, y - dy);
+                            let g2 = mosaic.get(x + dx, y + dy);
+                            let c1 = mosaic.get(x - dx * 2, y - dy * 2);
+                            let c2 = mosaic.get(x + dx * 2, y + dy * 2);
+                            let estimate = (g1 + g2) * 0.5 + (c * 2.0 - c1 - c2) * 0.25;
+                            estimate.max(g1.min(g2)).min(g1.max(g2))
+                        };
+                    }
+                });
+            plane
+        })
+        .collect();
+
+    // Fill in red and blue for each direction, and convert the
+    // results to CIELAB for the homogeneity test.
+    let candidates: Vec<Vec<[f32; 3]>> = green
+        .iter()
+        .map(|g| interpolate_red_blue(mosaic, g))
+        .collect();
+    let labs: Vec<Vec<[f32; 3]>> = candidates
+        .iter()
+        .map(|rgb| rgb.par_iter().map(|c| rgb_to_lab(*c)).collect())
+        .collect();
+
+    // Compute the homogeneity of each direction at each pixel: the
+    // number of immediate neighbors that are within an adaptive
+    // luminance and chroma tolerance.
+    let lab_at = |dir: usize, x: isize, y: isize| labs[dir][mosaic.index(x, y)];
+    let mut homogeneity = [vec![0u8; width * height], vec![0u8; width * height]];
+    {
+        let [ref mut homo_h, ref mut homo_v] = homogeneity;
+        homo_h
+            .par_chunks_mut(width)
+            .zip(homo_v.par_chunks_mut(width))
+            .enumerate()
+            .for_each(|(y, (row_h, row_v))| {
+                let y = y as isize;
+                for x in 0..width as isize {
+                    let center = [lab_at(0, x, y), lab_at(1, x, y)];
+
+                    // The tolerances are based on the differences along
+                    // each direction's own axis.
+                    let h_l = lum_dist(center[0], lab_at(0, x - 1, y))
+                        .max(lum_dist(center[0], lab_at(0, x + 1, y)));
+                    let v_l = lum_dist(center[1], lab_at(1, x, y - 1))
+                        .max(lum_dist(center[1], lab_at(1, x, y + 1)));
+                    let h_c = chroma_dist2(center[0], lab_at(0, x - 1, y))
+                        .max(chroma_dist2(center[0], lab_at(0, x + 1, y)));
+                    let v_c = chroma_dist2(center[1], lab_at(1, x, y - 1))
+                        .max(chroma_dist2(center[1], lab_at(1, x, y + 1)));
+                    let eps_l = h_l.min(v_l);
+                    let eps_c = h_c.min(v_c);
+
+                    let count = |dir: usize| {
+                        [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+                            .iter()
+                            .filter(|&&(nx, ny)| {
+                                let n = lab_at(dir, nx, ny);
+                                lum_dist(center[dir], n) <= eps_l
+                                    && chroma_dist2(center[dir], n) <= eps_c
+                            })
+                            .count() as u8
+                    };
+                    row_h[x as usize] = count(0);
+                    row_v[x as usize] = count(1);
+                }
+            });
+    }
+
+    // Pick the direction that's most homogeneous over a 3x3 window,
+    // averaging the two when there's no clear winner.
+    let mut rgb = vec![0.0f32; width * height * 3];
+    rgb.par_chunks_mut(width * 3)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let y = y as isize;
+            for x in 0..width as isize {
+                let mut score = [0u32; 2];
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        let i = mosaic.index(x + dx, y + dy);
+                        score[0] += homogeneity[0][i] as u32;
+                        score[1] += homogeneity[1][i] as u32;
+                    }
+                }
+
+                let i = mosaic.index(x, y);
+                let color = if score[0] > score[1] {
+                    candidates[0][i]
+                } else if score[1] > score[0] {
+                    candidates[1][i]
+                } else {
+                    let (a, b) = (candidates[0][i], candidates[1][i]);
+                    [
+                        (a[0] + b[0]) * 0.5,
+                        (a[1] + b[1]) * 0.5,
+                        (a[2] + b[2]) * 0.5,
+                    ]
+                };
+                row[(x as usize * 3)..(x as usize * 3 + 3)].copy_from_slice(&color);
+            }
+        });
+
+    rgb
+}
+
+/// Fills in red and blue given a fully interpolated green plane, by
+/// interpolating the color differences rather than the colors
+/// themselves.
+fn interpolate_red_blue(mosaic: &Mosaic, green: &[f32]) -> Vec<[f32; 3]> {
+    let width = mosaic.width;
+    let mut rgb = vec![[0.0f32; 3]; width * mosaic.height];
+
+    rgb.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+        let y = y as isize;
+        for x in 0..width as isize {
+            let own = mosaic.color(x, y);
+            let g = green[mosaic.index(x, y)];
+            let mut color = [0.0f32; 3];
+            color[1] = g;
+
+            for c in [0, 2] {
+                if c == own {
+                    color[c] = mosaic.get(x, y);
+                    continue;
+                }
+
+                // Use the edge-adjacent neighbors if any have the
+                // channel (green sites), otherwise the diagonals
+                // (red/blue sites).
+                let mut sum = 0.0f32;
+                let mut count = 0usize;
+                for offsets in [
+                    [(-1isize, 0isize), (1, 0), (0, -1), (0, 1)],
+                    [(-1, -1), (1, -1), (-1, 1), (1, 1)],
+                ] {
+                    for (dx, dy) in offsets {
+                        let (nx, ny) = (x + dx, y + dy);
+                        if mosaic.color(nx, ny) == c {
+                            sum += mosaic.get(nx, ny) - green[mosaic.index(nx, ny)];
+                            count += 1;
+                        }
+                    }
+                    if count > 0 {
+                        break;
+                    }
+                }
+                color[c] = g + sum / count.max(1) as f32;
+            }
+
+            row[x as usize] = color;
+        }
+    });
+
+    rgb
+}
+
+/// Converts linear RGB (treated as Rec.709/D65) to CIELAB.
+///
+/// The camera's actual color space isn't known here, but this is only
+/// used for comparing colors so that doesn't matter much.
+fn rgb_to_lab(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = [rgb[0].max(0.0), rgb[1].max(0.0), rgb[2].max(0.0)];
+    let x = (0.412453 * r + 0.357580 * g + 0.180423 * b) / 0.950456;
+    let y = 0.212671 * r + 0.715160 * g + 0.072169 * b;
+    let z = (0.019334 * r + 0.119193 * g + 0.950227 * b) / 1.088754;
+
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + (16.0 / 116.0)
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+#[inline(always)]
+fn lum_dist(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (a[0] - b[0]).abs()
+}
+
+#[inline(always)]
+fn chroma_dist2(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let da = a[1] - b[1];
+    let db = a[2] - b[2];
+    da * da + db * db
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// All four 2x2 Bayer arrangements: RGGB, BGGR, GRBG, and GBRG.
+    const CFAS: [[[usize; 2]; 2]; 4] = [
+        [[0, 1], [1, 2]],
+        [[2, 1], [1, 0]],
+        [[1, 0], [2, 1]],
+        [[1, 2], [0, 1]],
+    ];
+
+    /// Samples an RGB image function through a Bayer filter.
+    fn mosaic<F>(width: usize, height: usize, cfa: [[usize; 2]; 2], f: F) -> Vec<f32>
+    where
+        F: Fn(usize, usize) -> [f32; 3],
+    {
+        let mut mosaic = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                mosaic.push(f(x, y)[cfa[y % 2][x % 2]]);
+            }
+        }
+        mosaic
+    }
+
+    #[test]
+    fn flat_field() {
+        let color = [0.2, 0.5, 0.8];
+        let (width, height) = (9, 7);
+        for cfa in CFAS {
+            let mosaic = mosaic(width, height, cfa, |_, _| color);
+            for method in [Demosaic::Bilinear, Demosaic::Ahd] {
+                let rgb = demosaic(&mosaic, width, height, cfa, method);
+                assert_eq!(rgb.len(), width * height * 3);
+                for pixel in rgb.chunks(3) {
+                    for c in 0..3 {
+                        assert!(
+                            (pixel[c] - color[c]).abs() < 1.0e-5,
+                            "{:?} {:?}: {:?}",
+                            method,
+                            cfa,
+                            pixel
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_sensor_samples() {
+        let (width, height) = (8, 8);
+        let f = |x: usize, y: usize| {
+            let n = ((x * 7 + y * 13) % 11) as f32 / 10.0;
+            [n, 1.0 - n, n * 0.5]
+        };
+        for cfa in CFAS {
+            let mosaic = mosaic(width, height, cfa, f);
+            for method in [Demosaic::Bilinear, Demosaic::Ahd] {
+                let rgb = demosaic(&mosaic, width, height, cfa, method);
+                for y in 0..height {
+                    for x in 0..width {
+                        let i = y * width + x;
+                        assert_eq!(rgb[i * 3 + cfa[y % 2][x % 2]], mosaic[i], "{:?}", method);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gray_ramp() {
+        // Both methods reconstruct a linear gray ramp exactly, away
+        // from the edges where mirroring bends it.
+        let (width, height) = (12, 10);
+        let f = |x: usize, y: usize| {
+            let n = 0.1 + x as f32 * 0.03 + y as f32 * 0.02;
+            [n, n, n]
+        };
+        for cfa in CFAS {
+            let mosaic = mosaic(width, height, cfa, f);
+            for method in [Demosaic::Bilinear, Demosaic::Ahd] {
+                let rgb = demosaic(&mosaic, width, height, cfa, method);
+                for y in 3..(height - 3) {
+                    for x in 3..(width - 3) {
+                        let expected = f(x, y);
+                        let i = (y * width + x) * 3;
+                        for c in 0..3 {
+                            assert!(
+                                (rgb[i + c] - expected[c]).abs() < 1.0e-5,
+                                "{:?} {:?} at {}, {}: {:?} vs {:?}",
+                                method,
+                                cfa,
+                                x,
+                                y,
+                                &rgb[i..(i + 3)],
+                                expected
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Manual exposure entry, for images whose files lack exposure data.
+
+use eframe::egui;
+
+use crate::{
+    metadata::{parse_ratio, Confidence, ExposureEstimate, ExposureRoute, ExposureTags},
+    ImageInfo,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExposureEntryMode {
+    /// An exposure in stops, relative to a reference image.
+    EvOffset,
+
+    /// The exposure time, f-number, and ISO the image was taken with.
+    Camera,
+}
+
+/// The state of the exposure entry widgets.
+#[derive(Debug, Clone)]
+pub struct ExposureEntry {
+    pub mode: ExposureEntryMode,
+    pub ev: f32,
+    pub exposure_time: String, // E.g. "1/250" or "0.5".
+    pub fstop: f32,
+    pub iso: u32,
+}
+
+impl Default for ExposureEntry {
+    fn default() -> ExposureEntry {
+        ExposureEntry {
+            mode: ExposureEntryMode::EvOffset,
+            ev: 0.0,
+            exposure_time: "1/60".into(),
+            fstop: 8.0,
+            iso: 100,
+        }
+    }
+}
+
+impl ExposureEntry {
+    /// Draws the entry widgets.  Returns the entered exposure when the
+    /// user applies it.
+    ///
+    /// `reference` is the image that EV offsets are relative to, from
+    /// `reference_image()`.
+    pub fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        enabled: bool,
+        reference: Option<&ImageInfo>,
+    ) -> Option<ExposureEstimate> {
+        let mut applied = None;
+
+        ui.add_enabled_ui(enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.radio_value(&mut self.mode, ExposureEntryMode::EvOffset, "EV offset");
+                ui.radio_value(&mut self.mode, ExposureEntryMode::Camera, "Camera settings");
+            });
+
+            match self.mode {
+                ExposureEntryMode::EvOffset => {
+                    ui.add(
+                        egui::widgets::DragValue::new(&mut self.ev)
+                            .speed(0.05)
+                            .clamp_range(-32.0..=32.0)
+                            .max_decimals(2)
+                            .suffix(" EV"),
+                    )
+                    .on_hover_text("The image's exposure in stops, relative to the reference image, e.g. -2 for an image two stops darker.  If no other image has a known exposure, only the differences between the entered offsets matter, e.g. -2, 0, and 2 for a three-image bracket.");
+                    ui.label(match reference {
+                        Some(reference) => format!("Relative to \"{}\"", reference.filename),
+                        None => "Relative to other EV offsets".into(),
+                    });
+                }
+                ExposureEntryMode::Camera => {
+                    ui.horizontal(|ui| {
+                        ui.label("Shutter:");
+                        ui.add(
+                            egui::widgets::TextEdit::singleline(&mut self.exposure_time)
+                                .desired_width(64.0),
+                        )
+                        .on_hover_text("Exposure time in seconds, e.g. \"1/250\" or \"2\".");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::widgets::DragValue::new(&mut self.fstop)
+                                .speed(0.05)
+                                .clamp_range(0.5..=128.0)
+                                .max_decimals(1)
+                                .prefix("f/"),
+                        );
+                        ui.add(
+                            egui::widgets::DragValue::new(&mut self.iso)
+                                .speed(10.0)
+                                .clamp_range(1..=1_000_000)
+                                .prefix("ISO "),
+                        );
+                    });
+                }
+            }
+
+            let estimate = self.estimate(reference);
+            if ui
+                .add_enabled(estimate.is_some(), egui::widgets::Button::new("Set Exposure"))
+                .clicked()
+            {
+                applied = estimate;
+            }
+        });
+
+        applied
+    }
+
+    /// The exposure as currently entered, or `None` if it isn't valid.
+    pub fn estimate(&self, reference: Option<&ImageInfo>) -> Option<ExposureEstimate> {
+        match self.mode {
+            ExposureEntryMode::EvOffset => Some(ExposureEstimate {
+                exposure: self.ev.exp2() * reference.and_then(|r| r.exposure).unwrap_or(1.0),
+                exposure_time: None,
+                fstop: None,
+                iso: None,
+                route: ExposureRoute::Manual,
+                confidence: Confidence::High,
+            }),
+            ExposureEntryMode::Camera => {
+                let estimate = ExposureTags {
+                    exposure_time: Some(parse_ratio(&self.exposure_time)?),
+                    fstop: Some(((self.fstop * 10.0).round() as u32, 10)),
+                    iso: Some(self.iso),
+                    ..ExposureTags::default()
+                }
+                .estimate()?;
+                Some(ExposureEstimate {
+                    route: ExposureRoute::Manual,
+                    confidence: Confidence::High,
+                    ..estimate
+                })
+            }
+        }
+    }
+}
+
+/// Picks the image that EV offsets are relative to, from the other
+/// images of a set.  Exposures from the images' files are preferred,
+/// then manually entered ones.  Estimated exposures aren't used, since
+/// they're redone whenever the set changes.
+pub fn reference_image<'a, I>(others: I) -> Option<&'a ImageInfo>
+where
+    I: Iterator<Item = &'a ImageInfo> + Clone,
+{
+    let from_file = |info: &&ImageInfo| {
+        matches!(
+            info.exposure_source,
+            Some((ExposureRoute::ExposureTime, _))
+                | Some((ExposureRoute::Apex, _))
+                | Some((ExposureRoute::ExposureBias, _))
+        )
+    };
+    let manual =
+        |info: &&ImageInfo| matches!(info.exposure_source, Some((ExposureRoute::Manual, _)));
+
+    others
+        .clone()
+        .find(from_file)
+        .or_else(|| others.clone().find(manual))
+}
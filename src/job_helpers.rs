@@ -4,64 +4,55 @@ use colorbox::{formats, lut::Lut1D};
 
 use sensor_analysis::Histogram;
 
-use crate::{ImageInfo, SourceImage};
+use crate::{
+    demosaic::Demosaic,
+    metadata::{ExposureTags, ImageMetadata, Xmp},
+    ImageInfo, SourceImage,
+};
 use image_fmt::ImageBuf;
 
-pub fn load_image(path: &Path) -> Result<SourceImage, image_fmt::ReadError> {
+/// Options controlling how `load_image()` decodes images.
+#[derive(Debug, Copy, Clone)]
+pub struct LoadOptions {
+    /// The demosaicing method used for camera raw files.
+    pub demosaic: Demosaic,
+
+    /// Rotate and/or flip images as specified by their Exif orientation.
+    pub apply_orientation: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> LoadOptions {
+        LoadOptions {
+            demosaic: Demosaic::Ahd,
+            apply_orientation: true,
+        }
+    }
+}
+
+pub fn load_image(path: &Path, options: &LoadOptions) -> Result<SourceImage, image_fmt::ReadError> {
     // Load image.
-    let img = {
+    let (img, linear, camera_to_xyz, film_header) = if crate::raw::is_raw_path(path) {
+        let (img, camera_to_xyz) = crate::raw::load_raw(path, options.demosaic)?;
+        (img, true, camera_to_xyz, None)
+    } else {
         let mut img = image_fmt::load(BufReader::new(File::open(&path)?))?;
         img.data = img.data.to_rgb();
-        img
+        let film_header = image_fmt::film_header(BufReader::new(File::open(path)?))?;
+        let linear = img.data.is_float();
+        (img, linear, None, film_header)
     };
 
-    // Get exposure metadata from EXIF data.
-    let (exposure_time, fstop, sensitivity) = {
-        let mut exposure_time = None;
-        let mut fstop = None;
-        let mut sensitivity = None;
-
-        let mut file = std::io::BufReader::new(std::fs::File::open(&path)?);
-        if let Ok(img_exif) = exif::Reader::new().read_from_container(&mut file) {
-            if let Some(&exif::Value::Rational(ref n)) = img_exif
-                .get_field(exif::Tag::ExposureTime, exif::In::PRIMARY)
-                .map(|n| &n.value)
-            {
-                if n[0].num != 0 && n[0].denom != 0 {
-                    exposure_time = Some(n[0]);
-                }
-            }
-            if let Some(&exif::Value::Rational(ref n)) = img_exif
-                .get_field(exif::Tag::FNumber, exif::In::PRIMARY)
-                .map(|n| &n.value)
-            {
-                if n[0].num != 0 && n[0].denom != 0 {
-                    fstop = Some(n[0]);
-                }
-            }
-            if let Some(Some(n)) = img_exif
-                .get_field(exif::Tag::PhotographicSensitivity, exif::In::PRIMARY)
-                .map(|n| n.value.get_uint(0))
-            {
-                if n != 0 {
-                    sensitivity = Some(n);
-                }
-            }
-        }
+    let (exposure_tags, metadata) = read_metadata(path)?;
 
-        (exposure_time, fstop, sensitivity)
+    // Make it upright.
+    let img = match metadata.orientation {
+        Some(orientation) if options.apply_orientation => img.oriented(orientation),
+        _ => img,
     };
 
     // Calculate over-all exposure.
-    let total_exposure = match (exposure_time, fstop, sensitivity) {
-        (Some(exp), Some(fst), Some(sns)) => {
-            Some((sns as f64 * exp.to_f64() / (fst.to_f64() * fst.to_f64())) as f32)
-        }
-        (Some(exp), None, Some(sns)) => Some((sns as f64 * exp.to_f64()) as f32),
-        (Some(exp), Some(fst), None) => Some((exp.to_f64() / (fst.to_f64() * fst.to_f64())) as f32),
-        (Some(exp), None, None) => Some(exp.to_f64() as f32),
-        _ => None,
-    };
+    let exposure = exposure_tags.estimate();
 
     // Fill in image info.
     let image_info = ImageInfo {
@@ -73,11 +64,19 @@ pub fn load_image(path: &Path) -> Result<SourceImage, image_fmt::ReadError> {
 
         width: img.width(),
         height: img.height(),
-        exposure: total_exposure,
+        exposure: exposure.map(|e| e.exposure),
+        exposure_source: exposure.map(|e| (e.route, e.confidence)),
 
-        exposure_time: exposure_time.map(|n| (n.num, n.denom)),
-        fstop: fstop.map(|n| (n.num, n.denom)),
-        iso: sensitivity,
+        exposure_time: exposure.map_or(exposure_tags.exposure_time, |e| e.exposure_time),
+        fstop: exposure.map_or(exposure_tags.fstop, |e| e.fstop),
+        iso: exposure_tags.iso,
+        metadata,
+
+        linear,
+        camera_to_xyz,
+        film_header,
+        color: img.color.clone(),
+        alignment: None,
     };
 
     // Add image to our list of source images.
@@ -87,6 +86,147 @@ pub fn load_image(path: &Path) -> Result<SourceImage, image_fmt::ReadError> {
     })
 }
 
+/// Reads the exposure tags and other metadata of an image file, from
+/// its Exif data and XMP.
+fn read_metadata(path: &Path) -> std::io::Result<(ExposureTags, ImageMetadata)> {
+    // Exif.
+    let (mut exposure_tags, mut metadata) = {
+        use std::io::Seek;
+
+        let mut file = std::io::BufReader::new(std::fs::File::open(&path)?);
+        let img_exif = exif::Reader::new()
+            .read_from_container(&mut file)
+            .ok()
+            .or_else(|| {
+                // The exif crate doesn't handle WebP Exif chunks that
+                // have a JPEG-style "Exif\0\0" prefix, so fall back to
+                // our own WebP parsing.
+                file.rewind().ok()?;
+                let data = image_fmt::webp_exif(&mut file).ok()??;
+                exif::Reader::new().read_raw(data).ok()
+            });
+        match img_exif {
+            Some(img_exif) => (
+                ExposureTags::from_exif(&img_exif),
+                ImageMetadata::from_exif(&img_exif),
+            ),
+            None => (ExposureTags::default(), ImageMetadata::default()),
+        }
+    };
+
+    // Fill in anything the Exif data lacks from XMP.  A sidecar file
+    // takes precedence over embedded XMP, since that's where editing
+    // software writes its changes.
+    let sidecar_xmp = crate::metadata::find_xmp_sidecar(path).and_then(|p| std::fs::read(p).ok());
+    let embedded_xmp = image_fmt::xmp(BufReader::new(File::open(path)?))
+        .ok()
+        .flatten();
+    for xmp in [sidecar_xmp, embedded_xmp].iter().flatten() {
+        let xmp = Xmp::parse(xmp);
+        exposure_tags.fill_from_xmp(&xmp);
+        metadata.fill_from_xmp(&xmp);
+    }
+
+    Ok((exposure_tags, metadata))
+}
+
+/// The chromaticities an image's RGB is in, assuming sRGB/Rec.709 when
+/// the file doesn't say.
+pub fn image_chromaticities(info: &ImageInfo) -> image_fmt::Chromaticities {
+    info.color
+        .as_ref()
+        .and_then(|color| color.chromaticities)
+        .unwrap_or(image_fmt::REC709)
+}
+
+/// Converts chromaticities to their colorbox equivalent.
+pub fn colorbox_chromaticities(
+    chroma: image_fmt::Chromaticities,
+) -> colorbox::chroma::Chromaticities {
+    colorbox::chroma::Chromaticities {
+        r: chroma.r,
+        g: chroma.g,
+        b: chroma.b,
+        w: chroma.w,
+    }
+}
+
+/// Builds a matrix that converts linear RGB in the `from`
+/// chromaticities to linear RGB in the `to` chromaticities, adapting
+/// the white point with the given method if necessary.
+pub fn rgb_to_rgb_matrix(
+    from: image_fmt::Chromaticities,
+    to: image_fmt::Chromaticities,
+    adaptation: colorbox::matrix::AdaptationMethod,
+) -> colorbox::matrix::Matrix {
+    use colorbox::matrix;
+
+    let from = colorbox_chromaticities(from);
+    let to = colorbox_chromaticities(to);
+    matrix::compose(&[
+        matrix::rgb_to_xyz_matrix(from),
+        matrix::xyz_chromatic_adaptation_matrix(from.w, to.w, adaptation),
+        matrix::xyz_to_rgb_matrix(to),
+    ])
+}
+
+/// Builds a matrix that converts white balanced camera RGB to linear
+/// RGB in the `to` chromaticities, given the camera's matrix to CIE XYZ
+/// (see `raw::load_raw()`).
+///
+/// The camera's white (1.0, 1.0, 1.0) is adapted to the white point of
+/// `to` with the given method, so white balanced neutrals stay neutral.
+pub fn camera_to_rgb_matrix(
+    camera_to_xyz: [[f32; 3]; 3],
+    to: image_fmt::Chromaticities,
+    adaptation: colorbox::matrix::AdaptationMethod,
+) -> colorbox::matrix::Matrix {
+    use colorbox::matrix;
+
+    let mut camera_matrix = [[0.0f64; 3]; 3];
+    for row in 0..3 {
+        for col in 0..3 {
+            camera_matrix[row][col] = camera_to_xyz[row][col] as f64;
+        }
+    }
+    let white = matrix::transform_color([1.0, 1.0, 1.0], camera_matrix);
+    let white_sum = white[0] + white[1] + white[2];
+    let camera_white = (white[0] / white_sum, white[1] / white_sum);
+
+    let to = colorbox_chromaticities(to);
+    matrix::compose(&[
+        camera_matrix,
+        matrix::xyz_chromatic_adaptation_matrix(camera_white, to.w, adaptation),
+        matrix::xyz_to_rgb_matrix(to),
+    ])
+}
+
+/// Reads an image file's dimensions without decoding it, if possible.
+/// The dimensions are those that `load_image()` would produce with the
+/// same options, i.e. with the orientation applied.
+///
+/// Returns `None` for camera raw files and for files that can't be
+/// probed.  In the latter case `load_image()` will report the actual
+/// error.
+pub fn probe_dimensions(path: &Path, options: &LoadOptions) -> Option<(usize, usize)> {
+    if crate::raw::is_raw_path(path) {
+        return None;
+    }
+    let file = File::open(path).ok()?;
+    let (width, height) = image_fmt::probe(BufReader::new(file)).ok()?.dimensions;
+
+    // Orientations 5 through 8 swap the axes.
+    let orientation = if options.apply_orientation {
+        read_metadata(path).ok()?.1.orientation
+    } else {
+        None
+    };
+    match orientation {
+        Some(5..=8) => Some((height, width)),
+        _ => Some((width, height)),
+    }
+}
+
 pub fn make_image_preview(
     img: &SourceImage,
     max_width: Option<usize>,
@@ -124,12 +264,32 @@ pub fn make_image_preview(
         }
     };
 
-    let preview_img = img
-        .image
-        .clone()
-        .to_8_bit()
-        .resized(new_dim.0, new_dim.1)
-        .to_rgba();
+    // Very thin images could otherwise end up with a zero dimension.
+    let new_dim = (new_dim.0.max(1), new_dim.1.max(1));
+
+    // Resize before reducing the bit depth, so that high-bit-depth
+    // images keep their detail.
+    let mut preview_img =
+        match img
+            .image
+            .resized(new_dim.0, new_dim.1, &image_fmt::ResizeOptions::default())
+        {
+            Ok(preview_img) => preview_img,
+            Err(_) => return (vec![0, 0, 0, 255], 1, 1),
+        };
+    if preview_img.data.is_float() {
+        // Float images are scene-linear, so encode them for display
+        // rather than just clamping them.
+        preview_img.data = match preview_img.data.to_f32() {
+            ImageBuf::RgbF32(data) => ImageBuf::RgbF32(
+                data.iter()
+                    .map(|&n| colorbox::transfer_functions::srgb::from_linear(n))
+                    .collect(),
+            ),
+            _ => unreachable!(),
+        };
+    }
+    let preview_img = preview_img.to_8_bit().to_rgba();
 
     (
         if let image_fmt::ImageBuf::Rgba8(buf) = preview_img.data {
@@ -166,12 +326,229 @@ pub fn compute_image_histograms(src_img: &SourceImage) -> [Histogram; 3] {
             }
         }
 
-        _ => panic!(),
+        // Float images are quantized to 16 bits, which is plenty of
+        // precision for histograms.
+        ImageBuf::RgbF16(ref buf) => {
+            let bucket_count = 1 << 16;
+            for chan in 0..3 {
+                histograms[chan] = Histogram::from_iter(
+                    buf.chunks(3).map(|c| quantize_16(c[chan].to_f32())),
+                    bucket_count,
+                );
+            }
+        }
+
+        ImageBuf::RgbF32(ref buf) => {
+            let bucket_count = 1 << 16;
+            for chan in 0..3 {
+                histograms[chan] =
+                    Histogram::from_iter(buf.chunks(3).map(|c| quantize_16(c[chan])), bucket_count);
+            }
+        }
+
+        _ => unreachable!("images are converted to RGB when loaded"),
     }
 
     histograms
 }
 
+/// Samples roughly `max_samples` pixels spread evenly over an image, as
+/// approximately linear RGB in [0.0, 1.0].  Images with the same
+/// resolution are sampled at the same pixel positions.
+///
+/// Images that aren't already linear are assumed to be sRGB-like, which
+/// is close enough for comparing exposures between images.
+pub fn sample_linear_pixels(src_img: &SourceImage, max_samples: usize) -> Vec<[f32; 3]> {
+    let pixel_count = src_img.image.width() * src_img.image.height();
+    let stride = (pixel_count / max_samples.max(1)).max(1);
+    let linearize = |n: f32| {
+        let n = n.clamp(0.0, 1.0);
+        if src_img.info.linear {
+            n
+        } else {
+            colorbox::transfer_functions::srgb::to_linear(n)
+        }
+    };
+    let sample = |rgb: [f32; 3]| [linearize(rgb[0]), linearize(rgb[1]), linearize(rgb[2])];
+
+    match src_img.image.data {
+        ImageBuf::Rgb8(ref buf) => {
+            let norm = 1.0 / 255.0;
+            buf.chunks(3)
+                .step_by(stride)
+                .map(|c| sample([c[0] as f32 * norm, c[1] as f32 * norm, c[2] as f32 * norm]))
+                .collect()
+        }
+
+        ImageBuf::Rgb16(ref buf) => {
+            let norm = 1.0 / u16::MAX as f32;
+            buf.chunks(3)
+                .step_by(stride)
+                .map(|c| sample([c[0] as f32 * norm, c[1] as f32 * norm, c[2] as f32 * norm]))
+                .collect()
+        }
+
+        ImageBuf::RgbF16(ref buf) => buf
+            .chunks(3)
+            .step_by(stride)
+            .map(|c| sample([c[0].to_f32(), c[1].to_f32(), c[2].to_f32()]))
+            .collect(),
+
+        ImageBuf::RgbF32(ref buf) => buf
+            .chunks(3)
+            .step_by(stride)
+            .map(|c| sample([c[0], c[1], c[2]]))
+            .collect(),
+
+        _ => unreachable!("images are converted to RGB when loaded"),
+    }
+}
+
+/// Fills in the missing exposures of a set of images of the same scene,
+/// by comparing their pixel samples (from `sample_linear_pixels()`).
+///
+/// The estimates are scaled to line up with the known exposures of the
+/// images they can be related to.  Known exposures are returned as-is,
+/// and images whose exposure couldn't be estimated get `None`.  If none
+/// of the exposures are known, the largest group of related images is
+/// estimated relative to its first image at 1.0, so callers that need
+/// the estimates to line up with other images should make sure at
+/// least one is known.
+pub fn estimate_missing_exposures(
+    samples: &[Vec<[f32; 3]>],
+    known: &[Option<f32>],
+) -> Vec<Option<f32>> {
+    // Pixels darker than this are too noisy and quantized to be useful,
+    // and brighter ones may be (partially) clipped.
+    const WELL_EXPOSED: (f32, f32) = (0.01, 0.8);
+
+    let sample_slices: Vec<&[[f32; 3]]> = samples.iter().map(|s| &s[..]).collect();
+    let ratios = sensor_analysis::estimate_exposure_ratios(&sample_slices, WELL_EXPOSED);
+
+    // The scale of each group of related images, from the average (in
+    // stops) of the scales implied by its known exposures.
+    let mut log_scales = vec![(0.0f32, 0usize); known.len()];
+    for (&known, &(group, ratio)) in known.iter().zip(ratios.iter()) {
+        if let Some(known) = known {
+            log_scales[group].0 += (known / ratio).log2();
+            log_scales[group].1 += 1;
+        }
+    }
+    let mut scales: Vec<Option<f32>> = log_scales
+        .iter()
+        .map(|&(sum, count)| (count > 0).then(|| (sum / count as f32).exp2()))
+        .collect();
+    if known.iter().all(|known| known.is_none()) {
+        let mut group_sizes = vec![0usize; known.len()];
+        for &(group, _) in ratios.iter() {
+            group_sizes[group] += 1;
+        }
+        if let Some(largest) = (0..group_sizes.len()).rev().max_by_key(|&i| group_sizes[i]) {
+            scales[largest] = Some(1.0);
+        }
+    }
+
+    known
+        .iter()
+        .zip(ratios.iter())
+        .map(|(&known, &(group, ratio))| known.or(scales[group].map(|scale| ratio * scale)))
+        .collect()
+}
+
+/// Makes a grayscale version of an image for estimating its alignment,
+/// downscaled to fit within `max_res` on both axes.
+pub fn make_alignment_image(src_img: &SourceImage, max_res: usize) -> image_align::GrayImage {
+    let (width, height) = src_img.image.dimensions;
+    let scale = (max_res as f64 / width.max(height) as f64).min(1.0);
+    let new_width = ((width as f64 * scale).round() as usize).max(1);
+    let new_height = ((height as f64 * scale).round() as usize).max(1);
+
+    let img = src_img
+        .image
+        .resized(new_width, new_height, &image_fmt::ResizeOptions::default())
+        .unwrap_or_else(|_| src_img.image.clone());
+    let (width, height) = img.dimensions;
+
+    // Alignment only needs brighter to be larger, so there's no need
+    // to linearize.
+    let pixels = match img.data.to_f32() {
+        ImageBuf::RgbF32(data) => data
+            .chunks(3)
+            .map(|c| (c[0] + c[1] + c[2]) * (1.0 / 3.0))
+            .collect(),
+        _ => unreachable!("images are converted to RGB when loaded"),
+    };
+
+    image_align::GrayImage::new(pixels, width, height)
+}
+
+/// Resamples an image with an alignment transform (see `ImageInfo`), so
+/// that it lines up with the reference image of its set.
+///
+/// Returns which pixels are covered by the image after warping (see
+/// `image_align::warp_interleaved()`).
+pub fn warp_image(img: &mut image_fmt::Image, transform: &image_align::Transform) -> Vec<bool> {
+    use image_align::warp_interleaved;
+
+    let (width, height) = img.dimensions;
+    let (data, coverage) = match img.data {
+        ImageBuf::Rgb8(ref buf) => {
+            let (warped, coverage) = warp_interleaved(
+                buf,
+                3,
+                width,
+                height,
+                transform,
+                |n| n as f32,
+                |n| n.round() as u8,
+            );
+            (ImageBuf::Rgb8(warped), coverage)
+        }
+
+        ImageBuf::Rgb16(ref buf) => {
+            let (warped, coverage) = warp_interleaved(
+                buf,
+                3,
+                width,
+                height,
+                transform,
+                |n| n as f32,
+                |n| n.round() as u16,
+            );
+            (ImageBuf::Rgb16(warped), coverage)
+        }
+
+        ImageBuf::RgbF16(ref buf) => {
+            let (warped, coverage) = warp_interleaved(
+                buf,
+                3,
+                width,
+                height,
+                transform,
+                |n| n.to_f32(),
+                image_fmt::f16::from_f32,
+            );
+            (ImageBuf::RgbF16(warped), coverage)
+        }
+
+        ImageBuf::RgbF32(ref buf) => {
+            let (warped, coverage) =
+                warp_interleaved(buf, 3, width, height, transform, |n| n, |n| n);
+            (ImageBuf::RgbF32(warped), coverage)
+        }
+
+        _ => unreachable!("images are converted to RGB when loaded"),
+    };
+    img.data = data;
+
+    coverage
+}
+
+#[inline(always)]
+fn quantize_16(n: f32) -> u16 {
+    (n.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+}
+
 pub fn load_1d_lut<P: AsRef<Path>>(path: P) -> Result<Lut1D, formats::ReadError> {
     use std::io::Seek;
 
@@ -228,3 +605,107 @@ pub fn ensure_dir_exists<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_image(width: usize, height: usize, data: ImageBuf) -> SourceImage {
+        SourceImage {
+            image: image_fmt::Image {
+                dimensions: (width, height),
+                data,
+                color: None,
+            },
+            info: ImageInfo {
+                filename: String::new(),
+                full_filepath: String::new(),
+                width,
+                height,
+                exposure: None,
+                exposure_source: None,
+                exposure_time: None,
+                fstop: None,
+                iso: None,
+                metadata: ImageMetadata::default(),
+                linear: false,
+                camera_to_xyz: None,
+                film_header: None,
+                color: None,
+                alignment: None,
+            },
+        }
+    }
+
+    #[test]
+    fn image_preview_odd_dimensions() {
+        let img = source_image(7, 5, ImageBuf::Rgb8(vec![100; 7 * 5 * 3]));
+
+        let (pixels, width, height) = make_image_preview(&img, Some(3), None);
+        assert_eq!((width, height), (3, 2));
+        assert_eq!(pixels.len(), 3 * 2 * 4);
+        for pixel in pixels.chunks(4) {
+            assert_eq!(pixel, [100, 100, 100, 255]);
+        }
+
+        let (_, width, height) = make_image_preview(&img, Some(4), Some(4));
+        assert_eq!((width, height), (4, 2));
+
+        let (pixels, width, height) = make_image_preview(&img, None, None);
+        assert_eq!((width, height), (7, 5));
+        assert_eq!(pixels.len(), 7 * 5 * 4);
+
+        // Very thin images keep at least one pixel on both axes.
+        let img = source_image(1, 9, ImageBuf::Rgb16(vec![0; 9 * 3]));
+        let (pixels, width, height) = make_image_preview(&img, None, Some(4));
+        assert_eq!((width, height), (1, 4));
+        assert_eq!(pixels.len(), 4 * 4);
+    }
+
+    #[test]
+    fn image_preview_float() {
+        // Float images are linear, and get sRGB encoded for display.
+        // Resizing mustn't quantize them.
+        let img = source_image(4, 4, ImageBuf::RgbF32(vec![0.5; 4 * 4 * 3]));
+        let (pixels, width, height) = make_image_preview(&img, Some(2), None);
+        assert_eq!((width, height), (2, 2));
+        for pixel in pixels.chunks(4) {
+            assert_eq!(pixel, [188, 188, 188, 255]);
+        }
+    }
+
+    /// Linear samples of a scene with a wide range of brightnesses,
+    /// captured with the given exposure and clipped at 1.0.
+    fn capture(exposure: f32) -> Vec<[f32; 3]> {
+        (0..4096)
+            .map(|i| {
+                let n = (i as f32 / 4096.0 * 16.0 - 12.0).exp2();
+                [n, n * 0.8, n * 0.6].map(|c| (c * exposure).min(1.0))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn estimate_missing_exposures_per_group() {
+        // The first image has nothing in common with the others, which
+        // still line up with the known exposure they're related to.
+        let samples = [
+            vec![[1.0; 3]; 4096],
+            capture(1.0),
+            capture(4.0),
+            capture(16.0),
+        ];
+        let known = [None, None, Some(0.04), None];
+        let estimated = estimate_missing_exposures(&samples, &known);
+        assert_eq!(estimated[0], None);
+        assert_eq!(estimated[2], Some(0.04));
+        assert!((estimated[1].unwrap() / 0.01 - 1.0).abs() < 0.01);
+        assert!((estimated[3].unwrap() / 0.16 - 1.0).abs() < 0.01);
+
+        // Without known exposures, the largest group is used.
+        let estimated = estimate_missing_exposures(&samples, &[None; 4]);
+        assert_eq!(estimated[0], None);
+        assert!((estimated[1].unwrap() - 1.0).abs() < 0.01);
+        assert!((estimated[2].unwrap() / 4.0 - 1.0).abs() < 0.01);
+    }
+}
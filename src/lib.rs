@@ -1,4 +1,8 @@
+pub mod demosaic;
+pub mod exposure_entry;
 pub mod job_helpers;
+pub mod metadata;
+pub mod raw;
 
 pub use image_fmt::ImageBuf;
 
@@ -17,9 +21,47 @@ pub struct ImageInfo {
     pub height: usize,
     pub exposure: Option<f32>,
 
+    /// How `exposure` was computed, and how much it can be trusted.
+    pub exposure_source: Option<(metadata::ExposureRoute, metadata::Confidence)>,
+
     pub exposure_time: Option<(u32, u32)>, // Ratio.
     pub fstop: Option<(u32, u32)>,         // Ratio.
     pub iso: Option<u32>,
+
+    /// Camera, lens, and capture details from Exif and XMP.
+    pub metadata: metadata::ImageMetadata,
+
+    /// Whether the pixel data is already linear, e.g. from a camera raw
+    /// file or a float image.  Such images don't need a transfer
+    /// function estimated.
+    pub linear: bool,
+
+    /// Matrix from the pixel data's RGB to CIE XYZ, for camera raw files.
+    pub camera_to_xyz: Option<[[f32; 3]; 3]>,
+
+    /// The pixel encoding described by the header of DPX and Cineon
+    /// files.
+    pub film_header: Option<image_fmt::FilmHeader>,
+
+    /// The color encoding described by the file, e.g. from an embedded
+    /// ICC profile.
+    pub color: Option<image_fmt::ColorDescription>,
+
+    /// The estimated transform from the reference image of the image's
+    /// set to this image, if the set has been aligned.
+    pub alignment: Option<image_align::Transform>,
+}
+
+impl ImageInfo {
+    /// Replaces the image's exposure and the values it came from, e.g.
+    /// with one entered by the user.
+    pub fn set_exposure(&mut self, estimate: metadata::ExposureEstimate) {
+        self.exposure = Some(estimate.exposure);
+        self.exposure_source = Some((estimate.route, estimate.confidence));
+        self.exposure_time = estimate.exposure_time;
+        self.fstop = estimate.fstop;
+        self.iso = estimate.iso;
+    }
 }
 
 pub mod colors {
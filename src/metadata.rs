@@ -0,0 +1,1183 @@
+//! Capture metadata from Exif and XMP.
+
+use std::path::{Path, PathBuf};
+
+/// Information about how and with what an image was captured, beyond
+/// what's needed to compute its exposure.
+#[derive(Debug, Clone, Default)]
+pub struct ImageMetadata {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub camera_serial: Option<String>,
+    pub lens_make: Option<String>,
+    pub lens_model: Option<String>,
+    pub focal_length: Option<f32>,      // In mm.
+    pub focal_length_35mm: Option<f32>, // In mm, 35mm-film equivalent.
+
+    /// When the image was captured.
+    pub date_time: Option<DateTime>,
+
+    pub white_balance: Option<WhiteBalance>,
+    pub exposure_bias: Option<f32>, // In stops.
+
+    /// Exif orientation code, 1 through 8.  1 means the pixel data is
+    /// already upright.
+    pub orientation: Option<u16>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanosecond: u32,
+
+    /// Offset from UTC in minutes, if known.
+    pub offset: Option<i16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WhiteBalance {
+    Auto,
+    Manual,
+
+    /// A named light source or preset, e.g. "daylight".
+    Preset(String),
+}
+
+impl ImageMetadata {
+    pub fn from_exif(exif: &exif::Exif) -> ImageMetadata {
+        use exif::Tag;
+
+        let date_time = exif_date_time(
+            exif,
+            Tag::DateTimeOriginal,
+            Tag::SubSecTimeOriginal,
+            Tag::OffsetTimeOriginal,
+        )
+        .or_else(|| exif_date_time(exif, Tag::DateTime, Tag::SubSecTime, Tag::OffsetTime));
+
+        // The light source is more specific, but is only meaningful when
+        // the white balance wasn't chosen automatically.
+        let white_balance = match exif_uint(exif, Tag::WhiteBalance) {
+            Some(0) => Some(WhiteBalance::Auto),
+            _ => match exif_uint(exif, Tag::LightSource) {
+                None | Some(0) => exif_uint(exif, Tag::WhiteBalance).map(|_| WhiteBalance::Manual),
+                Some(_) => exif
+                    .get_field(Tag::LightSource, exif::In::PRIMARY)
+                    .map(|field| WhiteBalance::Preset(field.display_value().to_string())),
+            },
+        };
+
+        ImageMetadata {
+            camera_make: exif_string(exif, Tag::Make),
+            camera_model: exif_string(exif, Tag::Model),
+            camera_serial: exif_string(exif, Tag::BodySerialNumber),
+            lens_make: exif_string(exif, Tag::LensMake),
+            lens_model: exif_string(exif, Tag::LensModel),
+            focal_length: exif_rational(exif, Tag::FocalLength),
+            focal_length_35mm: exif_uint(exif, Tag::FocalLengthIn35mmFilm)
+                .filter(|&n| n != 0)
+                .map(|n| n as f32),
+            date_time,
+            white_balance,
+            exposure_bias: exif_rational(exif, Tag::ExposureBiasValue),
+            orientation: exif_uint(exif, Tag::Orientation)
+                .filter(|n| (1..=8).contains(n))
+                .map(|n| n as u16),
+        }
+    }
+
+    /// Fills in any fields that are still missing from XMP metadata.
+    pub fn fill_from_xmp(&mut self, xmp: &Xmp) {
+        let string = |ns, name| xmp.get(ns, name).map(|s| s.to_string());
+        let number = |ns, name| xmp.get(ns, name).and_then(parse_rational);
+
+        fill(&mut self.camera_make, string(NS_TIFF, "Make"));
+        fill(&mut self.camera_model, string(NS_TIFF, "Model"));
+        fill(
+            &mut self.camera_serial,
+            string(NS_EXIF_EX, "BodySerialNumber"),
+        );
+        fill(&mut self.camera_serial, string(NS_AUX, "SerialNumber"));
+        fill(&mut self.lens_make, string(NS_EXIF_EX, "LensMake"));
+        fill(&mut self.lens_model, string(NS_EXIF_EX, "LensModel"));
+        fill(&mut self.lens_model, string(NS_AUX, "Lens"));
+        fill(&mut self.focal_length, number(NS_EXIF, "FocalLength"));
+        fill(
+            &mut self.focal_length_35mm,
+            number(NS_EXIF, "FocalLengthIn35mmFilm").filter(|&n| n > 0.0),
+        );
+        for (ns, name) in [
+            (NS_EXIF, "DateTimeOriginal"),
+            (NS_PHOTOSHOP, "DateCreated"),
+            (NS_XMP, "CreateDate"),
+        ] {
+            fill(
+                &mut self.date_time,
+                xmp.get(ns, name).and_then(DateTime::from_iso8601),
+            );
+        }
+        fill(
+            &mut self.white_balance,
+            match xmp.get(NS_EXIF, "WhiteBalance") {
+                Some("0") => Some(WhiteBalance::Auto),
+                Some("1") => Some(WhiteBalance::Manual),
+                _ => None,
+            },
+        );
+        fill(
+            &mut self.white_balance,
+            match xmp.get(NS_CRS, "WhiteBalance") {
+                // "As Shot" says nothing about how it was shot.
+                None | Some("As Shot") => None,
+                Some("Auto") => Some(WhiteBalance::Auto),
+                Some("Custom") => Some(WhiteBalance::Manual),
+                Some(preset) => Some(WhiteBalance::Preset(preset.to_lowercase())),
+            },
+        );
+        fill(
+            &mut self.exposure_bias,
+            number(NS_EXIF, "ExposureBiasValue"),
+        );
+        fill(
+            &mut self.orientation,
+            xmp.get(NS_TIFF, "Orientation")
+                .and_then(|s| s.parse::<u16>().ok())
+                .filter(|n| (1..=8).contains(n)),
+        );
+    }
+
+    /// Human-readable (label, value) pairs of the fields that are
+    /// present, for display in the UI.
+    pub fn display_fields(&self) -> Vec<(&'static str, String)> {
+        // Models often already start with the make, e.g. "Canon" and
+        // "Canon EOS 5D".
+        let make_and_model = |make: &Option<String>, model: &Option<String>| match (make, model) {
+            (Some(make), Some(model)) if model.starts_with(make.as_str()) => Some(model.clone()),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (Some(s), None) | (None, Some(s)) => Some(s.clone()),
+            (None, None) => None,
+        };
+
+        let mut fields = Vec::new();
+        if let Some(camera) = make_and_model(&self.camera_make, &self.camera_model) {
+            fields.push(("Camera", camera));
+        }
+        if let Some(ref serial) = self.camera_serial {
+            fields.push(("Serial number", serial.clone()));
+        }
+        if let Some(lens) = make_and_model(&self.lens_make, &self.lens_model) {
+            fields.push(("Lens", lens));
+        }
+        match (self.focal_length, self.focal_length_35mm) {
+            (Some(f), Some(f35)) => {
+                fields.push(("Focal length", format!("{} mm ({} mm equiv.)", f, f35)))
+            }
+            (Some(f), None) => fields.push(("Focal length", format!("{} mm", f))),
+            (None, Some(f35)) => fields.push(("Focal length", format!("{} mm equiv.", f35))),
+            (None, None) => {}
+        }
+        if let Some(date_time) = self.date_time {
+            fields.push(("Captured", date_time.to_string()));
+        }
+        if let Some(ref white_balance) = self.white_balance {
+            fields.push((
+                "White balance",
+                match white_balance {
+                    WhiteBalance::Auto => "auto".into(),
+                    WhiteBalance::Manual => "manual".into(),
+                    WhiteBalance::Preset(preset) => preset.clone(),
+                },
+            ));
+        }
+        if let Some(bias) = self.exposure_bias {
+            fields.push(("Exposure bias", format!("{:+.1} EV", bias)));
+        }
+        if let Some(orientation) = self.orientation {
+            fields.push((
+                "Orientation",
+                match orientation {
+                    1 => "upright",
+                    2 => "flipped horizontally",
+                    3 => "rotated 180°",
+                    4 => "flipped vertically",
+                    5 => "transposed",
+                    6 => "rotated 90° clockwise",
+                    7 => "transversed",
+                    _ => "rotated 90° counter-clockwise",
+                }
+                .into(),
+            ));
+        }
+
+        fields
+    }
+}
+
+/// The exposure-related tags of an image, gathered from whichever
+/// metadata has them.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ExposureTags {
+    pub exposure_time: Option<(u32, u32)>, // Ratio, in seconds.
+    pub fstop: Option<(u32, u32)>,         // Ratio.
+    pub iso: Option<u32>,
+
+    pub shutter_speed_value: Option<f32>, // APEX Tv.
+    pub aperture_value: Option<f32>,      // APEX Av.
+    pub exposure_bias: Option<f32>,       // In stops.
+}
+
+/// How an image's exposure was computed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExposureRoute {
+    /// From the exposure time, and the f-number and ISO if present.
+    ExposureTime,
+
+    /// Like `ExposureTime`, but with the exposure time and/or f-number
+    /// derived from the APEX shutter speed and aperture values.
+    Apex,
+
+    /// From the exposure bias alone.  This is relative to whatever the
+    /// camera metered, so it's only meaningful for auto-bracketed
+    /// sequences where every image has it.
+    ExposureBias,
+
+    /// Entered by the user.
+    Manual,
+
+    /// Estimated from the image's pixels, relative to other images of
+    /// the same scene.
+    Estimated,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Low,
+    Medium,
+    High,
+}
+
+/// An image's computed exposure, and how it was arrived at.
+#[derive(Debug, Copy, Clone)]
+pub struct ExposureEstimate {
+    pub exposure: f32,
+
+    /// The values that went into the exposure, whether read directly
+    /// or derived from APEX values.
+    pub exposure_time: Option<(u32, u32)>, // Ratio, in seconds.
+    pub fstop: Option<(u32, u32)>, // Ratio.
+    pub iso: Option<u32>,
+
+    pub route: ExposureRoute,
+    pub confidence: Confidence,
+}
+
+impl ExposureTags {
+    pub fn from_exif(exif: &exif::Exif) -> ExposureTags {
+        use exif::Tag;
+
+        ExposureTags {
+            exposure_time: exif_ratio(exif, Tag::ExposureTime),
+            fstop: exif_ratio(exif, Tag::FNumber),
+            iso: exif_uint(exif, Tag::PhotographicSensitivity).filter(|&n| n != 0),
+            shutter_speed_value: exif_rational(exif, Tag::ShutterSpeedValue),
+            aperture_value: exif_rational(exif, Tag::ApertureValue),
+            exposure_bias: exif_rational(exif, Tag::ExposureBiasValue),
+        }
+    }
+
+    /// Fills in any tags that are still missing from XMP metadata.
+    pub fn fill_from_xmp(&mut self, xmp: &Xmp) {
+        let ratio = |ns, name| xmp.get(ns, name).and_then(parse_ratio);
+        let number = |ns, name| xmp.get(ns, name).and_then(parse_rational);
+
+        fill(&mut self.exposure_time, ratio(NS_EXIF, "ExposureTime"));
+        fill(&mut self.fstop, ratio(NS_EXIF, "FNumber"));
+        for (ns, name) in [
+            (NS_EXIF_EX, "PhotographicSensitivity"),
+            (NS_EXIF, "ISOSpeedRatings"),
+        ] {
+            fill(
+                &mut self.iso,
+                xmp.get(ns, name)
+                    .and_then(|s| s.trim().parse().ok())
+                    .filter(|&n| n != 0),
+            );
+        }
+        fill(
+            &mut self.shutter_speed_value,
+            number(NS_EXIF, "ShutterSpeedValue"),
+        );
+        fill(&mut self.aperture_value, number(NS_EXIF, "ApertureValue"));
+        fill(
+            &mut self.exposure_bias,
+            number(NS_EXIF, "ExposureBiasValue"),
+        );
+    }
+
+    /// Computes the exposure from the best available route.  Returns
+    /// `None` if there isn't enough information for any of them.
+    ///
+    /// The exposure bias is only used when there's no exposure time.
+    /// It's how far from the metered exposure the camera was asked to
+    /// go, and the camera gets there by changing the exposure time (in
+    /// aperture priority) or the f-number (in shutter priority), so
+    /// those already include it.  Applying it on top would count it
+    /// twice.
+    pub fn estimate(&self) -> Option<ExposureEstimate> {
+        // APEX values are only used to fill in what's missing, since
+        // they're often rounded to the nearest 1/3 stop.
+        let apex_time = self
+            .shutter_speed_value
+            .filter(|tv| tv.abs() < 32.0)
+            .map(|tv| to_ratio(2.0f64.powf(-tv as f64)));
+        let apex_fstop = self
+            .aperture_value
+            .filter(|av| (0.0..32.0).contains(av))
+            .map(|av| to_ratio(2.0f64.powf(av as f64 / 2.0)));
+        let exposure_time = self.exposure_time.or(apex_time);
+        let fstop = self.fstop.or(apex_fstop);
+        let used_apex = (self.exposure_time.is_none() && apex_time.is_some())
+            || (self.fstop.is_none() && apex_fstop.is_some());
+
+        let ratio = |n: (u32, u32)| n.0 as f64 / n.1 as f64;
+        let aperture_and_iso = fstop.map(|f| 1.0 / (ratio(f) * ratio(f))).unwrap_or(1.0)
+            * self.iso.map(|iso| iso as f64).unwrap_or(1.0);
+
+        let (exposure, route, confidence) = if let Some(exposure_time) = exposure_time {
+            let confidence = if used_apex || fstop.is_none() || self.iso.is_none() {
+                // Missing values are assumed to be the same across the
+                // set, which is usually but not always true.
+                Confidence::Medium
+            } else {
+                Confidence::High
+            };
+            let route = if used_apex {
+                ExposureRoute::Apex
+            } else {
+                ExposureRoute::ExposureTime
+            };
+            (ratio(exposure_time) * aperture_and_iso, route, confidence)
+        } else {
+            // Without an exposure time, the best we can do is assume the
+            // camera metered the same for the whole set (e.g. aperture
+            // priority auto-bracketing) and use the bias from that.
+            let bias = self.exposure_bias?;
+            (
+                2.0f64.powf(bias as f64) * aperture_and_iso,
+                ExposureRoute::ExposureBias,
+                Confidence::Low,
+            )
+        };
+
+        Some(ExposureEstimate {
+            exposure: exposure as f32,
+            exposure_time,
+            fstop,
+            iso: self.iso,
+            route,
+            confidence,
+        })
+    }
+}
+
+impl ExposureRoute {
+    pub fn ui_text(&self) -> &'static str {
+        match *self {
+            ExposureRoute::ExposureTime => "exposure time",
+            ExposureRoute::Apex => "APEX values",
+            ExposureRoute::ExposureBias => "exposure bias",
+            ExposureRoute::Manual => "manual entry",
+            ExposureRoute::Estimated => "pixel comparison",
+        }
+    }
+}
+
+impl Confidence {
+    pub fn ui_text(&self) -> &'static str {
+        match *self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        }
+    }
+}
+
+impl DateTime {
+    /// Parses the XMP date format, e.g. "2021-07-04T18:30:15.25+02:00".
+    /// Any of the trailing parts may be missing.
+    pub fn from_iso8601(text: &str) -> Option<DateTime> {
+        let text = text.trim();
+        let (date, time) = match text.find('T') {
+            Some(i) => (&text[..i], &text[(i + 1)..]),
+            None => (text, ""),
+        };
+
+        let mut date_parts = date.split('-');
+        let year = date_parts.next()?.parse().ok()?;
+        let month = date_parts.next().map(|s| s.parse()).unwrap_or(Ok(1)).ok()?;
+        let day = date_parts.next().map(|s| s.parse()).unwrap_or(Ok(1)).ok()?;
+
+        // Split off the time zone.
+        let (time, offset) = match time.find(&['Z', '+', '-'][..]) {
+            Some(i) => {
+                let zone = &time[i..];
+                let offset = if zone == "Z" {
+                    0
+                } else {
+                    let sign = if zone.starts_with('-') { -1 } else { 1 };
+                    let mut zone_parts = zone[1..].split(':');
+                    let hours: u8 = zone_parts.next()?.parse().ok()?;
+                    let minutes: u8 = zone_parts.next().map(|s| s.parse()).unwrap_or(Ok(0)).ok()?;
+                    if hours > 23 || minutes > 59 {
+                        return None;
+                    }
+                    sign * (hours as i16 * 60 + minutes as i16)
+                };
+                (&time[..i], Some(offset))
+            }
+            None => (time, None),
+        };
+
+        let mut time_parts = time.split(':').filter(|s| !s.is_empty());
+        let hour = time_parts.next().map(|s| s.parse()).unwrap_or(Ok(0)).ok()?;
+        let minute = time_parts.next().map(|s| s.parse()).unwrap_or(Ok(0)).ok()?;
+        let (second, nanosecond) = match time_parts.next() {
+            Some(s) => {
+                let (whole, fraction) = s.split_once('.').unwrap_or((s, "0"));
+                (whole.parse().ok()?, parse_nanoseconds(fraction)?)
+            }
+            None => (0, 0),
+        };
+
+        if !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return None;
+        }
+
+        Some(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            nanosecond,
+            offset,
+        })
+    }
+}
+
+impl std::fmt::Display for DateTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        if self.nanosecond != 0 {
+            let fraction = format!("{:09}", self.nanosecond);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+        if let Some(offset) = self.offset {
+            let sign = if offset < 0 { '-' } else { '+' };
+            let offset = offset.abs();
+            write!(f, " {}{:02}:{:02}", sign, offset / 60, offset % 60)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the XMP sidecar file of an image file, if it has one.  Both the
+/// "IMG_0001.xmp" and "IMG_0001.CR2.xmp" naming conventions are checked.
+pub fn find_xmp_sidecar(path: &Path) -> Option<PathBuf> {
+    for ext in ["xmp", "XMP"] {
+        let replaced = path.with_extension(ext);
+        let mut appended = path.as_os_str().to_owned();
+        appended.push(".");
+        appended.push(ext);
+
+        for candidate in [replaced, PathBuf::from(appended)] {
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+//-------------------------------------------------------------
+// XMP.
+
+const NS_AUX: &str = "http://ns.adobe.com/exif/1.0/aux/";
+const NS_CRS: &str = "http://ns.adobe.com/camera-raw-settings/1.0/";
+const NS_EXIF: &str = "http://ns.adobe.com/exif/1.0/";
+const NS_EXIF_EX: &str = "http://cipa.jp/exif/1.0/";
+const NS_PHOTOSHOP: &str = "http://ns.adobe.com/photoshop/1.0/";
+const NS_TIFF: &str = "http://ns.adobe.com/tiff/1.0/";
+const NS_XMP: &str = "http://ns.adobe.com/xap/1.0/";
+
+/// The simple properties of an XMP packet.
+///
+/// This is not a general RDF/XML parser: only the properties of the
+/// top-level `rdf:Description` elements are read, in both attribute and
+/// element form.  For arrays only the first item is kept, which is
+/// what's wanted for e.g. `exif:ISOSpeedRatings` or localized text.
+#[derive(Debug, Clone, Default)]
+pub struct Xmp {
+    properties: Vec<(String, String, String)>, // (namespace URI, name, value)
+}
+
+impl Xmp {
+    pub fn parse(data: &[u8]) -> Xmp {
+        struct Property {
+            name: String,
+            value: Option<String>,
+            depth: usize,
+        }
+
+        let text = String::from_utf8_lossy(data);
+        let mut namespaces = Vec::new(); // (prefix, URI)
+        let mut raw_properties = Vec::new(); // (qualified name, value)
+        let mut open_elements: Vec<String> = Vec::new();
+        let mut property: Option<Property> = None;
+
+        // Text is only a value when it's directly inside a property
+        // element or inside an array item.
+        let take_text =
+            |property: &mut Option<Property>, open_elements: &[String], text: String| {
+                if let Some(ref mut property) = *property {
+                    let is_value = open_elements.len() == property.depth + 1
+                        || open_elements.last().map(|e| e == "rdf:li").unwrap_or(false);
+                    if is_value && property.value.is_none() && !text.is_empty() {
+                        property.value = Some(text);
+                    }
+                }
+            };
+
+        let mut rest = &text[..];
+        while let Some(start) = rest.find('<') {
+            take_text(
+                &mut property,
+                &open_elements,
+                unescape(rest[..start].trim()),
+            );
+            rest = &rest[start..];
+
+            // Things that aren't elements.
+            let skip_to =
+                |rest: &str, end: &str| rest.find(end).map(|i| i + end.len()).unwrap_or(rest.len());
+            if rest.starts_with("<![CDATA[") {
+                let end = rest.find("]]>").unwrap_or(rest.len());
+                take_text(
+                    &mut property,
+                    &open_elements,
+                    rest[9..end].trim().to_string(),
+                );
+                rest = &rest[skip_to(rest, "]]>")..];
+                continue;
+            } else if rest.starts_with("<!--") {
+                rest = &rest[skip_to(rest, "-->")..];
+                continue;
+            } else if rest.starts_with("<?") {
+                rest = &rest[skip_to(rest, "?>")..];
+                continue;
+            } else if rest.starts_with("<!") {
+                rest = &rest[skip_to(rest, ">")..];
+                continue;
+            }
+
+            let end = match find_tag_end(rest) {
+                Some(end) => end,
+                None => break,
+            };
+            let tag = &rest[1..end];
+            rest = &rest[(end + 1)..];
+
+            // End tag.
+            if tag.starts_with('/') {
+                open_elements.pop();
+                if property
+                    .as_ref()
+                    .map(|p| p.depth == open_elements.len())
+                    .unwrap_or(false)
+                {
+                    let p = property.take().unwrap();
+                    if let Some(value) = p.value {
+                        raw_properties.push((p.name, value));
+                    }
+                }
+                continue;
+            }
+
+            // Start tag.
+            let is_empty = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+            let name = &tag[..name_end];
+            let attributes = parse_attributes(&tag[name_end..]);
+
+            for (attr_name, value) in attributes.iter() {
+                if let Some(prefix) = attr_name.strip_prefix("xmlns:") {
+                    namespaces.push((prefix.to_string(), value.clone()));
+                }
+            }
+
+            let parent_is_rdf = open_elements
+                .last()
+                .map(|e| e == "rdf:RDF")
+                .unwrap_or(false);
+            let in_top_description = open_elements.len() >= 2
+                && open_elements[open_elements.len() - 1] == "rdf:Description"
+                && open_elements[open_elements.len() - 2] == "rdf:RDF";
+            if name == "rdf:Description" && parent_is_rdf {
+                for (attr_name, value) in attributes {
+                    if !(attr_name.starts_with("xmlns:")
+                        || attr_name.starts_with("rdf:")
+                        || attr_name.starts_with("xml:"))
+                    {
+                        raw_properties.push((attr_name.to_string(), value));
+                    }
+                }
+            } else if in_top_description && property.is_none() && !is_empty {
+                property = Some(Property {
+                    name: name.to_string(),
+                    value: None,
+                    depth: open_elements.len(),
+                });
+            }
+
+            if !is_empty {
+                open_elements.push(name.to_string());
+            }
+        }
+
+        // Resolve the namespace prefixes.
+        let properties = raw_properties
+            .into_iter()
+            .filter_map(|(qualified_name, value)| {
+                let (prefix, name) = qualified_name.split_once(':')?;
+                let namespace = namespaces
+                    .iter()
+                    .rev()
+                    .find(|(p, _)| p == prefix)?
+                    .1
+                    .clone();
+                Some((namespace, name.to_string(), value))
+            })
+            .collect();
+
+        Xmp { properties }
+    }
+
+    /// Returns the value of a property, given its namespace URI and
+    /// name.
+    pub fn get(&self, namespace: &str, name: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|(ns, n, _)| ns == namespace && n == name)
+            .map(|(_, _, value)| value.as_str())
+    }
+}
+
+/// Finds the index of the `>` that ends the tag at the start of `text`,
+/// skipping over any in quoted attribute values.
+fn find_tag_end(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_attributes(mut text: &str) -> Vec<(&str, String)> {
+    let mut attributes = Vec::new();
+    loop {
+        text = text.trim_start();
+        let eq = match text.find('=') {
+            Some(eq) => eq,
+            None => break,
+        };
+        let name = text[..eq].trim();
+        text = text[(eq + 1)..].trim_start();
+
+        let quote = match text.chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => break,
+        };
+        let end = match text[1..].find(quote) {
+            Some(end) => end + 1,
+            None => break,
+        };
+        attributes.push((name, unescape(&text[1..end])));
+        text = &text[(end + 1)..];
+    }
+    attributes
+}
+
+/// Replaces XML character and entity references.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        unescaped.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let c = rest.find(';').and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                entity => {
+                    if let Some(hex) = entity.strip_prefix("#x") {
+                        u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+                    } else if let Some(dec) = entity.strip_prefix('#') {
+                        dec.parse().ok().and_then(char::from_u32)
+                    } else {
+                        None
+                    }
+                }
+            };
+            c.map(|c| (c, end))
+        });
+        match c {
+            Some((c, end)) => {
+                unescaped.push(c);
+                rest = &rest[(end + 1)..];
+            }
+            None => {
+                unescaped.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+fn fill<T>(field: &mut Option<T>, value: Option<T>) {
+    if field.is_none() {
+        *field = value;
+    }
+}
+
+/// Parses numbers like "28/10" or "2.8".
+fn parse_rational(text: &str) -> Option<f32> {
+    match text.split_once('/') {
+        Some((num, denom)) => {
+            let num: f64 = num.trim().parse().ok()?;
+            let denom: f64 = denom.trim().parse().ok()?;
+            if denom == 0.0 {
+                None
+            } else {
+                Some((num / denom) as f32)
+            }
+        }
+        None => text.trim().parse().ok(),
+    }
+}
+
+/// Parses ratios like "1/250", or decimal numbers.
+pub fn parse_ratio(text: &str) -> Option<(u32, u32)> {
+    let ratio = match text.split_once('/') {
+        Some((num, denom)) => (num.trim().parse().ok()?, denom.trim().parse().ok()?),
+        None => {
+            let n: f64 = text.trim().parse().ok()?;
+            if !(n > 0.0 && n.is_finite()) {
+                return None;
+            }
+            to_ratio(n)
+        }
+    };
+    if ratio.0 == 0 || ratio.1 == 0 {
+        None
+    } else {
+        Some(ratio)
+    }
+}
+
+/// Approximates a positive number as a ratio, preferring the forms
+/// that exposure times and f-numbers are usually written in, e.g.
+/// "1/250" and "28/10".
+fn to_ratio(n: f64) -> (u32, u32) {
+    if n > 0.0 && n < 1.0 && ((1.0 / n) - (1.0 / n).round()).abs() < 0.02 * (1.0 / n) {
+        (1, (1.0 / n).round() as u32)
+    } else if n < 1.0 {
+        let num = (n * 10000.0).round().max(1.0) as u32;
+        let mut gcd = (num, 10000);
+        while gcd.1 != 0 {
+            gcd = (gcd.1, gcd.0 % gcd.1);
+        }
+        (num / gcd.0, 10000 / gcd.0)
+    } else {
+        ((n * 10.0).round().min(u32::MAX as f64) as u32, 10)
+    }
+}
+
+/// Parses the digits after a decimal point as nanoseconds, ignoring any
+/// beyond nanosecond precision.
+fn parse_nanoseconds(digits: &str) -> Option<u32> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits = &digits[..digits.len().min(9)];
+    Some(format!("{:0<9}", digits).parse().unwrap())
+}
+
+//-------------------------------------------------------------
+// Exif.
+
+fn exif_string(exif: &exif::Exif, tag: exif::Tag) -> Option<String> {
+    match exif.get_field(tag, exif::In::PRIMARY).map(|f| &f.value) {
+        Some(exif::Value::Ascii(strings)) => strings
+            .first()
+            .map(|s| {
+                String::from_utf8_lossy(s)
+                    .trim_end_matches('\0')
+                    .trim()
+                    .to_string()
+            })
+            .filter(|s| !s.is_empty()),
+        _ => None,
+    }
+}
+
+fn exif_uint(exif: &exif::Exif, tag: exif::Tag) -> Option<u32> {
+    exif.get_field(tag, exif::In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+}
+
+fn exif_ratio(exif: &exif::Exif, tag: exif::Tag) -> Option<(u32, u32)> {
+    match exif.get_field(tag, exif::In::PRIMARY).map(|f| &f.value) {
+        Some(exif::Value::Rational(n)) if !n.is_empty() && n[0].num != 0 && n[0].denom != 0 => {
+            Some((n[0].num, n[0].denom))
+        }
+        _ => None,
+    }
+}
+
+fn exif_rational(exif: &exif::Exif, tag: exif::Tag) -> Option<f32> {
+    match exif.get_field(tag, exif::In::PRIMARY).map(|f| &f.value) {
+        Some(exif::Value::Rational(n)) if !n.is_empty() && n[0].denom != 0 => {
+            Some(n[0].to_f64() as f32)
+        }
+        Some(exif::Value::SRational(n)) if !n.is_empty() && n[0].denom != 0 => {
+            Some(n[0].to_f64() as f32)
+        }
+        _ => None,
+    }
+}
+
+fn exif_date_time(
+    exif: &exif::Exif,
+    tag: exif::Tag,
+    subsec_tag: exif::Tag,
+    offset_tag: exif::Tag,
+) -> Option<DateTime> {
+    let ascii = |tag| match exif.get_field(tag, exif::In::PRIMARY).map(|f| &f.value) {
+        Some(exif::Value::Ascii(strings)) => strings.first().cloned(),
+        _ => None,
+    };
+
+    let mut date_time = exif::DateTime::from_ascii(&ascii(tag)?).ok()?;
+    if let Some(subsec) = ascii(subsec_tag) {
+        let _ = date_time.parse_subsec(&subsec);
+    }
+    if let Some(offset) = ascii(offset_tag) {
+        let _ = date_time.parse_offset(&offset);
+    }
+
+    Some(DateTime {
+        year: date_time.year,
+        month: date_time.month,
+        day: date_time.day,
+        hour: date_time.hour,
+        minute: date_time.minute,
+        second: date_time.second,
+        nanosecond: date_time.nanosecond.unwrap_or(0),
+        offset: date_time.offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xmp(description: &str) -> Xmp {
+        Xmp::parse(
+            format!(
+                r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    {}
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#,
+                description
+            )
+            .as_bytes(),
+        )
+    }
+
+    #[test]
+    fn xmp_attribute_form() {
+        let xmp = xmp(r#"<rdf:Description rdf:about=""
+            xmlns:exif="http://ns.adobe.com/exif/1.0/"
+            xmlns:tiff="http://ns.adobe.com/tiff/1.0/"
+            exif:ExposureTime="1/250"
+            tiff:Make='Ampersand &amp; &quot;Sons&quot;'
+            tiff:Model="A &gt; B"/>"#);
+        assert_eq!(xmp.get(NS_EXIF, "ExposureTime"), Some("1/250"));
+        assert_eq!(xmp.get(NS_TIFF, "Make"), Some("Ampersand & \"Sons\""));
+        assert_eq!(xmp.get(NS_TIFF, "Model"), Some("A > B"));
+        assert_eq!(xmp.get(NS_TIFF, "about"), None);
+        assert_eq!(xmp.get(NS_EXIF, "FNumber"), None);
+    }
+
+    #[test]
+    fn xmp_element_form() {
+        let xmp = xmp(r#"<rdf:Description rdf:about=""
+            xmlns:ex="http://ns.adobe.com/exif/1.0/"
+            xmlns:aux="http://ns.adobe.com/exif/1.0/aux/">
+          <ex:FNumber>28/10</ex:FNumber>
+          <!-- <ex:ExposureTime>1/2</ex:ExposureTime> -->
+          <aux:Lens><![CDATA[24-70mm <f/2.8>]]></aux:Lens>
+          <ex:Flash rdf:parseType="Resource">
+            <ex:Fired>True</ex:Fired>
+          </ex:Flash>
+          <ex:UserComment>&#x41;&#66;&unknown;</ex:UserComment>
+        </rdf:Description>"#);
+
+        // Prefixes are resolved to namespaces, whatever they're called.
+        assert_eq!(xmp.get(NS_EXIF, "FNumber"), Some("28/10"));
+        assert_eq!(xmp.get(NS_EXIF, "ExposureTime"), None);
+        assert_eq!(xmp.get(NS_AUX, "Lens"), Some("24-70mm <f/2.8>"));
+        assert_eq!(xmp.get(NS_EXIF, "UserComment"), Some("AB&unknown;"));
+
+        // Struct fields aren't top-level properties.
+        assert_eq!(xmp.get(NS_EXIF, "Fired"), None);
+        assert_eq!(xmp.get(NS_EXIF, "Flash"), None);
+    }
+
+    #[test]
+    fn xmp_arrays() {
+        let xmp = xmp(r#"<rdf:Description rdf:about=""
+            xmlns:exif="http://ns.adobe.com/exif/1.0/"
+            xmlns:dc="http://purl.org/dc/elements/1.1/">
+          <exif:ISOSpeedRatings>
+            <rdf:Seq>
+              <rdf:li>400</rdf:li>
+              <rdf:li>800</rdf:li>
+            </rdf:Seq>
+          </exif:ISOSpeedRatings>
+          <dc:title>
+            <rdf:Alt>
+              <rdf:li xml:lang="x-default">Sunset &lt;3</rdf:li>
+              <rdf:li xml:lang="de">Sonnenuntergang</rdf:li>
+            </rdf:Alt>
+          </dc:title>
+        </rdf:Description>"#);
+        assert_eq!(xmp.get(NS_EXIF, "ISOSpeedRatings"), Some("400"));
+        assert_eq!(
+            xmp.get("http://purl.org/dc/elements/1.1/", "title"),
+            Some("Sunset <3")
+        );
+    }
+
+    #[test]
+    fn xmp_malformed() {
+        for text in [
+            "",
+            "<",
+            "<x:xmpmeta><rdf:RDF><rdf:Description exif:A=\"1",
+            "</rdf:RDF></rdf:RDF><rdf:Description a:b='c'/>",
+            "<![CDATA[",
+            "<!-- <rdf:RDF>",
+            "&amp;&#xffffffff;&#99999999999;",
+        ] {
+            assert!(Xmp::parse(text.as_bytes()).properties.is_empty());
+        }
+
+        // Undeclared prefixes.
+        let xmp = xmp(r#"<rdf:Description exif:ExposureTime="1/250"/>"#);
+        assert!(xmp.properties.is_empty());
+    }
+
+    #[test]
+    fn fill_from_xmp() {
+        let xmp = xmp(r#"<rdf:Description rdf:about=""
+            xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/"
+            xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+            xmlns:tiff="http://ns.adobe.com/tiff/1.0/"
+            xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+            photoshop:DateCreated="2021-07-04T18:30:15.25+02:00"
+            xmp:CreateDate="1999-01-01"
+            tiff:Make="Canon"
+            tiff:Orientation="9"
+            crs:WhiteBalance="Daylight"/>"#);
+
+        let mut metadata = ImageMetadata {
+            camera_make: Some("Nikon".into()),
+            ..ImageMetadata::default()
+        };
+        metadata.fill_from_xmp(&xmp);
+        assert_eq!(metadata.camera_make.as_deref(), Some("Nikon"));
+        assert_eq!(metadata.orientation, None);
+        assert_eq!(
+            metadata.white_balance,
+            Some(WhiteBalance::Preset("daylight".into()))
+        );
+        assert_eq!(
+            metadata.date_time.unwrap().to_string(),
+            "2021-07-04 18:30:15.25 +02:00"
+        );
+    }
+
+    #[test]
+    fn iso8601() {
+        let date = |text| DateTime::from_iso8601(text);
+
+        assert_eq!(
+            date("2021-07-04T18:30:15.25+02:00"),
+            Some(DateTime {
+                year: 2021,
+                month: 7,
+                day: 4,
+                hour: 18,
+                minute: 30,
+                second: 15,
+                nanosecond: 250_000_000,
+                offset: Some(120),
+            })
+        );
+        assert_eq!(
+            date(" 2021-07-04 ").unwrap().to_string(),
+            "2021-07-04 00:00:00"
+        );
+        assert_eq!(date("2021").unwrap().to_string(), "2021-01-01 00:00:00");
+        assert_eq!(
+            date("2021-07-04T18:30Z").unwrap().to_string(),
+            "2021-07-04 18:30:00 +00:00"
+        );
+        assert_eq!(date("2021-07-04T18:30-05:30").unwrap().offset, Some(-330));
+        assert_eq!(date("2021-07-04T18:30-05").unwrap().offset, Some(-300));
+        assert_eq!(
+            date("2021-07-04T18:30:15.1234567891").unwrap().nanosecond,
+            123_456_789
+        );
+    }
+
+    #[test]
+    fn iso8601_malformed() {
+        for text in [
+            "",
+            "T",
+            "July 4th",
+            "2021-13-01",
+            "2021-00-01",
+            "2021-07-32",
+            "99999-01-01",
+            "2021-07-04T24:00",
+            "2021-07-04T18:60",
+            "2021-07-04T18:30:61",
+            "2021-07-04T18:30:15.2x",
+            "2021-07-04T18:30:15.-2",
+            "2021-07-04T18:30+999:00",
+            "2021-07-04T18:30+24:00",
+            "2021-07-04T18:30+05:60",
+            "2021-07-04T18:30+",
+            "2021-07-04T18:30Zulu",
+            "2021-07-04T18:30+-1",
+        ] {
+            assert_eq!(DateTime::from_iso8601(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn ratios() {
+        assert_eq!(to_ratio(1.0 / 250.0), (1, 250));
+        assert_eq!(to_ratio(1.0 / 3.0), (1, 3));
+        assert_eq!(to_ratio(0.5), (1, 2));
+        assert_eq!(to_ratio(0.00001), (1, 100000));
+        assert_eq!(to_ratio(0.3), (3, 10));
+        assert_eq!(to_ratio(0.75), (3, 4));
+        assert_eq!(to_ratio(0.00004), (1, 25000));
+        assert_eq!(to_ratio(1.0), (10, 10));
+        assert_eq!(to_ratio(2.8), (28, 10));
+        assert_eq!(to_ratio(30.0), (300, 10));
+        assert_eq!(to_ratio(1.0e12), (u32::MAX, 10));
+
+        assert_eq!(parse_ratio("1/250"), Some((1, 250)));
+        assert_eq!(parse_ratio(" 28 / 10 "), Some((28, 10)));
+        assert_eq!(parse_ratio("2.8"), Some((28, 10)));
+        assert_eq!(parse_ratio("0.004"), Some((1, 250)));
+        assert_eq!(parse_ratio("30"), Some((300, 10)));
+        for text in [
+            "", "0", "0/1", "1/0", "-1", "-1/250", "inf", "NaN", "1/2/3", "f/2.8",
+        ] {
+            assert_eq!(parse_ratio(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn exposure_routes() {
+        let tags = ExposureTags {
+            exposure_time: Some((1, 100)),
+            fstop: Some((4, 1)),
+            iso: Some(200),
+            shutter_speed_value: Some(10.0),
+            aperture_value: Some(2.0),
+            exposure_bias: Some(-2.0),
+        };
+
+        // The exposure time already includes the bias.
+        let estimate = tags.estimate().unwrap();
+        assert_eq!(estimate.route, ExposureRoute::ExposureTime);
+        assert_eq!(estimate.confidence, Confidence::High);
+        assert!((estimate.exposure - 0.01 / 16.0 * 200.0).abs() < 1.0e-6);
+
+        // APEX values fill in what's missing.
+        let estimate = ExposureTags {
+            exposure_time: None,
+            ..tags
+        }
+        .estimate()
+        .unwrap();
+        assert_eq!(estimate.route, ExposureRoute::Apex);
+        assert_eq!(estimate.confidence, Confidence::Medium);
+        assert_eq!(estimate.exposure_time, Some((1, 1024)));
+
+        let estimate = ExposureTags {
+            exposure_time: None,
+            shutter_speed_value: None,
+            ..tags
+        }
+        .estimate()
+        .unwrap();
+        assert_eq!(estimate.route, ExposureRoute::ExposureBias);
+        assert_eq!(estimate.confidence, Confidence::Low);
+        assert!((estimate.exposure - 0.25 / 16.0 * 200.0).abs() < 1.0e-6);
+
+        assert!(ExposureTags {
+            exposure_bias: None,
+            ..ExposureTags::default()
+        }
+        .estimate()
+        .is_none());
+    }
+}
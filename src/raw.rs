@@ -0,0 +1,183 @@
+//! Camera raw loading.
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use image_fmt::{Image, ImageBuf, ReadError};
+
+use crate::demosaic::{demosaic, Demosaic};
+
+/// File extensions (lower case) of the camera raw formats we attempt to
+/// load with `load_raw()`.
+pub const RAW_EXTENSIONS: &[&str] = &[
+    "3fr", "arw", "cr2", "crw", "dcr", "dng", "erf", "iiq", "kdc", "mef", "mos", "mrw", "nef",
+    "nrw", "orf", "pef", "raf", "rw2", "sr2", "srf", "srw",
+];
+
+/// Returns whether the path has a camera raw file extension.
+pub fn is_raw_path(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => RAW_EXTENSIONS.contains(&ext.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// The camera raw file extensions in both lower and upper case, for use
+/// in file dialog filters.
+pub fn raw_file_extensions() -> Vec<String> {
+    RAW_EXTENSIONS
+        .iter()
+        .map(|e| e.to_string())
+        .chain(RAW_EXTENSIONS.iter().map(|e| e.to_uppercase()))
+        .collect()
+}
+
+/// A row-major 3x3 color matrix.
+pub type Matrix3 = [[f32; 3]; 3];
+
+/// Loads and develops a camera raw file.
+///
+/// The sensor data is normalized with the file's black and white levels,
+/// white balanced with the as-shot white balance, and demosaiced with
+/// the given method.  The result is linear `RgbF32` data in the camera's
+/// (white balanced) color space, with clipped highlights at exactly 1.0.
+///
+/// Also returns the matrix that converts that data to CIE XYZ, if the
+/// camera's color matrix is known.  White (1.0, 1.0, 1.0) maps to Y = 1.
+pub fn load_raw(
+    path: &Path,
+    demosaic_method: Demosaic,
+) -> Result<(Image, Option<Matrix3>), ReadError> {
+    // Open the file ourselves, so that IO errors are reported as such.
+    let mut file = BufReader::new(File::open(path)?);
+    let raw = rawloader::decode(&mut file).map_err(|e| ReadError::External {
+        detail: format!("couldn't decode the camera raw file: {}", e),
+    })?;
+
+    // Crop off the masked/garbage areas at the sensor edges.
+    let (top, right, bottom, left) = (raw.crops[0], raw.crops[1], raw.crops[2], raw.crops[3]);
+    let width = raw.width.saturating_sub(left + right);
+    let height = raw.height.saturating_sub(top + bottom);
+    if width == 0 || height == 0 {
+        return Err(ReadError::UnsupportedFeature);
+    }
+
+    // White balance multipliers, normalized so the smallest is 1.0.
+    // That way clipped sensor values stay clipped after balancing.
+    let wb = {
+        let c = raw.wb_coeffs;
+        if c[..3].iter().all(|n| n.is_finite() && *n > 0.0) {
+            let min = c[0].min(c[1]).min(c[2]);
+            [c[0] / min, c[1] / min, c[2] / min]
+        } else {
+            [1.0; 3]
+        }
+    };
+
+    // Per-channel black level and normalization factor.
+    let levels: Vec<(f32, f32)> = (0..3)
+        .map(|c| {
+            let black = raw.blacklevels[c] as f32;
+            let white = raw.whitelevels[c] as f32;
+            (black, 1.0 / (white - black).max(1.0))
+        })
+        .collect();
+
+    let sample = |i: usize| -> f32 {
+        match raw.data {
+            rawloader::RawImageData::Integer(ref data) => data[i] as f32,
+            rawloader::RawImageData::Float(ref data) => data[i],
+        }
+    };
+    let develop = |value: f32, c: usize| (value - levels[c].0) * levels[c].1 * wb[c];
+
+    let mut rgb = match raw.cpp {
+        // Mosaiced sensor data.
+        1 => {
+            // Only 2x2 RGB Bayer patterns are supported.  That excludes
+            // e.g. Fujifilm's X-Trans sensors and CYGM sensors.
+            let cfa = {
+                let mut cfa = [[0usize; 2]; 2];
+                for (y, row) in cfa.iter_mut().enumerate() {
+                    for (x, c) in row.iter_mut().enumerate() {
+                        *c = raw.cfa.color_at(top + y, left + x);
+                    }
+                }
+                cfa
+            };
+            let is_bayer = {
+                let mut colors = [cfa[0][0], cfa[0][1], cfa[1][0], cfa[1][1]];
+                colors.sort_unstable();
+                colors == [0, 1, 1, 2]
+            };
+            let is_periodic = (0..6)
+                .all(|y| (0..6).all(|x| raw.cfa.color_at(top + y, left + x) == cfa[y % 2][x % 2]));
+            if !is_bayer || !is_periodic {
+                return Err(ReadError::UnsupportedFeature);
+            }
+
+            let mut mosaic = vec![0.0f32; width * height];
+            for y in 0..height {
+                for x in 0..width {
+                    let i = (y + top) * raw.width + x + left;
+                    mosaic[y * width + x] = develop(sample(i), cfa[y % 2][x % 2]);
+                }
+            }
+
+            demosaic(&mosaic, width, height, cfa, demosaic_method)
+        }
+
+        // Already demosaiced, e.g. linear DNG.
+        3 => {
+            let mut rgb = vec![0.0f32; width * height * 3];
+            for y in 0..height {
+                for x in 0..width {
+                    let i = ((y + top) * raw.width + x + left) * 3;
+                    for c in 0..3 {
+                        rgb[(y * width + x) * 3 + c] = develop(sample(i + c), c);
+                    }
+                }
+            }
+            rgb
+        }
+
+        _ => return Err(ReadError::UnsupportedFeature),
+    };
+
+    for n in rgb.iter_mut() {
+        *n = n.clamp(0.0, 1.0);
+    }
+
+    // The camera's matrix applies to un-balanced camera RGB, so fold the
+    // inverse white balance into it.
+    let camera_to_xyz = if raw.xyz_to_cam.iter().flatten().all(|n| *n == 0.0) {
+        None
+    } else {
+        let cam_to_xyz = raw.cam_to_xyz();
+        let mut mat = [[0.0f32; 3]; 3];
+        for row in 0..3 {
+            for col in 0..3 {
+                mat[row][col] = cam_to_xyz[row][col] / wb[col];
+            }
+        }
+
+        let white_y = mat[1][0] + mat[1][1] + mat[1][2];
+        if white_y > 0.0 {
+            for n in mat.iter_mut().flatten() {
+                *n /= white_y;
+            }
+            Some(mat)
+        } else {
+            None
+        }
+    };
+
+    Ok((
+        Image {
+            dimensions: (width, height),
+            data: ImageBuf::RgbF32(rgb),
+            // Camera RGB, which `camera_to_xyz` describes instead.
+            color: None,
+        },
+        camera_to_xyz,
+    ))
+}
@@ -0,0 +1,280 @@
+use std::io::{BufRead, Seek, Write};
+
+use exr::{image::RgbChannels, prelude::*};
+
+use crate::HdrImage;
+
+/// The storage type of the channels in a written EXR file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExrPrecision {
+    /// 16-bit float channels.
+    Half,
+    /// 32-bit float channels.
+    Float,
+}
+
+/// The compression scheme of a written EXR file.
+///
+/// All of these are lossless.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExrCompression {
+    None,
+    /// Zlib compression, in blocks of 16 scanlines.
+    Zip,
+    /// Wavelet compression.  Usually the best choice for noisy images.
+    Piz,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ExrOptions {
+    pub precision: ExrPrecision,
+    pub compression: ExrCompression,
+
+    /// The xy chromaticity coordinates of the RGB primaries and white
+    /// point, as `[red, green, blue, white]`.
+    pub chromaticities: Option<[(f32, f32); 4]>,
+}
+
+impl Default for ExrOptions {
+    fn default() -> ExrOptions {
+        ExrOptions {
+            precision: ExrPrecision::Half,
+            compression: ExrCompression::Piz,
+            chromaticities: None,
+        }
+    }
+}
+
+/// Writes an RGB EXR file.
+///
+/// Like `write_hdr()`, the pixels are multiplied by `exposure` before
+/// being written.  The multiplier is also recorded in the header as a
+/// custom `exposure` attribute, so it can be undone later if needed.
+pub fn write_exr<W: Write + Seek>(
+    out: &mut W,
+    image: &[[f32; 3]],
+    width: usize,
+    height: usize,
+    exposure: f32,
+    options: &ExrOptions,
+) -> std::io::Result<()> {
+    assert_eq!(image.len(), width * height);
+
+    let mut image_attributes = ImageAttributes::with_size((width, height));
+    image_attributes.chromaticities =
+        options
+            .chromaticities
+            .map(|[r, g, b, w]| attribute::Chromaticities {
+                red: Vec2(r.0, r.1),
+                green: Vec2(g.0, g.1),
+                blue: Vec2(b.0, b.1),
+                white: Vec2(w.0, w.1),
+            });
+
+    let mut layer_attributes = LayerAttributes::default();
+    layer_attributes
+        .other
+        .insert(Text::from("exposure"), AttributeValue::F32(exposure));
+
+    let encoding = Encoding {
+        compression: match options.compression {
+            ExrCompression::None => Compression::Uncompressed,
+            ExrCompression::Zip => Compression::ZIP16,
+            ExrCompression::Piz => Compression::PIZ,
+        },
+        blocks: Blocks::ScanLines,
+        line_order: LineOrder::Increasing,
+    };
+
+    let get_pixel = |position: Vec2<usize>| -> [f32; 3] {
+        let pixel = image[position.y() * width + position.x()];
+        [
+            pixel[0] * exposure,
+            pixel[1] * exposure,
+            pixel[2] * exposure,
+        ]
+    };
+
+    let result = match options.precision {
+        ExrPrecision::Half => {
+            let channels = SpecificChannels::rgb(|position: Vec2<usize>| {
+                let [r, g, b] = get_pixel(position);
+                (f16::from_f32(r), f16::from_f32(g), f16::from_f32(b))
+            });
+            let layer = Layer::new((width, height), layer_attributes, encoding, channels);
+            Image::new(image_attributes, layer)
+                .write()
+                .to_buffered(&mut *out)
+        }
+
+        ExrPrecision::Float => {
+            let channels = SpecificChannels::rgb(|position: Vec2<usize>| {
+                let [r, g, b] = get_pixel(position);
+                (r, g, b)
+            });
+            let layer = Layer::new((width, height), layer_attributes, encoding, channels);
+            Image::new(image_attributes, layer)
+                .write()
+                .to_buffered(&mut *out)
+        }
+    };
+
+    match result {
+        Ok(()) => {}
+        Err(exr::error::Error::Io(e)) => return Err(e),
+        Err(e) => return Err(std::io::Error::other(e.to_string())),
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Reads the first RGB layer of an EXR file, e.g. one written by
+/// `write_exr()`.
+///
+/// Like `read_hdr()`, the pixel values are as stored, and the file's
+/// `exposure` attribute (or 1.0 if there isn't one) is returned along
+/// with them so it can be undone.
+pub fn read_exr<R: BufRead + Seek>(input: &mut R) -> std::io::Result<HdrImage> {
+    let result = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgb_channels(
+            |resolution: Vec2<usize>, _: &RgbChannels| {
+                (
+                    resolution.width(),
+                    vec![[0.0f32; 3]; resolution.width() * resolution.height()],
+                )
+            },
+            |(width, pixels): &mut (usize, Vec<[f32; 3]>),
+             position: Vec2<usize>,
+             (r, g, b): (f32, f32, f32)| {
+                pixels[position.y() * *width + position.x()] = [r, g, b];
+            },
+        )
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(input);
+
+    let image = match result {
+        Ok(image) => image,
+        Err(exr::error::Error::Io(e)) => return Err(e),
+        Err(e) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                e.to_string(),
+            ))
+        }
+    };
+
+    let layer = image.layer_data;
+    let exposure = match layer.attributes.other.get(&Text::from("exposure")) {
+        Some(AttributeValue::F32(exposure)) => *exposure,
+        _ => 1.0,
+    };
+    let primaries = image.attributes.chromaticities.map(|chroma| {
+        [
+            (chroma.red.x(), chroma.red.y()),
+            (chroma.green.x(), chroma.green.y()),
+            (chroma.blue.x(), chroma.blue.y()),
+            (chroma.white.x(), chroma.white.y()),
+        ]
+    });
+
+    Ok(HdrImage {
+        width: layer.size.width(),
+        height: layer.size.height(),
+        pixels: layer.channel_data.pixels.1,
+        exposure,
+        primaries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image() -> (Vec<[f32; 3]>, usize, usize) {
+        let (width, height) = (5, 3);
+        let pixels = (0..(width * height))
+            .map(|i| {
+                let n = i as f32;
+                [n * 0.25, n * 8.0 + 0.5, 1000.0 / (n + 1.0)]
+            })
+            .collect();
+        (pixels, width, height)
+    }
+
+    #[test]
+    fn exposure_round_trip() {
+        let (pixels, width, height) = test_image();
+        let options = ExrOptions {
+            precision: ExrPrecision::Float,
+            compression: ExrCompression::Zip,
+            chromaticities: Some([(0.64, 0.33), (0.3, 0.6), (0.15, 0.06), (0.3127, 0.329)]),
+        };
+
+        let mut data = std::io::Cursor::new(Vec::new());
+        write_exr(&mut data, &pixels, width, height, 4.0, &options).unwrap();
+        data.set_position(0);
+        let image = read_exr(&mut data).unwrap();
+
+        assert_eq!((image.width, image.height), (width, height));
+        assert_eq!(image.exposure, 4.0);
+        assert_eq!(image.primaries, options.chromaticities);
+        for (read, orig) in image.pixels.iter().zip(pixels.iter()) {
+            for chan in 0..3 {
+                assert_eq!(read[chan] / image.exposure, orig[chan]);
+            }
+        }
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let (pixels, width, height) = test_image();
+        for precision in [ExrPrecision::Half, ExrPrecision::Float] {
+            for compression in [
+                ExrCompression::None,
+                ExrCompression::Zip,
+                ExrCompression::Piz,
+            ] {
+                let options = ExrOptions {
+                    precision,
+                    compression,
+                    chromaticities: None,
+                };
+                let mut data = std::io::Cursor::new(Vec::new());
+                write_exr(&mut data, &pixels, width, height, 1.0, &options).unwrap();
+                data.set_position(0);
+                let image = read_exr(&mut data).unwrap();
+
+                assert_eq!((image.width, image.height), (width, height));
+                assert_eq!(image.exposure, 1.0);
+                assert_eq!(image.primaries, None);
+                for (read, orig) in image.pixels.iter().zip(pixels.iter()) {
+                    for chan in 0..3 {
+                        // Half floats have 11 bits of precision.
+                        let tolerance = match precision {
+                            ExrPrecision::Half => orig[chan] / 2048.0,
+                            ExrPrecision::Float => 0.0,
+                        };
+                        assert!(
+                            (read[chan] - orig[chan]).abs() <= tolerance,
+                            "{:?} {:?}: {:?} vs {:?}",
+                            precision,
+                            compression,
+                            read,
+                            orig
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bad_file() {
+        let mut data = std::io::Cursor::new(b"#?RADIANCE\n\n-Y 1 +X 1\n".to_vec());
+        assert!(read_exr(&mut data).is_err());
+    }
+}
@@ -0,0 +1,677 @@
+use std::io::{BufRead, Error, ErrorKind, Read, Write};
+
+/// The largest image, in pixels, that `read_hdr()` will allocate for.
+/// Anything larger is almost certainly a corrupt or malicious header.
+const MAX_PIXELS: usize = 1 << 28;
+
+/// A decoded Radiance .hdr or EXR image.
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+
+    /// Pixels in top-to-bottom, left-to-right order, regardless of the
+    /// orientation they were stored in.
+    pub pixels: Vec<[f32; 3]>,
+
+    /// The combined `EXPOSURE` of a .hdr file, or the `exposure`
+    /// attribute of an EXR file, or 1.0 if unspecified.
+    ///
+    /// Pixel values are as stored: to get the original values, divide
+    /// them by this.
+    pub exposure: f32,
+
+    /// The xy chromaticity coordinates of the RGB primaries and white
+    /// point from `PRIMARIES` or the EXR chromaticities, as `[red, green,
+    /// blue, white]`.
+    pub primaries: Option<[(f32, f32); 4]>,
+}
+
+/// Reads a Radiance .hdr file.
+///
+/// Supports flat, old-style RLE, and new-style (per-component) RLE
+/// scanlines, and all eight orientations.  Only the RGBE pixel format is
+/// supported, not XYZE.
+pub fn read_hdr<R: BufRead>(input: &mut R) -> std::io::Result<HdrImage> {
+    //---------
+    // Header.
+
+    let mut line = Vec::new();
+    input.read_until(b'\n', &mut line)?;
+    if !line.starts_with(b"#?") {
+        return Err(invalid_data("not a Radiance .hdr file"));
+    }
+
+    let mut exposure = 1.0f32;
+    let mut primaries = None;
+    loop {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return Err(invalid_data("unexpected end of header"));
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim();
+
+        if text.is_empty() {
+            break;
+        } else if let Some(format) = text.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("unsupported .hdr pixel format \"{}\"", format.trim()),
+                ));
+            }
+        } else if let Some(value) = text.strip_prefix("EXPOSURE=") {
+            // Multiple exposure lines are cumulative.
+            exposure *= value
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| invalid_data("invalid EXPOSURE"))?;
+        } else if let Some(values) = text.strip_prefix("PRIMARIES=") {
+            let values: Vec<f32> = values
+                .split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid_data("invalid PRIMARIES"))?;
+            if values.len() != 8 {
+                return Err(invalid_data("invalid PRIMARIES"));
+            }
+            primaries = Some([
+                (values[0], values[1]),
+                (values[2], values[3]),
+                (values[4], values[5]),
+                (values[6], values[7]),
+            ]);
+        }
+        // Everything else (comments, commands, etc.) is ignored.
+    }
+
+    //-------------
+    // Resolution.
+
+    line.clear();
+    input.read_until(b'\n', &mut line)?;
+    let orientation = Orientation::parse(String::from_utf8_lossy(&line).trim())
+        .ok_or_else(|| invalid_data("invalid resolution string"))?;
+    let (width, height) = orientation.dimensions();
+    let (scanline_count, scanline_len) = (orientation.major.2, orientation.minor.2);
+    let pixel_count = match width.checked_mul(height) {
+        Some(n) if n <= MAX_PIXELS && width <= MAX_PIXELS && height <= MAX_PIXELS => n,
+        _ => return Err(invalid_data("image dimensions are too large")),
+    };
+
+    //---------
+    // Pixels.
+
+    let mut pixels = vec![[0.0f32; 3]; pixel_count];
+    let mut scanline = vec![[0u8; 4]; scanline_len];
+    for s in 0..scanline_count {
+        read_scanline(input, &mut scanline)?;
+        for (i, rgbe) in scanline.iter().enumerate() {
+            let (x, y) = orientation.position(s, i);
+            pixels[y * width + x] = decode_rgbe(*rgbe);
+        }
+    }
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+        exposure,
+        primaries,
+    })
+}
+
+//-------------------------------------------------------------
+
+/// The layout of the pixel data, from the resolution string.
+///
+/// Each axis is `(is_x, is_increasing, length)`.  The major axis is the
+/// one that selects the scanline.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Orientation {
+    major: (bool, bool, usize),
+    minor: (bool, bool, usize),
+}
+
+impl Orientation {
+    /// Parses e.g. `-Y 512 +X 768`.
+    fn parse(text: &str) -> Option<Orientation> {
+        let parts: Vec<&str> = text.split_whitespace().collect();
+        if parts.len() != 4 {
+            return None;
+        }
+
+        let parse_axis = |axis: &str, len: &str| -> Option<(bool, bool, usize)> {
+            let is_increasing = match axis.get(0..1)? {
+                "+" => true,
+                "-" => false,
+                _ => return None,
+            };
+            let is_x = match axis.get(1..)? {
+                "X" => true,
+                "Y" => false,
+                _ => return None,
+            };
+            Some((is_x, is_increasing, len.parse().ok()?))
+        };
+
+        let major = parse_axis(parts[0], parts[1])?;
+        let minor = parse_axis(parts[2], parts[3])?;
+        if major.0 == minor.0 {
+            return None;
+        }
+
+        Some(Orientation { major, minor })
+    }
+
+    fn dimensions(&self) -> (usize, usize) {
+        if self.major.0 {
+            (self.major.2, self.minor.2)
+        } else {
+            (self.minor.2, self.major.2)
+        }
+    }
+
+    /// Returns the (x, y) position of the `i`th pixel of scanline `s`,
+    /// with y going from top to bottom.
+    fn position(&self, s: usize, i: usize) -> (usize, usize) {
+        // Note that in Radiance, +Y is up.
+        let coord = |(is_x, is_increasing, len): (bool, bool, usize), n: usize| {
+            if is_increasing == is_x {
+                n
+            } else {
+                len - 1 - n
+            }
+        };
+        let a = coord(self.major, s);
+        let b = coord(self.minor, i);
+        if self.major.0 {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+}
+
+fn read_scanline<R: Read>(input: &mut R, scanline: &mut [[u8; 4]]) -> std::io::Result<()> {
+    let len = scanline.len();
+    if len == 0 {
+        return Ok(());
+    }
+
+    let mut first = [0u8; 4];
+    input.read_exact(&mut first)?;
+
+    // New-style RLE, where each component is run-length encoded
+    // separately.
+    if (8..=0x7fff).contains(&len)
+        && first[0] == 2
+        && first[1] == 2
+        && ((first[2] as usize) << 8 | first[3] as usize) == len
+    {
+        for component in 0..4 {
+            let mut i = 0;
+            while i < len {
+                let mut count = [0u8; 1];
+                input.read_exact(&mut count)?;
+                let count = count[0] as usize;
+
+                if count > 128 {
+                    // Run.
+                    let run_len = count - 128;
+                    if i + run_len > len {
+                        return Err(invalid_data("RLE run overflows scanline"));
+                    }
+                    let mut value = [0u8; 1];
+                    input.read_exact(&mut value)?;
+                    for pixel in &mut scanline[i..(i + run_len)] {
+                        pixel[component] = value[0];
+                    }
+                    i += run_len;
+                } else {
+                    // Literal values.
+                    if count == 0 || i + count > len {
+                        return Err(invalid_data("invalid RLE literal count"));
+                    }
+                    let mut values = [0u8; 128];
+                    input.read_exact(&mut values[..count])?;
+                    for (pixel, value) in scanline[i..(i + count)].iter_mut().zip(values.iter()) {
+                        pixel[component] = *value;
+                    }
+                    i += count;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // Flat pixels, possibly with old-style RLE, where a pixel of
+    // (1, 1, 1, n) repeats the previous pixel.  Consecutive repeat
+    // pixels encode successively higher bytes of the count.
+    let mut pixel = first;
+    let mut i = 0;
+    let mut shift = 0;
+    loop {
+        if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+            if i == 0 {
+                return Err(invalid_data("RLE repeat at start of scanline"));
+            }
+            let count = (pixel[3] as usize) << shift;
+            if i + count > len {
+                return Err(invalid_data("RLE run overflows scanline"));
+            }
+            let previous = scanline[i - 1];
+            for p in &mut scanline[i..(i + count)] {
+                *p = previous;
+            }
+            i += count;
+            shift += 8;
+        } else {
+            scanline[i] = pixel;
+            i += 1;
+            shift = 0;
+        }
+
+        if i >= len {
+            break;
+        }
+        input.read_exact(&mut pixel)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a scanline with new-style RLE, where each component is
+/// run-length encoded separately.
+///
+/// The scanline must be between 8 and 32767 pixels long.
+pub(crate) fn write_rle_scanline<W: Write>(
+    out: &mut W,
+    scanline: &[[u8; 4]],
+) -> std::io::Result<()> {
+    let len = scanline.len();
+    assert!((8..=0x7fff).contains(&len));
+
+    out.write_all(&[2, 2, (len >> 8) as u8, (len & 0xff) as u8])?;
+
+    let mut component = Vec::with_capacity(len);
+    for c in 0..4 {
+        component.clear();
+        component.extend(scanline.iter().map(|rgbe| rgbe[c]));
+        write_rle_component(out, &component)?;
+    }
+
+    Ok(())
+}
+
+/// Adaptive run-length encoding of a single component's bytes, in the
+/// same way as Radiance itself: runs shorter than `MIN_RUN` are written
+/// as literals, unless they directly precede a long run.
+fn write_rle_component<W: Write>(out: &mut W, data: &[u8]) -> std::io::Result<()> {
+    const MIN_RUN: usize = 4;
+    const MAX_RUN: usize = 127;
+    const MAX_LITERAL: usize = 128;
+
+    let len = data.len();
+    let mut i = 0;
+    while i < len {
+        // Find the next run that's long enough to be worth encoding.
+        let mut run_start = i;
+        let mut run_len = 0;
+        let mut prev_run_len = 0;
+        while run_len < MIN_RUN && run_start < len {
+            run_start += run_len;
+            prev_run_len = run_len;
+            run_len = 1;
+            while run_start + run_len < len
+                && run_len < MAX_RUN
+                && data[run_start] == data[run_start + run_len]
+            {
+                run_len += 1;
+            }
+        }
+
+        // If the bytes before the run are themselves a short run, write
+        // them as such.
+        if prev_run_len > 1 && prev_run_len == run_start - i {
+            out.write_all(&[(128 + prev_run_len) as u8, data[i]])?;
+            i = run_start;
+        }
+
+        // Literals up to the start of the run.
+        while i < run_start {
+            let count = (run_start - i).min(MAX_LITERAL);
+            out.write_all(&[count as u8])?;
+            out.write_all(&data[i..(i + count)])?;
+            i += count;
+        }
+
+        // The run itself.
+        if run_len >= MIN_RUN {
+            out.write_all(&[(128 + run_len) as u8, data[run_start]])?;
+            i += run_len;
+        }
+    }
+
+    Ok(())
+}
+
+/// Encodes a color as RGBE, the way Radiance does: the mantissas are
+/// relative to the largest component's `frexp()` exponent, and are
+/// truncated.
+#[inline(always)]
+pub(crate) fn encode_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let largest = rgb[0].max(rgb[1]).max(rgb[2]);
+    if largest.is_nan() || largest <= 1.0e-32 {
+        return [0; 4];
+    }
+
+    // `frexp()`: largest = mantissa * 2^exp, with mantissa in [0.5, 1).
+    let exp = (((largest.to_bits() >> 23) & 0xff) as i32 - 126).min(127);
+    let scale = 256.0 * 2.0f64.powi(-exp);
+    let mantissa = |n: f32| (n.max(0.0) as f64 * scale).min(255.0) as u8;
+
+    [
+        mantissa(rgb[0]),
+        mantissa(rgb[1]),
+        mantissa(rgb[2]),
+        (exp + 128) as u8,
+    ]
+}
+
+/// Decodes an RGBE color the way Radiance does, i.e.
+/// `ldexp(mantissa + 0.5, exponent - 136)`.
+#[inline(always)]
+pub(crate) fn decode_rgbe(rgbe: [u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        [0.0; 3]
+    } else {
+        let scale = 2.0f64.powi(rgbe[3] as i32 - 136);
+        let decode = |n: u8| ((n as f64 + 0.5) * scale) as f32;
+        [decode(rgbe[0]), decode(rgbe[1]), decode(rgbe[2])]
+    }
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] =
+        b"#?RADIANCE\n# Comment.\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=2.0\nEXPOSURE=0.25\n\
+PRIMARIES=0.640 0.330 0.300 0.600 0.150 0.060 0.3127 0.3290\n\n";
+
+    fn file(resolution: &str, data: &[u8]) -> Vec<u8> {
+        let mut file = HEADER.to_vec();
+        file.extend_from_slice(resolution.as_bytes());
+        file.push(b'\n');
+        file.extend_from_slice(data);
+        file
+    }
+
+    #[test]
+    fn header() {
+        let data = file("-Y 1 +X 1", &[128, 64, 32, 128]);
+        let image = read_hdr(&mut &data[..]).unwrap();
+
+        assert_eq!((image.width, image.height), (1, 1));
+        assert_eq!(image.exposure, 0.5);
+        assert_eq!(
+            image.primaries,
+            Some([(0.64, 0.33), (0.3, 0.6), (0.15, 0.06), (0.3127, 0.329)])
+        );
+        // Radiance's `colr_color()`: (mantissa + 0.5) * 2^(exponent - 136).
+        assert_eq!(
+            image.pixels,
+            vec![[128.5 / 256.0, 64.5 / 256.0, 32.5 / 256.0]]
+        );
+    }
+
+    #[test]
+    fn bad_header() {
+        assert!(read_hdr(&mut &b"P6\n1 1\n255\n"[..]).is_err());
+        assert!(read_hdr(&mut &b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n"[..]).is_err());
+        assert!(read_hdr(&mut &b"#?RADIANCE\n\n-Y 1 -Y 1\n"[..]).is_err());
+        assert!(read_hdr(&mut &b"#?RADIANCE\n\n-Y 2 +X 2\n\x80\x80\x80\x81"[..]).is_err());
+    }
+
+    #[test]
+    fn flat() {
+        let data = file(
+            "-Y 2 +X 2",
+            &[
+                128, 0, 0, 128, 0, 128, 0, 128, //
+                0, 0, 128, 128, 128, 128, 128, 128,
+            ],
+        );
+        let image = read_hdr(&mut &data[..]).unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![
+                decode_rgbe([128, 0, 0, 128]),
+                decode_rgbe([0, 128, 0, 128]),
+                decode_rgbe([0, 0, 128, 128]),
+                decode_rgbe([128, 128, 128, 128]),
+            ]
+        );
+    }
+
+    #[test]
+    fn old_style_rle() {
+        let data = file("-Y 1 +X 4", &[128, 0, 0, 128, 1, 1, 1, 2, 0, 128, 0, 128]);
+        let image = read_hdr(&mut &data[..]).unwrap();
+        let (red, green) = (decode_rgbe([128, 0, 0, 128]), decode_rgbe([0, 128, 0, 128]));
+        assert_eq!(image.pixels, vec![red, red, red, green]);
+    }
+
+    #[test]
+    fn new_style_rle() {
+        // 8 pixels wide: the red component alternates via literals, and
+        // the rest are runs.
+        let data = file(
+            "-Y 1 +X 8",
+            &[
+                2, 2, 0, 8, // Scanline header.
+                8, 128, 0, 128, 0, 128, 0, 128, 0, // Red: 8 literals.
+                136, 64, // Green: run of 8.
+                132, 0, 132, 128, // Blue: two runs of 4.
+                136, 128, // Exponent: run of 8.
+            ],
+        );
+        let image = read_hdr(&mut &data[..]).unwrap();
+        for (i, pixel) in image.pixels.iter().enumerate() {
+            let r = if i % 2 == 0 { 128 } else { 0 };
+            let b = if i < 4 { 0 } else { 128 };
+            assert_eq!(*pixel, decode_rgbe([r, 64, b, 128]));
+        }
+    }
+
+    #[test]
+    fn new_style_rle_overflow() {
+        let data = file(
+            "-Y 1 +X 8",
+            &[2, 2, 0, 8, 137, 128, 136, 64, 136, 0, 136, 128],
+        );
+        assert!(read_hdr(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn orientations() {
+        // The same 3x2 image (values 1..=6 in reading order) stored in
+        // each of the eight orientations.
+        let red = |n: u8| [n, 0, 0, 135];
+        let cases: &[(&str, [u8; 6])] = &[
+            ("-Y 2 +X 3", [1, 2, 3, 4, 5, 6]),
+            ("-Y 2 -X 3", [3, 2, 1, 6, 5, 4]),
+            ("+Y 2 +X 3", [4, 5, 6, 1, 2, 3]),
+            ("+Y 2 -X 3", [6, 5, 4, 3, 2, 1]),
+            ("+X 3 -Y 2", [1, 4, 2, 5, 3, 6]),
+            ("+X 3 +Y 2", [4, 1, 5, 2, 6, 3]),
+            ("-X 3 -Y 2", [3, 6, 2, 5, 1, 4]),
+            ("-X 3 +Y 2", [6, 3, 5, 2, 4, 1]),
+        ];
+
+        for (resolution, order) in cases {
+            let data: Vec<u8> = order.iter().flat_map(|n| red(*n)).collect();
+            let image = read_hdr(&mut &file(resolution, &data)[..]).unwrap();
+            assert_eq!((image.width, image.height), (3, 2), "{}", resolution);
+            let values: Vec<f32> = image.pixels.iter().map(|p| p[0]).collect();
+            let expected: Vec<f32> = (1..=6).map(|n| decode_rgbe(red(n))[0]).collect();
+            assert_eq!(values, expected, "{}", resolution);
+        }
+    }
+
+    #[test]
+    fn too_large() {
+        let data = file("-Y 1000000000 +X 1000000000", &[]);
+        let e = read_hdr(&mut &data[..]).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        let data = file("-Y 18446744073709551615 +X 2", &[]);
+        assert!(read_hdr(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn radiance_convention() {
+        // Encoded like Radiance's `setcolr()`, decoded like its
+        // `colr_color()`.
+        assert_eq!(encode_rgbe([1.0, 0.5, 0.25]), [128, 64, 32, 129]);
+        assert_eq!(encode_rgbe([0.0, 3.0, 0.75]), [0, 192, 48, 130]);
+        assert_eq!(encode_rgbe([0.0; 3]), [0; 4]);
+        assert_eq!(
+            decode_rgbe([128, 64, 32, 129]),
+            [128.5 / 128.0, 64.5 / 128.0, 32.5 / 128.0]
+        );
+        assert_eq!(decode_rgbe([0, 0, 0, 0]), [0.0; 3]);
+
+        // Round trips stay within half a mantissa step of the largest
+        // component, over the whole exponent range.
+        for e in -100..120 {
+            let largest = 1.37f32 * 2.0f32.powi(e);
+            let rgb = [largest, largest * 0.3, largest * 0.01];
+            let decoded = decode_rgbe(encode_rgbe(rgb));
+            for i in 0..3 {
+                assert!(
+                    (decoded[i] - rgb[i]).abs() <= largest / 256.0,
+                    "{:?} vs {:?}",
+                    rgb,
+                    decoded
+                );
+            }
+        }
+    }
+
+    fn round_trip(pixels: &[[f32; 3]], width: usize, height: usize, rle: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        crate::write_hdr(
+            &mut data,
+            pixels,
+            width,
+            height,
+            1.0,
+            &crate::HdrOptions {
+                rle,
+                primaries: None,
+            },
+        )
+        .unwrap();
+        let image = read_hdr(&mut &data[..]).unwrap();
+
+        assert_eq!((image.width, image.height), (width, height));
+        for (a, b) in pixels.iter().zip(image.pixels.iter()) {
+            // The mantissas are 8-bit, relative to the largest component.
+            let tolerance = a[0].max(a[1]).max(a[2]) / 64.0;
+            for i in 0..3 {
+                assert!((a[i] - b[i]).abs() <= tolerance, "{:?} vs {:?}", a, b);
+            }
+        }
+
+        data
+    }
+
+    /// A test image with a mix of flat areas (good for runs) and noise
+    /// (good for literals).
+    fn test_pixels(width: usize, height: usize) -> Vec<[f32; 3]> {
+        (0..(width * height))
+            .map(|i| {
+                let x = i % width;
+                if x % 14 < 7 {
+                    [0.25, 0.5, 4.0]
+                } else {
+                    let n = (i * 7919 % 251) as f32;
+                    [n, 0.5, 1.0 / (n + 1.0)]
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn write_read_round_trip_flat() {
+        let (width, height) = (5, 3);
+        let pixels = test_pixels(width, height);
+        let data = round_trip(&pixels, width, height, false);
+
+        // Header + 4 bytes per pixel.
+        let header_len = data.len() - width * height * 4;
+        assert!(data[..header_len].ends_with(b"-Y 3 +X 5\n"));
+    }
+
+    #[test]
+    fn write_read_round_trip_rle() {
+        for &(width, height) in &[(8, 1), (9, 4), (100, 3), (300, 2)] {
+            let pixels = test_pixels(width, height);
+            let data = round_trip(&pixels, width, height, true);
+            let flat = round_trip(&pixels, width, height, false);
+            assert!(data.len() < flat.len(), "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn write_read_round_trip_rle_constant() {
+        // Long runs longer than the maximum run length.
+        let (width, height) = (1000, 2);
+        let pixels = vec![[1.0, 2.0, 3.0]; width * height];
+        let data = round_trip(&pixels, width, height, true);
+        assert!(data.len() < 200);
+    }
+
+    #[test]
+    fn write_rle_falls_back_to_flat() {
+        // Too narrow for RLE.
+        let (width, height) = (7, 2);
+        let pixels = test_pixels(width, height);
+        let data = round_trip(&pixels, width, height, true);
+        let flat = round_trip(&pixels, width, height, false);
+        assert_eq!(data, flat);
+    }
+
+    #[test]
+    fn write_read_primaries() {
+        // ACES AP0.
+        let primaries = [
+            (0.7347, 0.2653),
+            (0.0, 1.0),
+            (0.0001, -0.077),
+            (0.32168, 0.33767),
+        ];
+        let mut data = Vec::new();
+        crate::write_hdr(
+            &mut data,
+            &[[1.0, 2.0, 3.0]],
+            1,
+            1,
+            1.0,
+            &crate::HdrOptions {
+                rle: false,
+                primaries: Some(primaries),
+            },
+        )
+        .unwrap();
+
+        let image = read_hdr(&mut &data[..]).unwrap();
+        assert_eq!(image.primaries, Some(primaries));
+    }
+}
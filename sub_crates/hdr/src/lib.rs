@@ -1,26 +1,74 @@
-mod trifloat;
+mod exr_fmt;
+mod hdr_fmt;
 
 use std::io::Write;
 
+pub use exr_fmt::{read_exr, write_exr, ExrCompression, ExrOptions, ExrPrecision};
+pub use hdr_fmt::{read_hdr, HdrImage};
+
+#[derive(Debug, Copy, Clone)]
+pub struct HdrOptions {
+    /// Whether to run-length encode the scanlines.  Scanlines narrower
+    /// than 8 or wider than 32767 pixels can't be run-length encoded,
+    /// and are always written flat.
+    pub rle: bool,
+
+    /// The xy chromaticity coordinates of the RGB primaries and white
+    /// point, as `[red, green, blue, white]`.  Written as `PRIMARIES`.
+    pub primaries: Option<[(f32, f32); 4]>,
+}
+
+impl Default for HdrOptions {
+    fn default() -> HdrOptions {
+        HdrOptions {
+            rle: true,
+            primaries: None,
+        }
+    }
+}
+
 pub fn write_hdr<W: Write>(
     out: &mut W,
     image: &[[f32; 3]],
     width: usize,
     height: usize,
     exposure: f32,
+    options: &HdrOptions,
 ) -> std::io::Result<()> {
     assert_eq!(image.len(), width * height);
 
     out.write_all(b"#?RADIANCE\n")?;
-    out.write_all(b"FORMAT=32-bit_rle_rgbe\n\n")?;
+    out.write_all(b"FORMAT=32-bit_rle_rgbe\n")?;
+    if let Some([r, g, b, w]) = options.primaries {
+        out.write_all(
+            format!(
+                "PRIMARIES={} {} {} {} {} {} {} {}\n",
+                r.0, r.1, g.0, g.1, b.0, b.1, w.0, w.1
+            )
+            .as_bytes(),
+        )?;
+    }
+    out.write_all(b"\n")?;
     out.write_all(format!("-Y {} +X {}\n", height, width).as_bytes())?;
-    for pixel in image.iter() {
-        let pixel_adjusted = [
-            pixel[0] * exposure,
-            pixel[1] * exposure,
-            pixel[2] * exposure,
-        ];
-        out.write_all(&trifloat::encode(pixel_adjusted))?;
+
+    let use_rle = options.rle && (8..=0x7fff).contains(&width);
+    let mut scanline = vec![[0u8; 4]; width];
+    for row in image.chunks(width.max(1)) {
+        for (rgbe, pixel) in scanline.iter_mut().zip(row.iter()) {
+            *rgbe = hdr_fmt::encode_rgbe([
+                pixel[0] * exposure,
+                pixel[1] * exposure,
+                pixel[2] * exposure,
+            ]);
+        }
+
+        if use_rle {
+            hdr_fmt::write_rle_scanline(out, &scanline)?;
+        } else {
+            for rgbe in scanline.iter() {
+                out.write_all(rgbe)?;
+            }
+        }
     }
     out.flush()?;
 
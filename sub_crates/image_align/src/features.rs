@@ -0,0 +1,245 @@
+use rayon::prelude::*;
+
+use crate::GrayImage;
+
+/// The radius of the patches compared when matching features.
+const PATCH_RADIUS: isize = 7;
+
+/// How far from the coarse translation a feature is searched for.
+/// This leaves room for rotation and perspective changes.
+const SEARCH_RADIUS: isize = 12;
+
+/// The image is divided into a grid of this many cells on each axis,
+/// and the strongest feature in each cell is used.  This spreads the
+/// features over the whole image.
+const GRID_SIZE: usize = 16;
+
+/// The minimum normalized cross-correlation for two patches to count
+/// as a match.
+const MIN_CORRELATION: f32 = 0.8;
+
+/// A feature's position in the reference image and in the other image.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Match {
+    pub reference: (f64, f64),
+    pub image: (f64, f64),
+}
+
+/// Finds corners in `reference` and matches them to `image`, searching
+/// around where `shift` puts them.
+pub(crate) fn find_matches(
+    reference: &GrayImage,
+    image: &GrayImage,
+    shift: (i32, i32),
+) -> Vec<Match> {
+    // Replacing pixel values with their ranks makes differently exposed
+    // images look alike, since exposure changes preserve the ordering
+    // of pixel values (aside from clipping and noise).
+    let reference = equalized(reference);
+    let image = equalized(image);
+
+    find_corners(&reference)
+        .par_iter()
+        .filter_map(|&(x, y)| {
+            let patch = normalized_patch(&reference, x, y)?;
+            let (mx, my) = match_patch(&patch, &image, x + shift.0 as isize, y + shift.1 as isize)?;
+            Some(Match {
+                reference: (x as f64 + 0.5, y as f64 + 0.5),
+                image: (mx + 0.5, my + 0.5),
+            })
+        })
+        .collect()
+}
+
+/// Replaces each pixel's value with its rank in [0.0, 1.0].
+fn equalized(image: &GrayImage) -> GrayImage {
+    let mut sorted = image.pixels.clone();
+    sorted.par_sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let norm = 1.0 / sorted.len() as f32;
+    let pixels = image
+        .pixels
+        .par_iter()
+        .map(|&n| sorted.partition_point(|&m| m < n) as f32 * norm)
+        .collect();
+    GrayImage::new(pixels, image.width, image.height)
+}
+
+/// Finds the strongest Harris corner in each cell of a grid over the
+/// image, away from the edges.
+fn find_corners(image: &GrayImage) -> Vec<(isize, isize)> {
+    const WINDOW_RADIUS: usize = 2;
+    let margin = PATCH_RADIUS as usize + WINDOW_RADIUS + 1;
+    let (width, height) = (image.width, image.height);
+    if width <= margin * 2 || height <= margin * 2 {
+        return Vec::new();
+    }
+
+    // Gradients.
+    let mut gradients = vec![(0.0f32, 0.0f32); width * height];
+    gradients
+        .par_chunks_mut(width)
+        .enumerate()
+        .skip(1)
+        .take(height - 2)
+        .for_each(|(y, row)| {
+            for (x, gradient) in row.iter_mut().enumerate().take(width - 1).skip(1) {
+                *gradient = (
+                    (image.get(x + 1, y) - image.get(x - 1, y)) * 0.5,
+                    (image.get(x, y + 1) - image.get(x, y - 1)) * 0.5,
+                );
+            }
+        });
+
+    // Corner response, from the structure tensor summed over a small
+    // window around each pixel.
+    let mut responses = vec![0.0f32; width * height];
+    responses
+        .par_chunks_mut(width)
+        .enumerate()
+        .skip(margin)
+        .take(height - margin * 2)
+        .for_each(|(y, row)| {
+            for (x, response) in row.iter_mut().enumerate().take(width - margin).skip(margin) {
+                let (mut xx, mut yy, mut xy) = (0.0f32, 0.0f32, 0.0f32);
+                for wy in (y - WINDOW_RADIUS)..=(y + WINDOW_RADIUS) {
+                    for wx in (x - WINDOW_RADIUS)..=(x + WINDOW_RADIUS) {
+                        let (gx, gy) = gradients[wy * width + wx];
+                        xx += gx * gx;
+                        yy += gy * gy;
+                        xy += gx * gy;
+                    }
+                }
+                *response = (xx * yy - xy * xy) - 0.04 * (xx + yy) * (xx + yy);
+            }
+        });
+
+    // Best corner in each grid cell.
+    let mut cells = vec![(0.0f32, 0usize, 0usize); GRID_SIZE * GRID_SIZE];
+    for y in margin..(height - margin) {
+        for x in margin..(width - margin) {
+            let response = responses[y * width + x];
+            let cell = &mut cells[(y * GRID_SIZE / height) * GRID_SIZE + (x * GRID_SIZE / width)];
+            if response > cell.0 {
+                *cell = (response, x, y);
+            }
+        }
+    }
+
+    // Skip weak corners, which are mostly noise.
+    let max_response = cells.iter().fold(0.0f32, |a, c| a.max(c.0));
+    cells
+        .iter()
+        .filter(|c| c.0 > max_response * 0.01)
+        .map(|c| (c.1 as isize, c.2 as isize))
+        .collect()
+}
+
+/// Returns the patch around (x, y), normalized to zero mean and unit
+/// length.  Returns `None` for featureless patches.
+fn normalized_patch(image: &GrayImage, x: isize, y: isize) -> Option<Vec<f32>> {
+    let mut patch = Vec::with_capacity(((PATCH_RADIUS * 2 + 1) * (PATCH_RADIUS * 2 + 1)) as usize);
+    for py in (y - PATCH_RADIUS)..=(y + PATCH_RADIUS) {
+        for px in (x - PATCH_RADIUS)..=(x + PATCH_RADIUS) {
+            patch.push(image.get(px as usize, py as usize));
+        }
+    }
+
+    let mean = patch.iter().sum::<f32>() / patch.len() as f32;
+    let length = patch
+        .iter()
+        .map(|n| (n - mean) * (n - mean))
+        .sum::<f32>()
+        .sqrt();
+    if length < 1.0e-4 {
+        return None;
+    }
+    for n in patch.iter_mut() {
+        *n = (*n - mean) / length;
+    }
+    Some(patch)
+}
+
+/// Finds the position in `image` within the search radius of (x, y)
+/// whose surroundings best match `patch`, with sub-pixel precision.
+///
+/// Returns `None` if there's no good match, or if the best match is at
+/// the edge of the search area (and thus might be outside of it).
+fn match_patch(patch: &[f32], image: &GrayImage, x: isize, y: isize) -> Option<(f64, f64)> {
+    let search_size = (SEARCH_RADIUS * 2 + 1) as usize;
+    let in_bounds = |px: isize, py: isize| {
+        px - PATCH_RADIUS >= 0
+            && py - PATCH_RADIUS >= 0
+            && px + PATCH_RADIUS < image.width as isize
+            && py + PATCH_RADIUS < image.height as isize
+    };
+
+    // Correlation at each position in the search area.
+    let mut scores = vec![f32::NEG_INFINITY; search_size * search_size];
+    for sy in 0..search_size {
+        for sx in 0..search_size {
+            let px = x + sx as isize - SEARCH_RADIUS;
+            let py = y + sy as isize - SEARCH_RADIUS;
+            if in_bounds(px, py) {
+                scores[sy * search_size + sx] = correlation(patch, image, px, py);
+            }
+        }
+    }
+
+    let (best_i, &best) = scores
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(std::cmp::Ordering::Equal))?;
+    let (bx, by) = (best_i % search_size, best_i / search_size);
+    if best < MIN_CORRELATION
+        || bx == 0
+        || by == 0
+        || bx == search_size - 1
+        || by == search_size - 1
+    {
+        return None;
+    }
+
+    // Fit parabolas through the peak and its neighbors.
+    let score = |sx: usize, sy: usize| scores[sy * search_size + sx];
+    let peak_offset = |before: f32, after: f32| {
+        let curvature = before - 2.0 * best + after;
+        if before.is_finite() && after.is_finite() && curvature < 0.0 {
+            ((before - after) / (2.0 * curvature)).clamp(-0.5, 0.5) as f64
+        } else {
+            0.0
+        }
+    };
+    let offset_x = peak_offset(score(bx - 1, by), score(bx + 1, by));
+    let offset_y = peak_offset(score(bx, by - 1), score(bx, by + 1));
+
+    Some((
+        (x + bx as isize - SEARCH_RADIUS) as f64 + offset_x,
+        (y + by as isize - SEARCH_RADIUS) as f64 + offset_y,
+    ))
+}
+
+/// Normalized cross-correlation between a normalized patch and the
+/// patch around (x, y) in `image`.
+fn correlation(patch: &[f32], image: &GrayImage, x: isize, y: isize) -> f32 {
+    let mut sum = 0.0f32;
+    let mut sum_sq = 0.0f32;
+    let mut dot = 0.0f32;
+    let mut i = 0;
+    for py in (y - PATCH_RADIUS)..=(y + PATCH_RADIUS) {
+        for px in (x - PATCH_RADIUS)..=(x + PATCH_RADIUS) {
+            let n = image.get(px as usize, py as usize);
+            sum += n;
+            sum_sq += n * n;
+            dot += n * patch[i];
+            i += 1;
+        }
+    }
+
+    // Since the patch has zero mean, the image patch's mean doesn't
+    // affect the dot product.
+    let variance_sum = sum_sq - sum * sum / patch.len() as f32;
+    if variance_sum < 1.0e-8 {
+        return f32::NEG_INFINITY;
+    }
+    dot / variance_sum.sqrt()
+}
@@ -0,0 +1,260 @@
+//! A crate for aligning images of the same scene, such as the frames
+//! of a handheld exposure bracket.
+//!
+//! Alignment is estimated from grayscale versions of the images, and
+//! is robust to differences in exposure between them.  A translation
+//! is first found with median threshold bitmaps (Ward, 2003), and then
+//! refined with matched image features, optionally into a full
+//! homography.
+
+mod features;
+mod mtb;
+mod transform;
+mod warp;
+
+use rayon::prelude::*;
+
+pub use transform::Transform;
+pub use warp::warp_interleaved;
+
+/// The number of pyramid levels searched for the coarse translation.
+/// The largest offset that can be found is `2^MTB_LEVELS - 1` pixels.
+const MTB_LEVELS: usize = 7;
+
+/// How an image is allowed to move relative to the reference image.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Method {
+    /// Only shifts.  Fine for most handheld brackets.
+    Translation,
+
+    /// A full perspective transform, which also handles rotation and
+    /// the camera tilting between frames.
+    Homography,
+}
+
+/// A single-channel image.
+///
+/// The pixel values can be in any scale, as long as brighter is
+/// larger.  Linear luminance works well.
+#[derive(Debug, Clone)]
+pub struct GrayImage {
+    pub pixels: Vec<f32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl GrayImage {
+    pub fn new(pixels: Vec<f32>, width: usize, height: usize) -> GrayImage {
+        assert_eq!(pixels.len(), width * height);
+        GrayImage {
+            pixels,
+            width,
+            height,
+        }
+    }
+
+    #[inline(always)]
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Returns the image at half resolution, averaging 2x2 blocks of
+    /// pixels.
+    pub fn downscaled(&self) -> GrayImage {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = vec![0.0; width * height];
+        pixels
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                let y0 = (y * 2).min(self.height - 1);
+                let y1 = (y * 2 + 1).min(self.height - 1);
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let x0 = (x * 2).min(self.width - 1);
+                    let x1 = (x * 2 + 1).min(self.width - 1);
+                    *pixel =
+                        (self.get(x0, y0) + self.get(x1, y0) + self.get(x0, y1) + self.get(x1, y1))
+                            * 0.25;
+                }
+            });
+        GrayImage::new(pixels, width, height)
+    }
+
+    /// Resamples the image so that it lines up with the reference image
+    /// of `transform`.  See `warp_interleaved()`.
+    pub fn warped(&self, transform: &Transform) -> GrayImage {
+        GrayImage::new(
+            warp_interleaved(
+                &self.pixels,
+                1,
+                self.width,
+                self.height,
+                transform,
+                |n| n,
+                |n| n,
+            )
+            .0,
+            self.width,
+            self.height,
+        )
+    }
+}
+
+/// Estimates the transform from `reference` to `image`.
+///
+/// The images must have the same resolution.  If no features can be
+/// matched between them, this falls back to the coarse translation
+/// from their median threshold bitmaps.
+pub fn estimate_alignment(reference: &GrayImage, image: &GrayImage, method: Method) -> Transform {
+    assert_eq!(reference.width, image.width);
+    assert_eq!(reference.height, image.height);
+
+    let shift = mtb::estimate_translation(reference, image, MTB_LEVELS);
+    let coarse = Transform::translation(shift.0 as f64, shift.1 as f64);
+
+    let matches = features::find_matches(reference, image, shift);
+    let translation = transform::fit_translation(&matches);
+    match method {
+        Method::Translation => translation,
+        Method::Homography => transform::fit_homography_ransac(&matches).or(translation),
+    }
+    .unwrap_or(coarse)
+}
+
+/// Aligns a sequence of images, such as an exposure bracket sorted by
+/// exposure, to one of them.
+///
+/// Each image is aligned to its neighbor, and the transforms are then
+/// chained towards the reference.  Neighbors have the most in common,
+/// which makes this more reliable than aligning every image directly
+/// to the reference when exposures differ a lot.
+///
+/// Returns the transform from the reference image to each image.  The
+/// reference image's own transform is the identity.
+pub fn align_sequence(images: &[&GrayImage], reference: usize, method: Method) -> Vec<Transform> {
+    if images.is_empty() {
+        return Vec::new();
+    }
+    assert!(reference < images.len());
+
+    // Transforms from each image to the next one.
+    let steps: Vec<Transform> = (1..images.len())
+        .into_par_iter()
+        .map(|i| estimate_alignment(images[i - 1], images[i], method))
+        .collect();
+
+    let mut transforms = vec![Transform::identity(); images.len()];
+    for i in (reference + 1)..images.len() {
+        transforms[i] = transforms[i - 1].then(&steps[i - 1]);
+    }
+    for i in (0..reference).rev() {
+        let step_back = steps[i].inverse().unwrap_or_else(Transform::identity);
+        transforms[i] = transforms[i + 1].then(&step_back);
+    }
+
+    transforms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A smooth, feature-rich test pattern.
+    fn pattern(x: f64, y: f64) -> f32 {
+        let n = (x * 0.11).sin() * (y * 0.07).cos()
+            + (x * 0.031 + y * 0.047).sin()
+            + ((x - 90.0).hypot(y - 60.0) * 0.2).sin() * 0.5;
+        (0.5 + n * 0.2) as f32
+    }
+
+    fn make_image(width: usize, height: usize, transform: &Transform, gain: f32) -> GrayImage {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = transform.apply(x as f64 + 0.5, y as f64 + 0.5);
+                pixels.push(pattern(sx, sy) * gain);
+            }
+        }
+        GrayImage::new(pixels, width, height)
+    }
+
+    #[test]
+    fn translation() {
+        // The image content at reference position p is at p + offset
+        // in the image.
+        let offset = (13.25, -6.5);
+        let reference = make_image(320, 240, &Transform::identity(), 1.0);
+        let image = make_image(
+            320,
+            240,
+            &Transform::translation(-offset.0, -offset.1),
+            0.25,
+        );
+
+        let transform = estimate_alignment(&reference, &image, Method::Translation);
+        let (x, y) = transform.offset_at(160.0, 120.0);
+        assert!((x - offset.0).abs() < 0.2, "{} vs {}", x, offset.0);
+        assert!((y - offset.1).abs() < 0.2, "{} vs {}", y, offset.1);
+    }
+
+    #[test]
+    fn homography() {
+        let angle = 1.5f64.to_radians();
+        let (s, c) = angle.sin_cos();
+        let rotation = Transform {
+            matrix: [[c, -s, 5.0], [s, c, -3.0], [0.0, 0.0, 1.0]],
+        };
+        let reference = make_image(320, 240, &Transform::identity(), 1.0);
+        let image = make_image(320, 240, &rotation.inverse().unwrap(), 2.0);
+
+        let transform = estimate_alignment(&reference, &image, Method::Homography);
+        for &(x, y) in &[(40.0, 40.0), (280.0, 40.0), (160.0, 120.0), (40.0, 200.0)] {
+            let expected = rotation.apply(x, y);
+            let found = transform.apply(x, y);
+            assert!(
+                (expected.0 - found.0).abs() < 0.3,
+                "{:?} vs {:?}",
+                expected,
+                found
+            );
+            assert!(
+                (expected.1 - found.1).abs() < 0.3,
+                "{:?} vs {:?}",
+                expected,
+                found
+            );
+        }
+    }
+
+    #[test]
+    fn warp_coverage() {
+        let image = make_image(16, 8, &Transform::identity(), 1.0);
+        let shifted = Transform::translation(-3.0, 2.0);
+        let (pixels, coverage) = warp_interleaved(&image.pixels, 1, 16, 8, &shifted, |n| n, |n| n);
+        for y in 0..8 {
+            for x in 0..16 {
+                let covered = x >= 3 && y < 6;
+                assert_eq!(coverage[y * 16 + x], covered, "{} {}", x, y);
+                if covered {
+                    let expected = image.get(x - 3, y + 2);
+                    assert!((pixels[y * 16 + x] - expected).abs() < 1.0e-6);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sequence() {
+        let reference = make_image(256, 192, &Transform::identity(), 1.0);
+        let before = make_image(256, 192, &Transform::translation(4.0, 0.0), 0.5);
+        let after = make_image(256, 192, &Transform::translation(0.0, -7.0), 2.0);
+
+        let transforms = align_sequence(&[&before, &reference, &after], 1, Method::Translation);
+        assert_eq!(transforms[1], Transform::identity());
+        let (x, y) = transforms[0].offset_at(128.0, 96.0);
+        assert!((x + 4.0).abs() < 0.2 && y.abs() < 0.2, "{} {}", x, y);
+        let (x, y) = transforms[2].offset_at(128.0, 96.0);
+        assert!(x.abs() < 0.2 && (y - 7.0).abs() < 0.2, "{} {}", x, y);
+    }
+}
@@ -0,0 +1,126 @@
+//! Translation estimation with median threshold bitmaps (Ward, 2003).
+//!
+//! Thresholding an image at its median splits its pixels into two
+//! halves in the same way regardless of exposure, so the bitmaps of
+//! differently exposed images can be compared directly.
+
+use rayon::prelude::*;
+
+use crate::GrayImage;
+
+/// Pixels within this fraction of the median are left out, since noise
+/// can put them on either side of it.
+const EXCLUSION_TOLERANCE: f32 = 0.04;
+
+/// The smallest image dimension the pyramid is downscaled to.
+const MIN_PYRAMID_SIZE: usize = 16;
+
+struct Bitmaps {
+    above_median: Vec<bool>,
+    usable: Vec<bool>,
+    width: usize,
+    height: usize,
+}
+
+impl Bitmaps {
+    fn new(image: &GrayImage) -> Bitmaps {
+        let mut sorted = image.pixels.clone();
+        let mid = sorted.len() / 2;
+        let (_, &mut median, _) = sorted.select_nth_unstable_by(mid, |a, b| {
+            a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
+        });
+        let tolerance = (median * EXCLUSION_TOLERANCE).max(1.0e-6);
+
+        Bitmaps {
+            above_median: image.pixels.iter().map(|&n| n > median).collect(),
+            usable: image
+                .pixels
+                .iter()
+                .map(|&n| (n - median).abs() > tolerance)
+                .collect(),
+            width: image.width,
+            height: image.height,
+        }
+    }
+
+    /// The fraction of overlapping usable pixels that differ, with the
+    /// other bitmap's pixel at (x + dx, y + dy) compared to ours at
+    /// (x, y).
+    fn difference(&self, other: &Bitmaps, dx: isize, dy: isize) -> f32 {
+        let x_range = (0.max(-dx) as usize)
+            ..((self.width as isize).min(self.width as isize - dx).max(0) as usize);
+        let y_range = (0.max(-dy) as usize)
+            ..((self.height as isize).min(self.height as isize - dy).max(0) as usize);
+
+        let (differing, total) = y_range
+            .into_par_iter()
+            .map(|y| {
+                let mut differing = 0usize;
+                let mut total = 0usize;
+                let oy = (y as isize + dy) as usize;
+                for x in x_range.clone() {
+                    let ox = (x as isize + dx) as usize;
+                    let i = y * self.width + x;
+                    let oi = oy * other.width + ox;
+                    if self.usable[i] && other.usable[oi] {
+                        total += 1;
+                        if self.above_median[i] != other.above_median[oi] {
+                            differing += 1;
+                        }
+                    }
+                }
+                (differing, total)
+            })
+            .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+
+        if total == 0 {
+            f32::INFINITY
+        } else {
+            differing as f32 / total as f32
+        }
+    }
+}
+
+/// Estimates the whole-pixel shift of `image`'s content relative to
+/// `reference`, searching up to `2^levels - 1` pixels on each axis.
+pub(crate) fn estimate_translation(
+    reference: &GrayImage,
+    image: &GrayImage,
+    levels: usize,
+) -> (i32, i32) {
+    // Image pyramids, from full resolution down.
+    let mut pyramid = vec![(reference.clone(), image.clone())];
+    while pyramid.len() < levels {
+        let (r, i) = pyramid.last().unwrap();
+        if r.width.min(r.height) < MIN_PYRAMID_SIZE * 2 {
+            break;
+        }
+        let next = (r.downscaled(), i.downscaled());
+        pyramid.push(next);
+    }
+
+    // Search the neighboring shifts at each level, starting from the
+    // doubled result of the level below.
+    let mut shift = (0isize, 0isize);
+    for (i, (r, img)) in pyramid.iter().enumerate().rev() {
+        if i < pyramid.len() - 1 {
+            shift = (shift.0 * 2, shift.1 * 2);
+        }
+
+        let r_bitmaps = Bitmaps::new(r);
+        let img_bitmaps = Bitmaps::new(img);
+        let mut best = (f32::INFINITY, shift);
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let candidate = (shift.0 + dx, shift.1 + dy);
+                let difference = r_bitmaps.difference(&img_bitmaps, candidate.0, candidate.1);
+                if difference < best.0 {
+                    best = (difference, candidate);
+                }
+            }
+        }
+        shift = best.1;
+    }
+
+    (shift.0 as i32, shift.1 as i32)
+}
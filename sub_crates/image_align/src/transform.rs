@@ -0,0 +1,349 @@
+use crate::features::Match;
+
+/// The minimum number of consistent feature matches needed to trust a
+/// fitted transform.
+const MIN_INLIERS: usize = 12;
+
+/// How far (in pixels) a match can be from a fitted transform and still
+/// count as consistent with it.
+const INLIER_THRESHOLD: f64 = 1.5;
+
+/// The number of random samples tried when fitting a homography.
+const RANSAC_ITERATIONS: usize = 1000;
+
+/// A 2D projective transform, mapping pixel positions in a reference
+/// image to the corresponding positions in another image.
+///
+/// Positions are in pixels, with the image's top-left corner at (0, 0),
+/// so pixel centers are at half-integer coordinates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub matrix: [[f64; 3]; 3],
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    pub fn translation(x: f64, y: f64) -> Transform {
+        Transform {
+            matrix: [[1.0, 0.0, x], [0.0, 1.0, y], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Whether this is just a shift.
+    pub fn is_translation(&self) -> bool {
+        let m = &self.matrix;
+        m[0][0] == 1.0
+            && m[0][1] == 0.0
+            && m[1][0] == 0.0
+            && m[1][1] == 1.0
+            && m[2][0] == 0.0
+            && m[2][1] == 0.0
+            && m[2][2] == 1.0
+    }
+
+    #[inline(always)]
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let m = &self.matrix;
+        let w = m[2][0] * x + m[2][1] * y + m[2][2];
+        (
+            (m[0][0] * x + m[0][1] * y + m[0][2]) / w,
+            (m[1][0] * x + m[1][1] * y + m[1][2]) / w,
+        )
+    }
+
+    /// How far the point at (x, y) moves.
+    pub fn offset_at(&self, x: f64, y: f64) -> (f64, f64) {
+        let (tx, ty) = self.apply(x, y);
+        (tx - x, ty - y)
+    }
+
+    /// The transform that applies `self` and then `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        let a = &next.matrix;
+        let b = &self.matrix;
+        let mut matrix = [[0.0; 3]; 3];
+        for row in 0..3 {
+            for col in 0..3 {
+                matrix[row][col] = (0..3).map(|i| a[row][i] * b[i][col]).sum();
+            }
+        }
+        Transform { matrix }
+    }
+
+    /// Returns `None` if the transform is degenerate.
+    pub fn inverse(&self) -> Option<Transform> {
+        let m = &self.matrix;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let adjugate = [
+            [
+                cofactor(1, 2, 1, 2),
+                -cofactor(0, 2, 1, 2),
+                cofactor(0, 1, 1, 2),
+            ],
+            [
+                -cofactor(1, 2, 0, 2),
+                cofactor(0, 2, 0, 2),
+                -cofactor(0, 1, 0, 2),
+            ],
+            [
+                cofactor(1, 2, 0, 1),
+                -cofactor(0, 2, 0, 1),
+                cofactor(0, 1, 0, 1),
+            ],
+        ];
+        let det = m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+        if det.abs() < 1.0e-12 {
+            return None;
+        }
+
+        let mut matrix = adjugate;
+        for row in matrix.iter_mut() {
+            for n in row.iter_mut() {
+                *n /= det;
+            }
+        }
+        Some(Transform { matrix })
+    }
+
+    /// The same transform for versions of both images scaled by the
+    /// given factors, e.g. to apply a transform estimated on small
+    /// images to the full-resolution ones.
+    pub fn scaled(&self, scale_x: f64, scale_y: f64) -> Transform {
+        let scale = Transform {
+            matrix: [[scale_x, 0.0, 0.0], [0.0, scale_y, 0.0], [0.0, 0.0, 1.0]],
+        };
+        let unscale = Transform {
+            matrix: [
+                [1.0 / scale_x, 0.0, 0.0],
+                [0.0, 1.0 / scale_y, 0.0],
+                [0.0, 0.0, 1.0],
+            ],
+        };
+        unscale.then(self).then(&scale)
+    }
+}
+
+/// Fits a translation to feature matches, ignoring outliers.
+///
+/// Returns `None` if there aren't enough consistent matches.
+pub(crate) fn fit_translation(matches: &[Match]) -> Option<Transform> {
+    if matches.len() < MIN_INLIERS {
+        return None;
+    }
+
+    // Start from the median offset, which is robust to outliers, and
+    // then average the offsets that agree with it.
+    let median = |mut values: Vec<f64>| {
+        let mid = values.len() / 2;
+        *values
+            .select_nth_unstable_by(mid, |a, b| a.partial_cmp(b).unwrap())
+            .1
+    };
+    let median_x = median(matches.iter().map(|m| m.image.0 - m.reference.0).collect());
+    let median_y = median(matches.iter().map(|m| m.image.1 - m.reference.1).collect());
+
+    let inliers: Vec<(f64, f64)> = matches
+        .iter()
+        .map(|m| (m.image.0 - m.reference.0, m.image.1 - m.reference.1))
+        .filter(|&(x, y)| (x - median_x).hypot(y - median_y) < INLIER_THRESHOLD)
+        .collect();
+    if inliers.len() < MIN_INLIERS {
+        return None;
+    }
+
+    let norm = 1.0 / inliers.len() as f64;
+    Some(Transform::translation(
+        inliers.iter().map(|o| o.0).sum::<f64>() * norm,
+        inliers.iter().map(|o| o.1).sum::<f64>() * norm,
+    ))
+}
+
+/// Fits a homography to feature matches with RANSAC, ignoring outliers.
+///
+/// Returns `None` if there aren't enough consistent matches, or if the
+/// result is implausibly far from a rigid motion.
+pub(crate) fn fit_homography_ransac(matches: &[Match]) -> Option<Transform> {
+    if matches.len() < MIN_INLIERS {
+        return None;
+    }
+
+    let inliers_of = |transform: &Transform| -> Vec<Match> {
+        matches
+            .iter()
+            .filter(|m| {
+                let (x, y) = transform.apply(m.reference.0, m.reference.1);
+                (x - m.image.0).hypot(y - m.image.1) < INLIER_THRESHOLD
+            })
+            .copied()
+            .collect()
+    };
+
+    // Find the transform from four matches that the most other matches
+    // agree with.  A fixed seed keeps the results repeatable.
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let mut best: Option<(Transform, usize)> = None;
+    for _ in 0..RANSAC_ITERATIONS {
+        let mut sample = [0usize; 4];
+        for i in 0..4 {
+            sample[i] = loop {
+                let n = rng.next_below(matches.len());
+                if !sample[..i].contains(&n) {
+                    break n;
+                }
+            };
+        }
+        let sample_matches: Vec<Match> = sample.iter().map(|&i| matches[i]).collect();
+        if let Some(transform) = fit_homography(&sample_matches) {
+            let inlier_count = inliers_of(&transform).len();
+            if best.is_none_or(|(_, count)| inlier_count > count) {
+                best = Some((transform, inlier_count));
+            }
+        }
+    }
+
+    // Refit to all of the agreeing matches.
+    let (mut transform, _) = best?;
+    for _ in 0..2 {
+        let inliers = inliers_of(&transform);
+        if inliers.len() < MIN_INLIERS {
+            return None;
+        }
+        transform = fit_homography(&inliers)?;
+    }
+
+    if is_plausible(&transform) {
+        Some(transform)
+    } else {
+        None
+    }
+}
+
+/// Whether a transform could come from a handheld camera moving a bit
+/// between frames.  This rejects fits to bad matches that happen to
+/// agree with each other.
+fn is_plausible(transform: &Transform) -> bool {
+    let m = &transform.matrix;
+    let scale = m[0][0] * m[1][1] - m[0][1] * m[1][0];
+    scale > 0.8 && scale < 1.25 && m[2][0].abs() < 1.0e-3 && m[2][1].abs() < 1.0e-3
+}
+
+/// Least-squares homography fit, with the last matrix element fixed at
+/// one.  Needs at least four matches.
+fn fit_homography(matches: &[Match]) -> Option<Transform> {
+    if matches.len() < 4 {
+        return None;
+    }
+
+    // Normalize the points for numerical stability (Hartley, 1997).
+    let norm_a = normalizing_transform(matches.iter().map(|m| m.reference).collect());
+    let norm_b = normalizing_transform(matches.iter().map(|m| m.image).collect());
+
+    // Accumulate the normal equations.
+    let mut ata = [[0.0f64; 8]; 8];
+    let mut atb = [0.0f64; 8];
+    for m in matches.iter() {
+        let (x, y) = norm_a.apply(m.reference.0, m.reference.1);
+        let (u, v) = norm_b.apply(m.image.0, m.image.1);
+        let rows = [
+            ([x, y, 1.0, 0.0, 0.0, 0.0, -x * u, -y * u], u),
+            ([0.0, 0.0, 0.0, x, y, 1.0, -x * v, -y * v], v),
+        ];
+        for (row, b) in rows.iter() {
+            for i in 0..8 {
+                for j in 0..8 {
+                    ata[i][j] += row[i] * row[j];
+                }
+                atb[i] += row[i] * b;
+            }
+        }
+    }
+
+    let h = solve_8x8(ata, atb)?;
+    let normalized = Transform {
+        matrix: [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], 1.0]],
+    };
+    let transform = norm_a.then(&normalized).then(&norm_b.inverse()?);
+
+    // Scale so that the last element is one again.
+    let w = transform.matrix[2][2];
+    if w.abs() < 1.0e-12 {
+        return None;
+    }
+    let mut matrix = transform.matrix;
+    for row in matrix.iter_mut() {
+        for n in row.iter_mut() {
+            *n /= w;
+        }
+    }
+    Some(Transform { matrix })
+}
+
+/// A transform that moves the points' centroid to the origin, and
+/// scales them to an average distance of sqrt(2) from it.
+fn normalizing_transform(points: Vec<(f64, f64)>) -> Transform {
+    let norm = 1.0 / points.len() as f64;
+    let cx = points.iter().map(|p| p.0).sum::<f64>() * norm;
+    let cy = points.iter().map(|p| p.1).sum::<f64>() * norm;
+    let spread = points
+        .iter()
+        .map(|p| (p.0 - cx).hypot(p.1 - cy))
+        .sum::<f64>()
+        * norm;
+    let s = if spread > 0.0 {
+        std::f64::consts::SQRT_2 / spread
+    } else {
+        1.0
+    };
+    Transform {
+        matrix: [[s, 0.0, -cx * s], [0.0, s, -cy * s], [0.0, 0.0, 1.0]],
+    }
+}
+
+/// Solves a linear system with Gaussian elimination and partial
+/// pivoting.  Returns `None` if it's singular.
+fn solve_8x8(mut a: [[f64; 8]; 8], mut b: [f64; 8]) -> Option<[f64; 8]> {
+    for col in 0..8 {
+        let pivot =
+            (col..8).max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())?;
+        if a[pivot][col].abs() < 1.0e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        for row in (col + 1)..8 {
+            let factor = a[row][col] / a[col][col];
+            let pivot_row = a[col];
+            for (n, p) in a[row][col..].iter_mut().zip(pivot_row[col..].iter()) {
+                *n -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0f64; 8];
+    for row in (0..8).rev() {
+        let sum: f64 = ((row + 1)..8).map(|i| a[row][i] * x[i]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// A small, fast pseudo-random number generator.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
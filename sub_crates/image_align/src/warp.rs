@@ -0,0 +1,68 @@
+use rayon::prelude::*;
+
+use crate::Transform;
+
+/// Resamples an image so that it lines up with the reference image of
+/// `transform`, using bilinear interpolation.
+///
+/// `data` is the image's interleaved channel values, and `transform`
+/// maps positions in the reference image to positions in this image.
+/// `to_f32` and `from_f32` convert the channel values to and from
+/// floating point for interpolation.
+///
+/// The result has the same resolution as the input.  Parts of it that
+/// fall outside of the input image repeat the input's edge pixels, so
+/// along with it a coverage mask is returned, with one entry per pixel
+/// that's `false` for those parts.
+pub fn warp_interleaved<T, ToF, FromF>(
+    data: &[T],
+    channels: usize,
+    width: usize,
+    height: usize,
+    transform: &Transform,
+    to_f32: ToF,
+    from_f32: FromF,
+) -> (Vec<T>, Vec<bool>)
+where
+    T: Copy + Send + Sync,
+    ToF: Fn(T) -> f32 + Sync,
+    FromF: Fn(f32) -> T + Sync,
+{
+    assert_eq!(data.len(), width * height * channels);
+    if data.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+
+    let mut warped = data.to_vec();
+    let mut coverage = vec![false; width * height];
+    warped
+        .par_chunks_mut(width * channels)
+        .zip(coverage.par_chunks_mut(width))
+        .enumerate()
+        .for_each(|(y, (row, row_coverage))| {
+            for x in 0..width {
+                // Pixel centers are at half-integer positions.
+                let (sx, sy) = transform.apply(x as f64 + 0.5, y as f64 + 0.5);
+                row_coverage[x] =
+                    sx >= 0.0 && sx <= width as f64 && sy >= 0.0 && sy <= height as f64;
+                let sx = (sx - 0.5).max(0.0).min((width - 1) as f64);
+                let sy = (sy - 0.5).max(0.0).min((height - 1) as f64);
+
+                let x0 = sx.floor() as usize;
+                let y0 = sy.floor() as usize;
+                let x1 = (x0 + 1).min(width - 1);
+                let y1 = (y0 + 1).min(height - 1);
+                let fx = (sx - x0 as f64) as f32;
+                let fy = (sy - y0 as f64) as f32;
+
+                for c in 0..channels {
+                    let get = |px: usize, py: usize| to_f32(data[(py * width + px) * channels + c]);
+                    let top = get(x0, y0) * (1.0 - fx) + get(x1, y0) * fx;
+                    let bottom = get(x0, y1) * (1.0 - fx) + get(x1, y1) * fx;
+                    row[x * channels + c] = from_f32(top * (1.0 - fy) + bottom * fy);
+                }
+            }
+        });
+
+    (warped, coverage)
+}
@@ -0,0 +1,237 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{
+    dpx_fmt::{read_pixels, Channels, HeaderBytes, Packing, PixelLayout},
+    error::ReadError,
+    Characteristic, FilmHeader, Format, Image, ProbeInfo,
+};
+
+/// Size of the file, image, and data format headers.
+const HEADER_SIZE: usize = 712;
+
+struct CineonHeader {
+    layout: PixelLayout,
+    data_offset: u64,
+}
+
+pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
+    let header = read_header(&mut reader)?;
+    reader.seek(SeekFrom::Start(header.data_offset))?;
+    read_pixels(&mut reader, &header.layout, Format::Cineon)
+}
+
+pub fn probe<R: Read + Seek>(mut reader: R) -> Result<ProbeInfo, ReadError> {
+    let header = read_header(&mut reader)?;
+    Ok(header.layout.probe_info(Format::Cineon))
+}
+
+pub fn film_header<R: Read + Seek>(mut reader: R) -> Result<FilmHeader, ReadError> {
+    let header = read_header(&mut reader)?;
+
+    // Cineon files are printing density by definition, and don't
+    // record anything more specific.
+    Ok(FilmHeader {
+        transfer: Characteristic::PrintingDensity,
+        colorimetric: Characteristic::PrintingDensity,
+        code_bits: header.layout.bits,
+        black_code: None,
+        white_code: None,
+        timecode: None,
+    })
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<CineonHeader, ReadError> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE);
+    reader.take(HEADER_SIZE as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < HEADER_SIZE {
+        return Err(ReadError::Corrupt {
+            format: Format::Cineon,
+            detail: "truncated header".into(),
+        });
+    }
+    let big_endian = match &bytes[0..4] {
+        [0x80, 0x2a, 0x5f, 0xd7] => true,
+        [0xd7, 0x5f, 0x2a, 0x80] => false,
+        _ => return Err(ReadError::UnknownFormat),
+    };
+    let h = HeaderBytes {
+        bytes: &bytes,
+        big_endian,
+    };
+
+    let data_offset = h.u32_at(4);
+    let orientation = h.u8_at(192);
+    let channel_count = h.u8_at(193) as usize;
+
+    // Each channel has its own description, but we only support
+    // channels that all match.
+    let channel = |i: usize| {
+        let c = 196 + i * 28;
+        (h.u8_at(c + 2), h.u32_at(c + 4), h.u32_at(c + 8))
+    };
+    let (bits, width, height) = channel(0);
+    if !(1..=8).contains(&channel_count) || width == 0 || height == 0 {
+        return Err(ReadError::Corrupt {
+            format: Format::Cineon,
+            detail: "no image data".into(),
+        });
+    }
+    if (1..channel_count).any(|i| channel(i) != (bits, width, height)) {
+        return Err(ReadError::UnsupportedFeature);
+    }
+
+    let channels = match channel_count {
+        1 => Channels::Gray,
+        3 => Channels::Rgb,
+        _ => return Err(ReadError::UnsupportedFeature),
+    };
+
+    // Only pixel-interleaved, unsigned data is supported.
+    let interleave = h.u8_at(680);
+    let packing = h.u8_at(681);
+    let is_signed = h.u8_at(682) != 0;
+    let eol_padding = h.u32_at(684);
+    if interleave != 0 || is_signed {
+        return Err(ReadError::UnsupportedFeature);
+    }
+
+    // Packing is irrelevant for 8 and 16-bit data as long as it's
+    // byte aligned.  10-bit data is normally packed three to a 32-bit
+    // word.
+    let packing = match (bits, packing) {
+        (8, 0..=2) | (16, 0 | 3 | 4) => Packing::Packed,
+        (10, 5) => Packing::FilledA,
+        (10, 6) => Packing::FilledB,
+        _ => return Err(ReadError::UnsupportedFeature),
+    };
+
+    Ok(CineonHeader {
+        layout: PixelLayout {
+            width: width as usize,
+            height: height as usize,
+            channels,
+            bits,
+            packing,
+            big_endian,
+            eol_padding: if eol_padding == 0xffff_ffff {
+                0
+            } else {
+                eol_padding as usize
+            },
+            orientation: orientation as u16,
+        },
+        data_offset: data_offset as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dpx_fmt::tests::{expand, pack_row, test_samples};
+    use crate::ImageBuf;
+
+    fn cineon_file(
+        width: usize,
+        height: usize,
+        channel_count: u8,
+        bits: u8,
+        packing: u8,
+        big_endian: bool,
+        samples: &[u32],
+    ) -> Vec<u8> {
+        let mut file = vec![0u8; 1024];
+        let put_u32 = |file: &mut Vec<u8>, offset: usize, n: u32| {
+            let b = if big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            };
+            file[offset..(offset + 4)].copy_from_slice(&b);
+        };
+
+        put_u32(&mut file, 0, 0x802a_5fd7);
+        put_u32(&mut file, 4, 1024);
+        file[193] = channel_count;
+        for i in 0..(channel_count as usize) {
+            let c = 196 + i * 28;
+            file[c + 2] = bits;
+            put_u32(&mut file, c + 4, width as u32);
+            put_u32(&mut file, c + 8, height as u32);
+        }
+        file[681] = packing;
+
+        let row_packing = match packing {
+            5 => Packing::FilledA,
+            6 => Packing::FilledB,
+            _ => Packing::Packed,
+        };
+        let row_len = samples.len() / height;
+        for row in samples.chunks(row_len) {
+            file.extend(pack_row(row, bits, row_packing, big_endian));
+        }
+        file
+    }
+
+    fn load(file: &[u8]) -> Result<Image, ReadError> {
+        super::load(std::io::Cursor::new(file))
+    }
+
+    #[test]
+    fn bit_depths_and_packings() {
+        let (width, height) = (7, 2);
+        for (bits, packing) in [(10, 5), (10, 6), (16, 0)] {
+            for big_endian in [true, false] {
+                let samples = test_samples(width * height * 3, bits);
+                let file = cineon_file(width, height, 3, bits, packing, big_endian, &samples);
+                let image = load(&file).unwrap();
+                assert_eq!(image.dimensions, (width, height));
+                match image.data {
+                    ImageBuf::Rgb16(data) => {
+                        let expected: Vec<u16> = samples.iter().map(|&n| expand(n, bits)).collect();
+                        assert_eq!(data, expected, "{} bits, {}, {}", bits, packing, big_endian);
+                    }
+                    _ => panic!("unexpected buffer type"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn gray_8_bit() {
+        let samples = test_samples(3 * 3, 8);
+        let file = cineon_file(3, 3, 1, 8, 0, true, &samples);
+        match load(&file).unwrap().data {
+            ImageBuf::Rgb8(data) => {
+                for (pixel, &n) in data.chunks(3).zip(samples.iter()) {
+                    assert_eq!(pixel, [n as u8; 3]);
+                }
+            }
+            _ => panic!("unexpected buffer type"),
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let file = cineon_file(7, 2, 3, 10, 5, true, &test_samples(42, 10));
+        assert!(matches!(
+            load(&file[..(file.len() - 1)]),
+            Err(ReadError::Corrupt { .. })
+        ));
+        assert!(matches!(load(&file[..600]), Err(ReadError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn hostile_header() {
+        let mut file = cineon_file(7, 2, 3, 16, 0, true, &test_samples(42, 16));
+        for i in 0..3 {
+            let c = 196 + i * 28;
+            file[(c + 4)..(c + 8)].copy_from_slice(&u32::MAX.to_be_bytes());
+            file[(c + 8)..(c + 12)].copy_from_slice(&u32::MAX.to_be_bytes());
+        }
+        assert!(matches!(load(&file), Err(ReadError::LimitsExceeded { .. })));
+
+        // Mismatched channels.
+        file[196 + 28 + 2] = 8;
+        assert!(matches!(load(&file), Err(ReadError::UnsupportedFeature)));
+    }
+}
@@ -0,0 +1,609 @@
+/// CIE 1931 xy chromaticities of an RGB color space's primaries and
+/// white point.
+///
+/// Laid out the same as `colorbox::chroma::Chromaticities`, for easy
+/// conversion.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Chromaticities {
+    pub r: (f64, f64),
+    pub g: (f64, f64),
+    pub b: (f64, f64),
+    pub w: (f64, f64),
+}
+
+/// The chromaticities of sRGB / Rec.709.
+pub const REC709: Chromaticities = Chromaticities {
+    r: (0.640, 0.330),
+    g: (0.300, 0.600),
+    b: (0.150, 0.060),
+    w: (0.3127, 0.3290),
+};
+
+impl Chromaticities {
+    /// Whether all of the chromaticities are within rounding error of
+    /// each other, e.g. from fixed-point ICC values.
+    pub fn approx_eq(&self, other: &Chromaticities) -> bool {
+        let close =
+            |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).abs() < 0.002 && (a.1 - b.1).abs() < 0.002;
+        close(self.r, other.r)
+            && close(self.g, other.g)
+            && close(self.b, other.b)
+            && close(self.w, other.w)
+    }
+}
+
+/// A curve mapping encoded pixel values to linear values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransferCurve {
+    Linear,
+
+    /// A pure power function, `x^gamma`.
+    Gamma(f32),
+
+    /// An ICC parametric curve, in its most general form:
+    /// `(a * x + b)^g + e` when `x >= d`, and `c * x + f` otherwise.
+    Parametric {
+        g: f32,
+        a: f32,
+        b: f32,
+        c: f32,
+        d: f32,
+        e: f32,
+        f: f32,
+    },
+
+    /// Linear values at evenly spaced encoded values over [0.0, 1.0].
+    Table(Vec<f32>),
+}
+
+impl TransferCurve {
+    /// The sRGB transfer curve.
+    pub fn srgb() -> TransferCurve {
+        TransferCurve::Parametric {
+            g: 2.4,
+            a: 1.0 / 1.055,
+            b: 0.055 / 1.055,
+            c: 1.0 / 12.92,
+            d: 0.04045,
+            e: 0.0,
+            f: 0.0,
+        }
+    }
+
+    pub fn to_linear(&self, n: f32) -> f32 {
+        match *self {
+            TransferCurve::Linear => n,
+            TransferCurve::Gamma(gamma) => n.max(0.0).powf(gamma),
+            TransferCurve::Parametric {
+                g,
+                a,
+                b,
+                c,
+                d,
+                e,
+                f,
+            } => {
+                if n >= d {
+                    (a * n + b).max(0.0).powf(g) + e
+                } else {
+                    c * n + f
+                }
+            }
+            TransferCurve::Table(ref table) => match table.len() {
+                0 => n,
+                1 => table[0],
+                len => {
+                    let x = n.clamp(0.0, 1.0) * (len - 1) as f32;
+                    let i = (x as usize).min(len - 2);
+                    let alpha = x - i as f32;
+                    table[i] + ((table[i + 1] - table[i]) * alpha)
+                }
+            },
+        }
+    }
+
+    /// Whether this is (approximately) the sRGB transfer curve.
+    pub fn is_srgb(&self) -> bool {
+        let srgb = TransferCurve::srgb();
+        (0..=16).all(|i| {
+            let n = i as f32 / 16.0;
+            (self.to_linear(n) - srgb.to_linear(n)).abs() < 0.005
+        })
+    }
+}
+
+/// The color encoding of an image, as described by its file.
+///
+/// Any of the parts can be missing, e.g. a PNG file might only specify
+/// a gamma.  Images without a description are usually meant to be
+/// interpreted as sRGB.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ColorDescription {
+    pub chromaticities: Option<Chromaticities>,
+
+    /// Shared by all channels.
+    pub transfer: Option<TransferCurve>,
+
+    /// The embedded ICC profile, if the description came from one.
+    /// Kept so that it's not lost when the profile uses features that
+    /// we can't represent in the other fields.
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+impl ColorDescription {
+    pub fn srgb() -> ColorDescription {
+        ColorDescription {
+            chromaticities: Some(REC709),
+            transfer: Some(TransferCurve::srgb()),
+            icc_profile: None,
+        }
+    }
+
+    /// Parses the primaries, white point, and transfer curve out of an
+    /// ICC profile.
+    ///
+    /// Only matrix/TRC profiles are understood.  For anything else (or
+    /// an invalid profile) the parsed fields are left empty, but the
+    /// profile itself is still kept.
+    pub fn from_icc_profile(profile: Vec<u8>) -> ColorDescription {
+        let (chromaticities, transfer) = icc::parse(&profile).unwrap_or((None, None));
+        ColorDescription {
+            chromaticities,
+            transfer,
+            icc_profile: Some(profile),
+        }
+    }
+
+    /// Whether this (approximately) describes sRGB.  Missing parts are
+    /// assumed to match.
+    pub fn is_srgb(&self) -> bool {
+        let chroma_matches = self
+            .chromaticities
+            .map(|chroma| chroma.approx_eq(&REC709))
+            .unwrap_or(true);
+        let transfer_matches = self
+            .transfer
+            .as_ref()
+            .map(|transfer| transfer.is_srgb())
+            .unwrap_or(true);
+
+        chroma_matches && transfer_matches
+    }
+}
+
+/// A minimal reader for ICC matrix/TRC profiles.
+mod icc {
+    use super::{Chromaticities, TransferCurve};
+
+    type Matrix = [[f64; 3]; 3];
+
+    /// The D50 profile connection space illuminant.
+    const D50: [f64; 3] = [0.9642, 1.0, 0.8249];
+
+    /// Bradford cone response matrix.
+    const BRADFORD: Matrix = [
+        [0.8951, 0.2664, -0.1614],
+        [-0.7502, 1.7135, 0.0367],
+        [0.0389, -0.0685, 1.0296],
+    ];
+
+    pub fn parse(profile: &[u8]) -> Option<(Option<Chromaticities>, Option<TransferCurve>)> {
+        if profile.len() < 132 || &profile[36..40] != b"acsp" {
+            return None;
+        }
+
+        match &profile[16..20] {
+            b"RGB " => Some((
+                parse_chromaticities(profile),
+                find_tag(profile, b"rTRC").and_then(parse_trc),
+            )),
+            b"GRAY" => Some((None, find_tag(profile, b"kTRC").and_then(parse_trc))),
+            _ => None,
+        }
+    }
+
+    fn parse_chromaticities(profile: &[u8]) -> Option<Chromaticities> {
+        let colorants = [
+            parse_xyz(find_tag(profile, b"rXYZ")?)?,
+            parse_xyz(find_tag(profile, b"gXYZ")?)?,
+            parse_xyz(find_tag(profile, b"bXYZ")?)?,
+        ];
+
+        // The colorants are adapted to D50.  To get the actual primaries
+        // we undo that, using the recorded adaptation if there is one,
+        // and otherwise Bradford adaptation to the media white point (as
+        // was usual for v2 profiles).
+        let from_d50 = match find_tag(profile, b"chad") {
+            Some(chad) if chad.get(0..4)? == b"sf32" => {
+                let n = |i: usize| s15_fixed16_at(chad, 8 + i * 4);
+                invert([
+                    [n(0)?, n(1)?, n(2)?],
+                    [n(3)?, n(4)?, n(5)?],
+                    [n(6)?, n(7)?, n(8)?],
+                ])?
+            }
+            _ => {
+                let white = find_tag(profile, b"wtpt").and_then(parse_xyz);
+                bradford(D50, white.unwrap_or(D50))?
+            }
+        };
+
+        let xy = |xyz: [f64; 3]| -> Option<(f64, f64)> {
+            let xyz = transform(xyz, &from_d50);
+            let sum = xyz[0] + xyz[1] + xyz[2];
+            if sum.abs() < 1.0e-9 {
+                None
+            } else {
+                Some((xyz[0] / sum, xyz[1] / sum))
+            }
+        };
+
+        Some(Chromaticities {
+            r: xy(colorants[0])?,
+            g: xy(colorants[1])?,
+            b: xy(colorants[2])?,
+            w: xy(D50)?,
+        })
+    }
+
+    /// Returns the data of the tag with the given signature.
+    fn find_tag<'a>(profile: &'a [u8], signature: &[u8; 4]) -> Option<&'a [u8]> {
+        // Don't trust the tag count to fit in the profile.
+        let tag_count =
+            (u32_at(profile, 128)? as usize).min(profile.len().saturating_sub(132) / 12);
+        (0..tag_count).find_map(|i| {
+            let entry = 132 + i * 12;
+            if profile.get(entry..(entry + 4))? != signature {
+                return None;
+            }
+            let offset = u32_at(profile, entry + 4)? as usize;
+            let size = u32_at(profile, entry + 8)? as usize;
+            profile.get(offset..offset.checked_add(size)?)
+        })
+    }
+
+    fn parse_xyz(data: &[u8]) -> Option<[f64; 3]> {
+        if data.get(0..4)? != b"XYZ " {
+            return None;
+        }
+        Some([
+            s15_fixed16_at(data, 8)?,
+            s15_fixed16_at(data, 12)?,
+            s15_fixed16_at(data, 16)?,
+        ])
+    }
+
+    fn parse_trc(data: &[u8]) -> Option<TransferCurve> {
+        let u16_at = |i: usize| -> Option<u16> {
+            let b = data.get(i..(i + 2))?;
+            Some(u16::from_be_bytes([b[0], b[1]]))
+        };
+
+        match data.get(0..4)? {
+            b"curv" => {
+                let count = u32_at(data, 8)?;
+                match count {
+                    0 => Some(TransferCurve::Linear),
+                    // A u8Fixed8Number.
+                    1 => Some(TransferCurve::Gamma(u16_at(12)? as f32 / 256.0)),
+                    _ => (0..count as usize)
+                        .map(|i| u16_at(12 + i * 2).map(|n| n as f32 / 65535.0))
+                        .collect::<Option<Vec<_>>>()
+                        .map(TransferCurve::Table),
+                }
+            }
+            b"para" => {
+                let param_count = match u16_at(8)? {
+                    0 => 1,
+                    1 => 3,
+                    2 => 4,
+                    3 => 5,
+                    4 => 7,
+                    _ => return None,
+                };
+                let mut p = [0.0f32; 7];
+                for (i, n) in p.iter_mut().take(param_count).enumerate() {
+                    *n = s15_fixed16_at(data, 12 + i * 4)? as f32;
+                }
+                let [g, a, b, c, d, e, f] = p;
+
+                // Convert to the general form.
+                Some(match param_count {
+                    1 => TransferCurve::Gamma(g),
+                    3 => TransferCurve::Parametric {
+                        g,
+                        a,
+                        b,
+                        c: 0.0,
+                        d: -b / a,
+                        e: 0.0,
+                        f: 0.0,
+                    },
+                    4 => TransferCurve::Parametric {
+                        g,
+                        a,
+                        b,
+                        c: 0.0,
+                        d: -b / a,
+                        e: c,
+                        f: c,
+                    },
+                    5 => TransferCurve::Parametric {
+                        g,
+                        a,
+                        b,
+                        c,
+                        d,
+                        e: 0.0,
+                        f: 0.0,
+                    },
+                    _ => TransferCurve::Parametric {
+                        g,
+                        a,
+                        b,
+                        c,
+                        d,
+                        e,
+                        f,
+                    },
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+        let b = data.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn s15_fixed16_at(data: &[u8], offset: usize) -> Option<f64> {
+        u32_at(data, offset).map(|n| n as i32 as f64 / 65536.0)
+    }
+
+    /// Bradford chromatic adaptation from one XYZ white to another.
+    fn bradford(src_white: [f64; 3], dst_white: [f64; 3]) -> Option<Matrix> {
+        let src_cone = transform(src_white, &BRADFORD);
+        let dst_cone = transform(dst_white, &BRADFORD);
+        let mut scale = [[0.0; 3]; 3];
+        for i in 0..3 {
+            scale[i][i] = dst_cone[i] / src_cone[i];
+        }
+        Some(multiply(&invert(BRADFORD)?, &multiply(&scale, &BRADFORD)))
+    }
+
+    fn transform(v: [f64; 3], m: &Matrix) -> [f64; 3] {
+        [
+            m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+            m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+            m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2],
+        ]
+    }
+
+    fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+        let mut m = [[0.0; 3]; 3];
+        for i in 0..3 {
+            for j in 0..3 {
+                m[i][j] = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+            }
+        }
+        m
+    }
+
+    fn invert(m: Matrix) -> Option<Matrix> {
+        let det = m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0]);
+        if det.abs() < 1.0e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        Some([
+            [
+                (m[1][1] * m[2][2] - m[1][2] * m[2][1]) * inv_det,
+                (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv_det,
+                (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv_det,
+            ],
+            [
+                (m[1][2] * m[2][0] - m[1][0] * m[2][2]) * inv_det,
+                (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv_det,
+                (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv_det,
+            ],
+            [
+                (m[1][0] * m[2][1] - m[1][1] * m[2][0]) * inv_det,
+                (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv_det,
+                (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv_det,
+            ],
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles an ICC profile from raw tag data.
+    fn profile(version: u8, color_space: &[u8; 4], tags: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut table = Vec::new();
+        let mut data = Vec::new();
+        let data_start = 132 + tags.len() * 12;
+        for (signature, tag) in tags.iter() {
+            table.extend(*signature);
+            table.extend(((data_start + data.len()) as u32).to_be_bytes());
+            table.extend((tag.len() as u32).to_be_bytes());
+            data.extend(tag);
+            data.resize(data.len().next_multiple_of(4), 0);
+        }
+
+        let mut profile = vec![0u8; 128];
+        profile[0..4].copy_from_slice(&((data_start + data.len()) as u32).to_be_bytes());
+        profile[8] = version;
+        profile[12..16].copy_from_slice(b"mntr");
+        profile[16..20].copy_from_slice(color_space);
+        profile[20..24].copy_from_slice(b"XYZ ");
+        profile[36..40].copy_from_slice(b"acsp");
+        profile.extend((tags.len() as u32).to_be_bytes());
+        profile.extend(table);
+        profile.extend(data);
+        profile
+    }
+
+    /// Tag data from big-endian 32-bit words, after the type signature.
+    fn tag(kind: &[u8; 4], words: &[u32]) -> Vec<u8> {
+        let mut data = kind.to_vec();
+        data.extend([0; 4]);
+        data.extend(words.iter().flat_map(|n| n.to_be_bytes()));
+        data
+    }
+
+    /// The sRGB curve as a 1024 entry table, like in the widespread
+    /// "sRGB IEC61966-2.1" v2 profile.
+    fn srgb_table() -> Vec<u8> {
+        let srgb = TransferCurve::srgb();
+        let mut data = b"curv".to_vec();
+        data.extend([0; 4]);
+        data.extend(1024u32.to_be_bytes());
+        for i in 0..1024 {
+            let n = srgb.to_linear(i as f32 / 1023.0);
+            data.extend(((n * 65535.0).round() as u16).to_be_bytes());
+        }
+        data
+    }
+
+    /// The tags of the "sRGB IEC61966-2.1" v2 profile, which records
+    /// the D65 media white point instead of a chromatic adaptation.
+    fn srgb_v2() -> Vec<u8> {
+        profile(
+            2,
+            b"RGB ",
+            &[
+                (b"wtpt", tag(b"XYZ ", &[0xf351, 0x10000, 0x116cc])),
+                (b"rXYZ", tag(b"XYZ ", &[0x6fa2, 0x38f5, 0x0390])),
+                (b"gXYZ", tag(b"XYZ ", &[0x6299, 0xb785, 0x18da])),
+                (b"bXYZ", tag(b"XYZ ", &[0x24a0, 0x0f84, 0xb6cf])),
+                (b"rTRC", srgb_table()),
+                (b"gTRC", srgb_table()),
+                (b"bTRC", srgb_table()),
+            ],
+        )
+    }
+
+    /// The tags of Apple's "Display P3" v4 profile, with its chromatic
+    /// adaptation and parametric sRGB curve.
+    fn display_p3_v4() -> Vec<u8> {
+        let trc = || {
+            let mut data = b"para".to_vec();
+            data.extend([0; 4]);
+            data.extend([0, 3, 0, 0]);
+            for n in [0x26666u32, 0xf2a7, 0x0d59, 0x13d0, 0x0a5b] {
+                data.extend(n.to_be_bytes());
+            }
+            data
+        };
+        profile(
+            4,
+            b"RGB ",
+            &[
+                (b"wtpt", tag(b"XYZ ", &[0xf6d6, 0x10000, 0xd32d])),
+                (b"rXYZ", tag(b"XYZ ", &[0x83df, 0x3dbf, 0xffff_ffbb])),
+                (b"gXYZ", tag(b"XYZ ", &[0x4abf, 0xb137, 0x0ab9])),
+                (b"bXYZ", tag(b"XYZ ", &[0x2838, 0x110b, 0xc8b9])),
+                (
+                    b"chad",
+                    tag(
+                        b"sf32",
+                        &[
+                            0x10c42,
+                            0x05de,
+                            0xffff_f326,
+                            0x07f3,
+                            0xfd90,
+                            0xffff_fba1,
+                            0xffff_fda2,
+                            0x03dc,
+                            0xc06e,
+                        ],
+                    ),
+                ),
+                (b"rTRC", trc()),
+                (b"gTRC", trc()),
+                (b"bTRC", trc()),
+            ],
+        )
+    }
+
+    #[test]
+    fn srgb_profile() {
+        let profile = srgb_v2();
+        let color = ColorDescription::from_icc_profile(profile.clone());
+        assert!(color.chromaticities.unwrap().approx_eq(&REC709));
+        assert!(matches!(color.transfer, Some(TransferCurve::Table(ref t)) if t.len() == 1024));
+        assert!(color.is_srgb());
+        assert_eq!(color.icc_profile, Some(profile));
+    }
+
+    #[test]
+    fn display_p3_profile() {
+        let color = ColorDescription::from_icc_profile(display_p3_v4());
+        let p3 = Chromaticities {
+            r: (0.680, 0.320),
+            g: (0.265, 0.690),
+            b: (0.150, 0.060),
+            w: (0.3127, 0.3290),
+        };
+        assert!(color.chromaticities.unwrap().approx_eq(&p3));
+        assert!(matches!(
+            color.transfer,
+            Some(TransferCurve::Parametric { .. })
+        ));
+        assert!(color.transfer.as_ref().unwrap().is_srgb());
+        assert!(!color.is_srgb());
+    }
+
+    #[test]
+    fn gray_profile() {
+        let mut trc = b"curv".to_vec();
+        trc.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+        trc.extend((563u16).to_be_bytes());
+        let profile = profile(2, b"GRAY", &[(b"kTRC", trc)]);
+
+        let color = ColorDescription::from_icc_profile(profile);
+        assert_eq!(color.chromaticities, None);
+        assert_eq!(color.transfer, Some(TransferCurve::Gamma(563.0 / 256.0)));
+    }
+
+    #[test]
+    fn invalid_profiles() {
+        let empty = |profile: Vec<u8>| {
+            let color = ColorDescription::from_icc_profile(profile.clone());
+            color.chromaticities.is_none()
+                && color.transfer.is_none()
+                && color.icc_profile == Some(profile)
+        };
+
+        assert!(empty(Vec::new()));
+        assert!(empty(vec![0; 200]));
+
+        // Truncated anywhere, including in the tag table and tag data.
+        let profile = display_p3_v4();
+        for len in [131, 140, 200, 300] {
+            let color = ColorDescription::from_icc_profile(profile[..len].to_vec());
+            assert_eq!(color.transfer, None, "{}", len);
+        }
+
+        // A tag count that's far larger than the tag table.
+        let mut profile = srgb_v2();
+        profile[128..132].copy_from_slice(&u32::MAX.to_be_bytes());
+        let color = ColorDescription::from_icc_profile(profile);
+        assert!(color.chromaticities.unwrap().approx_eq(&REC709));
+
+        // Tag data past the end of the profile, or overflowing.
+        let mut profile = srgb_v2();
+        profile[136..140].copy_from_slice(&0xffff_fff0u32.to_be_bytes());
+        profile[148..152].copy_from_slice(&0x7fff_ffffu32.to_be_bytes());
+        let color = ColorDescription::from_icc_profile(profile);
+        assert_eq!(color.chromaticities, None);
+        assert!(color.transfer.unwrap().is_srgb());
+    }
+}
@@ -0,0 +1,818 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{error::ReadError, BitDepth, ChannelLayout, Format, Image, ImageBuf, ProbeInfo};
+
+/// Header fields of DPX and Cineon files that describe how the pixel
+/// data is encoded.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FilmHeader {
+    /// The transfer characteristic of the code values.  Always
+    /// `PrintingDensity` for Cineon files.
+    pub transfer: Characteristic,
+
+    /// The colorimetric specification of the code values.  Always
+    /// `PrintingDensity` for Cineon files.
+    pub colorimetric: Characteristic,
+
+    /// The bit depth of the code values in the file.
+    pub code_bits: u8,
+
+    /// The code values of reference black and white, if specified.
+    /// Cineon files don't specify them, but conventionally use 95 and
+    /// 685 for 10-bit data.
+    pub black_code: Option<u32>,
+    pub white_code: Option<u32>,
+
+    /// The SMPTE timecode of the frame, if specified.
+    pub timecode: Option<Timecode>,
+}
+
+/// DPX transfer characteristic and colorimetric specification codes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Characteristic {
+    UserDefined,
+    PrintingDensity,
+    Linear,
+    Logarithmic,
+    UnspecifiedVideo,
+    Smpte274M,
+    Rec709,
+    Rec601Bg,
+    Rec601M,
+    NtscComposite,
+    PalComposite,
+    ZLinear,
+    ZHomogeneous,
+
+    /// Codes not covered above, e.g. from newer revisions of the spec.
+    Other(u8),
+}
+
+impl Characteristic {
+    pub fn from_code(code: u8) -> Characteristic {
+        use Characteristic::*;
+        match code {
+            0 => UserDefined,
+            1 => PrintingDensity,
+            2 => Linear,
+            3 => Logarithmic,
+            4 => UnspecifiedVideo,
+            5 => Smpte274M,
+            6 => Rec709,
+            7 => Rec601Bg,
+            8 => Rec601M,
+            9 => NtscComposite,
+            10 => PalComposite,
+            11 => ZLinear,
+            12 => ZHomogeneous,
+            _ => Other(code),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+}
+
+impl Timecode {
+    /// Decodes a SMPTE 12M binary-coded-decimal timecode, ignoring the
+    /// flag bits.
+    fn from_bcd(bcd: u32) -> Option<Timecode> {
+        let digits = |byte: u32, tens_mask: u32| -> Option<u8> {
+            let tens = (byte >> 4) & tens_mask;
+            let units = byte & 0xf;
+            if tens > 9 || units > 9 {
+                None
+            } else {
+                Some((tens * 10 + units) as u8)
+            }
+        };
+
+        Some(Timecode {
+            hours: digits(bcd >> 24, 0x3)?,
+            minutes: digits((bcd >> 16) & 0xff, 0x7)?,
+            seconds: digits((bcd >> 8) & 0xff, 0x7)?,
+            frames: digits(bcd & 0xff, 0x3)?,
+        })
+    }
+}
+
+impl std::fmt::Display for Timecode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}:{:02}",
+            self.hours, self.minutes, self.seconds, self.frames
+        )
+    }
+}
+
+//-------------------------------------------------------------
+
+/// Size of the generic file and image headers.
+const GENERIC_HEADER_SIZE: usize = 1664;
+
+/// Marks unspecified 32-bit header fields.
+const UNDEFINED: u32 = 0xffff_ffff;
+
+/// The parts of a DPX header needed for decoding.
+struct DpxHeader {
+    layout: PixelLayout,
+    data_offset: u64,
+    film: FilmHeader,
+}
+
+pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
+    let header = read_header(&mut reader)?;
+    reader.seek(SeekFrom::Start(header.data_offset))?;
+    read_pixels(&mut reader, &header.layout, Format::Dpx)
+}
+
+pub fn probe<R: Read + Seek>(mut reader: R) -> Result<ProbeInfo, ReadError> {
+    let header = read_header(&mut reader)?;
+    Ok(header.layout.probe_info(Format::Dpx))
+}
+
+pub fn film_header<R: Read + Seek>(mut reader: R) -> Result<FilmHeader, ReadError> {
+    Ok(read_header(&mut reader)?.film)
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<DpxHeader, ReadError> {
+    let corrupt = |detail: &str| ReadError::Corrupt {
+        format: Format::Dpx,
+        detail: detail.into(),
+    };
+
+    // The industry headers are optional, so only the generic headers
+    // are required to be present.
+    let mut bytes = Vec::with_capacity(2048);
+    reader.take(2048).read_to_end(&mut bytes)?;
+    if bytes.len() < GENERIC_HEADER_SIZE {
+        return Err(corrupt("truncated header"));
+    }
+    let big_endian = match &bytes[0..4] {
+        b"SDPX" => true,
+        b"XPDS" => false,
+        _ => return Err(ReadError::UnknownFormat),
+    };
+    let h = HeaderBytes {
+        bytes: &bytes,
+        big_endian,
+    };
+
+    let image_offset = h.u32_at(4);
+    let industry_header_size = h.u32_at(28);
+    let orientation = h.u16_at(768);
+    let element_count = h.u16_at(770);
+    let width = h.u32_at(772) as usize;
+    let height = h.u32_at(776) as usize;
+    if element_count == 0 || width == 0 || height == 0 {
+        return Err(corrupt("no image data"));
+    }
+
+    // Only the first image element is read.
+    let e = 780;
+    let is_signed = h.u32_at(e) == 1;
+    let black_code = h.u32_at(e + 4);
+    let white_code = h.u32_at(e + 12);
+    let descriptor = h.u8_at(e + 20);
+    let transfer = h.u8_at(e + 21);
+    let colorimetric = h.u8_at(e + 22);
+    let bits = h.u8_at(e + 23);
+    let packing = h.u16_at(e + 24);
+    let encoding = h.u16_at(e + 26);
+    let element_offset = h.u32_at(e + 28);
+    let eol_padding = h.u32_at(e + 32);
+
+    let channels = match descriptor {
+        6 => Channels::Gray,
+        50 => Channels::Rgb,
+        51 => Channels::Rgba,
+        52 => Channels::Abgr,
+        _ => return Err(ReadError::UnsupportedFeature),
+    };
+    let packing = match packing {
+        0 => Packing::Packed,
+        1 => Packing::FilledA,
+        2 => Packing::FilledB,
+        _ => return Err(ReadError::UnsupportedFeature),
+    };
+    if is_signed || encoding != 0 || ![8, 10, 12, 16].contains(&bits) {
+        return Err(ReadError::UnsupportedFeature);
+    }
+
+    let data_offset = if element_offset != 0 && element_offset != UNDEFINED {
+        element_offset
+    } else {
+        image_offset
+    };
+
+    // The television header, with the timecode, follows the film
+    // header.
+    let timecode =
+        if industry_header_size != 0 && industry_header_size != UNDEFINED && bytes.len() >= 1924 {
+            Some(h.u32_at(1920))
+                .filter(|&tc| tc != UNDEFINED)
+                .and_then(Timecode::from_bcd)
+        } else {
+            None
+        };
+
+    let defined = |n: u32| if n == UNDEFINED { None } else { Some(n) };
+
+    Ok(DpxHeader {
+        layout: PixelLayout {
+            width,
+            height,
+            channels,
+            bits,
+            packing,
+            big_endian,
+            eol_padding: defined(eol_padding).unwrap_or(0) as usize,
+            orientation,
+        },
+        data_offset: data_offset as u64,
+        film: FilmHeader {
+            transfer: Characteristic::from_code(transfer),
+            colorimetric: Characteristic::from_code(colorimetric),
+            code_bits: bits,
+            black_code: defined(black_code),
+            white_code: defined(white_code),
+            timecode,
+        },
+    })
+}
+
+//-------------------------------------------------------------
+// Shared with the Cineon loader.
+
+/// Endian-aware access to raw header fields.
+pub(crate) struct HeaderBytes<'a> {
+    pub bytes: &'a [u8],
+    pub big_endian: bool,
+}
+
+impl<'a> HeaderBytes<'a> {
+    pub fn u8_at(&self, offset: usize) -> u8 {
+        self.bytes[offset]
+    }
+
+    pub fn u16_at(&self, offset: usize) -> u16 {
+        let b = [self.bytes[offset], self.bytes[offset + 1]];
+        if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        }
+    }
+
+    pub fn u32_at(&self, offset: usize) -> u32 {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(&self.bytes[offset..(offset + 4)]);
+        if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+}
+
+/// The channels of an image element, in storage order.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Channels {
+    Gray,
+    Rgb,
+    Rgba,
+    Abgr,
+}
+
+impl Channels {
+    fn count(&self) -> usize {
+        match *self {
+            Channels::Gray => 1,
+            Channels::Rgb => 3,
+            Channels::Rgba | Channels::Abgr => 4,
+        }
+    }
+}
+
+/// How samples are packed into 32-bit words.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Packing {
+    /// Samples are packed back-to-back, starting from the least
+    /// significant bit, and may span word boundaries.
+    Packed,
+
+    /// Samples are padded to word (10-bit) or 16-bit (12-bit)
+    /// boundaries, with the padding in the least significant bits.
+    FilledA,
+
+    /// Like `FilledA`, but with the padding in the most significant
+    /// bits.
+    FilledB,
+}
+
+pub(crate) struct PixelLayout {
+    pub width: usize,
+    pub height: usize,
+    pub channels: Channels,
+    pub bits: u8,
+    pub packing: Packing,
+    pub big_endian: bool,
+    pub eol_padding: usize,
+
+    /// 0 = left-to-right, top-to-bottom.  1-3 additionally flip
+    /// horizontally, vertically, or both.  4-7 (transposed) aren't
+    /// supported.
+    pub orientation: u16,
+}
+
+impl PixelLayout {
+    /// The number of bytes of a row, not including end-of-line padding,
+    /// or `None` if it overflows.
+    fn row_bytes(&self) -> Option<usize> {
+        let samples = self.width.checked_mul(self.channels.count())?;
+        match (self.bits, self.packing) {
+            (8, _) => Some(samples),
+            (16, _) | (12, Packing::FilledA | Packing::FilledB) => samples.checked_mul(2),
+            (10, Packing::FilledA | Packing::FilledB) => samples.div_ceil(3).checked_mul(4),
+            (_, _) => Some(samples.checked_mul(self.bits as usize)?.div_ceil(32) * 4),
+        }
+    }
+
+    pub fn probe_info(&self, format: Format) -> ProbeInfo {
+        ProbeInfo {
+            format,
+            dimensions: (self.width, self.height),
+            channels: match self.channels {
+                Channels::Gray => ChannelLayout::Gray,
+                Channels::Rgb => ChannelLayout::Rgb,
+                Channels::Rgba | Channels::Abgr => ChannelLayout::Rgba,
+            },
+            bit_depth: Some(if self.bits == 8 {
+                BitDepth::U8
+            } else {
+                BitDepth::U16
+            }),
+            frame_count: 1,
+        }
+    }
+}
+
+/// The most samples `read_pixels()` will decode.  Anything larger is
+/// almost certainly a corrupt or malicious header.
+const MAX_SAMPLES: usize = 1 << 30;
+
+/// Reads the pixel data, starting at the reader's current position.
+///
+/// 8-bit data is returned as 8-bit buffers, and everything else as
+/// 16-bit buffers spanning the full 16-bit range.
+pub(crate) fn read_pixels<R: Read>(
+    reader: &mut R,
+    layout: &PixelLayout,
+    format: Format,
+) -> Result<Image, ReadError> {
+    if layout.orientation > 3 {
+        return Err(ReadError::UnsupportedFeature);
+    }
+
+    let row_samples = layout.width.checked_mul(layout.channels.count());
+    let total_samples = row_samples.and_then(|n| n.checked_mul(layout.height));
+    let (row_samples, row_bytes) = match (row_samples, total_samples, layout.row_bytes()) {
+        (Some(row_samples), Some(total), Some(row_bytes)) if total <= MAX_SAMPLES => {
+            (row_samples, row_bytes)
+        }
+        _ => return Err(ReadError::LimitsExceeded { format }),
+    };
+    let truncated = || ReadError::Corrupt {
+        format,
+        detail: "truncated pixel data".into(),
+    };
+
+    // The buffers grow as rows are actually read, so a header that
+    // claims more data than the file has doesn't allocate it up front.
+    let mut row = vec![0u8; row_bytes];
+    let mut samples_8 = Vec::new();
+    let mut samples_16 = Vec::new();
+    for y in 0..layout.height {
+        reader.read_exact(&mut row).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => truncated(),
+            _ => ReadError::IO(e),
+        })?;
+        if layout.bits == 8 {
+            samples_8.extend_from_slice(&row[..row_samples]);
+        } else {
+            unpack_row(&row, row_samples, layout, &mut samples_16);
+        }
+
+        // The last row's padding may be missing.
+        if y + 1 < layout.height && layout.eol_padding > 0 {
+            let padding = layout.eol_padding as u64;
+            if std::io::copy(&mut reader.take(padding), &mut std::io::sink())? < padding {
+                return Err(truncated());
+            }
+        }
+    }
+
+    let image_data = if layout.bits == 8 {
+        let (samples, has_alpha) = arrange(samples_8, layout);
+        if has_alpha {
+            ImageBuf::Rgba8(samples)
+        } else {
+            ImageBuf::Rgb8(samples)
+        }
+    } else {
+        let (samples, has_alpha) = arrange(samples_16, layout);
+        if has_alpha {
+            ImageBuf::Rgba16(samples)
+        } else {
+            ImageBuf::Rgb16(samples)
+        }
+    };
+
+    Ok(Image {
+        dimensions: (layout.width, layout.height),
+        data: image_data,
+        // The film header describes the encoding instead.
+        color: None,
+    })
+}
+
+/// Unpacks a row of 10, 12, or 16-bit samples, scaling them to the full
+/// 16-bit range.
+fn unpack_row(row: &[u8], sample_count: usize, layout: &PixelLayout, out: &mut Vec<u16>) {
+    let bits = layout.bits as u32;
+    let read_u16 = |b: &[u8]| {
+        if layout.big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        }
+    };
+    let read_u32 = |b: &[u8]| {
+        if layout.big_endian {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        }
+    };
+    // Bit replication, so that max values map to max values.
+    let scale = |v: u32| -> u16 {
+        if bits == 16 {
+            v as u16
+        } else {
+            ((v << (16 - bits)) | (v >> (2 * bits - 16))) as u16
+        }
+    };
+    let mask = (1u32 << bits) - 1;
+
+    let start = out.len();
+    match (layout.bits, layout.packing) {
+        (16, _) => {
+            out.extend(row.chunks_exact(2).map(read_u16));
+        }
+
+        (12, Packing::FilledA) => {
+            out.extend(row.chunks_exact(2).map(|b| scale(read_u16(b) as u32 >> 4)));
+        }
+        (12, Packing::FilledB) => {
+            out.extend(
+                row.chunks_exact(2)
+                    .map(|b| scale(read_u16(b) as u32 & mask)),
+            );
+        }
+
+        (10, Packing::FilledA | Packing::FilledB) => {
+            let shifts = if layout.packing == Packing::FilledA {
+                [22, 12, 2]
+            } else {
+                [20, 10, 0]
+            };
+            for word in row.chunks_exact(4).map(read_u32) {
+                for shift in shifts {
+                    out.push(scale((word >> shift) & mask));
+                }
+            }
+        }
+
+        (_, _) => {
+            let words: Vec<u32> = row.chunks_exact(4).map(read_u32).collect();
+            for i in 0..sample_count {
+                let bit = i * bits as usize;
+                let (word_i, shift) = (bit / 32, (bit % 32) as u32);
+                let mut v = words[word_i] >> shift;
+                if shift + bits > 32 {
+                    v |= words[word_i + 1] << (32 - shift);
+                }
+                out.push(scale(v & mask));
+            }
+        }
+    }
+    out.truncate(start + sample_count);
+}
+
+/// Converts samples in storage order to `RGBRGB...` or `RGBARGBA...`,
+/// applying the orientation.  Returns whether there's alpha.
+fn arrange<T: Copy + Default>(samples: Vec<T>, layout: &PixelLayout) -> (Vec<T>, bool) {
+    let (width, height) = (layout.width, layout.height);
+    let in_count = layout.channels.count();
+    let (out_count, order): (usize, &[usize]) = match layout.channels {
+        Channels::Gray => (3, &[0, 0, 0]),
+        Channels::Rgb => (3, &[0, 1, 2]),
+        Channels::Rgba => (4, &[0, 1, 2, 3]),
+        Channels::Abgr => (4, &[3, 2, 1, 0]),
+    };
+    let flip_x = layout.orientation & 1 != 0;
+    let flip_y = layout.orientation & 2 != 0;
+
+    if matches!(layout.channels, Channels::Rgb | Channels::Rgba) && !flip_x && !flip_y {
+        return (samples, out_count == 4);
+    }
+
+    let mut out = vec![T::default(); width * height * out_count];
+    for y in 0..height {
+        let src_y = if flip_y { height - 1 - y } else { y };
+        for x in 0..width {
+            let src_x = if flip_x { width - 1 - x } else { x };
+            let src = (src_y * width + src_x) * in_count;
+            let dst = (y * width + x) * out_count;
+            for (c, &i) in order.iter().enumerate() {
+                out[dst + c] = samples[src + i];
+            }
+        }
+    }
+
+    (out, out_count == 4)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Packs one row of samples as stored in a DPX or Cineon file.
+    pub(crate) fn pack_row(
+        samples: &[u32],
+        bits: u8,
+        packing: Packing,
+        big_endian: bool,
+    ) -> Vec<u8> {
+        let u16_bytes = |n: u32| {
+            if big_endian {
+                (n as u16).to_be_bytes()
+            } else {
+                (n as u16).to_le_bytes()
+            }
+        };
+        let u32_bytes = |n: u32| {
+            if big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            }
+        };
+
+        match (bits, packing) {
+            (8, _) => samples.iter().map(|&n| n as u8).collect(),
+            (16, _) => samples.iter().flat_map(|&n| u16_bytes(n)).collect(),
+            (12, Packing::FilledA) => samples.iter().flat_map(|&n| u16_bytes(n << 4)).collect(),
+            (12, Packing::FilledB) => samples.iter().flat_map(|&n| u16_bytes(n)).collect(),
+            (10, Packing::FilledA | Packing::FilledB) => {
+                let shifts = if packing == Packing::FilledA {
+                    [22, 12, 2]
+                } else {
+                    [20, 10, 0]
+                };
+                samples
+                    .chunks(3)
+                    .flat_map(|chunk| {
+                        let word = chunk
+                            .iter()
+                            .zip(shifts.iter())
+                            .fold(0u32, |word, (&n, shift)| word | (n << shift));
+                        u32_bytes(word)
+                    })
+                    .collect()
+            }
+            (_, _) => {
+                let mut words = vec![0u32; (samples.len() * bits as usize).div_ceil(32)];
+                for (i, &n) in samples.iter().enumerate() {
+                    let bit = i * bits as usize;
+                    let (word_i, shift) = (bit / 32, bit % 32);
+                    words[word_i] |= n << shift;
+                    if shift + bits as usize > 32 {
+                        words[word_i + 1] |= n >> (32 - shift);
+                    }
+                }
+                words.into_iter().flat_map(u32_bytes).collect()
+            }
+        }
+    }
+
+    /// Test samples that exercise all bits, including the extremes.
+    pub(crate) fn test_samples(count: usize, bits: u8) -> Vec<u32> {
+        let max = (1u32 << bits) - 1;
+        (0..count)
+            .map(|i| match i % 4 {
+                0 => 0,
+                1 => max,
+                _ => (i as u32).wrapping_mul(2654435761) & max,
+            })
+            .collect()
+    }
+
+    /// What `read_pixels()` should turn a sample into.
+    pub(crate) fn expand(n: u32, bits: u8) -> u16 {
+        match bits {
+            16 => n as u16,
+            _ => ((n << (16 - bits)) | (n >> (2 * bits - 16))) as u16,
+        }
+    }
+
+    fn dpx_file(
+        width: usize,
+        height: usize,
+        descriptor: u8,
+        bits: u8,
+        packing: Packing,
+        big_endian: bool,
+        samples: &[u32],
+    ) -> Vec<u8> {
+        let mut file = vec![0u8; 2048];
+        let put_u16 = |file: &mut Vec<u8>, offset: usize, n: u16| {
+            let b = if big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            };
+            file[offset..(offset + 2)].copy_from_slice(&b);
+        };
+        let put_u32 = |file: &mut Vec<u8>, offset: usize, n: u32| {
+            let b = if big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            };
+            file[offset..(offset + 4)].copy_from_slice(&b);
+        };
+
+        file[0..4].copy_from_slice(if big_endian { b"SDPX" } else { b"XPDS" });
+        put_u32(&mut file, 4, 2048);
+        put_u32(&mut file, 28, UNDEFINED);
+        put_u16(&mut file, 770, 1);
+        put_u32(&mut file, 772, width as u32);
+        put_u32(&mut file, 776, height as u32);
+        put_u32(&mut file, 784, 64);
+        put_u32(&mut file, 792, 940);
+        file[800] = descriptor;
+        file[801] = 2;
+        file[802] = 6;
+        file[803] = bits;
+        let method = match packing {
+            Packing::Packed => 0,
+            Packing::FilledA => 1,
+            Packing::FilledB => 2,
+        };
+        put_u16(&mut file, 804, method);
+        put_u32(&mut file, 808, 2048);
+        put_u32(&mut file, 812, UNDEFINED);
+
+        let row_len = samples.len() / height;
+        for row in samples.chunks(row_len) {
+            file.extend(pack_row(row, bits, packing, big_endian));
+        }
+        file
+    }
+
+    fn load(file: &[u8]) -> Result<Image, ReadError> {
+        super::load(std::io::Cursor::new(file))
+    }
+
+    #[test]
+    fn bit_depths_and_packings() {
+        let (width, height) = (5, 3);
+        let cases = [
+            (8, Packing::Packed),
+            (8, Packing::FilledA),
+            (10, Packing::FilledA),
+            (10, Packing::FilledB),
+            (10, Packing::Packed),
+            (12, Packing::FilledA),
+            (12, Packing::FilledB),
+            (12, Packing::Packed),
+            (16, Packing::Packed),
+            (16, Packing::FilledA),
+        ];
+        for (bits, packing) in cases {
+            for big_endian in [true, false] {
+                let samples = test_samples(width * height * 3, bits);
+                let file = dpx_file(width, height, 50, bits, packing, big_endian, &samples);
+                let image = load(&file).unwrap();
+                assert_eq!(image.dimensions, (width, height));
+
+                match image.data {
+                    ImageBuf::Rgb8(data) => {
+                        assert_eq!(bits, 8);
+                        let expected: Vec<u8> = samples.iter().map(|&n| n as u8).collect();
+                        assert_eq!(data, expected);
+                    }
+                    ImageBuf::Rgb16(data) => {
+                        let expected: Vec<u16> = samples.iter().map(|&n| expand(n, bits)).collect();
+                        assert_eq!(
+                            data, expected,
+                            "{} bits, {:?}, {}",
+                            bits, packing, big_endian
+                        );
+                    }
+                    _ => panic!("unexpected buffer type"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn max_values_expand_to_max() {
+        for bits in [10, 12] {
+            assert_eq!(expand((1 << bits) - 1, bits), 0xffff);
+        }
+    }
+
+    #[test]
+    fn gray_and_abgr() {
+        let samples = test_samples(4 * 2, 10);
+        let file = dpx_file(4, 2, 6, 10, Packing::FilledA, true, &samples);
+        match load(&file).unwrap().data {
+            ImageBuf::Rgb16(data) => {
+                for (pixel, &n) in data.chunks(3).zip(samples.iter()) {
+                    assert_eq!(pixel, [expand(n, 10); 3]);
+                }
+            }
+            _ => panic!("unexpected buffer type"),
+        }
+
+        let samples = test_samples(3 * 2 * 4, 8);
+        let file = dpx_file(3, 2, 52, 8, Packing::Packed, false, &samples);
+        match load(&file).unwrap().data {
+            ImageBuf::Rgba8(data) => {
+                for (pixel, abgr) in data.chunks(4).zip(samples.chunks(4)) {
+                    assert_eq!(pixel, [abgr[3], abgr[2], abgr[1], abgr[0]].map(|n| n as u8));
+                }
+            }
+            _ => panic!("unexpected buffer type"),
+        }
+    }
+
+    #[test]
+    fn header_fields() {
+        let file = dpx_file(2, 2, 50, 10, Packing::FilledA, true, &test_samples(12, 10));
+        let film = film_header(std::io::Cursor::new(&file)).unwrap();
+        assert_eq!(film.transfer, Characteristic::Linear);
+        assert_eq!(film.colorimetric, Characteristic::Rec709);
+        assert_eq!(film.code_bits, 10);
+        assert_eq!((film.black_code, film.white_code), (Some(64), Some(940)));
+        assert_eq!(film.timecode, None);
+    }
+
+    #[test]
+    fn truncated() {
+        let file = dpx_file(5, 3, 50, 10, Packing::FilledA, true, &test_samples(45, 10));
+        assert!(matches!(
+            load(&file[..(file.len() - 1)]),
+            Err(ReadError::Corrupt { .. })
+        ));
+        assert!(matches!(
+            load(&file[..1000]),
+            Err(ReadError::Corrupt { .. })
+        ));
+        assert!(matches!(load(&file[..2]), Err(ReadError::Corrupt { .. })));
+    }
+
+    #[test]
+    fn hostile_header() {
+        let mut file = dpx_file(5, 3, 50, 16, Packing::Packed, true, &test_samples(45, 16));
+
+        // Dimensions whose data size overflows.
+        file[772..776].copy_from_slice(&u32::MAX.to_be_bytes());
+        file[776..780].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(load(&file), Err(ReadError::LimitsExceeded { .. })));
+
+        // Too large to be plausible, but not overflowing.
+        file[772..776].copy_from_slice(&100_000u32.to_be_bytes());
+        file[776..780].copy_from_slice(&100_000u32.to_be_bytes());
+        assert!(matches!(load(&file), Err(ReadError::LimitsExceeded { .. })));
+
+        // Plausible, but far more data than the file has.
+        file[772..776].copy_from_slice(&10_000u32.to_be_bytes());
+        file[776..780].copy_from_slice(&10_000u32.to_be_bytes());
+        assert!(matches!(load(&file), Err(ReadError::Corrupt { .. })));
+
+        // Huge end-of-line padding.
+        file[772..776].copy_from_slice(&5u32.to_be_bytes());
+        file[776..780].copy_from_slice(&3u32.to_be_bytes());
+        file[812..816].copy_from_slice(&0xffff_fff0u32.to_be_bytes());
+        assert!(matches!(load(&file), Err(ReadError::Corrupt { .. })));
+    }
+}
@@ -1,8 +1,35 @@
+use crate::Format;
+
 #[derive(Debug)]
 pub enum ReadError {
     IO(std::io::Error),
     UnknownFormat,
     UnsupportedFeature,
+
+    /// The file format was recognized, but the file's contents are
+    /// invalid or truncated.
+    Corrupt {
+        format: Format,
+        detail: String,
+    },
+
+    /// The image is too large to decode within the decoder's limits.
+    LimitsExceeded {
+        format: Format,
+    },
+
+    /// The decoder failed for reasons unrelated to the file itself,
+    /// e.g. an internal error.
+    Decoder {
+        format: Format,
+        detail: String,
+    },
+
+    /// A decoder for a format outside of this crate (e.g. camera raw)
+    /// failed, with its error message.
+    External {
+        detail: String,
+    },
 }
 
 impl std::error::Error for ReadError {
@@ -20,6 +47,55 @@ impl std::fmt::Display for ReadError {
             ReadError::IO(e) => e.fmt(f),
             ReadError::UnknownFormat => write!(f, "UnknownFormat: could not determine the image file format."),
             ReadError::UnsupportedFeature => write!(f, "UnsupportedFeature: the image file uses a feature that is currently unsupported such that image loading isn't possible."),
+            ReadError::Corrupt { format, detail } => write!(f, "Corrupt: the {} file is invalid or damaged ({}).", format, detail),
+            ReadError::LimitsExceeded { format } => write!(f, "LimitsExceeded: the {} file is too large to decode.", format),
+            ReadError::Decoder { format, detail } => write!(f, "Decoder: the {} decoder failed ({}).", format, detail),
+            ReadError::External { detail } => write!(f, "External: {}.", detail),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WriteError {
+    IO(std::io::Error),
+    UnsupportedFormat,
+    UnsupportedFeature,
+    InvalidImage,
+}
+
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WriteError::IO(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteError::IO(e) => e.fmt(f),
+            WriteError::UnsupportedFormat => write!(f, "UnsupportedFormat: writing to the requested image file format isn't supported."),
+            WriteError::UnsupportedFeature => write!(f, "UnsupportedFeature: the image file format can't store the image data as requested (e.g. the bit depth)."),
+            WriteError::InvalidImage => write!(f, "InvalidImage: the image data doesn't match its dimensions, or the dimensions are invalid for the file format."),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ResizeError {
+    ZeroSize,
+    InvalidImage,
+}
+
+impl std::error::Error for ResizeError {}
+
+impl std::fmt::Display for ResizeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResizeError::ZeroSize => write!(f, "ZeroSize: the source or target image has a zero width or height."),
+            ResizeError::InvalidImage => write!(f, "InvalidImage: the image data doesn't match its dimensions, or the dimensions are too large to resize."),
         }
     }
 }
@@ -38,12 +114,18 @@ impl From<tiff::TiffError> for ReadError {
         use tiff::TiffError::*;
         match other {
             IoError(e) => Self::IO(e),
-            FormatError(_) => Self::UnknownFormat,
-
+            FormatError(e) => Self::Corrupt {
+                format: Format::Tiff,
+                detail: e.to_string(),
+            },
             IntSizeError | UnsupportedError(_) => Self::UnsupportedFeature,
-
-            LimitsExceeded => panic!(),
-            UsageError(_) => panic!(),
+            LimitsExceeded => Self::LimitsExceeded {
+                format: Format::Tiff,
+            },
+            UsageError(e) => Self::Decoder {
+                format: Format::Tiff,
+                detail: e.to_string(),
+            },
         }
     }
 }
@@ -53,10 +135,17 @@ impl From<png::DecodingError> for ReadError {
         use png::DecodingError::*;
         match other {
             IoError(e) => Self::IO(e),
-            Format(_) => Self::UnknownFormat,
-
-            Parameter(_) => panic!(),
-            LimitsExceeded => panic!(),
+            Format(e) => Self::Corrupt {
+                format: crate::Format::Png,
+                detail: e.to_string(),
+            },
+            LimitsExceeded => Self::LimitsExceeded {
+                format: crate::Format::Png,
+            },
+            Parameter(e) => Self::Decoder {
+                format: crate::Format::Png,
+                detail: e.to_string(),
+            },
         }
     }
 }
@@ -66,10 +155,96 @@ impl From<jpeg_decoder::Error> for ReadError {
         use jpeg_decoder::Error::*;
         match other {
             Io(e) => Self::IO(e),
-            Format(_) => Self::UnknownFormat,
+            Format(detail) => Self::Corrupt {
+                format: crate::Format::Jpeg,
+                detail,
+            },
             Unsupported(_) => Self::UnsupportedFeature,
+            Internal(e) => Self::Decoder {
+                format: crate::Format::Jpeg,
+                detail: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<exr::error::Error> for ReadError {
+    fn from(other: exr::error::Error) -> Self {
+        use exr::error::Error::*;
+        match other {
+            Io(e) => Self::IO(e),
+            Invalid(detail) => Self::Corrupt {
+                format: Format::Exr,
+                detail: detail.to_string(),
+            },
+            NotSupported(_) => Self::UnsupportedFeature,
+
+            Aborted => Self::IO(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "exr decoding was aborted",
+            )),
+        }
+    }
+}
+
+impl From<image_webp::DecodingError> for ReadError {
+    fn from(other: image_webp::DecodingError) -> Self {
+        use image_webp::DecodingError::*;
+        match other {
+            IoError(e) => Self::IO(e),
+            RiffSignatureInvalid(_) | WebpSignatureInvalid(_) => Self::UnknownFormat,
+            UnsupportedFeature(_) => Self::UnsupportedFeature,
+            ImageTooLarge => Self::LimitsExceeded {
+                format: Format::WebP,
+            },
+            e => Self::Corrupt {
+                format: Format::WebP,
+                detail: e.to_string(),
+            },
+        }
+    }
+}
+
+impl From<std::io::Error> for WriteError {
+    fn from(other: std::io::Error) -> Self {
+        Self::IO(other)
+    }
+}
+
+impl From<tiff::TiffError> for WriteError {
+    fn from(other: tiff::TiffError) -> Self {
+        use tiff::TiffError::*;
+        match other {
+            IoError(e) => Self::IO(e),
+            FormatError(_) | UsageError(_) => Self::InvalidImage,
+            IntSizeError | UnsupportedError(_) | LimitsExceeded => Self::UnsupportedFeature,
+        }
+    }
+}
+
+impl From<png::EncodingError> for WriteError {
+    fn from(other: png::EncodingError) -> Self {
+        use png::EncodingError::*;
+        match other {
+            IoError(e) => Self::IO(e),
+            Format(_) | Parameter(_) => Self::InvalidImage,
+            LimitsExceeded => Self::UnsupportedFeature,
+        }
+    }
+}
+
+impl From<exr::error::Error> for WriteError {
+    fn from(other: exr::error::Error) -> Self {
+        use exr::error::Error::*;
+        match other {
+            Io(e) => Self::IO(e),
+            Invalid(_) => Self::InvalidImage,
+            NotSupported(_) => Self::UnsupportedFeature,
 
-            Internal(_) => panic!(),
+            Aborted => Self::IO(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                "exr encoding was aborted",
+            )),
         }
     }
 }
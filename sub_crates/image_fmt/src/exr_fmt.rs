@@ -0,0 +1,223 @@
+use std::io::{BufReader, Read, Seek, Write};
+
+use exr::{
+    image::RgbaChannels,
+    meta::attribute::SampleType,
+    prelude::{f16, traits::*, Vec2},
+};
+
+use crate::{
+    error::{ReadError, WriteError},
+    BitDepth, ChannelLayout, Chromaticities, ColorDescription, Format, Image, ImageBuf, ProbeInfo,
+    TransferCurve,
+};
+
+/// Temporary pixel storage used while decoding.
+///
+/// All samples are decoded to f32 regardless of how they're stored in
+/// the file, and are converted to the final buffer type afterwards.
+struct ExrPixels {
+    width: usize,
+    has_alpha: bool,
+    is_half: bool,
+    data: Vec<f32>, // RGBARGBARGBA...
+}
+
+pub fn load<R: Read + Seek>(reader: R) -> Result<Image, ReadError> {
+    // Reads the first layer that has RGB channels.  Both scanline and
+    // tiled files are handled by the exr crate, and for multi-resolution
+    // files we only take the full-resolution level.
+    let image = exr::prelude::read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .rgba_channels(
+            |resolution: Vec2<usize>, (r, g, b, a): &RgbaChannels| {
+                let is_half = [r, g, b].iter().all(|c| c.sample_type == SampleType::F16)
+                    && a.as_ref()
+                        .map(|a| a.sample_type == SampleType::F16)
+                        .unwrap_or(true);
+
+                ExrPixels {
+                    width: resolution.width(),
+                    has_alpha: a.is_some(),
+                    is_half,
+                    data: vec![0.0; resolution.width() * resolution.height() * 4],
+                }
+            },
+            |pixels: &mut ExrPixels, position: Vec2<usize>, (r, g, b, a): (f32, f32, f32, f32)| {
+                let i = (position.y() * pixels.width + position.x()) * 4;
+                pixels.data[i..(i + 4)].copy_from_slice(&[r, g, b, a]);
+            },
+        )
+        .first_valid_layer()
+        .all_attributes()
+        .from_buffered(reader)?;
+
+    let layer = image.layer_data;
+    let dimensions = (layer.size.width(), layer.size.height());
+    let pixels = layer.channel_data.pixels;
+    assert_eq!(pixels.data.len(), dimensions.0 * dimensions.1 * 4);
+
+    let data = if pixels.is_half {
+        ImageBuf::RgbaF16(pixels.data.iter().map(|&v| f16::from_f32(v)).collect())
+    } else {
+        ImageBuf::RgbaF32(pixels.data)
+    };
+
+    // OpenEXR is always linear, and only optionally specifies its
+    // chromaticities.
+    let chromaticities = image.attributes.chromaticities.map(|chroma| {
+        let xy = |v: Vec2<f32>| (v.x() as f64, v.y() as f64);
+        Chromaticities {
+            r: xy(chroma.red),
+            g: xy(chroma.green),
+            b: xy(chroma.blue),
+            w: xy(chroma.white),
+        }
+    });
+
+    Ok(Image {
+        dimensions,
+        data: if pixels.has_alpha {
+            data
+        } else {
+            data.to_rgb()
+        },
+        color: Some(ColorDescription {
+            chromaticities,
+            transfer: Some(TransferCurve::Linear),
+            icc_profile: None,
+        }),
+    })
+}
+
+pub fn probe<R: Read + Seek>(reader: R) -> Result<ProbeInfo, ReadError> {
+    let meta = exr::meta::MetaData::read_from_buffered(BufReader::new(reader), false)?;
+
+    let has_channel = |header: &exr::meta::header::Header, name: &str| {
+        header.channels.list.iter().any(|c| c.name.eq(name))
+    };
+    let is_rgb = |header: &exr::meta::header::Header| {
+        ["R", "G", "B"].iter().all(|name| has_channel(header, name))
+    };
+
+    // Describe the same layer that `load()` would read, if any.
+    let header = meta
+        .headers
+        .iter()
+        .find(|header| is_rgb(header))
+        .unwrap_or(&meta.headers[0]);
+
+    let (channels, names): (_, &[&str]) = match (
+        is_rgb(header),
+        has_channel(header, "Y"),
+        has_channel(header, "A"),
+    ) {
+        (true, _, false) => (ChannelLayout::Rgb, &["R", "G", "B"]),
+        (true, _, true) => (ChannelLayout::Rgba, &["R", "G", "B", "A"]),
+        (false, true, false) => (ChannelLayout::Gray, &["Y"]),
+        (false, true, true) => (ChannelLayout::GrayAlpha, &["Y", "A"]),
+        (false, false, _) => (ChannelLayout::Other, &[]),
+    };
+
+    let sample_types: Vec<SampleType> = header
+        .channels
+        .list
+        .iter()
+        .filter(|c| names.is_empty() || names.iter().any(|name| c.name.eq(name)))
+        .map(|c| c.sample_type)
+        .collect();
+    let bit_depth = if sample_types.iter().all(|t| *t == SampleType::F16) {
+        Some(BitDepth::F16)
+    } else if sample_types.iter().all(|t| *t != SampleType::U32) {
+        Some(BitDepth::F32)
+    } else {
+        None
+    };
+
+    Ok(ProbeInfo {
+        format: Format::Exr,
+        dimensions: (header.layer_size.width(), header.layer_size.height()),
+        channels,
+        bit_depth,
+        frame_count: 1,
+    })
+}
+
+pub fn save<W: Write + Seek>(
+    writer: W,
+    image: &Image,
+    bit_depth: BitDepth,
+) -> Result<(), WriteError> {
+    use exr::prelude::{
+        Blocks, Compression, Encoding, Image as ExrImage, ImageAttributes, Layer, LayerAttributes,
+        LineOrder, SpecificChannels,
+    };
+
+    let dimensions = image.dimensions;
+    let has_alpha = image.data.has_alpha();
+    let channel_count = if has_alpha { 4 } else { 3 };
+    let data = match image.data.clone().to_f32() {
+        ImageBuf::RgbF32(data) | ImageBuf::RgbaF32(data) => data,
+        _ => unreachable!(),
+    };
+
+    let sample = |position: Vec2<usize>, channel: usize| -> f32 {
+        data[(position.y() * dimensions.0 + position.x()) * channel_count + channel]
+    };
+    let half_sample =
+        |position: Vec2<usize>, channel: usize| -> f16 { f16::from_f32(sample(position, channel)) };
+
+    let attributes = ImageAttributes::with_size(dimensions);
+    let encoding = Encoding {
+        compression: Compression::ZIP16,
+        blocks: Blocks::ScanLines,
+        line_order: LineOrder::Increasing,
+    };
+
+    match (bit_depth, has_alpha) {
+        (BitDepth::F16, false) => {
+            let channels = SpecificChannels::rgb(|p: Vec2<usize>| {
+                (half_sample(p, 0), half_sample(p, 1), half_sample(p, 2))
+            });
+            let layer = Layer::new(dimensions, LayerAttributes::default(), encoding, channels);
+            ExrImage::new(attributes, layer)
+                .write()
+                .to_buffered(writer)?;
+        }
+        (BitDepth::F16, true) => {
+            let channels = SpecificChannels::rgba(|p: Vec2<usize>| {
+                (
+                    half_sample(p, 0),
+                    half_sample(p, 1),
+                    half_sample(p, 2),
+                    half_sample(p, 3),
+                )
+            });
+            let layer = Layer::new(dimensions, LayerAttributes::default(), encoding, channels);
+            ExrImage::new(attributes, layer)
+                .write()
+                .to_buffered(writer)?;
+        }
+        (BitDepth::F32, false) => {
+            let channels =
+                SpecificChannels::rgb(|p: Vec2<usize>| (sample(p, 0), sample(p, 1), sample(p, 2)));
+            let layer = Layer::new(dimensions, LayerAttributes::default(), encoding, channels);
+            ExrImage::new(attributes, layer)
+                .write()
+                .to_buffered(writer)?;
+        }
+        (BitDepth::F32, true) => {
+            let channels = SpecificChannels::rgba(|p: Vec2<usize>| {
+                (sample(p, 0), sample(p, 1), sample(p, 2), sample(p, 3))
+            });
+            let layer = Layer::new(dimensions, LayerAttributes::default(), encoding, channels);
+            ExrImage::new(attributes, layer)
+                .write()
+                .to_buffered(writer)?;
+        }
+        (BitDepth::U8 | BitDepth::U16, _) => return Err(WriteError::UnsupportedFeature),
+    }
+
+    Ok(())
+}
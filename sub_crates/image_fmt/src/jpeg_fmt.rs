@@ -1,18 +1,28 @@
 use std::io::{Read, Seek};
 
-use crate::{error::ReadError, Image, ImageBuf};
+use crate::{
+    error::ReadError, BitDepth, ChannelLayout, ColorDescription, Format, Image, ImageBuf, ProbeInfo,
+};
 
 pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
     let mut decoder = jpeg_decoder::Decoder::new(&mut reader);
     decoder.read_info()?;
 
-    let info = decoder.info().unwrap();
+    let info = decoder.info().ok_or(ReadError::Decoder {
+        format: Format::Jpeg,
+        detail: "no image info after reading the headers".into(),
+    })?;
     let dimensions = (info.width as usize, info.height as usize);
     let pixel_count = dimensions.0 * dimensions.1;
     let pixel_format = info.pixel_format;
 
     let pixel_data = decoder.decode()?;
 
+    // From the APP2 segments.
+    let color = decoder
+        .icc_profile()
+        .map(ColorDescription::from_icc_profile);
+
     use jpeg_decoder::PixelFormat::*;
     return match pixel_format {
         //------
@@ -22,6 +32,7 @@ pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
             Ok(Image {
                 dimensions: (dimensions.0 as usize, dimensions.1 as usize),
                 data: ImageBuf::Rgb8(pixel_data),
+                color,
             })
         }
 
@@ -32,6 +43,7 @@ pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
             Ok(Image {
                 dimensions: (dimensions.0 as usize, dimensions.1 as usize),
                 data: ImageBuf::Rgb8(pixel_data.iter().map(|&c| [c, c, c]).flatten().collect()),
+                color,
             })
         }
         L16 => {
@@ -42,7 +54,7 @@ pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
                 // their 16-bit buffers, but examining the code in that
                 // crate indicates that it's native endian.  So this
                 // transformation should be correct.
-                data: ImageBuf::Rgba16(
+                data: ImageBuf::Rgb16(
                     pixel_data
                         .chunks(2)
                         .map(|c| {
@@ -52,9 +64,45 @@ pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
                         .flatten()
                         .collect(),
                 ),
+                color,
             })
         }
 
         _ => Err(ReadError::UnsupportedFeature),
     };
 }
+
+pub fn probe<R: Read + Seek>(mut reader: R) -> Result<ProbeInfo, ReadError> {
+    // Only reads the headers.
+    let mut decoder = jpeg_decoder::Decoder::new(&mut reader);
+    decoder.read_info()?;
+    let info = decoder.info().ok_or(ReadError::Decoder {
+        format: Format::Jpeg,
+        detail: "no image info after reading the headers".into(),
+    })?;
+
+    use jpeg_decoder::PixelFormat::*;
+    let (channels, bit_depth) = match info.pixel_format {
+        L8 => (ChannelLayout::Gray, BitDepth::U8),
+        L16 => (ChannelLayout::Gray, BitDepth::U16),
+        RGB24 => (ChannelLayout::Rgb, BitDepth::U8),
+        CMYK32 => (ChannelLayout::Cmyk, BitDepth::U8),
+    };
+
+    Ok(ProbeInfo {
+        format: Format::Jpeg,
+        dimensions: (info.width as usize, info.height as usize),
+        channels,
+        bit_depth: Some(bit_depth),
+        frame_count: 1,
+    })
+}
+
+/// Reads the XMP packet from the APP1 segment, if any.
+pub fn xmp<R: Read>(reader: R) -> Result<Option<Vec<u8>>, ReadError> {
+    // The metadata segments come before the frame header, so this
+    // doesn't need to decode anything.
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+    decoder.read_info()?;
+    Ok(decoder.xmp_data().map(|data| data.to_vec()))
+}
@@ -1,16 +1,31 @@
+mod cineon_fmt;
+mod color;
+mod dpx_fmt;
 mod error;
+mod exr_fmt;
 mod jpeg_fmt;
+mod orientation;
 mod png_fmt;
+mod resize;
 mod tiff_fmt;
+mod webp_fmt;
 
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, Write};
 
-pub use error::ReadError;
+pub use color::{Chromaticities, ColorDescription, TransferCurve, REC709};
+pub use dpx_fmt::{Characteristic, FilmHeader, Timecode};
+pub use error::{ReadError, ResizeError, WriteError};
+pub use half::f16;
+pub use resize::{ResizeFilter, ResizeOptions};
 
 #[derive(Debug, Clone)]
 pub struct Image {
     pub dimensions: (usize, usize),
     pub data: ImageBuf,
+
+    /// The color encoding described by the file (e.g. by an embedded
+    /// ICC profile), if any.
+    pub color: Option<ColorDescription>,
 }
 
 impl Image {
@@ -24,50 +39,28 @@ impl Image {
         self.dimensions.1
     }
 
-    pub fn resized(&self, width: usize, height: usize) -> Self {
-        use fast_image_resize as fir;
-        use ImageBuf::*;
+    /// Resizes the image, keeping its buffer type.
+    pub fn resized(
+        &self,
+        width: usize,
+        height: usize,
+        options: &ResizeOptions,
+    ) -> Result<Self, ResizeError> {
+        resize::resize(self, width, height, options)
+    }
 
-        match self.data {
-            Rgb8(ref data) => {
-                assert_eq!(data.len(), self.dimensions.0 * self.dimensions.1 * 3);
-                let src_view = fir::DynamicImageView::U8x3(
-                    fir::ImageView::from_buffer(
-                        (self.dimensions.0 as u32).try_into().unwrap(),
-                        (self.dimensions.1 as u32).try_into().unwrap(),
-                        &data,
-                    )
-                    .unwrap(),
-                );
-
-                let mut new_data = vec![0u8; width * height * 3];
-                let mut dst_view = fir::DynamicImageViewMut::U8x3(
-                    fir::ImageViewMut::from_buffer(
-                        (width as u32).try_into().unwrap(),
-                        (height as u32).try_into().unwrap(),
-                        &mut new_data,
-                    )
-                    .unwrap(),
-                );
-
-                let mut resizer =
-                    fir::Resizer::new(fir::ResizeAlg::Convolution(fir::FilterType::Bilinear));
-                resizer.resize(&src_view, &mut dst_view).unwrap();
-
-                Image {
-                    dimensions: (width, height),
-                    data: Rgb8(new_data),
-                }
-            }
-
-            _ => todo!(),
-        }
+    /// Rotates and/or flips the image as described by an Exif
+    /// orientation code (1 through 8), so that it's upright.  Other codes
+    /// leave the image as-is.
+    pub fn oriented(self, exif_orientation: u16) -> Self {
+        orientation::orient(self, exif_orientation)
     }
 
     pub fn to_8_bit(self) -> Self {
         Image {
             dimensions: self.dimensions,
             data: self.data.to_8_bit(),
+            color: self.color,
         }
     }
 
@@ -75,11 +68,16 @@ impl Image {
         Image {
             dimensions: self.dimensions,
             data: self.data.to_rgba(),
+            color: self.color,
         }
     }
 }
 
 /// Image data, laid out as `RGBRGBRGB...` or `RGBARGBARGBA...` in scanline order.
+///
+/// Integer variants span their type's full range, so e.g. `u8::MAX`
+/// is 1.0.  Float variants are stored as-is, and may contain values
+/// outside of [0.0, 1.0].
 #[derive(Debug, Clone)]
 pub enum ImageBuf {
     /// 8-bit unsigned RGB channels.
@@ -88,55 +86,65 @@ pub enum ImageBuf {
     /// 16-bit unsigned RGB channels.
     Rgb16(Vec<u16>),
 
+    /// 16-bit float RGB channels.
+    RgbF16(Vec<f16>),
+
+    /// 32-bit float RGB channels.
+    RgbF32(Vec<f32>),
+
     /// 8-bit unsigned RGBA channels.
     Rgba8(Vec<u8>),
 
     /// 16-bit unsigned RGBA channels.
     Rgba16(Vec<u16>),
+
+    /// 16-bit float RGBA channels.
+    RgbaF16(Vec<f16>),
+
+    /// 32-bit float RGBA channels.
+    RgbaF32(Vec<f32>),
 }
 
 impl ImageBuf {
+    /// Whether the buffer has an alpha channel.
+    pub fn has_alpha(&self) -> bool {
+        use ImageBuf::*;
+        match *self {
+            Rgba8(_) | Rgba16(_) | RgbaF16(_) | RgbaF32(_) => true,
+            Rgb8(_) | Rgb16(_) | RgbF16(_) | RgbF32(_) => false,
+        }
+    }
+
+    /// The total number of channel values in the buffer.
+    fn len(&self) -> usize {
+        use ImageBuf::*;
+        match *self {
+            Rgb8(ref d) | Rgba8(ref d) => d.len(),
+            Rgb16(ref d) | Rgba16(ref d) => d.len(),
+            RgbF16(ref d) | RgbaF16(ref d) => d.len(),
+            RgbF32(ref d) | RgbaF32(ref d) => d.len(),
+        }
+    }
+
+    /// Whether the buffer stores floating point channels.
+    pub fn is_float(&self) -> bool {
+        use ImageBuf::*;
+        match *self {
+            RgbF16(_) | RgbF32(_) | RgbaF16(_) | RgbaF32(_) => true,
+            Rgb8(_) | Rgb16(_) | Rgba8(_) | Rgba16(_) => false,
+        }
+    }
+
     pub fn to_rgb(self) -> Self {
         use ImageBuf::*;
 
         match self {
-            Rgb8(_) | Rgb16(_) => self,
-
-            Rgba8(mut data) => {
-                let mut from_i = 4;
-                let mut to_i = 3;
-
-                while from_i < data.len() {
-                    for _ in 0..3 {
-                        data[to_i] = data[from_i];
-                        from_i += 1;
-                        to_i += 1;
-                    }
-                    from_i += 1;
-                }
-
-                data.truncate(data.len() / 4 * 3);
-
-                Rgb8(data)
-            }
-
-            Rgba16(mut data) => {
-                let mut from_i = 4;
-                let mut to_i = 3;
-
-                while from_i < data.len() {
-                    for _ in 0..3 {
-                        data[to_i] = data[from_i];
-                        from_i += 1;
-                        to_i += 1;
-                    }
-                    from_i += 1;
-                }
-
-                data.truncate(data.len() / 4 * 3);
-
-                Rgb16(data)
-            }
+            Rgb8(_) | Rgb16(_) | RgbF16(_) | RgbF32(_) => self,
+
+            Rgba8(data) => Rgb8(strip_alpha(data)),
+            Rgba16(data) => Rgb16(strip_alpha(data)),
+            RgbaF16(data) => RgbF16(strip_alpha(data)),
+            RgbaF32(data) => RgbF32(strip_alpha(data)),
         }
     }
 
@@ -144,93 +152,345 @@ impl ImageBuf {
         use ImageBuf::*;
 
         match self {
-            Rgba8(_) | Rgba16(_) => self,
-
-            Rgb8(mut data) => {
-                let mut from_i = data.len() - 1;
-                data.resize(data.len() / 3 * 4, 0);
-                let mut to_i = data.len() - 1;
-
-                while from_i > 2 {
-                    data[to_i] = std::u8::MAX;
-                    to_i -= 1;
-                    for _ in 0..3 {
-                        data[to_i] = data[from_i];
-                        from_i -= 1;
-                        to_i -= 1;
-                    }
-                }
-
-                Rgba8(data)
-            }
-
-            Rgb16(mut data) => {
-                let mut from_i = data.len() - 1;
-                data.resize(data.len() / 3 * 4, 0);
-                let mut to_i = data.len() - 1;
-
-                while from_i > 2 {
-                    data[to_i] = std::u16::MAX;
-                    to_i -= 1;
-                    for _ in 0..3 {
-                        data[to_i] = data[from_i];
-                        from_i -= 1;
-                        to_i -= 1;
-                    }
-                }
-
-                Rgba16(data)
-            }
+            Rgba8(_) | Rgba16(_) | RgbaF16(_) | RgbaF32(_) => self,
+
+            Rgb8(data) => Rgba8(add_alpha(data, u8::MAX)),
+            Rgb16(data) => Rgba16(add_alpha(data, u16::MAX)),
+            RgbF16(data) => RgbaF16(add_alpha(data, f16::ONE)),
+            RgbF32(data) => RgbaF32(add_alpha(data, 1.0)),
         }
     }
 
+    /// Float values are clamped to [0.0, 1.0] before quantization.
     pub fn to_8_bit(self) -> Self {
         use ImageBuf::*;
+        let quantize = |v: f32| (v.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8;
         match self {
             Rgb8(_) | Rgba8(_) => self,
             Rgb16(data) => Rgb8(data.iter().map(|&v| (v >> 8) as u8).collect()),
             Rgba16(data) => Rgba8(data.iter().map(|&v| (v >> 8) as u8).collect()),
+            RgbF16(data) => Rgb8(data.iter().map(|&v| quantize(v.to_f32())).collect()),
+            RgbaF16(data) => Rgba8(data.iter().map(|&v| quantize(v.to_f32())).collect()),
+            RgbF32(data) => Rgb8(data.iter().map(|&v| quantize(v)).collect()),
+            RgbaF32(data) => Rgba8(data.iter().map(|&v| quantize(v)).collect()),
         }
     }
 
+    /// Float values are clamped to [0.0, 1.0] before quantization.
     pub fn to_16_bit(self) -> Self {
         use ImageBuf::*;
+        let quantize = |v: f32| (v.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16;
         match self {
             Rgb16(_) | Rgba16(_) => self,
             Rgb8(data) => Rgb16(data.iter().map(|&v| (v as u16) << 8).collect()),
             Rgba8(data) => Rgba16(data.iter().map(|&v| (v as u16) << 8).collect()),
+            RgbF16(data) => Rgb16(data.iter().map(|&v| quantize(v.to_f32())).collect()),
+            RgbaF16(data) => Rgba16(data.iter().map(|&v| quantize(v.to_f32())).collect()),
+            RgbF32(data) => Rgb16(data.iter().map(|&v| quantize(v)).collect()),
+            RgbaF32(data) => Rgba16(data.iter().map(|&v| quantize(v)).collect()),
+        }
+    }
+
+    /// Integer values are normalized to [0.0, 1.0].
+    pub fn to_f16(self) -> Self {
+        use ImageBuf::*;
+        match self {
+            RgbF16(_) | RgbaF16(_) => self,
+            _ => match self.to_f32() {
+                RgbF32(data) => RgbF16(data.iter().map(|&v| f16::from_f32(v)).collect()),
+                RgbaF32(data) => RgbaF16(data.iter().map(|&v| f16::from_f32(v)).collect()),
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    /// Integer values are normalized to [0.0, 1.0].
+    pub fn to_f32(self) -> Self {
+        use ImageBuf::*;
+        let norm_8 = 1.0 / u8::MAX as f32;
+        let norm_16 = 1.0 / u16::MAX as f32;
+        match self {
+            RgbF32(_) | RgbaF32(_) => self,
+            Rgb8(data) => RgbF32(data.iter().map(|&v| v as f32 * norm_8).collect()),
+            Rgba8(data) => RgbaF32(data.iter().map(|&v| v as f32 * norm_8).collect()),
+            Rgb16(data) => RgbF32(data.iter().map(|&v| v as f32 * norm_16).collect()),
+            Rgba16(data) => RgbaF32(data.iter().map(|&v| v as f32 * norm_16).collect()),
+            RgbF16(data) => RgbF32(data.iter().map(|&v| v.to_f32()).collect()),
+            RgbaF16(data) => RgbaF32(data.iter().map(|&v| v.to_f32()).collect()),
         }
     }
 }
 
-pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
-    // Try tiff.
-    match tiff_fmt::load(&mut reader) {
-        Err(ReadError::UnknownFormat) => {} // Continue to try next format.
-        r => {
-            return r;
+/// Converts `RGBARGBA...` data to `RGBRGB...` in place.
+fn strip_alpha<T: Copy>(mut data: Vec<T>) -> Vec<T> {
+    let mut from_i = 4;
+    let mut to_i = 3;
+
+    while from_i < data.len() {
+        for _ in 0..3 {
+            data[to_i] = data[from_i];
+            from_i += 1;
+            to_i += 1;
         }
+        from_i += 1;
+    }
+
+    data.truncate(data.len() / 4 * 3);
+
+    data
+}
+
+/// Converts `RGBRGB...` data to `RGBARGBA...` in place, filling in
+/// alpha with the given value.
+fn add_alpha<T: Copy>(mut data: Vec<T>, alpha: T) -> Vec<T> {
+    let pixel_count = data.len() / 3;
+    data.resize(pixel_count * 4, alpha);
+
+    // Work backwards so we don't overwrite anything we still need.
+    for i in (0..pixel_count).rev() {
+        data[i * 4 + 3] = alpha;
+        data[i * 4 + 2] = data[i * 3 + 2];
+        data[i * 4 + 1] = data[i * 3 + 1];
+        data[i * 4] = data[i * 3];
+    }
+
+    data
+}
+
+pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
+    match detect_format(&mut reader)? {
+        Format::Tiff => tiff_fmt::load(reader),
+        Format::Png => png_fmt::load(reader),
+        Format::Jpeg => jpeg_fmt::load(reader),
+        Format::Exr => exr_fmt::load(reader),
+        Format::WebP => webp_fmt::load(reader),
+        Format::Dpx => dpx_fmt::load(reader),
+        Format::Cineon => cineon_fmt::load(reader),
+    }
+}
+
+/// Reads the basic properties of an image file without decoding its
+/// pixel data.
+///
+/// This is much cheaper than `load()`, and is useful for e.g.
+/// validating a set of images before loading them.
+pub fn probe<R: Read + Seek>(mut reader: R) -> Result<ProbeInfo, ReadError> {
+    match detect_format(&mut reader)? {
+        Format::Tiff => tiff_fmt::probe(reader),
+        Format::Png => png_fmt::probe(reader),
+        Format::Jpeg => jpeg_fmt::probe(reader),
+        Format::Exr => exr_fmt::probe(reader),
+        Format::WebP => webp_fmt::probe(reader),
+        Format::Dpx => dpx_fmt::probe(reader),
+        Format::Cineon => cineon_fmt::probe(reader),
     }
+}
+
+/// Determines the file format from the magic bytes at the start of the
+/// reader, and rewinds it afterwards.
+fn detect_format<R: Read + Seek>(reader: &mut R) -> Result<Format, ReadError> {
+    let mut magic = Vec::with_capacity(12);
+    reader.by_ref().take(12).read_to_end(&mut magic)?;
     reader.rewind()?;
 
-    // Try png.
-    match png_fmt::load(&mut reader) {
-        Err(ReadError::UnknownFormat) => {} // Continue to try next format.
-        r => {
-            return r;
+    Format::from_magic_bytes(&magic).ok_or(ReadError::UnknownFormat)
+}
+
+/// The basic properties of an image file, as returned by `probe()`.
+#[derive(Debug, Copy, Clone)]
+pub struct ProbeInfo {
+    pub format: Format,
+    pub dimensions: (usize, usize),
+
+    /// The channels stored in the file.  Palette images report the
+    /// channels of their palette.
+    pub channels: ChannelLayout,
+
+    /// The smallest bit depth that can hold the file's channels without
+    /// loss, or `None` if none of them can (e.g. 32-bit integer TIFFs).
+    pub bit_depth: Option<BitDepth>,
+
+    /// The number of images/frames in the file.  Only the first is
+    /// loaded by `load()`.
+    pub frame_count: usize,
+}
+
+impl ProbeInfo {
+    #[inline(always)]
+    pub fn width(&self) -> usize {
+        self.dimensions.0
+    }
+
+    #[inline(always)]
+    pub fn height(&self) -> usize {
+        self.dimensions.1
+    }
+}
+
+/// The channels stored in an image file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ChannelLayout {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    Rgba,
+    Cmyk,
+
+    /// Anything else, e.g. EXR files without RGB or Y channels.
+    Other,
+}
+
+/// An image file format.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Format {
+    Tiff,
+    Png,
+    Jpeg,
+    Exr,
+    WebP,
+    Dpx,
+    Cineon,
+}
+
+impl Format {
+    /// Determines the format from a file extension, case insensitively.
+    pub fn from_extension(ext: &str) -> Option<Format> {
+        match ext.to_lowercase().as_str() {
+            "tif" | "tiff" => Some(Format::Tiff),
+            "png" => Some(Format::Png),
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            "exr" => Some(Format::Exr),
+            "webp" => Some(Format::WebP),
+            "dpx" => Some(Format::Dpx),
+            "cin" => Some(Format::Cineon),
+            _ => None,
         }
     }
-    reader.rewind()?;
 
-    // Try jpeg.
-    match jpeg_fmt::load(&mut reader) {
-        Err(ReadError::UnknownFormat) => {} // Continue to try next format.
-        r => {
-            return r;
+    /// Determines the format from the first bytes of a file.  At least
+    /// 12 bytes are needed to recognize all formats.
+    pub fn from_magic_bytes(bytes: &[u8]) -> Option<Format> {
+        // Classic TIFF and BigTIFF, in both byte orders.
+        if [b"II*\0", b"MM\0*", b"II+\0", b"MM\0+"]
+            .iter()
+            .any(|magic| bytes.starts_with(*magic))
+        {
+            Some(Format::Tiff)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Format::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Format::Jpeg)
+        } else if bytes.starts_with(&[0x76, 0x2f, 0x31, 0x01]) {
+            Some(Format::Exr)
+        } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+            Some(Format::WebP)
+        } else if bytes.starts_with(b"SDPX") || bytes.starts_with(b"XPDS") {
+            Some(Format::Dpx)
+        } else if bytes.starts_with(&[0x80, 0x2a, 0x5f, 0xd7])
+            || bytes.starts_with(&[0xd7, 0x5f, 0x2a, 0x80])
+        {
+            Some(Format::Cineon)
+        } else {
+            None
         }
     }
-    reader.rewind()?;
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match *self {
+            Format::Tiff => "TIFF",
+            Format::Png => "PNG",
+            Format::Jpeg => "JPEG",
+            Format::Exr => "OpenEXR",
+            Format::WebP => "WebP",
+            Format::Dpx => "DPX",
+            Format::Cineon => "Cineon",
+        })
+    }
+}
+
+/// The storage type of the channels in an image file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BitDepth {
+    U8,
+    U16,
+    F16,
+    F32,
+}
 
-    // No formats matched.
-    return Err(ReadError::UnknownFormat);
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SaveOptions {
+    /// The bit depth to write.  If `None`, the closest that the format
+    /// supports to the image's own bit depth is used.
+    pub bit_depth: Option<BitDepth>,
+}
+
+/// Writes an image file in the given format.
+///
+/// Supported formats and bit depths:
+///
+/// - PNG: 8 and 16 bit.
+/// - TIFF: 8 and 16 bit, and 32-bit float.
+/// - EXR: 16 and 32-bit float.
+///
+/// Alpha is written if the image has it.  Float data is clamped to
+/// [0.0, 1.0] when written to integer bit depths, and integer data is
+/// normalized to [0.0, 1.0] when written to float bit depths.
+pub fn save<W: Write + Seek>(
+    writer: W,
+    image: &Image,
+    format: Format,
+    options: &SaveOptions,
+) -> Result<(), WriteError> {
+    let channel_count = if image.data.has_alpha() { 4 } else { 3 };
+    if image.data.len() != image.width() * image.height() * channel_count {
+        return Err(WriteError::InvalidImage);
+    }
+
+    use ImageBuf::*;
+    let bit_depth = options.bit_depth.unwrap_or(match (format, &image.data) {
+        (Format::Exr, Rgb8(_) | Rgba8(_) | RgbF16(_) | RgbaF16(_)) => BitDepth::F16,
+        (Format::Exr, _) => BitDepth::F32,
+        (Format::Tiff, RgbF16(_) | RgbaF16(_) | RgbF32(_) | RgbaF32(_)) => BitDepth::F32,
+        (_, Rgb8(_) | Rgba8(_)) => BitDepth::U8,
+        (_, _) => BitDepth::U16,
+    });
+
+    match format {
+        Format::Tiff => tiff_fmt::save(writer, image, bit_depth),
+        Format::Png => png_fmt::save(writer, image, bit_depth),
+        Format::Exr => exr_fmt::save(writer, image, bit_depth),
+        Format::Jpeg | Format::WebP | Format::Dpx | Format::Cineon => {
+            Err(WriteError::UnsupportedFormat)
+        }
+    }
+}
+
+/// Reads the raw Exif data (starting at the TIFF header) embedded in a
+/// WebP file, if any.
+pub fn webp_exif<R: Read + Seek>(reader: R) -> Result<Option<Vec<u8>>, ReadError> {
+    webp_fmt::exif(reader)
+}
+
+/// Reads the raw XMP packet embedded in a TIFF, PNG, JPEG, or WebP file,
+/// if any.  Returns `None` for other formats.
+pub fn xmp<R: Read + Seek>(mut reader: R) -> Result<Option<Vec<u8>>, ReadError> {
+    match detect_format(&mut reader)? {
+        Format::Tiff => tiff_fmt::xmp(reader),
+        Format::Png => png_fmt::xmp(reader),
+        Format::Jpeg => jpeg_fmt::xmp(reader),
+        Format::WebP => webp_fmt::xmp(reader),
+        Format::Exr | Format::Dpx | Format::Cineon => Ok(None),
+    }
+}
+
+/// Reads the header fields describing the pixel encoding of a DPX or
+/// Cineon file.  Returns `None` for other formats.
+pub fn film_header<R: Read + Seek>(mut reader: R) -> Result<Option<FilmHeader>, ReadError> {
+    match detect_format(&mut reader)? {
+        Format::Dpx => dpx_fmt::film_header(reader).map(Some),
+        Format::Cineon => cineon_fmt::film_header(reader).map(Some),
+        _ => Ok(None),
+    }
 }
@@ -0,0 +1,148 @@
+use crate::{Image, ImageBuf};
+
+pub(crate) fn orient(image: Image, orientation: u16) -> Image {
+    if !(2..=8).contains(&orientation) {
+        return image;
+    }
+
+    // Malformed buffers are left alone rather than panicking.
+    let channel_count = if image.data.has_alpha() { 4 } else { 3 };
+    if image.data.len() != image.width() * image.height() * channel_count {
+        return image;
+    }
+
+    // Orientations 5 through 8 swap the axes.
+    let dimensions = if orientation >= 5 {
+        (image.height(), image.width())
+    } else {
+        image.dimensions
+    };
+
+    use ImageBuf::*;
+    let data = match image.data {
+        Rgb8(ref data) => Rgb8(orient_pixels(data, 3, image.dimensions, orientation)),
+        Rgb16(ref data) => Rgb16(orient_pixels(data, 3, image.dimensions, orientation)),
+        RgbF16(ref data) => RgbF16(orient_pixels(data, 3, image.dimensions, orientation)),
+        RgbF32(ref data) => RgbF32(orient_pixels(data, 3, image.dimensions, orientation)),
+        Rgba8(ref data) => Rgba8(orient_pixels(data, 4, image.dimensions, orientation)),
+        Rgba16(ref data) => Rgba16(orient_pixels(data, 4, image.dimensions, orientation)),
+        RgbaF16(ref data) => RgbaF16(orient_pixels(data, 4, image.dimensions, orientation)),
+        RgbaF32(ref data) => RgbaF32(orient_pixels(data, 4, image.dimensions, orientation)),
+    };
+
+    Image {
+        dimensions,
+        data,
+        color: image.color,
+    }
+}
+
+/// Builds the upright pixel data, by looking up where each of its
+/// pixels comes from in the stored data.
+fn orient_pixels<T: Copy>(
+    data: &[T],
+    channel_count: usize,
+    (width, height): (usize, usize),
+    orientation: u16,
+) -> Vec<T> {
+    let (out_width, out_height) = if orientation >= 5 {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    let mut oriented = Vec::with_capacity(data.len());
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let (src_x, src_y) = match orientation {
+                2 => (width - 1 - x, y),              // Flipped horizontally.
+                3 => (width - 1 - x, height - 1 - y), // Rotated 180°.
+                4 => (x, height - 1 - y),             // Flipped vertically.
+                5 => (y, x),                          // Transposed.
+                6 => (y, height - 1 - x),             // Rotated 90° clockwise.
+                7 => (width - 1 - y, height - 1 - x), // Transversed.
+                8 => (width - 1 - y, x),              // Rotated 90° counter-clockwise.
+                _ => (x, y),
+            };
+            let i = (src_y * width + src_x) * channel_count;
+            oriented.extend_from_slice(&data[i..(i + channel_count)]);
+        }
+    }
+
+    oriented
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x3 image, with each pixel's channels set to its letter:
+    ///
+    /// ```text
+    /// a b
+    /// c d
+    /// e f
+    /// ```
+    fn image() -> Image {
+        Image {
+            dimensions: (2, 3),
+            data: ImageBuf::Rgb8(b"abcdef".iter().flat_map(|&c| [c; 3]).collect()),
+            color: None,
+        }
+    }
+
+    fn letters(image: &Image) -> String {
+        match image.data {
+            ImageBuf::Rgb8(ref data) => data.chunks(3).map(|c| c[0] as char).collect(),
+            _ => panic!("unexpected buffer type"),
+        }
+    }
+
+    #[test]
+    fn all_orientations() {
+        for (orientation, dimensions, expected) in [
+            (1, (2, 3), "abcdef"),
+            (2, (2, 3), "badcfe"),
+            (3, (2, 3), "fedcba"),
+            (4, (2, 3), "efcdab"),
+            (5, (3, 2), "acebdf"),
+            (6, (3, 2), "ecafdb"),
+            (7, (3, 2), "fdbeca"),
+            (8, (3, 2), "bdface"),
+        ] {
+            let oriented = orient(image(), orientation);
+            assert_eq!(oriented.dimensions, dimensions, "{}", orientation);
+            assert_eq!(letters(&oriented), expected, "{}", orientation);
+        }
+    }
+
+    #[test]
+    fn alpha() {
+        let image = Image {
+            dimensions: (2, 1),
+            data: ImageBuf::RgbaF32(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0]),
+            color: None,
+        };
+        match orient(image, 3).data {
+            ImageBuf::RgbaF32(data) => {
+                assert_eq!(data, [5.0, 6.0, 7.0, 8.0, 1.0, 2.0, 3.0, 4.0])
+            }
+            _ => panic!("unexpected buffer type"),
+        }
+    }
+
+    #[test]
+    fn left_alone() {
+        // Invalid orientations.
+        for orientation in [0, 9, u16::MAX] {
+            assert_eq!(letters(&orient(image(), orientation)), "abcdef");
+        }
+
+        // Malformed buffers.
+        let mut malformed = image();
+        malformed.dimensions = (3, 3);
+        let oriented = orient(malformed, 6);
+        assert_eq!(oriented.dimensions, (3, 3));
+        assert_eq!(letters(&oriented), "abcdef");
+    }
+}
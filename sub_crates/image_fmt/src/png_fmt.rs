@@ -1,109 +1,243 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
-use crate::{error::ReadError, Image, ImageBuf};
+use crate::{
+    error::{ReadError, WriteError},
+    BitDepth, ChannelLayout, Chromaticities, ColorDescription, Format, Image, ImageBuf, ProbeInfo,
+    TransferCurve,
+};
 
 pub fn load<R: Read>(mut reader: R) -> Result<Image, ReadError> {
-    let decoder = png::Decoder::new_with_limits(
+    let mut decoder = png::Decoder::new_with_limits(
         &mut reader,
         png::Limits {
             bytes: std::usize::MAX,
         },
     );
+
+    // Expand palettes to RGB (or RGBA, if they have transparency) and
+    // 1/2/4-bit grayscale to 8-bit, so that we only have to deal with 8
+    // and 16-bit direct color below.
+    decoder.set_transformations(png::Transformations::EXPAND);
     let mut reader = decoder.read_info()?;
 
     let info = reader.info();
     let dimensions = (info.width as usize, info.height as usize);
-    let bit_depth = info.bit_depth;
-    let color_type = info.color_type;
-
-    if color_type == png::ColorType::Indexed {
-        return Err(ReadError::UnsupportedFeature);
-    }
+    let color = color_description(info);
+    let (color_type, bit_depth) = reader.output_color_type();
 
     let mut pixel_data = vec![0u8; reader.output_buffer_size()];
     reader.next_frame(&mut pixel_data)?;
 
+    let to_u16 = |data: &[u8]| -> Vec<u16> {
+        data.chunks(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect()
+    };
+
     use png::{BitDepth::*, ColorType::*};
-    return match (color_type, bit_depth) {
+    let data = match (color_type, bit_depth) {
         //------
         // RGB.
-        (Rgb, Eight) => Ok(Image {
-            dimensions: dimensions,
-            data: ImageBuf::Rgb8(pixel_data),
-        }),
-        (Rgb, Sixteen) => Ok(Image {
-            dimensions: dimensions,
-            data: ImageBuf::Rgb16(
-                pixel_data
-                    .chunks(2)
-                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
-                    .collect(),
-            ),
-        }),
+        (Rgb, Eight) => ImageBuf::Rgb8(pixel_data),
+        (Rgb, Sixteen) => ImageBuf::Rgb16(to_u16(&pixel_data)),
 
         //-------
         // RGBA.
-        (Rgba, Eight) => Ok(Image {
-            dimensions: dimensions,
-            data: ImageBuf::Rgba8(pixel_data),
-        }),
-        (Rgba, Sixteen) => Ok(Image {
-            dimensions: dimensions,
-            data: ImageBuf::Rgba16(
-                pixel_data
-                    .chunks(2)
-                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
-                    .collect(),
-            ),
-        }),
+        (Rgba, Eight) => ImageBuf::Rgba8(pixel_data),
+        (Rgba, Sixteen) => ImageBuf::Rgba16(to_u16(&pixel_data)),
 
         //------------
         // Grayscale.
-        (Grayscale, Eight) => Ok(Image {
-            dimensions: dimensions,
-            data: ImageBuf::Rgba8(pixel_data.iter().map(|&c| [c, c, c]).flatten().collect()),
-        }),
-        (Grayscale, Sixteen) => Ok(Image {
-            dimensions: dimensions,
-            data: ImageBuf::Rgba16(
-                pixel_data
-                    .chunks(2)
-                    .map(|c| {
-                        let v = u16::from_be_bytes([c[0], c[1]]);
-                        [v, v, v]
-                    })
-                    .flatten()
-                    .collect(),
-            ),
-        }),
+        (Grayscale, Eight) => ImageBuf::Rgb8(pixel_data.iter().flat_map(|&c| [c, c, c]).collect()),
+        (Grayscale, Sixteen) => ImageBuf::Rgb16(
+            to_u16(&pixel_data)
+                .iter()
+                .flat_map(|&v| [v, v, v])
+                .collect(),
+        ),
 
         //--------------------
         // Grayscale + alpha.
-        (GrayscaleAlpha, Eight) => Ok(Image {
-            dimensions: dimensions,
-            data: ImageBuf::Rgba8(
-                pixel_data
-                    .chunks(2)
-                    .map(|c| [c[0], c[0], c[0], c[1]])
-                    .flatten()
-                    .collect(),
-            ),
-        }),
-        (GrayscaleAlpha, Sixteen) => Ok(Image {
-            dimensions: dimensions,
-            data: ImageBuf::Rgba16(
-                pixel_data
-                    .chunks(4)
-                    .map(|c| {
-                        let v = u16::from_be_bytes([c[0], c[1]]);
-                        let a = u16::from_be_bytes([c[2], c[3]]);
-                        [v, v, v, a]
-                    })
-                    .flatten()
-                    .collect(),
-            ),
-        }),
+        (GrayscaleAlpha, Eight) => ImageBuf::Rgba8(
+            pixel_data
+                .chunks(2)
+                .flat_map(|c| [c[0], c[0], c[0], c[1]])
+                .collect(),
+        ),
+        (GrayscaleAlpha, Sixteen) => ImageBuf::Rgba16(
+            to_u16(&pixel_data)
+                .chunks(2)
+                .flat_map(|c| [c[0], c[0], c[0], c[1]])
+                .collect(),
+        ),
 
         _ => return Err(ReadError::UnsupportedFeature),
     };
+
+    Ok(Image {
+        dimensions,
+        data,
+        color,
+    })
+}
+
+/// Builds a color description from the iCCP, sRGB, cHRM, and gAMA
+/// chunks, in that order of precedence (as recommended by the PNG spec).
+fn color_description(info: &png::Info) -> Option<ColorDescription> {
+    if let Some(ref profile) = info.icc_profile {
+        return Some(ColorDescription::from_icc_profile(profile.to_vec()));
+    }
+    if info.srgb.is_some() {
+        return Some(ColorDescription::srgb());
+    }
+
+    let xy = |(x, y): (png::ScaledFloat, png::ScaledFloat)| {
+        (x.into_value() as f64, y.into_value() as f64)
+    };
+    let chromaticities = info.source_chromaticities.map(|chroma| Chromaticities {
+        r: xy(chroma.red),
+        g: xy(chroma.green),
+        b: xy(chroma.blue),
+        w: xy(chroma.white),
+    });
+    // gAMA stores the encoding exponent.
+    let transfer = info
+        .source_gamma
+        .map(|gamma| gamma.into_value())
+        .filter(|&gamma| gamma > 0.0)
+        .map(|gamma| TransferCurve::Gamma(1.0 / gamma));
+
+    if chromaticities.is_none() && transfer.is_none() {
+        None
+    } else {
+        Some(ColorDescription {
+            chromaticities,
+            transfer,
+            icc_profile: None,
+        })
+    }
+}
+
+pub fn probe<R: Read>(reader: R) -> Result<ProbeInfo, ReadError> {
+    // Only reads up to the start of the image data.
+    let reader = png::Decoder::new(reader).read_info()?;
+    let info = reader.info();
+
+    // A tRNS chunk is expanded into an alpha channel on load.
+    let has_trns = info.trns.is_some();
+    let channels = match info.color_type {
+        png::ColorType::Grayscale if has_trns => ChannelLayout::GrayAlpha,
+        png::ColorType::Grayscale => ChannelLayout::Gray,
+        png::ColorType::GrayscaleAlpha => ChannelLayout::GrayAlpha,
+        png::ColorType::Rgb | png::ColorType::Indexed if has_trns => ChannelLayout::Rgba,
+        png::ColorType::Rgb | png::ColorType::Indexed => ChannelLayout::Rgb,
+        png::ColorType::Rgba => ChannelLayout::Rgba,
+    };
+
+    // Palettes and 1/2/4-bit grayscale are expanded to 8-bit.
+    let bit_depth = match info.bit_depth {
+        png::BitDepth::Sixteen => BitDepth::U16,
+        _ => BitDepth::U8,
+    };
+
+    Ok(ProbeInfo {
+        format: Format::Png,
+        dimensions: (info.width as usize, info.height as usize),
+        channels,
+        bit_depth: Some(bit_depth),
+        frame_count: info
+            .animation_control
+            .map(|actl| actl.num_frames as usize)
+            .unwrap_or(1),
+    })
+}
+
+/// Reads the XMP packet from the iTXt chunk reserved for it, if any.
+pub fn xmp<R: Read>(reader: R) -> Result<Option<Vec<u8>>, ReadError> {
+    let reader = png::Decoder::new(reader).read_info()?;
+    for chunk in reader.info().utf8_text.iter() {
+        if chunk.keyword == "XML:com.adobe.xmp" {
+            return Ok(Some(chunk.get_text()?.into_bytes()));
+        }
+    }
+    Ok(None)
+}
+
+pub fn save<W: Write>(writer: W, image: &Image, bit_depth: BitDepth) -> Result<(), WriteError> {
+    let (pixel_data, png_depth) = match bit_depth {
+        BitDepth::U8 => match image.data.clone().to_8_bit() {
+            ImageBuf::Rgb8(data) | ImageBuf::Rgba8(data) => (data, png::BitDepth::Eight),
+            _ => unreachable!(),
+        },
+        BitDepth::U16 => match image.data.clone().to_16_bit() {
+            ImageBuf::Rgb16(data) | ImageBuf::Rgba16(data) => (
+                data.iter().flat_map(|v| v.to_be_bytes()).collect(),
+                png::BitDepth::Sixteen,
+            ),
+            _ => unreachable!(),
+        },
+        BitDepth::F16 | BitDepth::F32 => return Err(WriteError::UnsupportedFeature),
+    };
+
+    let mut encoder = png::Encoder::new(
+        writer,
+        image
+            .width()
+            .try_into()
+            .map_err(|_| WriteError::InvalidImage)?,
+        image
+            .height()
+            .try_into()
+            .map_err(|_| WriteError::InvalidImage)?,
+    );
+    encoder.set_color(if image.data.has_alpha() {
+        png::ColorType::Rgba
+    } else {
+        png::ColorType::Rgb
+    });
+    encoder.set_depth(png_depth);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixel_data)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray_png(bit_depth: png::BitDepth, data: &[u8]) -> Vec<u8> {
+        let mut file = Vec::new();
+        let mut encoder = png::Encoder::new(&mut file, 2, 2);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(bit_depth);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(data)
+            .unwrap();
+        file
+    }
+
+    #[test]
+    fn grayscale() {
+        let file = gray_png(png::BitDepth::Eight, &[0, 85, 170, 255]);
+        match load(&file[..]).unwrap().data {
+            ImageBuf::Rgb8(data) => {
+                assert_eq!(data, [0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255])
+            }
+            _ => panic!("unexpected buffer type"),
+        }
+
+        let file = gray_png(png::BitDepth::Sixteen, &[0, 1, 2, 3, 4, 5, 255, 255]);
+        match load(&file[..]).unwrap().data {
+            ImageBuf::Rgb16(data) => assert_eq!(
+                data,
+                [1, 1, 1, 0x0203, 0x0203, 0x0203, 0x0405, 0x0405, 0x0405, 0xffff, 0xffff, 0xffff]
+            ),
+            _ => panic!("unexpected buffer type"),
+        }
+    }
 }
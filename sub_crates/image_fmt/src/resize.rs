@@ -0,0 +1,484 @@
+use std::num::NonZeroU32;
+
+use crate::{Image, ImageBuf, ResizeError};
+
+/// The reconstruction filter used when resizing.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ResizeFilter {
+    /// Averages the source pixels covered by each target pixel.  Fast,
+    /// but blocky when upscaling.
+    Box,
+
+    /// Triangle filter.  Soft, but without ringing.
+    Bilinear,
+
+    /// Three-lobed Lanczos.  Sharp, but with some ringing around
+    /// high-contrast edges.
+    Lanczos3,
+
+    /// Mitchell-Netravali (B = C = 1/3).  A balance between sharpness,
+    /// ringing, and blur.
+    Mitchell,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ResizeOptions {
+    pub filter: ResizeFilter,
+
+    /// Resample in linear light rather than in the image's encoded
+    /// space.  Integer images are assumed to be sRGB encoded and float
+    /// images to already be linear, so this only affects integer images.
+    /// Alpha is always resampled as-is.
+    pub linear_light: bool,
+}
+
+impl Default for ResizeOptions {
+    fn default() -> ResizeOptions {
+        ResizeOptions {
+            filter: ResizeFilter::Bilinear,
+            linear_light: false,
+        }
+    }
+}
+
+pub(crate) fn resize(
+    image: &Image,
+    width: usize,
+    height: usize,
+    options: &ResizeOptions,
+) -> Result<Image, ResizeError> {
+    let src_dims = to_dims(image.width(), image.height())?;
+    let dst_dims = to_dims(width, height)?;
+
+    let channel_count = if image.data.has_alpha() { 4 } else { 3 };
+    if image.data.len() != image.width() * image.height() * channel_count {
+        return Err(ResizeError::InvalidImage);
+    }
+
+    let resize_bytes = |data: Vec<u8>, layout: Layout| {
+        fir_resize(data, src_dims, dst_dims, layout, options.filter)
+    };
+
+    use ImageBuf::*;
+    let data = match image.data {
+        // Integer data in encoded space can be resized directly.
+        Rgb8(ref data) if !options.linear_light => Rgb8(resize_bytes(data.clone(), Layout::U8x3)?),
+        Rgba8(ref data) if !options.linear_light => {
+            Rgba8(resize_bytes(data.clone(), Layout::U8x4)?)
+        }
+        Rgb16(ref data) if !options.linear_light => Rgb16(u16_from_bytes(&resize_bytes(
+            u16_to_bytes(data),
+            Layout::U16x3,
+        )?)),
+        Rgba16(ref data) if !options.linear_light => Rgba16(u16_from_bytes(&resize_bytes(
+            u16_to_bytes(data),
+            Layout::U16x4,
+        )?)),
+
+        // Everything else goes through 32-bit float.
+        _ => {
+            let decode = options.linear_light && !image.data.is_float();
+            let is_color = |i: usize| channel_count == 3 || i % 4 != 3;
+
+            let mut data = match image.data.clone().to_f32() {
+                RgbF32(data) | RgbaF32(data) => data,
+                _ => unreachable!(),
+            };
+            if decode {
+                for (i, n) in data.iter_mut().enumerate() {
+                    if is_color(i) {
+                        *n = srgb_to_linear(*n);
+                    }
+                }
+            }
+
+            let mut resized = resample_f32(
+                &data,
+                (image.width(), image.height()),
+                (width, height),
+                channel_count,
+                options.filter,
+            );
+
+            if decode {
+                for (i, n) in resized.iter_mut().enumerate() {
+                    if is_color(i) {
+                        *n = linear_to_srgb(*n);
+                    }
+                }
+            }
+
+            let resized = if channel_count == 4 {
+                RgbaF32(resized)
+            } else {
+                RgbF32(resized)
+            };
+            match image.data {
+                Rgb8(_) | Rgba8(_) => resized.to_8_bit(),
+                Rgb16(_) | Rgba16(_) => resized.to_16_bit(),
+                RgbF16(_) | RgbaF16(_) => resized.to_f16(),
+                RgbF32(_) | RgbaF32(_) => resized,
+            }
+        }
+    };
+
+    Ok(Image {
+        dimensions: (width, height),
+        data,
+        color: image.color.clone(),
+    })
+}
+
+fn to_dims(width: usize, height: usize) -> Result<(NonZeroU32, NonZeroU32), ResizeError> {
+    let width = u32::try_from(width).map_err(|_| ResizeError::InvalidImage)?;
+    let height = u32::try_from(height).map_err(|_| ResizeError::InvalidImage)?;
+    match (NonZeroU32::new(width), NonZeroU32::new(height)) {
+        (Some(w), Some(h)) => Ok((w, h)),
+        _ => Err(ResizeError::ZeroSize),
+    }
+}
+
+/// Pixel layouts of the buffers passed to `fir_resize()`.
+#[derive(Debug, Copy, Clone)]
+enum Layout {
+    U8x3,
+    U8x4,
+    U16x3,
+    U16x4,
+}
+
+/// Resizes a buffer of native-endian pixel data.
+fn fir_resize(
+    data: Vec<u8>,
+    src_dims: (NonZeroU32, NonZeroU32),
+    dst_dims: (NonZeroU32, NonZeroU32),
+    layout: Layout,
+    filter: ResizeFilter,
+) -> Result<Vec<u8>, ResizeError> {
+    use fast_image_resize as fir;
+
+    let pixel_type = match layout {
+        Layout::U8x3 => fir::PixelType::U8x3,
+        Layout::U8x4 => fir::PixelType::U8x4,
+        Layout::U16x3 => fir::PixelType::U16x3,
+        Layout::U16x4 => fir::PixelType::U16x4,
+    };
+    let filter_type = match filter {
+        ResizeFilter::Box => fir::FilterType::Box,
+        ResizeFilter::Bilinear => fir::FilterType::Bilinear,
+        ResizeFilter::Lanczos3 => fir::FilterType::Lanczos3,
+        ResizeFilter::Mitchell => fir::FilterType::Mitchell,
+    };
+
+    // Both of these only fail if the buffer doesn't match the dimensions
+    // and pixel type.
+    let src_image = fir::Image::from_vec_u8(src_dims.0, src_dims.1, data, pixel_type)
+        .map_err(|_| ResizeError::InvalidImage)?;
+    let mut dst_image = fir::Image::new(dst_dims.0, dst_dims.1, pixel_type);
+
+    let mut resizer = fir::Resizer::new(fir::ResizeAlg::Convolution(filter_type));
+    resizer
+        .resize(&src_image.view(), &mut dst_image.view_mut())
+        .map_err(|_| ResizeError::InvalidImage)?;
+
+    Ok(dst_image.buffer().to_vec())
+}
+
+/// Resizes interleaved float data with a separable filter.
+///
+/// `fast_image_resize` rounds its float results to integers, so it
+/// can't be used for float data.
+fn resample_f32(
+    data: &[f32],
+    src_dims: (usize, usize),
+    dst_dims: (usize, usize),
+    channel_count: usize,
+    filter: ResizeFilter,
+) -> Vec<f32> {
+    let (src_w, src_h) = src_dims;
+    let (dst_w, dst_h) = dst_dims;
+    let x_weights = filter_weights(src_w, dst_w, filter);
+    let y_weights = filter_weights(src_h, dst_h, filter);
+
+    // Horizontal pass.
+    let mut horizontal = vec![0.0f32; dst_w * src_h * channel_count];
+    for (src_row, dst_row) in data
+        .chunks_exact(src_w * channel_count)
+        .zip(horizontal.chunks_exact_mut(dst_w * channel_count))
+    {
+        for (dst_pixel, (start, weights)) in dst_row
+            .chunks_exact_mut(channel_count)
+            .zip(x_weights.iter())
+        {
+            for (i, weight) in weights.iter().enumerate() {
+                let src_pixel = &src_row[((start + i) * channel_count)..];
+                for c in 0..channel_count {
+                    dst_pixel[c] += src_pixel[c] * weight;
+                }
+            }
+        }
+    }
+
+    // Vertical pass.
+    let row_len = dst_w * channel_count;
+    let mut resized = vec![0.0f32; row_len * dst_h];
+    for (dst_row, (start, weights)) in resized.chunks_exact_mut(row_len).zip(y_weights.iter()) {
+        for (i, weight) in weights.iter().enumerate() {
+            let src_row = &horizontal[((start + i) * row_len)..((start + i + 1) * row_len)];
+            for (d, s) in dst_row.iter_mut().zip(src_row.iter()) {
+                *d += s * weight;
+            }
+        }
+    }
+
+    resized
+}
+
+/// Computes the normalized filter weights for resampling one axis from
+/// `src_len` to `dst_len` pixels.  Each target pixel gets the index of
+/// its first source pixel and the weights of its consecutive source
+/// pixels.
+fn filter_weights(src_len: usize, dst_len: usize, filter: ResizeFilter) -> Vec<(usize, Vec<f32>)> {
+    let (support, f): (f64, fn(f64) -> f64) = match filter {
+        ResizeFilter::Box => (0.5, |x| if (-0.5..0.5).contains(&x) { 1.0 } else { 0.0 }),
+        ResizeFilter::Bilinear => (1.0, |x| (1.0 - x.abs()).max(0.0)),
+        ResizeFilter::Lanczos3 => (3.0, |x| {
+            let sinc = |x: f64| {
+                if x == 0.0 {
+                    1.0
+                } else {
+                    let x = x * std::f64::consts::PI;
+                    x.sin() / x
+                }
+            };
+            if x.abs() < 3.0 {
+                sinc(x) * sinc(x / 3.0)
+            } else {
+                0.0
+            }
+        }),
+        ResizeFilter::Mitchell => (2.0, |x| {
+            let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+            let x = x.abs();
+            let n = if x < 1.0 {
+                (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                    + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                    + (6.0 - 2.0 * b)
+            } else if x < 2.0 {
+                (-b - 6.0 * c) * x * x * x
+                    + (6.0 * b + 30.0 * c) * x * x
+                    + (-12.0 * b - 48.0 * c) * x
+                    + (8.0 * b + 24.0 * c)
+            } else {
+                0.0
+            };
+            n / 6.0
+        }),
+    };
+
+    // When downscaling, the filter is widened to cover all of the
+    // source pixels.
+    let scale = src_len as f64 / dst_len as f64;
+    let filter_scale = scale.max(1.0);
+    let radius = support * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * scale;
+            let start = ((center - radius).floor().max(0.0) as usize).min(src_len - 1);
+            let end = ((center + radius).ceil() as usize).clamp(start + 1, src_len);
+            let mut weights: Vec<f64> = (start..end)
+                .map(|j| f((j as f64 + 0.5 - center) / filter_scale))
+                .collect();
+
+            let sum: f64 = weights.iter().sum();
+            if sum.abs() > 1.0e-10 {
+                for w in weights.iter_mut() {
+                    *w /= sum;
+                }
+            } else {
+                // Fall back to the nearest pixel.
+                let nearest = (center as usize).clamp(start, end - 1) - start;
+                for (j, w) in weights.iter_mut().enumerate() {
+                    *w = if j == nearest { 1.0 } else { 0.0 };
+                }
+            }
+
+            (start, weights.iter().map(|&w| w as f32).collect())
+        })
+        .collect()
+}
+
+fn u16_to_bytes(data: &[u16]) -> Vec<u8> {
+    data.iter().flat_map(|n| n.to_ne_bytes()).collect()
+}
+
+fn u16_from_bytes(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+        .collect()
+}
+
+fn srgb_to_linear(n: f32) -> f32 {
+    if n <= 0.04045 {
+        n / 12.92
+    } else {
+        ((n + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(n: f32) -> f32 {
+    if n <= 0.0031308 {
+        n * 12.92
+    } else {
+        n.powf(1.0 / 2.4) * 1.055 - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: usize, height: usize, data: ImageBuf) -> Image {
+        Image {
+            dimensions: (width, height),
+            data,
+            color: None,
+        }
+    }
+
+    /// A black and white checkerboard of single pixels.
+    fn checkerboard(width: usize, height: usize) -> Vec<u8> {
+        (0..(width * height))
+            .flat_map(|i| {
+                let n = [0, 255][(i % width + i / width) % 2];
+                [n, n, n]
+            })
+            .collect()
+    }
+
+    fn options(filter: ResizeFilter, linear_light: bool) -> ResizeOptions {
+        ResizeOptions {
+            filter,
+            linear_light,
+        }
+    }
+
+    #[test]
+    fn encoded_vs_linear_light() {
+        let img = image(8, 8, ImageBuf::Rgb8(checkerboard(8, 8)));
+
+        // Averaging the encoded values gives the middle code value,
+        // while averaging in linear light gives half the light.
+        let encoded = img
+            .resized(4, 4, &options(ResizeFilter::Box, false))
+            .unwrap();
+        let linear = img
+            .resized(4, 4, &options(ResizeFilter::Box, true))
+            .unwrap();
+        match (encoded.data, linear.data) {
+            (ImageBuf::Rgb8(encoded), ImageBuf::Rgb8(linear)) => {
+                assert!(
+                    encoded.iter().all(|&n| n == 127 || n == 128),
+                    "{:?}",
+                    encoded
+                );
+                assert!(linear.iter().all(|&n| n == 188), "{:?}", linear);
+            }
+            _ => panic!("the buffer type changed"),
+        }
+    }
+
+    #[test]
+    fn linear_light_leaves_floats_and_alpha_alone() {
+        let data: Vec<f32> = (0..16)
+            .flat_map(|i| {
+                let n = if i % 2 == 0 { 0.0 } else { 4.0 };
+                [n, n, n, if i % 2 == 0 { 0.0 } else { 1.0 }]
+            })
+            .collect();
+        let img = image(4, 4, ImageBuf::RgbaF32(data));
+        for linear_light in [false, true] {
+            let resized = img
+                .resized(2, 2, &options(ResizeFilter::Box, linear_light))
+                .unwrap();
+            match resized.data {
+                ImageBuf::RgbaF32(data) => {
+                    for pixel in data.chunks(4) {
+                        assert_eq!(pixel, [2.0, 2.0, 2.0, 0.5]);
+                    }
+                }
+                _ => panic!("the buffer type changed"),
+            }
+        }
+    }
+
+    #[test]
+    fn odd_dimensions() {
+        let filters = [
+            ResizeFilter::Box,
+            ResizeFilter::Bilinear,
+            ResizeFilter::Lanczos3,
+            ResizeFilter::Mitchell,
+        ];
+        let cases = [(7, 5, 3, 2), (5, 7, 1, 1), (1, 9, 1, 4), (3, 3, 7, 5)];
+        for &(src_w, src_h, dst_w, dst_h) in cases.iter() {
+            for &filter in filters.iter() {
+                for linear_light in [false, true] {
+                    let opts = options(filter, linear_light);
+
+                    let img = image(src_w, src_h, ImageBuf::Rgb8(vec![200; src_w * src_h * 3]));
+                    let resized = img.resized(dst_w, dst_h, &opts).unwrap();
+                    assert_eq!(resized.dimensions, (dst_w, dst_h));
+                    match resized.data {
+                        ImageBuf::Rgb8(data) => {
+                            assert_eq!(data.len(), dst_w * dst_h * 3);
+                            assert!(data.iter().all(|&n| (n as i32 - 200).abs() <= 1));
+                        }
+                        _ => panic!("the buffer type changed"),
+                    }
+
+                    let img = image(
+                        src_w,
+                        src_h,
+                        ImageBuf::Rgba16(vec![40000; src_w * src_h * 4]),
+                    );
+                    let resized = img.resized(dst_w, dst_h, &opts).unwrap();
+                    match resized.data {
+                        ImageBuf::Rgba16(data) => {
+                            assert_eq!(data.len(), dst_w * dst_h * 4);
+                            assert!(data.iter().all(|&n| (n as i32 - 40000).abs() <= 2));
+                        }
+                        _ => panic!("the buffer type changed"),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let opts = ResizeOptions::default();
+        let img = image(4, 4, ImageBuf::Rgb8(vec![0; 4 * 4 * 3]));
+        assert!(matches!(
+            img.resized(0, 4, &opts),
+            Err(ResizeError::ZeroSize)
+        ));
+        assert!(matches!(
+            image(0, 0, ImageBuf::Rgb8(Vec::new())).resized(4, 4, &opts),
+            Err(ResizeError::ZeroSize)
+        ));
+
+        // Buffer doesn't match the dimensions.
+        let img = image(4, 4, ImageBuf::Rgb8(vec![0; 10]));
+        assert!(matches!(
+            img.resized(2, 2, &opts),
+            Err(ResizeError::InvalidImage)
+        ));
+        let img = image(4, 4, ImageBuf::RgbF32(vec![0.0; 4 * 4 * 4]));
+        assert!(matches!(
+            img.resized(2, 2, &opts),
+            Err(ResizeError::InvalidImage)
+        ));
+    }
+}
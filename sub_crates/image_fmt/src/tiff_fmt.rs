@@ -1,102 +1,809 @@
-use std::io::{Read, Seek};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
 
-use tiff::{decoder::DecodingResult, ColorType};
+use half::f16;
+use tiff::{decoder::DecodingResult, encoder::colortype, tags::Tag, ColorType};
 
-use crate::{error::ReadError, Image, ImageBuf};
+use crate::{
+    error::{ReadError, WriteError},
+    BitDepth, ChannelLayout, ColorDescription, Format, Image, ImageBuf, ProbeInfo,
+};
+
+// Tag values that need special handling when loading.
+const PHOTOMETRIC_WHITE_IS_ZERO: u16 = 0;
+const PHOTOMETRIC_BLACK_IS_ZERO: u16 = 1;
+const PHOTOMETRIC_PALETTE: u16 = 3;
+const SAMPLE_FORMAT_UINT: u16 = 1;
+const SAMPLE_FORMAT_FLOAT: u16 = 3;
+const COMPRESSION_NONE: u16 = 1;
+const COMPRESSION_JPEG: u16 = 7;
+const PREDICTOR_NONE: u16 = 1;
+const PREDICTOR_FLOATING_POINT: u16 = 3;
+const TAG_ICC_PROFILE: u16 = 34675;
+const TAG_XMP: u16 = 700;
 
 pub fn load<R: Read + Seek>(mut reader: R) -> Result<Image, ReadError> {
+    let tags = {
+        let mut decoder = tiff::decoder::Decoder::new(&mut reader)?;
+        Tags::read(&mut decoder)?
+    };
+    reader.seek(SeekFrom::Start(0))?;
+
+    // The tiff crate can't decode palette images, half floats, or 1/2/4
+    // bit samples.  But it can decode their raw samples if we describe
+    // them as something it does support, which we do by patching the
+    // tags in an in-memory copy of the file.  We then interpret the raw
+    // samples ourselves.
+    let mut patches = Vec::new();
+
+    let palette = if tags.photometric == PHOTOMETRIC_PALETTE {
+        match tags.color_map {
+            Some(ref color_map) if tags.bits <= 16 && color_map.len() == 3 << tags.bits => {}
+            _ => {
+                return Err(ReadError::Corrupt {
+                    format: Format::Tiff,
+                    detail: "missing or invalid color map".into(),
+                })
+            }
+        }
+        patches.push((
+            Tag::PhotometricInterpretation,
+            PHOTOMETRIC_BLACK_IS_ZERO as u32,
+        ));
+        tags.color_map
+    } else {
+        None
+    };
+
+    let is_half = tags.sample_format == SAMPLE_FORMAT_FLOAT && tags.bits == 16;
+    if is_half {
+        // The tiff crate would invert the bit patterns as integers.
+        if tags.photometric == PHOTOMETRIC_WHITE_IS_ZERO {
+            return Err(ReadError::UnsupportedFeature);
+        }
+        // The floating point predictor shuffles the bytes of whole
+        // floats, which the tiff crate only does for 32 and 64-bit
+        // floats, not for the integers we decode half floats as.
+        if tags.predictor == PREDICTOR_FLOATING_POINT {
+            return Err(ReadError::UnsupportedFeature);
+        }
+        patches.push((Tag::SampleFormat, SAMPLE_FORMAT_UINT as u32));
+    }
+
+    // Packed samples are read as 8-bit samples, one per byte, by
+    // claiming the image is as wide as its rows are in bytes.
+    let packed = if matches!(tags.bits, 1 | 2 | 4) && tags.sample_format == SAMPLE_FORMAT_UINT {
+        if tags.samples_per_pixel != 1 || tags.is_tiled || tags.predictor != PREDICTOR_NONE {
+            return Err(ReadError::UnsupportedFeature);
+        }
+        let row_bytes = (tags.width * tags.bits as usize).div_ceil(8);
+        patches.push((Tag::BitsPerSample, 8));
+        patches.push((Tag::ImageWidth, row_bytes as u32));
+        Some((tags.bits as u8, tags.width))
+    } else {
+        None
+    };
+
+    let interpretation = Interpretation {
+        palette,
+        is_half,
+        packed,
+        compression: tags.compression,
+    };
+
+    let mut image = if patches.is_empty() {
+        decode(reader, interpretation)?
+    } else {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        for (tag, value) in patches {
+            if !patch_tag(&mut bytes, tag, value) {
+                return Err(ReadError::UnsupportedFeature);
+            }
+        }
+        decode(Cursor::new(bytes), interpretation)?
+    };
+    image.color = tags.icc_profile.map(ColorDescription::from_icc_profile);
+
+    Ok(image)
+}
+
+/// The tags that decide whether we need to help the tiff crate along.
+struct Tags {
+    width: usize,
+    photometric: u16,
+    sample_format: u16,
+    bits: u16,
+    samples_per_pixel: u16,
+    compression: u16,
+    predictor: u16,
+    is_tiled: bool,
+    color_map: Option<Vec<u16>>,
+    icc_profile: Option<Vec<u8>>,
+}
+
+impl Tags {
+    fn read<R: Read + Seek>(decoder: &mut tiff::decoder::Decoder<R>) -> Result<Tags, ReadError> {
+        let first = |values: Option<Vec<u16>>, default: u16| {
+            values
+                .and_then(|values| values.first().copied())
+                .unwrap_or(default)
+        };
+
+        Ok(Tags {
+            width: decoder.dimensions()?.0 as usize,
+            photometric: decoder
+                .find_tag_unsigned(Tag::PhotometricInterpretation)?
+                .unwrap_or(PHOTOMETRIC_BLACK_IS_ZERO),
+            sample_format: first(
+                decoder.find_tag_unsigned_vec(Tag::SampleFormat)?,
+                SAMPLE_FORMAT_UINT,
+            ),
+            bits: first(decoder.find_tag_unsigned_vec(Tag::BitsPerSample)?, 1),
+            samples_per_pixel: decoder
+                .find_tag_unsigned(Tag::SamplesPerPixel)?
+                .unwrap_or(1),
+            compression: decoder
+                .find_tag_unsigned(Tag::Compression)?
+                .unwrap_or(COMPRESSION_NONE),
+            predictor: decoder
+                .find_tag_unsigned(Tag::Predictor)?
+                .unwrap_or(PREDICTOR_NONE),
+            is_tiled: decoder.find_tag(Tag::TileWidth)?.is_some(),
+            color_map: decoder.find_tag_unsigned_vec(Tag::ColorMap)?,
+            icc_profile: decoder
+                .find_tag(Tag::Unknown(TAG_ICC_PROFILE))?
+                .map(|value| value.into_u8_vec())
+                .transpose()?,
+        })
+    }
+}
+
+/// How to interpret the samples returned by the tiff crate.
+struct Interpretation {
+    /// The samples are indices into this TIFF color map.
+    palette: Option<Vec<u16>>,
+
+    /// The samples are the bit patterns of half floats.
+    is_half: bool,
+
+    /// The samples are bytes of packed samples with the given bit depth,
+    /// in an image of the given width.
+    packed: Option<(u8, usize)>,
+
+    compression: u16,
+}
+
+fn decode<R: Read + Seek>(reader: R, interpretation: Interpretation) -> Result<Image, ReadError> {
     let mut decoder =
-        tiff::decoder::Decoder::new(&mut reader)?.with_limits(tiff::decoder::Limits::unlimited());
+        tiff::decoder::Decoder::new(reader)?.with_limits(tiff::decoder::Limits::unlimited());
 
     let dimensions = {
         let tmp = decoder.dimensions()?;
         (tmp.0 as usize, tmp.1 as usize)
     };
-    let pixel_count = dimensions.0 * dimensions.1;
     let colortype = decoder.colortype()?;
     let data = decoder.read_image()?;
 
-    return match (colortype, data) {
-        //------
-        // RGB.
-        (ColorType::RGB(_), DecodingResult::U8(pixel_data)) => {
-            assert_eq!(pixel_data.len(), pixel_count * 3);
-            Ok(Image {
-                dimensions: dimensions,
-                data: ImageBuf::Rgb8(pixel_data),
-            })
+    let (dimensions, data) = match (interpretation.packed, data) {
+        (Some((bits, width)), DecodingResult::U8(packed)) => {
+            let dimensions = (width, dimensions.1);
+            let samples = unpack_bits(&packed, dimensions, bits);
+            let samples = if interpretation.palette.is_some() {
+                samples
+            } else {
+                // Scale up to the full 8-bit range.
+                let scale = 255 / ((1u8 << bits) - 1);
+                samples.iter().map(|&n| n * scale).collect()
+            };
+            (dimensions, DecodingResult::U8(samples))
         }
-        (ColorType::RGB(_), DecodingResult::U16(pixel_data)) => {
-            assert_eq!(pixel_data.len(), pixel_count * 3);
-            Ok(Image {
-                dimensions: (dimensions.0 as usize, dimensions.1 as usize),
-                data: ImageBuf::Rgb16(pixel_data),
-            })
+        (_, data) => (dimensions, data),
+    };
+    let pixel_count = dimensions.0 * dimensions.1;
+
+    let channels = match colortype {
+        ColorType::Gray(_) => 1,
+        ColorType::GrayA(_) => 2,
+        ColorType::RGB(_) => 3,
+        ColorType::RGBA(_) | ColorType::CMYK(_) => 4,
+        // JPEG compressed YCbCr is converted to RGB by the decoder, but
+        // otherwise we'd have to deal with chroma subsampling ourselves.
+        ColorType::YCbCr(_) if interpretation.compression == COMPRESSION_JPEG => 3,
+        _ => return Err(ReadError::UnsupportedFeature),
+    };
+
+    if let Some(color_map) = interpretation.palette {
+        let indices: Vec<usize> = match data {
+            DecodingResult::U8(d) => d.iter().map(|&n| n as usize).collect(),
+            DecodingResult::U16(d) => d.iter().map(|&n| n as usize).collect(),
+            _ => return Err(ReadError::UnsupportedFeature),
+        };
+        if channels != 1 {
+            return Err(ReadError::UnsupportedFeature);
         }
+        check_len(indices.len(), pixel_count)?;
 
-        //-------
-        // RGBA.
-        (ColorType::RGBA(_), DecodingResult::U8(pixel_data)) => {
-            assert_eq!(pixel_data.len(), pixel_count * 4);
-            Ok(Image {
-                dimensions: dimensions,
-                data: ImageBuf::Rgba8(pixel_data),
-            })
+        // The color map is all of the reds, then all of the greens, then
+        // all of the blues.
+        let entries = color_map.len() / 3;
+        return Ok(Image {
+            dimensions,
+            data: ImageBuf::Rgb16(
+                indices
+                    .iter()
+                    .flat_map(|&i| {
+                        [
+                            color_map[i],
+                            color_map[entries + i],
+                            color_map[entries * 2 + i],
+                        ]
+                    })
+                    .collect(),
+            ),
+            color: None,
+        });
+    }
+
+    let data = match (interpretation.is_half, data) {
+        (true, DecodingResult::U16(d)) => {
+            check_len(d.len(), pixel_count * channels)?;
+            let d: Vec<f16> = d.iter().map(|&n| f16::from_bits(n)).collect();
+            let d = to_rgb(d, colortype, f16::to_f32, f16::from_f32);
+            if has_alpha(colortype) {
+                ImageBuf::RgbaF16(d)
+            } else {
+                ImageBuf::RgbF16(d)
+            }
         }
-        (ColorType::RGBA(_), DecodingResult::U16(pixel_data)) => {
-            assert_eq!(pixel_data.len(), pixel_count * 4);
-            Ok(Image {
-                dimensions: dimensions,
-                data: ImageBuf::Rgba16(pixel_data),
-            })
+        (true, _) => return Err(ReadError::UnsupportedFeature),
+
+        (false, DecodingResult::U8(d)) => {
+            check_len(d.len(), pixel_count * channels)?;
+            let d = to_rgb(
+                d,
+                colortype,
+                |n| n as f32 / 255.0,
+                |n| (n * 255.0).round() as u8,
+            );
+            if has_alpha(colortype) {
+                ImageBuf::Rgba8(d)
+            } else {
+                ImageBuf::Rgb8(d)
+            }
+        }
+        (false, DecodingResult::U16(d)) => {
+            check_len(d.len(), pixel_count * channels)?;
+            let d = to_rgb(
+                d,
+                colortype,
+                |n| n as f32 / 65535.0,
+                |n| (n * 65535.0).round() as u16,
+            );
+            if has_alpha(colortype) {
+                ImageBuf::Rgba16(d)
+            } else {
+                ImageBuf::Rgb16(d)
+            }
+        }
+        (false, DecodingResult::F32(d)) => {
+            check_len(d.len(), pixel_count * channels)?;
+            let d = to_rgb(d, colortype, |n| n, |n| n);
+            if has_alpha(colortype) {
+                ImageBuf::RgbaF32(d)
+            } else {
+                ImageBuf::RgbF32(d)
+            }
         }
+        (false, DecodingResult::F64(d)) => {
+            check_len(d.len(), pixel_count * channels)?;
+            let d = to_rgb(
+                d.iter().map(|&n| n as f32).collect(),
+                colortype,
+                |n| n,
+                |n| n,
+            );
+            if has_alpha(colortype) {
+                ImageBuf::RgbaF32(d)
+            } else {
+                ImageBuf::RgbF32(d)
+            }
+        }
+
+        _ => return Err(ReadError::UnsupportedFeature),
+    };
+
+    Ok(Image {
+        dimensions,
+        data,
+        color: None,
+    })
+}
+
+fn check_len(len: usize, expected_len: usize) -> Result<(), ReadError> {
+    if len == expected_len {
+        Ok(())
+    } else {
+        Err(ReadError::Corrupt {
+            format: Format::Tiff,
+            detail: "image data doesn't match the image dimensions".into(),
+        })
+    }
+}
+
+fn has_alpha(colortype: ColorType) -> bool {
+    matches!(colortype, ColorType::GrayA(_) | ColorType::RGBA(_))
+}
 
-        //------------
-        // Grayscale.
-        (ColorType::Gray(_), DecodingResult::U8(pixel_data)) => {
-            assert_eq!(pixel_data.len(), pixel_count);
-            Ok(Image {
-                dimensions: dimensions,
-                data: ImageBuf::Rgb8(pixel_data.iter().map(|&c| [c, c, c]).flatten().collect()),
+/// Converts gray, gray + alpha, and CMYK samples to RGB(A) samples.
+/// Other color types are returned unchanged.
+///
+/// `to_unit` and `from_unit` convert samples to and from the [0, 1]
+/// range, and are only used for CMYK.
+fn to_rgb<T: Copy>(
+    data: Vec<T>,
+    colortype: ColorType,
+    to_unit: impl Fn(T) -> f32,
+    from_unit: impl Fn(f32) -> T,
+) -> Vec<T> {
+    match colortype {
+        ColorType::Gray(_) => data.iter().flat_map(|&c| [c, c, c]).collect(),
+        ColorType::GrayA(_) => data
+            .chunks(2)
+            .flat_map(|c| [c[0], c[0], c[0], c[1]])
+            .collect(),
+        // Naive conversion, without any ink profile.
+        ColorType::CMYK(_) => data
+            .chunks(4)
+            .flat_map(|c| {
+                let k = 1.0 - to_unit(c[3]);
+                [
+                    from_unit((1.0 - to_unit(c[0])) * k),
+                    from_unit((1.0 - to_unit(c[1])) * k),
+                    from_unit((1.0 - to_unit(c[2])) * k),
+                ]
             })
+            .collect(),
+        _ => data,
+    }
+}
+
+/// Unpacks 1, 2, or 4-bit samples into one byte per sample.  Each row
+/// of packed samples starts on a byte boundary, most significant bits
+/// first.
+fn unpack_bits(packed: &[u8], dimensions: (usize, usize), bits: u8) -> Vec<u8> {
+    let (width, height) = dimensions;
+    let bits = bits as usize;
+    let row_bytes = (width * bits).div_ceil(8);
+    let mask = (1u8 << bits) - 1;
+
+    let mut unpacked = Vec::with_capacity(width * height);
+    for row in packed.chunks(row_bytes).take(height) {
+        for x in 0..width {
+            let bit = x * bits;
+            let byte = row.get(bit / 8).copied().unwrap_or(0);
+            unpacked.push((byte >> (8 - bits - bit % 8)) & mask);
         }
-        (ColorType::Gray(_), DecodingResult::U16(pixel_data)) => {
-            assert_eq!(pixel_data.len(), pixel_count);
-            Ok(Image {
-                dimensions: dimensions,
-                data: ImageBuf::Rgb16(pixel_data.iter().map(|&c| [c, c, c]).flatten().collect()),
-            })
+    }
+    unpacked
+}
+
+/// Overwrites the value(s) of a SHORT or LONG tag in the first IFD of
+/// an in-memory TIFF file.  Returns false if the tag wasn't found or
+/// can't hold the value.
+fn patch_tag(bytes: &mut [u8], tag: Tag, value: u32) -> bool {
+    let big_endian = match bytes.get(0..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return false,
+    };
+    let read = |bytes: &[u8], offset: usize, len: usize| -> Option<usize> {
+        let b = bytes.get(offset..offset.checked_add(len)?)?;
+        let n = if big_endian {
+            b.iter().fold(0u64, |n, &b| (n << 8) | b as u64)
+        } else {
+            b.iter().rev().fold(0u64, |n, &b| (n << 8) | b as u64)
+        };
+        usize::try_from(n).ok()
+    };
+
+    // BigTIFF has wider entry counts, value counts, and offsets.
+    let (ifd_offset, word_size, entry_count_size) = match read(bytes, 2, 2) {
+        Some(42) => (read(bytes, 4, 4), 4, 2),
+        Some(43) => (read(bytes, 8, 8), 8, 8),
+        _ => return false,
+    };
+    let ifd_offset = match ifd_offset {
+        Some(offset) => offset,
+        None => return false,
+    };
+    let entry_count = read(bytes, ifd_offset, entry_count_size).unwrap_or(0);
+    let entry_size = 4 + word_size * 2;
+
+    for i in 0..entry_count {
+        let entry = ifd_offset + entry_count_size + i * entry_size;
+        if read(bytes, entry, 2) != Some(tag.to_u16() as usize) {
+            continue;
         }
 
-        //--------------------
-        // Grayscale + alpha.
-        (ColorType::GrayA(_), DecodingResult::U8(pixel_data)) => {
-            assert_eq!(pixel_data.len(), pixel_count * 2);
-            Ok(Image {
-                dimensions: dimensions,
-                data: ImageBuf::Rgba8(
-                    pixel_data
-                        .chunks(2)
-                        .map(|c| [c[0], c[0], c[0], c[1]])
-                        .flatten()
-                        .collect(),
-                ),
-            })
+        let value_bytes = match (read(bytes, entry + 2, 2), big_endian) {
+            // SHORT.
+            (Some(3), _) if value > u16::MAX as u32 => return false,
+            (Some(3), true) => (value as u16).to_be_bytes().to_vec(),
+            (Some(3), false) => (value as u16).to_le_bytes().to_vec(),
+            // LONG.
+            (Some(4), true) => value.to_be_bytes().to_vec(),
+            (Some(4), false) => value.to_le_bytes().to_vec(),
+            _ => return false,
+        };
+        let count = match read(bytes, entry + 4, word_size) {
+            Some(count) => count,
+            None => return false,
+        };
+        let len = value_bytes.len();
+        let values_offset = if count * len <= word_size {
+            entry + 4 + word_size
+        } else {
+            match read(bytes, entry + 4 + word_size, word_size) {
+                Some(offset) => offset,
+                None => return false,
+            }
+        };
+
+        for j in 0..count {
+            let start = values_offset + j * len;
+            match bytes.get_mut(start..(start + len)) {
+                Some(b) => b.copy_from_slice(&value_bytes),
+                None => return false,
+            }
         }
-        (ColorType::GrayA(_), DecodingResult::U16(pixel_data)) => {
-            assert_eq!(pixel_data.len(), pixel_count * 2);
-            Ok(Image {
-                dimensions: dimensions,
-                data: ImageBuf::Rgba16(
-                    pixel_data
-                        .chunks(2)
-                        .map(|c| [c[0], c[0], c[0], c[1]])
-                        .flatten()
-                        .collect(),
-                ),
+        return true;
+    }
+
+    false
+}
+
+pub fn probe<R: Read + Seek>(mut reader: R) -> Result<ProbeInfo, ReadError> {
+    let mut decoder =
+        tiff::decoder::Decoder::new(&mut reader)?.with_limits(tiff::decoder::Limits::unlimited());
+
+    let dimensions = decoder.dimensions()?;
+    let photometric = decoder.find_tag_unsigned::<u16>(Tag::PhotometricInterpretation)?;
+    let colortype = if photometric == Some(PHOTOMETRIC_PALETTE) {
+        // The tiff crate doesn't support palettes directly, but we do.
+        ColorType::Palette(8)
+    } else {
+        decoder.colortype()?
+    };
+
+    // 1 = unsigned integer, 3 = float.
+    let sample_format = decoder
+        .find_tag_unsigned_vec::<u16>(Tag::SampleFormat)?
+        .and_then(|formats| formats.first().copied())
+        .unwrap_or(1);
+
+    let (channels, bits) = match colortype {
+        ColorType::Gray(bits) => (ChannelLayout::Gray, bits),
+        ColorType::GrayA(bits) => (ChannelLayout::GrayAlpha, bits),
+        ColorType::RGB(bits) | ColorType::YCbCr(bits) => (ChannelLayout::Rgb, bits),
+        ColorType::RGBA(bits) => (ChannelLayout::Rgba, bits),
+        ColorType::CMYK(bits) => (ChannelLayout::Cmyk, bits),
+        // TIFF palettes are always 16-bit.
+        ColorType::Palette(_) => (ChannelLayout::Rgb, 16),
+    };
+    let bit_depth = match (sample_format, bits) {
+        (1, 1..=8) => Some(BitDepth::U8),
+        (1, 9..=16) => Some(BitDepth::U16),
+        (3, 16) => Some(BitDepth::F16),
+        (3, 32) => Some(BitDepth::F32),
+        _ => None,
+    };
+
+    // Walking the IFD chain only reads the tags, not the image data.
+    let mut frame_count = 1;
+    while decoder.more_images() {
+        decoder.next_image()?;
+        frame_count += 1;
+    }
+
+    Ok(ProbeInfo {
+        format: Format::Tiff,
+        dimensions: (dimensions.0 as usize, dimensions.1 as usize),
+        channels,
+        bit_depth,
+        frame_count,
+    })
+}
+
+/// Reads the XMP packet from the first IFD, if any.
+pub fn xmp<R: Read + Seek>(mut reader: R) -> Result<Option<Vec<u8>>, ReadError> {
+    let mut decoder = tiff::decoder::Decoder::new(&mut reader)?;
+    Ok(decoder
+        .find_tag(Tag::Unknown(TAG_XMP))?
+        .map(|value| value.into_u8_vec())
+        .transpose()?)
+}
+
+pub fn save<W: Write + Seek>(
+    writer: W,
+    image: &Image,
+    bit_depth: BitDepth,
+) -> Result<(), WriteError> {
+    let width: u32 = image
+        .width()
+        .try_into()
+        .map_err(|_| WriteError::InvalidImage)?;
+    let height: u32 = image
+        .height()
+        .try_into()
+        .map_err(|_| WriteError::InvalidImage)?;
+
+    let mut encoder = tiff::encoder::TiffEncoder::new(writer)?;
+    match bit_depth {
+        BitDepth::U8 => match image.data.clone().to_8_bit() {
+            ImageBuf::Rgb8(data) => encoder.write_image::<colortype::RGB8>(width, height, &data)?,
+            ImageBuf::Rgba8(data) => {
+                encoder.write_image::<colortype::RGBA8>(width, height, &data)?
+            }
+            _ => unreachable!(),
+        },
+        BitDepth::U16 => match image.data.clone().to_16_bit() {
+            ImageBuf::Rgb16(data) => {
+                encoder.write_image::<colortype::RGB16>(width, height, &data)?
+            }
+            ImageBuf::Rgba16(data) => {
+                encoder.write_image::<colortype::RGBA16>(width, height, &data)?
+            }
+            _ => unreachable!(),
+        },
+        BitDepth::F32 => match image.data.clone().to_f32() {
+            ImageBuf::RgbF32(data) => {
+                encoder.write_image::<colortype::RGB32Float>(width, height, &data)?
+            }
+            ImageBuf::RgbaF32(data) => {
+                encoder.write_image::<colortype::RGBA32Float>(width, height, &data)?
+            }
+            _ => unreachable!(),
+        },
+        BitDepth::F16 => return Err(WriteError::UnsupportedFeature),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+
+    /// Builds a single-strip, uncompressed TIFF file with the given
+    /// tags, in addition to the ones that describe the strip.
+    fn tiff_file(big_endian: bool, tags: &[(Tag, u16, Vec<u32>)], data: &[u8]) -> Vec<u8> {
+        let u16_bytes = |n: u16| {
+            if big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            }
+        };
+        let u32_bytes = |n: u32| {
+            if big_endian {
+                n.to_be_bytes()
+            } else {
+                n.to_le_bytes()
+            }
+        };
+
+        let mut tags = tags.to_vec();
+        tags.push((Tag::Compression, SHORT, vec![COMPRESSION_NONE as u32]));
+        tags.push((Tag::StripOffsets, LONG, vec![8]));
+        tags.push((Tag::StripByteCounts, LONG, vec![data.len() as u32]));
+        tags.sort_by_key(|(tag, _, _)| tag.to_u16());
+
+        let mut file = Vec::new();
+        file.extend(if big_endian { b"MM" } else { b"II" });
+        file.extend(u16_bytes(42));
+        let ifd_offset = (8 + data.len()).next_multiple_of(2);
+        file.extend(u32_bytes(ifd_offset as u32));
+        file.extend(data);
+        file.resize(ifd_offset, 0);
+
+        // Values that don't fit in an entry go after the IFD.
+        let mut extra_offset = ifd_offset + 2 + tags.len() * 12 + 4;
+        let mut extra = Vec::new();
+        file.extend(u16_bytes(tags.len() as u16));
+        for (tag, kind, values) in tags.iter() {
+            let bytes: Vec<u8> = values
+                .iter()
+                .flat_map(|&n| match *kind {
+                    SHORT => u16_bytes(n as u16).to_vec(),
+                    _ => u32_bytes(n).to_vec(),
+                })
+                .collect();
+            file.extend(u16_bytes(tag.to_u16()));
+            file.extend(u16_bytes(*kind));
+            file.extend(u32_bytes(values.len() as u32));
+            if bytes.len() <= 4 {
+                file.extend(&bytes);
+                file.resize(file.len() + 4 - bytes.len(), 0);
+            } else {
+                file.extend(u32_bytes(extra_offset as u32));
+                extra_offset += bytes.len();
+                extra.extend(bytes);
+            }
+        }
+        file.extend(u32_bytes(0));
+        file.extend(extra);
+        file
+    }
+
+    fn gray_tags(
+        width: u32,
+        height: u32,
+        bits: u16,
+        photometric: u16,
+    ) -> Vec<(Tag, u16, Vec<u32>)> {
+        vec![
+            (Tag::ImageWidth, LONG, vec![width]),
+            (Tag::ImageLength, LONG, vec![height]),
+            (Tag::BitsPerSample, SHORT, vec![bits as u32]),
+            (
+                Tag::PhotometricInterpretation,
+                SHORT,
+                vec![photometric as u32],
+            ),
+            (Tag::SamplesPerPixel, SHORT, vec![1]),
+            (Tag::RowsPerStrip, LONG, vec![height]),
+        ]
+    }
+
+    /// Packs samples most significant bits first, with each row starting
+    /// on a byte boundary.
+    fn pack_bits(samples: &[u8], width: usize, bits: usize) -> Vec<u8> {
+        let row_bytes = (width * bits).div_ceil(8);
+        samples
+            .chunks(width)
+            .flat_map(|row| {
+                let mut bytes = vec![0u8; row_bytes];
+                for (x, &n) in row.iter().enumerate() {
+                    let bit = x * bits;
+                    bytes[bit / 8] |= n << (8 - bits - bit % 8);
+                }
+                bytes
             })
+            .collect()
+    }
+
+    fn load(file: Vec<u8>) -> Result<Image, ReadError> {
+        super::load(Cursor::new(file))
+    }
+
+    #[test]
+    fn low_bit_depth_gray() {
+        let (width, height) = (11, 3);
+        for bits in [1, 2, 4] {
+            for big_endian in [true, false] {
+                let max = (1u8 << bits) - 1;
+                let samples: Vec<u8> = (0..(width * height))
+                    .map(|i| (i * 7 % 13) as u8 & max)
+                    .collect();
+                let file = tiff_file(
+                    big_endian,
+                    &gray_tags(width as u32, height as u32, bits, PHOTOMETRIC_BLACK_IS_ZERO),
+                    &pack_bits(&samples, width, bits as usize),
+                );
+                let image = load(file).unwrap();
+                assert_eq!(image.dimensions, (width, height));
+                match image.data {
+                    ImageBuf::Rgb8(data) => {
+                        for (pixel, &n) in data.chunks(3).zip(samples.iter()) {
+                            let v = (n as u32 * 255 / max as u32) as u8;
+                            assert_eq!(pixel, [v; 3], "{} bits", bits);
+                        }
+                    }
+                    _ => panic!("unexpected buffer type"),
+                }
+            }
         }
+    }
 
-        _ => Err(ReadError::UnsupportedFeature),
-    };
+    #[test]
+    fn palette() {
+        let (width, height) = (5, 4);
+        for bits in [4u16, 8] {
+            for big_endian in [true, false] {
+                let entries = 1usize << bits;
+                let color_map: Vec<u32> = (0..(entries * 3))
+                    .map(|i| (i as u32 * 40503) & 0xffff)
+                    .collect();
+                let indices: Vec<u8> = (0..(width * height))
+                    .map(|i| (i * 5 % entries) as u8)
+                    .collect();
+                let data = if bits == 8 {
+                    indices.clone()
+                } else {
+                    pack_bits(&indices, width, bits as usize)
+                };
+                let mut tags = gray_tags(width as u32, height as u32, bits, PHOTOMETRIC_PALETTE);
+                tags.push((Tag::ColorMap, SHORT, color_map.clone()));
+
+                match load(tiff_file(big_endian, &tags, &data)).unwrap().data {
+                    ImageBuf::Rgb16(data) => {
+                        for (pixel, &i) in data.chunks(3).zip(indices.iter()) {
+                            let i = i as usize;
+                            let expected = [
+                                color_map[i] as u16,
+                                color_map[entries + i] as u16,
+                                color_map[entries * 2 + i] as u16,
+                            ];
+                            assert_eq!(pixel, expected, "{} bits", bits);
+                        }
+                    }
+                    _ => panic!("unexpected buffer type"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn palette_bad_color_map() {
+        let mut tags = gray_tags(2, 2, 4, PHOTOMETRIC_PALETTE);
+        tags.push((Tag::ColorMap, SHORT, vec![0; 3 * 8]));
+        assert!(matches!(
+            load(tiff_file(true, &tags, &[0; 2])),
+            Err(ReadError::Corrupt { .. })
+        ));
+    }
+
+    fn half_tags(width: u32, height: u32, predictor: u16) -> Vec<(Tag, u16, Vec<u32>)> {
+        vec![
+            (Tag::ImageWidth, LONG, vec![width]),
+            (Tag::ImageLength, LONG, vec![height]),
+            (Tag::BitsPerSample, SHORT, vec![16, 16, 16]),
+            (Tag::PhotometricInterpretation, SHORT, vec![2]),
+            (Tag::SamplesPerPixel, SHORT, vec![3]),
+            (Tag::RowsPerStrip, LONG, vec![height]),
+            (Tag::Predictor, SHORT, vec![predictor as u32]),
+            (
+                Tag::SampleFormat,
+                SHORT,
+                vec![SAMPLE_FORMAT_FLOAT as u32; 3],
+            ),
+        ]
+    }
+
+    #[test]
+    fn half_float() {
+        let (width, height) = (3, 2);
+        let samples: Vec<f16> = (0..(width * height * 3))
+            .map(|i| f16::from_f32(i as f32 * 0.37 - 2.0))
+            .collect();
+        for big_endian in [true, false] {
+            let data: Vec<u8> = samples
+                .iter()
+                .flat_map(|n| {
+                    if big_endian {
+                        n.to_be_bytes()
+                    } else {
+                        n.to_le_bytes()
+                    }
+                })
+                .collect();
+            let tags = half_tags(width as u32, height as u32, PREDICTOR_NONE);
+            match load(tiff_file(big_endian, &tags, &data)).unwrap().data {
+                ImageBuf::RgbF16(data) => assert_eq!(data, samples),
+                _ => panic!("unexpected buffer type"),
+            }
+        }
+    }
+
+    #[test]
+    fn half_float_predictor() {
+        let tags = half_tags(1, 1, PREDICTOR_FLOATING_POINT);
+        assert!(matches!(
+            load(tiff_file(true, &tags, &[0; 6])),
+            Err(ReadError::UnsupportedFeature)
+        ));
+    }
 }
@@ -0,0 +1,82 @@
+use std::io::{BufReader, Read, Seek};
+
+use image_webp::WebPDecoder;
+
+use crate::{
+    error::ReadError, BitDepth, ChannelLayout, ColorDescription, Format, Image, ImageBuf, ProbeInfo,
+};
+
+pub fn load<R: Read + Seek>(reader: R) -> Result<Image, ReadError> {
+    // Handles lossy (VP8), lossless (VP8L), and extended files with
+    // alpha.  For animated files we just take the first frame.
+    let mut decoder = WebPDecoder::new(BufReader::new(reader))?;
+
+    let (width, height) = decoder.dimensions();
+    let dimensions = (width as usize, height as usize);
+
+    let mut pixel_data = vec![
+        0u8;
+        decoder
+            .output_buffer_size()
+            .ok_or(ReadError::UnsupportedFeature)?
+    ];
+    decoder.read_image(&mut pixel_data)?;
+
+    let color = decoder
+        .icc_profile()?
+        .map(ColorDescription::from_icc_profile);
+
+    Ok(Image {
+        dimensions,
+        data: if decoder.has_alpha() {
+            ImageBuf::Rgba8(pixel_data)
+        } else {
+            ImageBuf::Rgb8(pixel_data)
+        },
+        color,
+    })
+}
+
+pub fn probe<R: Read + Seek>(reader: R) -> Result<ProbeInfo, ReadError> {
+    // Only reads the chunk headers.
+    let decoder = WebPDecoder::new(BufReader::new(reader))?;
+    let (width, height) = decoder.dimensions();
+
+    Ok(ProbeInfo {
+        format: Format::WebP,
+        dimensions: (width as usize, height as usize),
+        channels: if decoder.has_alpha() {
+            ChannelLayout::Rgba
+        } else {
+            ChannelLayout::Rgb
+        },
+        bit_depth: Some(BitDepth::U8),
+        frame_count: if decoder.is_animated() {
+            decoder.num_frames() as usize
+        } else {
+            1
+        },
+    })
+}
+
+/// Reads the contents of the EXIF chunk, if any.
+///
+/// Some encoders prefix the chunk contents with a JPEG-style `Exif\0\0`
+/// header, which is stripped here so that the returned data always
+/// starts with the TIFF header.
+pub fn exif<R: Read + Seek>(reader: R) -> Result<Option<Vec<u8>>, ReadError> {
+    let mut decoder = WebPDecoder::new(BufReader::new(reader))?;
+
+    Ok(decoder.exif_metadata()?.map(|mut data| {
+        if data.starts_with(b"Exif\0\0") {
+            data.drain(..6);
+        }
+        data
+    }))
+}
+
+/// Reads the contents of the XMP chunk, if any.
+pub fn xmp<R: Read + Seek>(reader: R) -> Result<Option<Vec<u8>>, ReadError> {
+    let mut decoder = WebPDecoder::new(BufReader::new(reader))?;
+    Ok(decoder.xmp_metadata()?)
+}
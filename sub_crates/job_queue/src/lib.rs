@@ -1,4 +1,5 @@
 use std::collections::VecDeque;
+use std::sync::Mutex;
 
 use scheduled_thread_pool::{JobHandle, ScheduledThreadPool};
 use shared_data::Shared;
@@ -32,7 +33,7 @@ impl JobQueue {
     /// - A job finishes.
     /// - Cancelation is requested.
     pub fn set_update_fn<F: Fn() + Send + 'static>(&mut self, cleanup_function: F) {
-        self.job_status.lock_mut().update_fn = Some(Box::new(cleanup_function));
+        self.job_status.lock_mut().update_fn = Some(Mutex::new(Box::new(cleanup_function)));
     }
 
     pub fn add_job<F>(&self, name: &str, job: F)
@@ -49,9 +50,7 @@ impl JobQueue {
             self.runner.execute(move || {
                 let job_status = local_job_status;
 
-                if let Some(update_fn) = &job_status.lock().update_fn {
-                    update_fn();
-                }
+                job_status.lock().call_update_fn();
 
                 // Actually run the job.
                 // TODO: this use of `AssertUndwindSafe` is a workaround
@@ -70,9 +69,7 @@ impl JobQueue {
                 job_status.jobs.pop_front(); // This job.
                 job_status.do_cancel = false;
                 job_status.clear_progress();
-                if let Some(update_fn) = &job_status.update_fn {
-                    update_fn();
-                }
+                job_status.call_update_fn();
             }),
             job_name2,
         ));
@@ -97,9 +94,7 @@ impl JobQueue {
             // Mark currently running job for cancelation.
             job_status.do_cancel = true;
         }
-        if let Some(update_fn) = &job_status.update_fn {
-            update_fn();
-        }
+        job_status.call_update_fn();
     }
 
     /// Cancel all jobs that aren't currently running.
@@ -110,9 +105,7 @@ impl JobQueue {
         while job_status.jobs.len() > 1 {
             job_status.jobs.pop_back().unwrap().0.cancel();
         }
-        if let Some(update_fn) = &job_status.update_fn {
-            update_fn();
-        }
+        job_status.call_update_fn();
     }
 
     pub fn cancel_jobs_with_name(&self, name: &str) {
@@ -131,9 +124,7 @@ impl JobQueue {
                 job_status.do_cancel = true;
             }
         }
-        if let Some(update_fn) = &job_status.update_fn {
-            update_fn();
-        }
+        job_status.call_update_fn();
     }
 
     /// Cancel all jobs that aren't currently running that match the given name.
@@ -148,9 +139,7 @@ impl JobQueue {
                 }
             }
         }
-        if let Some(update_fn) = &job_status.update_fn {
-            update_fn();
-        }
+        job_status.call_update_fn();
     }
 
     pub fn is_canceling(&self) -> bool {
@@ -198,46 +187,46 @@ pub struct JobStatus {
     job_progress: Option<(String, f32)>,
     log: VecDeque<(String, LogLevel)>,
     do_cancel: bool,
-    update_fn: Option<Box<dyn Fn() + Send + 'static>>,
+
+    // In a `Mutex` so that `JobStatus` is `Sync`, which lets jobs check
+    // for cancelation from multiple threads, without requiring the
+    // function itself to be.
+    update_fn: Option<Mutex<Box<dyn Fn() + Send + 'static>>>,
 }
 
 impl JobStatus {
+    fn call_update_fn(&self) {
+        if let Some(update_fn) = &self.update_fn {
+            update_fn.lock().unwrap()();
+        }
+    }
+
     pub fn is_canceled(&self) -> bool {
         self.do_cancel
     }
 
     pub fn set_progress(&mut self, text: String, ratio: f32) {
         self.job_progress = Some((text, ratio));
-        if let Some(update_fn) = &self.update_fn {
-            update_fn();
-        }
+        self.call_update_fn();
     }
 
     pub fn clear_progress(&mut self) {
         self.job_progress = None;
-        if let Some(update_fn) = &self.update_fn {
-            update_fn();
-        }
+        self.call_update_fn();
     }
 
     pub fn log_error(&mut self, message: String) {
         self.log.push_front((message, LogLevel::Error));
-        if let Some(update_fn) = &self.update_fn {
-            update_fn();
-        }
+        self.call_update_fn();
     }
 
     pub fn log_warning(&mut self, message: String) {
         self.log.push_front((message, LogLevel::Warning));
-        if let Some(update_fn) = &self.update_fn {
-            update_fn();
-        }
+        self.call_update_fn();
     }
 
     pub fn log_note(&mut self, message: String) {
         self.log.push_front((message, LogLevel::Note));
-        if let Some(update_fn) = &self.update_fn {
-            update_fn();
-        }
+        self.call_update_fn();
     }
 }
@@ -10,6 +10,9 @@ mod gamut_map;
 mod hsv_lut;
 mod tone_map;
 
+pub use agx::{make_agx_display_p3, make_agx_rec2020, make_agx_rec709, AgX};
+pub use tone_map::{ToneCurve, Tonemapper};
+
 /// Helper function to decompress in-memory xz-compressed data.
 fn decompress_xz(data: &[u8]) -> Vec<u8> {
     let mut decompressed_data = std::io::Cursor::new(Vec::new());
@@ -0,0 +1,210 @@
+use rayon::prelude::*;
+
+/// The minimum number of well-exposed pixels two images need in common
+/// for their exposure ratio to be estimated directly.
+const MIN_OVERLAP: usize = 64;
+
+/// Estimates the relative exposures of images of the same scene, from
+/// the pixels that are well exposed in both images of each pair.
+///
+/// `images` are the pixels of each image, sampled at the same pixel
+/// positions in all of them.  The pixel values should be (at least
+/// approximately) linear, with the image's range mapped to [0.0, 1.0].
+/// Only pixels with all channels within `well_exposed` in both images
+/// of a pair are used, which excludes both noise and clipping.
+///
+/// Images are grouped by which other images they can be related to,
+/// directly or through other images.  Returns, for each image, the
+/// index of the first image in its group and its exposure relative to
+/// that image.  Images that don't share enough well-exposed pixels with
+/// any other image are in a group of their own.
+pub fn estimate_exposure_ratios(
+    images: &[&[[f32; 3]]],
+    well_exposed: (f32, f32),
+) -> Vec<(usize, f32)> {
+    let image_count = images.len();
+
+    // The log2 exposure difference between each pair of images that
+    // has enough overlap, as (i, j, log2(exposure_j / exposure_i), weight).
+    let pairs: Vec<(usize, usize)> = (0..image_count)
+        .flat_map(|i| ((i + 1)..image_count).map(move |j| (i, j)))
+        .collect();
+    let differences: Vec<(usize, usize, f32, f32)> = pairs
+        .par_iter()
+        .filter_map(|&(i, j)| {
+            let (difference, overlap) =
+                log_exposure_difference(images[i], images[j], well_exposed)?;
+            Some((i, j, difference, overlap as f32))
+        })
+        .collect();
+
+    // Find the groups of connected images, each labeled with its
+    // lowest image index.
+    let mut groups: Vec<usize> = (0..image_count).collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &(i, j, _, _) in differences.iter() {
+            if groups[i] != groups[j] {
+                let group = groups[i].min(groups[j]);
+                groups[i] = group;
+                groups[j] = group;
+                changed = true;
+            }
+        }
+    }
+
+    // Solve for the log exposures that best fit all of the pairwise
+    // differences (weighted by overlap), with the first image of each
+    // group fixed at zero.  There are few enough images that simple
+    // Gauss-Seidel iteration converges quickly.
+    let mut log_exposures = vec![0.0f32; image_count];
+    for _ in 0..256 {
+        let mut max_change = 0.0f32;
+        for n in 0..image_count {
+            if groups[n] == n {
+                continue;
+            }
+            let mut sum = 0.0;
+            let mut weight_sum = 0.0;
+            for &(i, j, difference, weight) in differences.iter() {
+                if i == n {
+                    sum += (log_exposures[j] - difference) * weight;
+                    weight_sum += weight;
+                } else if j == n {
+                    sum += (log_exposures[i] + difference) * weight;
+                    weight_sum += weight;
+                }
+            }
+            let new = sum / weight_sum;
+            max_change = max_change.max((new - log_exposures[n]).abs());
+            log_exposures[n] = new;
+        }
+        if max_change < 0.0001 {
+            break;
+        }
+    }
+
+    groups
+        .iter()
+        .zip(log_exposures.iter())
+        .map(|(&group, &e)| (group, e.exp2()))
+        .collect()
+}
+
+/// Computes log2(exposure_b / exposure_a) from the median brightness
+/// ratio of the pixels that are well exposed in both images.
+///
+/// Also returns the number of pixels that went into it.  Returns `None`
+/// if there are too few of them.
+fn log_exposure_difference(
+    image_a: &[[f32; 3]],
+    image_b: &[[f32; 3]],
+    well_exposed: (f32, f32),
+) -> Option<(f32, usize)> {
+    let is_well_exposed = |rgb: &[f32; 3]| {
+        rgb.iter()
+            .all(|&n| n >= well_exposed.0 && n <= well_exposed.1)
+    };
+
+    let mut log_ratios: Vec<f32> = image_a
+        .iter()
+        .zip(image_b.iter())
+        .filter(|(a, b)| is_well_exposed(a) && is_well_exposed(b))
+        .map(|(a, b)| ((b[0] + b[1] + b[2]) / (a[0] + a[1] + a[2])).log2())
+        .filter(|n| n.is_finite())
+        .collect();
+    if log_ratios.len() < MIN_OVERLAP {
+        return None;
+    }
+
+    // The median is robust to the occasional moving object or
+    // not-quite-linear pixel.
+    let mid = log_ratios.len() / 2;
+    let (_, median, _) = log_ratios.select_nth_unstable_by(mid, |a, b| a.total_cmp(b));
+
+    Some((*median, log_ratios.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WELL_EXPOSED: (f32, f32) = (0.01, 0.8);
+
+    /// A scene with a wide range of (linear) brightnesses, captured
+    /// with the given exposure and clipped at 1.0.
+    fn capture(exposure: f32) -> Vec<[f32; 3]> {
+        (0..4096)
+            .map(|i| {
+                let n = (i as f32 / 4096.0 * 16.0 - 12.0).exp2();
+                let rgb = [n, n * 0.8, n * 0.6];
+                rgb.map(|c| (c * exposure).min(1.0))
+            })
+            .collect()
+    }
+
+    fn assert_ratios(estimated: &[(usize, f32)], expected: &[(usize, f32)]) {
+        assert_eq!(estimated.len(), expected.len());
+        for (&(e_group, e), &(x_group, x)) in estimated.iter().zip(expected.iter()) {
+            assert_eq!(e_group, x_group);
+            assert!((e / x - 1.0).abs() < 0.01, "{} != {}", e, x);
+        }
+    }
+
+    #[test]
+    fn bracketed_set() {
+        let images = [capture(1.0), capture(4.0), capture(0.25), capture(16.0)];
+        let slices: Vec<&[[f32; 3]]> = images.iter().map(|img| &img[..]).collect();
+        assert_ratios(
+            &estimate_exposure_ratios(&slices, WELL_EXPOSED),
+            &[(0, 1.0), (0, 4.0), (0, 0.25), (0, 16.0)],
+        );
+    }
+
+    #[test]
+    fn chained_through_middle_image() {
+        // The darkest and brightest images have nothing well exposed in
+        // common, so they can only be related through the middle one.
+        let images = [capture(1.0), capture(8.0), capture(64.0)];
+        let slices: Vec<&[[f32; 3]]> = images.iter().map(|img| &img[..]).collect();
+        assert!(log_exposure_difference(slices[0], slices[2], WELL_EXPOSED).is_none());
+        assert_ratios(
+            &estimate_exposure_ratios(&slices, WELL_EXPOSED),
+            &[(0, 1.0), (0, 8.0), (0, 64.0)],
+        );
+    }
+
+    #[test]
+    fn no_overlap() {
+        let black = vec![[0.0f32; 3]; 4096];
+        let images = [capture(1.0), black, capture(2.0)];
+        let slices: Vec<&[[f32; 3]]> = images.iter().map(|img| &img[..]).collect();
+        assert_ratios(
+            &estimate_exposure_ratios(&slices, WELL_EXPOSED),
+            &[(0, 1.0), (1, 1.0), (0, 2.0)],
+        );
+    }
+
+    #[test]
+    fn first_image_isolated() {
+        // The other images should still be related to each other.
+        let white = vec![[1.0f32; 3]; 4096];
+        let images = [white, capture(2.0), capture(0.5), capture(8.0)];
+        let slices: Vec<&[[f32; 3]]> = images.iter().map(|img| &img[..]).collect();
+        assert_ratios(
+            &estimate_exposure_ratios(&slices, WELL_EXPOSED),
+            &[(0, 1.0), (1, 1.0), (1, 0.25), (1, 4.0)],
+        );
+    }
+
+    #[test]
+    fn single_and_empty() {
+        let image = capture(1.0);
+        assert_ratios(
+            &estimate_exposure_ratios(&[&image], WELL_EXPOSED),
+            &[(0, 1.0)],
+        );
+        assert!(estimate_exposure_ratios(&[], WELL_EXPOSED).is_empty());
+    }
+}
@@ -1,12 +1,14 @@
 //! A crate for computing various things about camera sensors.
 
 pub mod emor;
+mod exposure_estimation;
 mod exposure_mapping;
 mod histogram;
 pub mod utils;
 
 pub use histogram::Histogram;
 
+pub use exposure_estimation::estimate_exposure_ratios;
 pub use exposure_mapping::ExposureMapping;
 
 /// Estimate a transfer function to fit the given histogram-exposure